@@ -14,14 +14,15 @@ use std::thread;
 use std::str::FromStr;
 
 use scaproust::*;
-fn create_session() -> Session {
+fn create_session(spin_budget: Duration) -> Session {
     SessionBuilder::new().
         with("tcp", Tcp).
+        with_spin_budget(spin_budget).
         build().expect("Failed to create session !")
 }
 
 fn usage(program: &str) -> ! {
-    let _ = writeln!(stderr(), "Usage: {} <bind-to> <msg-size> <roundtrips>", program);
+    let _ = writeln!(stderr(), "Usage: {} <bind-to> <msg-size> <roundtrips> [spin-budget-us]", program);
     std::process::exit(1)
 }
 
@@ -32,15 +33,16 @@ fn main() {
     let args: Vec<&str> = os_args.iter().map(|x| x.as_ref()).collect();
     let program = args[0];
 
-    if args.len() != 4 {
+    if args.len() != 4 && args.len() != 5 {
         usage(program);
     }
 
     let url = &args[1];
     let msg_size = usize::from_str(args[2]).expect("Failed to parse msg-size");
     let roundtrips = usize::from_str(args[3]).expect("Failed to parse roundtrips");
+    let spin_budget = args.get(4).map_or(0, |x| u64::from_str(x).expect("Failed to parse spin-budget-us"));
 
-    let mut session = create_session();
+    let mut session = create_session(Duration::from_micros(spin_budget));
     let mut socket = session.create_socket::<Pair>().expect("Failed to create socket !");
 
     socket.set_tcp_nodelay(true).expect("Failed to set tcp nodelay !");
@@ -14,14 +14,15 @@ use std::thread;
 use std::str::FromStr;
 
 use scaproust::*;
-fn create_session() -> Session {
+fn create_session(spin_budget: Duration) -> Session {
     SessionBuilder::new().
         with("tcp", Tcp).
+        with_spin_budget(spin_budget).
         build().expect("Failed to create session !")
 }
 
 fn usage(program: &str) -> ! {
-    let _ = writeln!(stderr(), "Usage: {} <connect-to> <msg-size> <roundtrips>", program);
+    let _ = writeln!(stderr(), "Usage: {} <connect-to> <msg-size> <roundtrips> [spin-budget-us]", program);
     std::process::exit(1)
 }
 
@@ -32,15 +33,16 @@ fn main() {
     let args: Vec<&str> = os_args.iter().map(|x| x.as_ref()).collect();
     let program = args[0];
 
-    if args.len() != 4 {
+    if args.len() != 4 && args.len() != 5 {
         usage(program);
     }
 
     let url = &args[1];
     let msg_size = usize::from_str(args[2]).expect("Failed to parse msg-size");
     let roundtrips = usize::from_str(args[3]).expect("Failed to parse roundtrips");
+    let spin_budget = args.get(4).map_or(0, |x| u64::from_str(x).expect("Failed to parse spin-budget-us"));
 
-    let mut session = create_session();
+    let mut session = create_session(Duration::from_micros(spin_budget));
     let mut socket = session.create_socket::<Pair>().expect("Failed to create socket !");
 
     socket.set_tcp_nodelay(true).expect("Failed to set tcp nodelay !");
@@ -50,10 +52,13 @@ fn main() {
 
     let buffer = vec![6; msg_size];
     let mut msg = Message::from_body(buffer);
+    let mut roundtrip_times = Vec::with_capacity(roundtrips);
     let start = Instant::now();
     for _ in 0..roundtrips {
+        let roundtrip_start = Instant::now();
         socket.send_msg(msg).unwrap();
         msg = socket.recv_msg().unwrap();
+        roundtrip_times.push(roundtrip_start.elapsed());
     }
     let elapsed  = start.elapsed();
     let seconds = elapsed.as_secs() as f64;
@@ -64,4 +69,11 @@ fn main() {
     println!("message size: {} [B]", msg_size);
     println!("roundtrip count: {}", roundtrips);
     println!("average latency: {:0.3} [us]", latency);
+
+    roundtrip_times.sort();
+    if let Some(p99) = roundtrip_times.get(roundtrips * 99 / 100) {
+        let p99_micros = (p99.as_secs() as f64 * 1_000_000f64) + p99.subsec_nanos() as f64 / 1_000f64;
+
+        println!("99th percentile latency: {:0.3} [us]", p99_micros / 2f64);
+    }
 }
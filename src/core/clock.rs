@@ -0,0 +1,133 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::time::Instant;
+#[cfg(feature = "testing")]
+use std::time::Duration;
+#[cfg(feature = "testing")]
+use std::sync::{Arc, Condvar, Mutex};
+
+use mio::{Ready, SetReadiness};
+
+/// Time source of the I/O thread, deciding when the scheduled tasks,
+/// like the send and recv timeouts or the reconnections, are due.
+/// See [SessionBuilder::with_clock](struct.SessionBuilder.html#method.with_clock).
+pub trait Clock {
+    /// Returns the current time.
+    fn now(&self) -> Instant;
+
+    /// Called once by the I/O thread before it starts.
+    /// A clock whose time does not flow along with the system clock must keep the waker,
+    /// and wake it each time its time changes, so that the tasks that became due are run.
+    fn set_waker(&mut self, _: ClockWaker) {}
+
+    /// Called by the I/O thread each time it schedules a task, with the time the task is due at.
+    fn on_scheduled(&self, _: Instant) {}
+}
+
+/// Wakes the I/O thread up so it runs the tasks that became due, see [Clock::set_waker](trait.Clock.html#method.set_waker).
+#[derive(Clone)]
+pub struct ClockWaker {
+    readiness: SetReadiness
+}
+
+impl ClockWaker {
+    #[doc(hidden)]
+    pub fn new(set_readiness: SetReadiness) -> ClockWaker {
+        ClockWaker { readiness: set_readiness }
+    }
+
+    pub fn wake(&self) {
+        let _ = self.readiness.set_readiness(Ready::readable());
+    }
+}
+
+/// The default clock, following the system monotonic clock.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+}
+
+/// A clock that only moves forward when told to, so that the tests involving timeouts
+/// neither depend on the system clock nor wait for it.
+/// The clones of a test clock share the same time, one is given to the session, another one is kept by the test.
+#[cfg(feature = "testing")]
+#[derive(Clone)]
+pub struct TestClock {
+    state: Arc<Mutex<TestClockState>>,
+    scheduled: Arc<Condvar>
+}
+
+#[cfg(feature = "testing")]
+struct TestClockState {
+    now: Instant,
+    waker: Option<ClockWaker>,
+    scheduled_count: usize
+}
+
+#[cfg(feature = "testing")]
+impl TestClock {
+    pub fn new() -> TestClock {
+        let state = TestClockState {
+            now: Instant::now(),
+            waker: None,
+            scheduled_count: 0
+        };
+
+        TestClock {
+            state: Arc::new(Mutex::new(state)),
+            scheduled: Arc::new(Condvar::new())
+        }
+    }
+
+    /// Moves the time forward, making the tasks scheduled within `delay` due.
+    pub fn advance(&self, delay: Duration) {
+        let mut state = self.state.lock().unwrap();
+
+        state.now += delay;
+
+        if let Some(ref waker) = state.waker {
+            waker.wake();
+        }
+    }
+
+    /// Blocks until the I/O thread has scheduled `count` tasks since the clock was created,
+    /// so that the test advances the time once the tasks it expects are actually scheduled.
+    pub fn wait_scheduled(&self, count: usize) {
+        let mut state = self.state.lock().unwrap();
+
+        while state.scheduled_count < count {
+            state = self.scheduled.wait(state).unwrap();
+        }
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Default for TestClock {
+    fn default() -> TestClock {
+        TestClock::new()
+    }
+}
+
+#[cfg(feature = "testing")]
+impl Clock for TestClock {
+    fn now(&self) -> Instant {
+        self.state.lock().unwrap().now
+    }
+
+    fn set_waker(&mut self, waker: ClockWaker) {
+        self.state.lock().unwrap().waker = Some(waker);
+    }
+
+    fn on_scheduled(&self, _: Instant) {
+        self.state.lock().unwrap().scheduled_count += 1;
+        self.scheduled.notify_all();
+    }
+}
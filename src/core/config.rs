@@ -4,26 +4,57 @@
 // or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
 // This file may not be copied, modified, or distributed except according to those terms.
 
+use std::error;
+use std::fmt;
+use std::io;
 use std::io::Result;
+use std::net::SocketAddr;
 use std::time::Duration;
 
+use transport::{ProtocolVersions, OversizePolicy, FEATURE_EXTENSIONS};
 use io_error::*;
 
+#[derive(Clone)]
 pub struct Config {
+    pub linger: Option<Duration>,
     pub send_timeout: Option<Duration>,
     pub send_priority: u8,
     pub recv_timeout: Option<Duration>,
     pub recv_priority: u8,
+    pub recv_weight: u32,
     pub retry_ivl: Duration,
     pub retry_ivl_max: Option<Duration>,
+    pub max_rebind_attempts: Option<u32>,
+    pub max_accepted_pipes: Option<usize>,
     pub tcp_no_delay: bool,
-    pub recv_max_size: u64
+    pub tcp_keepalive: Option<Duration>,
+    pub reuse_addr: bool,
+    pub connect_source_addr: Option<SocketAddr>,
+    pub ipc_permissions: Option<u32>,
+    pub recv_max_size: u64,
+    pub send_chunk_size: usize,
+    pub oversize_policy: OversizePolicy,
+    pub send_queue_size: usize,
+    pub max_pending_retries: usize,
+    pub send_requires_peer: bool,
+    pub dedup_window: usize,
+    pub enforce_deadlines: bool,
+    pub frame_extensions: bool,
+    pub size_histogram: bool,
+    pub recv_rate_limit: u32,
+    pub protocol_version: u8,
+    pub compatible_protocol_versions: Vec<u8>
 }
 
+#[derive(Clone, Debug, PartialEq)]
 pub enum ConfigOption {
     /// Specifies how long the socket should try to send pending outbound messages 
-    /// after `drop` have been called. Default value is 1 second.
-    Linger(Duration),
+    /// after `drop` have been called on its last handle, which then blocks until the messages are sent or the linger expires.
+    /// The queued `send_async` messages are sent first, then each pipe is flushed.
+    /// `Some(Duration::from_millis(0))` discards the pending messages immediately,
+    /// and `None` waits for them to be sent however long it takes.
+    /// Default value is zero.
+    Linger(Option<Duration>),
 
     /// See [Socket::set_send_timeout](struct.Socket.html#method.set_send_timeout).
     SendTimeout(Option<Duration>),
@@ -37,28 +68,159 @@ pub enum ConfigOption {
     /// See [Socket::set_recv_priority](struct.Socket.html#method.set_recv_priority).
     RecvPriority(u8),
 
+    /// Number of messages received in a row from an endpoint before moving to the next endpoint
+    /// of the same recv priority, when several of them have messages available.
+    /// Endpoints are serviced in weighted round-robin, so an endpoint of weight 3 is serviced
+    /// three times as often as an endpoint of weight 1, without starving it like a lower priority would.
+    /// This option applies to the endpoints created after it is set, and can be overridden per endpoint
+    /// with [Socket::apply_endpoint_config](struct.Socket.html#method.apply_endpoint_config).
+    /// Zero value is handled like 1. Default value is 1.
+    RecvWeight(u32),
+
     /// Maximum message size that can be received, in bytes. 
     /// Zero value means that the received size is limited only by available addressable memory. 
+    /// The pipe of a peer announcing a longer message fails with an error carrying a [MessageTooLong](struct.MessageTooLong.html),
+    /// unless the `OversizePolicy` says otherwise.
     /// Default is 1024kB.
     RecvMaxSize(u64),
 
+    /// What a pipe does when it receives a message longer than `RecvMaxSize`:
+    /// either fail and close, or read the message off the stream and discard it, keeping the pipe alive.
+    /// Discarded messages are counted, see [Socket::dropped_count](struct.Socket.html#method.dropped_count).
+    /// Applies to endpoints subsequently added to the socket. Default value is `ClosePipe`.
+    OversizePolicy(OversizePolicy),
+
     /// For connection-based transports such as TCP, this option specifies how long to wait, 
     /// when connection is broken before trying to re-establish it. 
     /// Note that actual reconnect interval may be randomised to some extent 
     /// to prevent severe reconnection storms. Default value is 0.1 second.
     RetryIvl(Duration),
 
-    /// This option is to be used only in addition to ReconnectInterval option.
-    /// It specifies maximum reconnection interval. On each reconnect attempt,
-    /// the previous interval is doubled until ReconnectIntervalMax is reached.
-    /// Value of `None` means that no exponential backoff is performed and reconnect interval is based only on ReconnectInterval.
+    /// This option is to be used only in addition to RetryIvl option.
+    /// It specifies maximum reconnection interval. On each consecutive failed reconnect or rebind attempt,
+    /// the previous interval is doubled until RetryIvlMax is reached.
+    /// The interval goes back to RetryIvl once a connection completes its handshake, or a rebind succeeds.
+    /// Value of `None` means that no exponential backoff is performed and reconnect interval is based only on RetryIvl.
     /// If RetryIvlMax is less than RetryIvl, it is ignored. 
     /// Default value is `None`.
     RetryIvlMax(Option<Duration>),
 
+    /// Number of consecutive failed attempts after which a bound endpoint stops trying to bind again,
+    /// when its listener was lost or when it was asked to rebind to another address.
+    /// The endpoint is then closed, and the last error is recorded as an `EndpointClosed` event,
+    /// see [SessionBuilder::with_event_ring](struct.SessionBuilder.html#method.with_event_ring).
+    /// Value of `None` means that the endpoint keeps trying forever. Default value is `None`.
+    MaxRebindAttempts(Option<u32>),
+
+    /// Maximum number of accepted pipes the socket holds at once, over all its bound endpoints.
+    /// Once it is reached, the connections accepted on top of it are sent a busy signal instead of the handshake 
+    /// and closed, so the peers back off instead of retrying at once, see [PeerBusy](struct.PeerBusy.html).
+    /// The busy signal is only sent over the tcp and ipc transports, the other ones just close the connection.
+    /// Value of `None` means that there is no limit. Default value is `None`.
+    MaxAcceptedPipes(Option<usize>),
+
     /// See [Socket::set_tcp_nodelay](struct.Socket.html#method.set_tcp_nodelay).
     TcpNoDelay(bool),
 
+    /// For TCP based transports, how long a connection can stay idle before keepalive probes are sent,
+    /// so that a peer silently gone, behind a NAT gateway for example, is eventually detected.
+    /// Applies to both the connected pipes and the pipes accepted by the endpoints subsequently added to the socket.
+    /// Value of `None` means that keepalive is disabled. Default value is `None`.
+    TcpKeepalive(Option<Duration>),
+
+    /// For TCP based transports, whether `SO_REUSEADDR` is set on the listening socket before it is bound,
+    /// so that a restarted service can bind again an address whose previous connections are still in `TIME_WAIT`.
+    /// Applies to the endpoints subsequently bound by the socket. Default value is `true`.
+    /// Ignored on Windows, where this option would let another process hijack the port,
+    /// the platform default being kept instead.
+    ReuseAddr(bool),
+
+    /// For TCP based transports, the local address the connecting socket is bound to before connecting,
+    /// so that outbound connections of a multi-homed host leave through a given interface.
+    /// A port of zero lets the system pick one. Applies to the endpoints subsequently connected by the socket,
+    /// including their reconnections. Value of `None` means that the system chooses. Default value is `None`.
+    ConnectSourceAddr(Option<SocketAddr>),
+
+    /// For the IPC transport on Unix, the permission bits given to the socket file of the endpoints subsequently bound by the socket.
+    /// They are applied before the socket starts listening, so it is never connectable with the default permissions.
+    /// Ignored for abstract addresses, which have no file, and on Windows.
+    /// Value of `None` means that the permissions are left to the process umask. Default value is `None`.
+    IpcSocketPermissions(Option<u32>),
+
+    /// Maximum number of payload bytes written to a stream each time it becomes writable.
+    /// Large messages are then sent in several chunks, letting other endpoints make progress in between.
+    /// Zero value means that messages are written in as few writes as possible.
+    /// Applies to endpoints subsequently added to the socket. Default is 0.
+    SendChunkSize(usize),
+
+    /// See [Socket::set_send_queue_size](struct.Socket.html#method.set_send_queue_size).
+    SendQueueSize(usize),
+
+    /// Maximum number of endpoints waiting for a reconnection or a rebind attempt,
+    /// each of them keeping its specification until the attempt.
+    /// When the limit is exceeded, the endpoint waiting for the longest time is dropped.
+    /// See [Socket::pending_retries](struct.Socket.html#method.pending_retries).
+    /// Default value is 1024.
+    MaxPendingRetries(usize),
+
+    /// When enabled, sending while no pipe of the socket has completed its handshake with a peer
+    /// fails immediately with a `NotConnected` error, instead of holding the message until a peer shows up.
+    /// A message already held is not affected when the last peer goes away.
+    /// Default value is `false`.
+    SendRequiresPeer(bool),
+
+    /// Number of dedup ids, set by [Socket::send_with_dedup_id](struct.Socket.html#method.send_with_dedup_id),
+    /// remembered by the receiving socket. When enabled, a received message whose id is still in the window
+    /// is dropped instead of being delivered, and the pending recv operation waits for the next message.
+    /// Messages without an id are always delivered.
+    /// Dropped messages are counted, see [Socket::duplicate_count](struct.Socket.html#method.duplicate_count).
+    /// The id is only transmitted by the pipes having negotiated the frame extensions,
+    /// which a nonzero value implicitly advertises, see `FrameExtensions`.
+    /// Zero value disables deduplication. Default value is 0.
+    DedupWindow(usize),
+
+    /// When enabled, a received message whose deadline, set by 
+    /// [Socket::send_with_deadline](struct.Socket.html#method.send_with_deadline), has already passed
+    /// is dropped instead of being delivered, and the pending recv operation waits for the next message.
+    /// Dropped messages are counted, see [Socket::get_expired_count](struct.Socket.html#method.get_expired_count).
+    /// The deadline is only transmitted by the pipes having negotiated the frame extensions,
+    /// which this option implicitly advertises, see `FrameExtensions`.
+    /// Default value is `false`.
+    EnforceDeadlines(bool),
+
+    /// When enabled, the socket advertises in the handshake of the endpoints subsequently added to it
+    /// that the frames it exchanges with its peers may start with an extension block,
+    /// carrying the message fields that are not part of the payload, like its deadline or its dedup id.
+    /// The block is only used on the pipes whose both sides advertised it, 
+    /// those fields being dropped on the other pipes, so the sender of such messages must enable this option
+    /// while the receiver enables it either explicitly or through `EnforceDeadlines` or `DedupWindow`.
+    /// The handshake of a socket advertising it is rejected by the other implementations of the protocols.
+    /// Default value is `false`.
+    FrameExtensions(bool),
+
+    /// When enabled, the sizes of the messages sent and received by the socket are accumulated in a histogram.
+    /// Disabling it stops the accumulation but keeps the counts gathered so far.
+    /// See [Socket::size_histogram](struct.Socket.html#method.size_histogram).
+    /// Default value is `false`.
+    SizeHistogram(bool),
+
+    /// Maximum number of messages received from each endpoint per second.
+    /// Once an endpoint reaches it, the socket stops reading from that endpoint until the end of the second,
+    /// letting the transport apply backpressure to the peer without affecting the other endpoints.
+    /// Zero value means that the received messages are not limited. Default is 0.
+    RecvRateLimit(u32),
+
+    /// Version of the SP protocol advertised in the handshake of the endpoints subsequently added to the socket.
+    /// A peer advertising another version is rejected, 
+    /// unless that version is listed in the `CompatibleProtocolVersions` option.
+    /// Default value is 0.
+    ProtocolVersion(u8),
+
+    /// Versions of the SP protocol accepted in the handshake of a peer, besides the advertised one.
+    /// Applies to endpoints subsequently added to the socket.
+    /// Default value is an empty list.
+    CompatibleProtocolVersions(Vec<u8>),
+
     /// Defined on `Sub` socket. Subscribes for a particular topic.
     /// A single `Sub` socket can handle multiple subscriptions.
     Subscribe(String),
@@ -66,6 +228,19 @@ pub enum ConfigOption {
     /// Defined on Sub` socket. Unsubscribes from a particular topic.
     Unsubscribe(String),
 
+    /// Defined on `Sub` socket. Subscribes for several topics at once, keeping the current subscriptions.
+    /// Topics already subscribed are left as is. When one of the topics is rejected, none of them is added.
+    SubscribeMany(Vec<Vec<u8>>),
+
+    /// Defined on `Sub` socket. Unsubscribes from several topics at once.
+    /// Topics that are not subscribed are ignored.
+    UnsubscribeMany(Vec<Vec<u8>>),
+
+    /// Defined on `Sub` socket. Replaces the whole set of subscriptions at once,
+    /// so no message can be matched against a mix of the previous and the new topics.
+    /// See [Socket::set_subscriptions](struct.Socket.html#method.set_subscriptions).
+    Subscriptions(Vec<Vec<u8>>),
+
     /// This option is defined on the Req socket.
     /// If a reply is not received in the specified amount of time, 
     /// the request will be automatically resent. 
@@ -76,20 +251,151 @@ pub enum ConfigOption {
     /// Once the deadline expires, receive function will return a TimedOut error 
     /// and all subsequent responses to the survey will be silently dropped.
     /// Default value is 1 second.
-    SurveyDeadline(Duration)
+    SurveyDeadline(Duration),
+
+    /// Defined on `Surveyor` socket. While a survey is active, it is periodically
+    /// resent to the ready peers that did not receive it yet, such as late connecting respondents.
+    /// Resending stops when the survey deadline expires. Zero disables resending, which is the default.
+    SurveyResendIvl(Duration),
+
+    /// Defined on `Rep` and `Respondent` sockets. Maximum number of hops a request or a survey
+    /// can go through devices before reaching the socket. A message with a longer backtrace
+    /// fails the pending recv with an `InvalidData` error. Zero value is rejected. Default value is 8.
+    MaxTtl(u8),
+
+    /// Defined on `Pub` socket. When enabled, the last published message of each topic is retained
+    /// and delivered to each subscriber pipe as soon as it is opened, the subscriber filtering it as usual.
+    /// Topics are told apart by the prefix of the message set with [PubRetainTopicLen](#variant.PubRetainTopicLen).
+    /// Default value is `false`.
+    PubRetainLast(bool),
+
+    /// Defined on `Pub` socket. Length of the message prefix that makes the topic of the retained messages,
+    /// a message shorter than that being its own topic. Changing the value discards the retained messages.
+    /// Zero value means that a single message is retained, whatever its topic.
+    /// Default value is 0.
+    PubRetainTopicLen(usize),
+
+    /// Defined on `Pub` socket. Specifies how many of the last published messages are retained
+    /// and replayed in order to each subscriber pipe as soon as it is opened,
+    /// so a reconnecting subscriber can catch up. Messages published during the replay are queued after it.
+    /// When [PubRetainLast](#variant.PubRetainLast) is also enabled, the retained messages that are not
+    /// part of the history are replayed first.
+    /// Default value is 0.
+    PubHistory(usize),
+
+    /// Defined on `Pub` socket. Maximum number of messages queued for each subscriber pipe
+    /// that is still busy writing a previous message when a new one is published.
+    /// Once a queue is full, the [PubDropPolicy](#variant.PubDropPolicy) option applies.
+    /// Zero value means that nothing is queued: a busy pipe does not get the message.
+    /// Default value is 0.
+    SendBufferSize(usize),
+
+    /// Defined on `Pub` socket. What to do when a message is published while the queue of a pipe is full,
+    /// see [SendBufferSize](#variant.SendBufferSize). Dropped messages are counted,
+    /// see [SocketStats::pub_dropped](struct.SocketStats.html#structfield.pub_dropped).
+    /// Default value is `DropNewest`.
+    PubDropPolicy(PubDropPolicy),
+
+    /// Enables credit based flow control between `Pull` and `Push` sockets, both ends must enable it.
+    /// On a `Pull` socket, this is the number of messages each peer is allowed to send in advance,
+    /// credits being granted again as the received messages are consumed by the application.
+    /// On a `Push` socket, any non-zero value makes it send to a peer only while it holds credits
+    /// granted by that peer, the peer being reported as not ready otherwise.
+    /// Enabling it is advertised in the handshake of the endpoints subsequently added to the socket,
+    /// and credits are only exchanged with the peers that advertised it too, the other ones being sent
+    /// to freely. When enabled on a `Pull` socket that already has peers, the window is granted to those
+    /// having advertised it. The handshake of a socket advertising it is rejected by the other
+    /// implementations of the protocols.
+    /// Default value is 0, which disables flow control.
+    CreditFlowControl(u32),
+
+    /// Enables acknowledged delivery between `Push` and `Pull` sockets, both ends must enable it.
+    /// Each message sent by the `Push` socket carries a sequence id that the `Pull` socket acknowledges,
+    /// the message being resent if the acknowledgement is not received in time.
+    /// The `Pull` socket drops the duplicates it receives from the same pipe, 
+    /// but a message resent to another peer may still be delivered twice.
+    /// Enabling it is advertised in the handshake of the endpoints subsequently added to the socket,
+    /// and only the pipes whose both sides advertised it carry sequence ids, the messages exchanged
+    /// on the other pipes being delivered as is, without acknowledgement.
+    /// The handshake of a socket advertising it is rejected by the other implementations of the protocols.
+    /// Default value is `false`.
+    RequireAck(bool),
+
+    /// Defined on `Pull` socket. When enabled, once the socket has had peers and none is connected anymore,
+    /// `recv` fails with an `UnexpectedEof` error instead of waiting for a new peer,
+    /// so a batch consumer can tell when all the producers are gone.
+    /// Default value is `false`.
+    RecvEofOnNoPeers(bool),
+
+    /// Defined on `Push` socket, when acknowledged delivery is enabled.
+    /// Specifies how long to wait for the acknowledgement of a message before resending it.
+    /// Default value is 1 second.
+    AckResendIvl(Duration)
+}
+
+/// What a `Pub` socket does with a message published while the queue of a subscriber pipe is full.
+/// See [ConfigOption::PubDropPolicy](enum.ConfigOption.html#variant.PubDropPolicy).
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PubDropPolicy {
+    /// The published message is not queued for that pipe.
+    DropNewest,
+    /// The oldest message queued for that pipe is dropped to make room for the published one.
+    DropOldest,
+    /// The send operation waits until every queue has room, or the send timeout is reached.
+    Block
+}
+
+/// Whether an endpoint connects to a remote address or binds a local one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum EndpointRole {
+    Connect,
+    Bind
+}
+
+/// Declarative description of a socket endpoint.
+/// See [Socket::apply_endpoint_config](struct.Socket.html#method.apply_endpoint_config).
+#[derive(Clone, Debug, PartialEq)]
+pub struct EndpointConfig {
+    pub url: String,
+    pub role: EndpointRole,
+    /// Options specific to the endpoint, overriding the socket values.
+    /// Only `SendPriority`, `RecvPriority`, `RecvWeight`, `TcpNoDelay`, `TcpKeepalive`, `ReuseAddr`,
+    /// `ConnectSourceAddr`, `IpcSocketPermissions`, `RecvMaxSize`, `SendChunkSize`, `OversizePolicy`,
+    /// `ProtocolVersion` and `CompatibleProtocolVersions` are supported.
+    pub options: Vec<ConfigOption>
 }
 
 impl Default for Config {
     fn default() -> Config {
         Config {
+            linger: Some(Duration::from_millis(0)),
             send_timeout: None,
             send_priority: 8,
             recv_timeout: None,
             recv_priority: 8,
+            recv_weight: 1,
             retry_ivl: Duration::from_millis(100),
             retry_ivl_max: None,
+            max_rebind_attempts: None,
+            max_accepted_pipes: None,
             tcp_no_delay: false,
-            recv_max_size: 1024 * 1024
+            tcp_keepalive: None,
+            reuse_addr: true,
+            connect_source_addr: None,
+            ipc_permissions: None,
+            recv_max_size: 1024 * 1024,
+            send_chunk_size: 0,
+            oversize_policy: OversizePolicy::ClosePipe,
+            send_queue_size: 16,
+            max_pending_retries: 1024,
+            send_requires_peer: false,
+            dedup_window: 0,
+            enforce_deadlines: false,
+            frame_extensions: false,
+            size_histogram: false,
+            recv_rate_limit: 0,
+            protocol_version: 0,
+            compatible_protocol_versions: Vec::new()
         }
     }
 }
@@ -97,18 +403,83 @@ impl Default for Config {
 impl Config {
     pub fn set(&mut self, cfg_opt: ConfigOption) -> Result<()> {
         match cfg_opt {
+            ConfigOption::Linger(linger) => self.linger = linger,
             ConfigOption::SendTimeout(timeout) => self.send_timeout = timeout,
             ConfigOption::SendPriority(priority) => self.send_priority = priority,
             ConfigOption::RecvTimeout(timeout) => self.recv_timeout = timeout,
             ConfigOption::RecvPriority(priority) => self.recv_priority = priority,
+            ConfigOption::RecvWeight(weight) => self.recv_weight = weight,
             ConfigOption::RetryIvl(ivl) => self.retry_ivl = ivl,
             ConfigOption::RetryIvlMax(ivl) => self.retry_ivl_max = ivl,
+            ConfigOption::MaxRebindAttempts(x) => self.max_rebind_attempts = x,
+            ConfigOption::MaxAcceptedPipes(x) => self.max_accepted_pipes = x,
             ConfigOption::RecvMaxSize(x) => self.recv_max_size = x,
             ConfigOption::TcpNoDelay(x) => self.tcp_no_delay = x,
-            _ => return Err(invalid_input_io_error("option not supported"))
+            ConfigOption::TcpKeepalive(x) => self.tcp_keepalive = x,
+            ConfigOption::ReuseAddr(x) => self.reuse_addr = x,
+            ConfigOption::ConnectSourceAddr(x) => self.connect_source_addr = x,
+            ConfigOption::IpcSocketPermissions(x) => self.ipc_permissions = x,
+            ConfigOption::SendChunkSize(x) => self.send_chunk_size = x,
+            ConfigOption::OversizePolicy(x) => self.oversize_policy = x,
+            ConfigOption::SendQueueSize(x) => self.send_queue_size = x,
+            ConfigOption::MaxPendingRetries(x) => self.max_pending_retries = x,
+            ConfigOption::SendRequiresPeer(x) => self.send_requires_peer = x,
+            ConfigOption::DedupWindow(x) => self.dedup_window = x,
+            ConfigOption::EnforceDeadlines(x) => self.enforce_deadlines = x,
+            ConfigOption::FrameExtensions(x) => self.frame_extensions = x,
+            ConfigOption::SizeHistogram(x) => self.size_histogram = x,
+            ConfigOption::RecvRateLimit(x) => self.recv_rate_limit = x,
+            ConfigOption::ProtocolVersion(x) => self.protocol_version = x,
+            ConfigOption::CompatibleProtocolVersions(x) => self.compatible_protocol_versions = x,
+            _ => return Err(unsupported_option_io_error("option not supported"))
         }
         Ok(())
     }
+
+    /// Returns the delay before the next reconnect or rebind attempt of an endpoint,
+    /// given the number of consecutive attempts that already failed.
+    pub fn retry_delay(&self, attempts: u32) -> Duration {
+        let ivl_max = match self.retry_ivl_max {
+            Some(ivl_max) if ivl_max > self.retry_ivl => ivl_max,
+            _ => return self.retry_ivl
+        };
+        let mut ivl = self.retry_ivl;
+
+        for _ in 0..attempts {
+            if ivl >= ivl_max {
+                break;
+            }
+            ivl = ivl.checked_mul(2).unwrap_or(ivl_max);
+        }
+
+        ivl.min(ivl_max)
+    }
+
+    /// Returns the delay before reconnecting an endpoint whose peer is busy, 
+    /// which is the maximum reconnect interval when there is one.
+    pub fn longest_retry_delay(&self) -> Duration {
+        match self.retry_ivl_max {
+            Some(ivl_max) if ivl_max > self.retry_ivl => ivl_max,
+            _ => self.retry_ivl
+        }
+    }
+
+    pub fn protocol_versions(&self) -> ProtocolVersions {
+        ProtocolVersions {
+            advertised: self.protocol_version,
+            compatible: self.compatible_protocol_versions.clone(),
+            features: self.features()
+        }
+    }
+
+    /// Returns the feature bits advertised in the handshake, according to the generic options.
+    pub fn features(&self) -> u8 {
+        if self.frame_extensions || self.enforce_deadlines || self.dedup_window > 0 {
+            FEATURE_EXTENSIONS
+        } else {
+            0
+        }
+    }
 }
 
 impl ConfigOption {
@@ -119,12 +490,159 @@ impl ConfigOption {
             ConfigOption::SendTimeout(_)  |
             ConfigOption::SendPriority(_) |
             ConfigOption::RecvMaxSize(_)  |
+            ConfigOption::OversizePolicy(_) |
             ConfigOption::RecvTimeout(_)  |
             ConfigOption::RecvPriority(_) |
+            ConfigOption::RecvWeight(_)   |
             ConfigOption::RetryIvl(_)     |
             ConfigOption::RetryIvlMax(_)  |
-            ConfigOption::TcpNoDelay(_)   => true,
+            ConfigOption::MaxRebindAttempts(_) |
+            ConfigOption::MaxAcceptedPipes(_) |
+            ConfigOption::TcpNoDelay(_)   |
+            ConfigOption::TcpKeepalive(_) |
+            ConfigOption::ReuseAddr(_)    |
+            ConfigOption::ConnectSourceAddr(_) |
+            ConfigOption::IpcSocketPermissions(_) |
+            ConfigOption::SendChunkSize(_) |
+            ConfigOption::SendQueueSize(_) |
+            ConfigOption::MaxPendingRetries(_) |
+            ConfigOption::SendRequiresPeer(_) |
+            ConfigOption::DedupWindow(_) |
+            ConfigOption::EnforceDeadlines(_) |
+            ConfigOption::FrameExtensions(_) |
+            ConfigOption::SizeHistogram(_) |
+            ConfigOption::RecvRateLimit(_) |
+            ConfigOption::ProtocolVersion(_) |
+            ConfigOption::CompatibleProtocolVersions(_) => true,
             _ => false
         }
     }
+
+    /// Returns the name of the option variant, as written in the code.
+    #[doc(hidden)]
+    pub fn name(&self) -> &'static str {
+        match *self {
+            ConfigOption::Linger(_) => "Linger",
+            ConfigOption::SendTimeout(_) => "SendTimeout",
+            ConfigOption::SendPriority(_) => "SendPriority",
+            ConfigOption::RecvTimeout(_) => "RecvTimeout",
+            ConfigOption::RecvPriority(_) => "RecvPriority",
+            ConfigOption::RecvWeight(_) => "RecvWeight",
+            ConfigOption::RecvMaxSize(_) => "RecvMaxSize",
+            ConfigOption::OversizePolicy(_) => "OversizePolicy",
+            ConfigOption::RetryIvl(_) => "RetryIvl",
+            ConfigOption::RetryIvlMax(_) => "RetryIvlMax",
+            ConfigOption::MaxRebindAttempts(_) => "MaxRebindAttempts",
+            ConfigOption::MaxAcceptedPipes(_) => "MaxAcceptedPipes",
+            ConfigOption::TcpNoDelay(_) => "TcpNoDelay",
+            ConfigOption::TcpKeepalive(_) => "TcpKeepalive",
+            ConfigOption::ReuseAddr(_) => "ReuseAddr",
+            ConfigOption::ConnectSourceAddr(_) => "ConnectSourceAddr",
+            ConfigOption::IpcSocketPermissions(_) => "IpcSocketPermissions",
+            ConfigOption::SendChunkSize(_) => "SendChunkSize",
+            ConfigOption::SendQueueSize(_) => "SendQueueSize",
+            ConfigOption::MaxPendingRetries(_) => "MaxPendingRetries",
+            ConfigOption::SendRequiresPeer(_) => "SendRequiresPeer",
+            ConfigOption::DedupWindow(_) => "DedupWindow",
+            ConfigOption::EnforceDeadlines(_) => "EnforceDeadlines",
+            ConfigOption::FrameExtensions(_) => "FrameExtensions",
+            ConfigOption::SizeHistogram(_) => "SizeHistogram",
+            ConfigOption::RecvRateLimit(_) => "RecvRateLimit",
+            ConfigOption::ProtocolVersion(_) => "ProtocolVersion",
+            ConfigOption::CompatibleProtocolVersions(_) => "CompatibleProtocolVersions",
+            ConfigOption::Subscribe(_) => "Subscribe",
+            ConfigOption::Unsubscribe(_) => "Unsubscribe",
+            ConfigOption::SubscribeMany(_) => "SubscribeMany",
+            ConfigOption::UnsubscribeMany(_) => "UnsubscribeMany",
+            ConfigOption::Subscriptions(_) => "Subscriptions",
+            ConfigOption::ReqResendIvl(_) => "ReqResendIvl",
+            ConfigOption::SurveyDeadline(_) => "SurveyDeadline",
+            ConfigOption::SurveyResendIvl(_) => "SurveyResendIvl",
+            ConfigOption::MaxTtl(_) => "MaxTtl",
+            ConfigOption::PubRetainLast(_) => "PubRetainLast",
+            ConfigOption::PubRetainTopicLen(_) => "PubRetainTopicLen",
+            ConfigOption::PubHistory(_) => "PubHistory",
+            ConfigOption::SendBufferSize(_) => "SendBufferSize",
+            ConfigOption::PubDropPolicy(_) => "PubDropPolicy",
+            ConfigOption::CreditFlowControl(_) => "CreditFlowControl",
+            ConfigOption::RequireAck(_) => "RequireAck",
+            ConfigOption::RecvEofOnNoPeers(_) => "RecvEofOnNoPeers",
+            ConfigOption::AckResendIvl(_) => "AckResendIvl"
+        }
+    }
+}
+
+/// Why an option was rejected, see `OptionRejected`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    /// The option does not apply to the socket protocol, or to an endpoint.
+    NotSupported,
+    /// The option applies, but its value is not valid.
+    InvalidValue
+}
+
+/// Error carried by the `io::Error` returned when an option is rejected,
+/// either by [Socket::set_option](struct.Socket.html#method.set_option)
+/// or while creating an endpoint with option overrides.
+/// The `io::Error` keeps the kind of the error raised while applying the option,
+/// that error being the source of this one.
+#[derive(Debug)]
+pub struct OptionRejected {
+    pub option: &'static str,
+    pub reason: RejectReason,
+    source: io::Error
+}
+
+impl OptionRejected {
+    /// Extracts the option rejection carried by the specified error, if any.
+    pub fn of(err: &io::Error) -> Option<&OptionRejected> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<OptionRejected>())
+    }
+}
+
+impl fmt::Display for OptionRejected {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} rejected: {}", self.option, self.source)
+    }
+}
+
+impl error::Error for OptionRejected {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// Tells the errors raised for an option that does not apply apart from the invalid values.
+#[derive(Debug)]
+struct UnsupportedOption(&'static str);
+
+impl fmt::Display for UnsupportedOption {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl error::Error for UnsupportedOption {}
+
+/// Error raised while applying an option that does not apply,
+/// the option being then rejected with the `NotSupported` reason.
+#[doc(hidden)]
+pub fn unsupported_option_io_error(msg: &'static str) -> io::Error {
+    invalid_input_io_error(UnsupportedOption(msg))
+}
+
+/// Wraps the error raised while applying an option in an `OptionRejected` naming the option, keeping the error kind,
+/// so that the caller can tell which option was rejected and why.
+#[doc(hidden)]
+pub fn rejected_option_io_error(name: &'static str, e: io::Error) -> io::Error {
+    let is_unsupported = e.get_ref().and_then(|inner| inner.downcast_ref::<UnsupportedOption>()).is_some();
+    let why = if is_unsupported { RejectReason::NotSupported } else { RejectReason::InvalidValue };
+    let kind = e.kind();
+    let rejected = OptionRejected {
+        option: name,
+        reason: why,
+        source: e
+    };
+
+    io::Error::new(kind, rejected)
 }
@@ -6,10 +6,11 @@
 
 use std::fmt;
 use std::io::Result;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use core::{EndpointId, EndpointSpec, Scheduled};
 use core::network::Network;
+use core::event_ring::ErrorCategory;
 
 pub trait Context : Network + Scheduler + fmt::Debug {
     fn raise(&mut self, evt: Event);
@@ -28,6 +29,8 @@ pub trait Context : Network + Scheduler + fmt::Debug {
 pub trait Scheduler {
     fn schedule(&mut self, schedulable: Schedulable, delay: Duration) -> Result<Scheduled>;
     fn cancel(&mut self, scheduled: Scheduled);
+    /// Returns the current time of the clock the scheduled tasks are due against.
+    fn now(&self) -> Instant;
 }
 
 #[derive(Debug, Copy, Clone, PartialEq, Eq)]
@@ -39,6 +42,9 @@ pub enum Command {
 pub enum Event {
     CanSend(bool),
     CanRecv(bool),
+    /// The endpoint was given up after too many failed attempts or an error that can not be retried,
+    /// with the OS code of the last error if any, and its category.
+    EndpointClosed(EndpointId, Option<i32>, ErrorCategory),
     Closed
 }
 
@@ -48,7 +54,14 @@ pub enum Schedulable {
     SendTimeout,
     RecvTimeout,
     ReqResend,
-    SurveyCancel
+    SurveyCancel,
+    SurveyResend,
+    AckResend(u32),
+    DrainTimeout(EndpointId),
+    LingerTimeout,
+    ShutdownTimeout,
+    ConnectTimeout(EndpointId),
+    RecvRateWindow(EndpointId)
 }
 
 impl fmt::Debug for Scheduled {
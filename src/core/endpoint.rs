@@ -5,18 +5,29 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::rc::Rc;
+use std::time::Duration;
 
 use super::{EndpointId, Message, EndpointSpec, EndpointDesc};
 use super::context::Context;
+use super::socket::HandleId;
+use transport::Features;
 
 pub enum Request {
-    Close(bool)
+    Close(bool),
+    CloseGraceful(bool, Duration),
+    Rebind(String),
+    PauseReconnect,
+    ResumeReconnect,
+    /// Asks for the credentials of the peer process, replied to the specified socket handle.
+    PeerCred(HandleId)
 }
 
 pub struct Endpoint {
     id: EndpointId,
     url: Option<String>,
-    desc: EndpointDesc
+    desc: EndpointDesc,
+    attempts: u32,
+    features: Features
 }
 
 pub struct Pipe(Endpoint);
@@ -28,6 +39,8 @@ impl Endpoint {
             id: id,
             url: Some(url),
             desc: desc,
+            attempts: 0,
+            features: Features::default()
         }
     }
 
@@ -35,7 +48,9 @@ impl Endpoint {
         Endpoint {
             id: id,
             url: None,
-            desc: desc
+            desc: desc,
+            attempts: 0,
+            features: Features::default()
         }
     }
 
@@ -43,7 +58,9 @@ impl Endpoint {
         Endpoint {
             id: id,
             url: Some(spec.url),
-            desc: spec.desc
+            desc: spec.desc,
+            attempts: spec.attempts,
+            features: Features::default()
         }
     }
 
@@ -62,7 +79,8 @@ impl Endpoint {
         match self.url.take() {
             Some(url) => Some(EndpointSpec {
                 url: url,
-                desc: self.desc} ),
+                desc: self.desc,
+                attempts: self.attempts} ),
             None => None,
         }
     }
@@ -72,6 +90,9 @@ impl Endpoint {
     fn get_recv_priority(&self) -> u8 {
         self.desc.recv_priority
     }
+    fn get_recv_weight(&self) -> u32 {
+        self.desc.recv_weight
+    }
 }
 
 impl Pipe {
@@ -99,12 +120,31 @@ impl Pipe {
     pub fn close(self, network: &mut dyn Context) -> Option<EndpointSpec> {
         self.0.close(network, true)
     }
+    pub fn reject(self, network: &mut dyn Context) {
+        network.reject(self.0.id)
+    }
+    pub fn reset_attempts(&mut self) {
+        self.0.attempts = 0;
+    }
+    pub fn is_connected(&self) -> bool {
+        self.0.url.is_some()
+    }
+    /// Features advertised by both sides of the pipe, known once the handshake is completed.
+    pub fn get_features(&self) -> Features {
+        self.0.features
+    }
+    pub fn set_features(&mut self, features: Features) {
+        self.0.features = features;
+    }
     pub fn get_send_priority(&self) -> u8 {
         self.0.get_send_priority()
     }
     pub fn get_recv_priority(&self) -> u8 {
         self.0.get_recv_priority()
     }
+    pub fn get_recv_weight(&self) -> u32 {
+        self.0.get_recv_weight()
+    }
 }
 
 impl Acceptor {
@@ -126,4 +166,7 @@ impl Acceptor {
     pub fn get_recv_priority(&self) -> u8 {
         self.0.get_recv_priority()
     }
+    pub fn get_recv_weight(&self) -> u32 {
+        self.0.get_recv_weight()
+    }
 }
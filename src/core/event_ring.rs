@@ -0,0 +1,302 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+//! Fixed size ring of the most recent reactor events, for post-mortem debugging.
+//! The reactor thread is the only writer, and each event is stored as a few integers
+//! in a slot guarded by a sequence number, so recording an event never allocates nor locks.
+//! Readers copy the slots and discard the ones overwritten while they were being read.
+
+use std::io;
+use std::sync::atomic::{AtomicU64, Ordering, fence};
+
+use super::{SocketId, EndpointId};
+
+const NO_OS_ERROR: u64 = u64::MAX;
+const NO_ERROR_CATEGORY: u64 = 0;
+
+/// What happened to an endpoint, as recorded by the reactor.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReactorEventKind {
+    PipeOpened,
+    PipeError,
+    PipeClosed,
+    PipeAccepted,
+    AcceptorOpened,
+    AcceptorError,
+    AcceptorClosed,
+    /// The socket gave up an endpoint after too many failed attempts.
+    EndpointClosed
+}
+
+impl ReactorEventKind {
+    fn code(self) -> u64 {
+        match self {
+            ReactorEventKind::PipeOpened     => 1,
+            ReactorEventKind::PipeError      => 2,
+            ReactorEventKind::PipeClosed     => 3,
+            ReactorEventKind::PipeAccepted   => 4,
+            ReactorEventKind::AcceptorOpened => 5,
+            ReactorEventKind::AcceptorError  => 6,
+            ReactorEventKind::AcceptorClosed => 7,
+            ReactorEventKind::EndpointClosed => 8
+        }
+    }
+
+    fn from_code(code: u64) -> Option<ReactorEventKind> {
+        match code {
+            1 => Some(ReactorEventKind::PipeOpened),
+            2 => Some(ReactorEventKind::PipeError),
+            3 => Some(ReactorEventKind::PipeClosed),
+            4 => Some(ReactorEventKind::PipeAccepted),
+            5 => Some(ReactorEventKind::AcceptorOpened),
+            6 => Some(ReactorEventKind::AcceptorError),
+            7 => Some(ReactorEventKind::AcceptorClosed),
+            8 => Some(ReactorEventKind::EndpointClosed),
+            _ => None
+        }
+    }
+}
+
+/// Cause of an endpoint error, which decides how the socket retries the endpoint:
+/// a connection that was reset is reestablished without waiting for the reconnect backoff,
+/// while an endpoint that is denied the permission is closed instead of being retried.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCategory {
+    ConnectionRefused,
+    /// The connection was reset, aborted, or closed by the peer.
+    Reset,
+    Timeout,
+    PermissionDenied,
+    /// The address is already in use or is not available.
+    AddrInUse,
+    Other
+}
+
+impl ErrorCategory {
+    /// Returns the category of the specified error, based on its kind.
+    pub fn of(err: &io::Error) -> ErrorCategory {
+        match err.kind() {
+            io::ErrorKind::ConnectionRefused => ErrorCategory::ConnectionRefused,
+            io::ErrorKind::ConnectionReset   |
+            io::ErrorKind::ConnectionAborted |
+            io::ErrorKind::BrokenPipe        |
+            io::ErrorKind::UnexpectedEof     => ErrorCategory::Reset,
+            io::ErrorKind::TimedOut          => ErrorCategory::Timeout,
+            io::ErrorKind::PermissionDenied  => ErrorCategory::PermissionDenied,
+            io::ErrorKind::AddrInUse         |
+            io::ErrorKind::AddrNotAvailable  => ErrorCategory::AddrInUse,
+            _                                => ErrorCategory::Other
+        }
+    }
+
+    fn code(self) -> u64 {
+        match self {
+            ErrorCategory::ConnectionRefused => 1,
+            ErrorCategory::Reset             => 2,
+            ErrorCategory::Timeout           => 3,
+            ErrorCategory::PermissionDenied  => 4,
+            ErrorCategory::AddrInUse         => 5,
+            ErrorCategory::Other             => 6
+        }
+    }
+
+    fn from_code(code: u64) -> Option<ErrorCategory> {
+        match code {
+            1 => Some(ErrorCategory::ConnectionRefused),
+            2 => Some(ErrorCategory::Reset),
+            3 => Some(ErrorCategory::Timeout),
+            4 => Some(ErrorCategory::PermissionDenied),
+            5 => Some(ErrorCategory::AddrInUse),
+            6 => Some(ErrorCategory::Other),
+            _ => None
+        }
+    }
+}
+
+/// An event recorded by the reactor, see [SessionBuilder::with_event_ring](struct.SessionBuilder.html#method.with_event_ring).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ReactorEvent {
+    /// Position of the event since the session was created, starting at zero.
+    pub seq: u64,
+    pub kind: ReactorEventKind,
+    pub socket_id: usize,
+    /// Id of the endpoint, as returned by [Endpoint::id](struct.Endpoint.html#method.id).
+    /// For an accepted pipe, this is the id of the new pipe, not the one of the acceptor.
+    pub endpoint_id: usize,
+    /// OS error code of the error events, when the error has one.
+    pub os_error: Option<i32>,
+    /// Category of the error events.
+    pub error_category: Option<ErrorCategory>
+}
+
+struct Slot {
+    // odd while the slot is being written, 2 * (seq + 1) once the event seq is stored.
+    version: AtomicU64,
+    kind: AtomicU64,
+    socket_id: AtomicU64,
+    endpoint_id: AtomicU64,
+    os_error: AtomicU64,
+    error_category: AtomicU64
+}
+
+impl Slot {
+    fn new() -> Slot {
+        Slot {
+            version: AtomicU64::new(0),
+            kind: AtomicU64::new(0),
+            socket_id: AtomicU64::new(0),
+            endpoint_id: AtomicU64::new(0),
+            os_error: AtomicU64::new(NO_OS_ERROR),
+            error_category: AtomicU64::new(NO_ERROR_CATEGORY)
+        }
+    }
+}
+
+#[doc(hidden)]
+pub struct EventRing {
+    slots: Box<[Slot]>,
+    head: AtomicU64
+}
+
+impl EventRing {
+    pub fn new(capacity: usize) -> EventRing {
+        let slots: Vec<Slot> = (0..capacity.max(1)).map(|_| Slot::new()).collect();
+
+        EventRing {
+            slots: slots.into_boxed_slice(),
+            head: AtomicU64::new(0)
+        }
+    }
+
+    /// Must only be called from a single thread, the reactor one.
+    pub fn record(&self, kind: ReactorEventKind, sid: SocketId, eid: EndpointId, err: Option<&io::Error>) {
+        let os_error = err.and_then(|e| e.raw_os_error());
+        let category = err.map(ErrorCategory::of);
+
+        self.record_error(kind, sid, eid, os_error, category)
+    }
+
+    /// Same as `record`, for an error that is already reduced to its code and category.
+    pub fn record_error(&self, kind: ReactorEventKind, sid: SocketId, eid: EndpointId, os_error: Option<i32>, category: Option<ErrorCategory>) {
+        let seq = self.head.load(Ordering::Relaxed);
+        let slot = &self.slots[(seq % self.slots.len() as u64) as usize];
+        let os_error = os_error.map_or(NO_OS_ERROR, |code| code as u32 as u64);
+        let category = category.map_or(NO_ERROR_CATEGORY, ErrorCategory::code);
+
+        slot.version.store(2 * seq + 1, Ordering::Relaxed);
+        fence(Ordering::Release);
+        slot.kind.store(kind.code(), Ordering::Relaxed);
+        slot.socket_id.store(sid.0 as u64, Ordering::Relaxed);
+        slot.endpoint_id.store(eid.0 as u64, Ordering::Relaxed);
+        slot.os_error.store(os_error, Ordering::Relaxed);
+        slot.error_category.store(category, Ordering::Relaxed);
+        slot.version.store(2 * seq + 2, Ordering::Release);
+        self.head.store(seq + 1, Ordering::Release);
+    }
+
+    /// Returns the events still in the ring, oldest first.
+    pub fn snapshot(&self) -> Vec<ReactorEvent> {
+        let head = self.head.load(Ordering::Acquire);
+        let capacity = self.slots.len() as u64;
+        let first = head.saturating_sub(capacity);
+
+        (first..head).filter_map(|seq| self.read(seq)).collect()
+    }
+
+    fn read(&self, index: u64) -> Option<ReactorEvent> {
+        let slot = &self.slots[(index % self.slots.len() as u64) as usize];
+        let version = slot.version.load(Ordering::Acquire);
+
+        if version != 2 * index + 2 {
+            return None;
+        }
+
+        let kind = slot.kind.load(Ordering::Relaxed);
+        let socket_id = slot.socket_id.load(Ordering::Relaxed);
+        let endpoint_id = slot.endpoint_id.load(Ordering::Relaxed);
+        let os_error = slot.os_error.load(Ordering::Relaxed);
+        let category = slot.error_category.load(Ordering::Relaxed);
+
+        fence(Ordering::Acquire);
+        if slot.version.load(Ordering::Relaxed) != version {
+            return None;
+        }
+
+        ReactorEventKind::from_code(kind).map(|event_kind| ReactorEvent {
+            seq: index,
+            kind: event_kind,
+            socket_id: socket_id as usize,
+            endpoint_id: endpoint_id as usize,
+            os_error: if os_error == NO_OS_ERROR { None } else { Some(os_error as u32 as i32) },
+            error_category: ErrorCategory::from_code(category)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshot_returns_recorded_events_in_order() {
+        let ring = EventRing::new(4);
+
+        ring.record(ReactorEventKind::AcceptorOpened, SocketId::from(1), EndpointId::from(2), None);
+        ring.record(ReactorEventKind::PipeAccepted, SocketId::from(1), EndpointId::from(3), None);
+
+        let events = ring.snapshot();
+        let kinds: Vec<ReactorEventKind> = events.iter().map(|e| e.kind).collect();
+
+        assert_eq!(vec![ReactorEventKind::AcceptorOpened, ReactorEventKind::PipeAccepted], kinds);
+        assert_eq!(0, events[0].seq);
+        assert_eq!(3, events[1].endpoint_id);
+    }
+
+    #[test]
+    fn snapshot_keeps_only_the_most_recent_events() {
+        let ring = EventRing::new(2);
+        let err = io::Error::from_raw_os_error(104);
+
+        ring.record(ReactorEventKind::PipeOpened, SocketId::from(1), EndpointId::from(1), None);
+        ring.record(ReactorEventKind::PipeOpened, SocketId::from(1), EndpointId::from(2), None);
+        ring.record(ReactorEventKind::PipeError, SocketId::from(1), EndpointId::from(2), Some(&err));
+
+        let events = ring.snapshot();
+
+        assert_eq!(2, events.len());
+        assert_eq!(1, events[0].seq);
+        assert_eq!(2, events[1].seq);
+        assert_eq!(None, events[0].os_error);
+        assert_eq!(Some(104), events[1].os_error);
+    }
+
+    #[test]
+    fn error_events_carry_the_error_category() {
+        let ring = EventRing::new(4);
+        let denied = io::Error::new(io::ErrorKind::PermissionDenied, "test");
+
+        ring.record(ReactorEventKind::PipeOpened, SocketId::from(1), EndpointId::from(1), None);
+        ring.record(ReactorEventKind::PipeError, SocketId::from(1), EndpointId::from(1), Some(&denied));
+
+        let events = ring.snapshot();
+
+        assert_eq!(None, events[0].error_category);
+        assert_eq!(Some(ErrorCategory::PermissionDenied), events[1].error_category);
+    }
+
+    #[test]
+    fn errors_are_categorized_by_kind() {
+        let category = |kind| ErrorCategory::of(&io::Error::new(kind, "test"));
+
+        assert_eq!(ErrorCategory::ConnectionRefused, category(io::ErrorKind::ConnectionRefused));
+        assert_eq!(ErrorCategory::Reset, category(io::ErrorKind::ConnectionReset));
+        assert_eq!(ErrorCategory::Reset, category(io::ErrorKind::UnexpectedEof));
+        assert_eq!(ErrorCategory::Timeout, category(io::ErrorKind::TimedOut));
+        assert_eq!(ErrorCategory::PermissionDenied, category(io::ErrorKind::PermissionDenied));
+        assert_eq!(ErrorCategory::AddrInUse, category(io::ErrorKind::AddrInUse));
+        assert_eq!(ErrorCategory::Other, category(io::ErrorKind::InvalidData));
+    }
+}
@@ -12,12 +12,26 @@
 #[doc(hidden)] pub mod endpoint;
 #[doc(hidden)] pub mod device;
 #[doc(hidden)] pub mod probe;
+#[doc(hidden)] pub mod trace;
+#[doc(hidden)] pub mod event_ring;
+#[doc(hidden)] pub mod clock;
+#[doc(hidden)] pub mod pool;
+#[cfg(unix)]
+#[doc(hidden)] pub mod ready_fd;
 
 #[cfg(test)]
 pub mod tests;
 
 use std::fmt;
+use std::io;
+use std::net::SocketAddr;
 use std::hash::{BuildHasher, Hasher};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use transport::{ProtocolVersions, OversizePolicy};
+use self::pool::RecvPool;
+use io_error::*;
 
 #[doc(hidden)]
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
@@ -29,7 +43,8 @@ pub struct Scheduled(usize);
 /*                                                                           */
 /*****************************************************************************/
 
-#[doc(hidden)]
+/// Identifies an endpoint of a socket, or a connection accepted by a bound endpoint.
+/// Converts into the value returned by [Endpoint::id](../struct.Endpoint.html#method.id).
 #[derive(Copy, Clone, PartialEq, Eq, Hash)]
 pub struct EndpointId(usize);
 
@@ -57,6 +72,17 @@ impl<'x> Into<usize> for &'x EndpointId {
     }
 }
 
+/// Chooses the ids of the endpoints created by a session,
+/// see [SessionBuilder::with_endpoint_id_allocator](../struct.SessionBuilder.html#method.with_endpoint_id_allocator).
+/// The allocator runs on the I/O thread, each time a socket connects, binds or accepts a connection.
+pub trait EndpointIdAllocator {
+    /// Returns the id of a new endpoint of the socket `socket_id`.
+    /// `seq` is the next value of the session counter, which the default allocator returns as is.
+    /// The id must not be used by another endpoint of the session, else the next counter value
+    /// that is not used by an endpoint is taken instead.
+    fn allocate(&mut self, socket_id: usize, seq: usize) -> usize;
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* Endpoint                                                                  */
@@ -72,15 +98,26 @@ pub struct EndpointTmpl {
 #[doc(hidden)]
 pub struct EndpointSpec {
     pub url: String,
-    pub desc: EndpointDesc
+    pub desc: EndpointDesc,
+    /// Number of consecutive reconnect attempts that failed.
+    pub attempts: u32
 }
 
 #[doc(hidden)]
 pub struct EndpointDesc {
     pub send_priority: u8,
     pub recv_priority: u8,
+    pub recv_weight: u32,
     pub tcp_no_delay: bool,
-    pub recv_max_size: u64
+    pub tcp_keepalive: Option<Duration>,
+    pub reuse_addr: bool,
+    pub connect_source_addr: Option<SocketAddr>,
+    pub ipc_permissions: Option<u32>,
+    pub recv_max_size: u64,
+    pub send_chunk_size: usize,
+    pub oversize_policy: OversizePolicy,
+    pub protocol_versions: ProtocolVersions,
+    pub recv_pool: Option<RecvPool>
 }
 
 /*****************************************************************************/
@@ -105,6 +142,12 @@ impl From<usize> for SocketId {
     }
 }
 
+impl From<SocketId> for usize {
+    fn from(id: SocketId) -> usize {
+        id.0
+    }
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* DeviceId                                                                  */
@@ -171,33 +214,81 @@ pub struct PollRes {
 #[derive(Default, Debug)]
 pub struct Message {
     pub header: Vec<u8>,
-    pub body: Vec<u8>
+    pub body: Vec<u8>,
+    frame: Option<Arc<[u8]>>,
+    deadline: Option<SystemTime>,
+    dedup_id: Option<u64>,
+    originator: Option<EndpointId>,
+    hop_count: usize,
+    reply_to: Option<Vec<u8>>
 }
 
 impl Message {
     pub fn new() -> Message {
         Message {
             header: Vec::new(),
-            body: Vec::new()
+            body: Vec::new(),
+            frame: None,
+            deadline: None,
+            dedup_id: None,
+            originator: None,
+            hop_count: 0,
+            reply_to: None
         }
     }
 
     pub fn from_body(body: Vec<u8>) -> Message {
         Message {
             header: Vec::new(),
-            body: body
+            body: body,
+            frame: None,
+            deadline: None,
+            dedup_id: None,
+            originator: None,
+            hop_count: 0,
+            reply_to: None
         }
     }
 
     pub fn from_header_and_body(header: Vec<u8>, body: Vec<u8>) -> Message {
         Message {
             header: header,
-            body: body
+            body: body,
+            frame: None,
+            deadline: None,
+            dedup_id: None,
+            originator: None,
+            hop_count: 0,
+            reply_to: None
+        }
+    }
+
+    /// Creates a message from a frame that is written as is by the transports,
+    /// the 8 bytes length prefix included, ignoring the header set by the protocol if any.
+    #[doc(hidden)]
+    pub fn from_frame(frame: Arc<[u8]>) -> Message {
+        Message {
+            header: Vec::new(),
+            body: Vec::new(),
+            frame: Some(frame),
+            deadline: None,
+            dedup_id: None,
+            originator: None,
+            hop_count: 0,
+            reply_to: None
         }
     }
 
     pub fn len(&self) -> usize {
-        self.header.len() + self.body.len()
+        match self.frame {
+            Some(ref frame) => frame.len().saturating_sub(8),
+            None => self.header.len() + self.body.len()
+        }
+    }
+
+    #[doc(hidden)]
+    pub fn get_frame(&self) -> Option<&[u8]> {
+        self.frame.as_ref().map(|frame| &frame[..])
     }
 
     pub fn get_header(&self) -> &[u8] {
@@ -215,6 +306,96 @@ impl Message {
     pub fn without_header(self) -> Message {
         Message::from_body(self.body)
     }
+
+    /// Copies the message, replacing its header but keeping its body and its fields.
+    #[doc(hidden)]
+    pub fn with_header(&self, new_header: Vec<u8>) -> Message {
+        Message {
+            header: new_header,
+            body: self.body.clone(),
+            frame: self.frame.clone(),
+            deadline: self.deadline,
+            dedup_id: self.dedup_id,
+            originator: self.originator,
+            hop_count: self.hop_count,
+            reply_to: self.reply_to.clone()
+        }
+    }
+
+    /// Time after which the receiving socket drops the message instead of delivering it,
+    /// see [Socket::send_with_deadline](struct.Socket.html#method.send_with_deadline).
+    /// The deadline is carried in the extension block of the frame,
+    /// which is only written when both sides of the pipe advertised it in their handshake,
+    /// see [ConfigOption::FrameExtensions](enum.ConfigOption.html#variant.FrameExtensions).
+    /// It is otherwise dropped, and the message is delivered whenever it arrives.
+    pub fn get_deadline(&self) -> Option<SystemTime> {
+        self.deadline
+    }
+
+    pub fn set_deadline(&mut self, deadline: Option<SystemTime>) {
+        self.deadline = deadline;
+    }
+
+    /// Id of the message used by the receiving socket to drop the duplicates,
+    /// see [Socket::send_with_dedup_id](struct.Socket.html#method.send_with_dedup_id).
+    /// Like the deadline, it is carried in the extension block of the frame.
+    pub fn get_dedup_id(&self) -> Option<u64> {
+        self.dedup_id
+    }
+
+    pub fn set_dedup_id(&mut self, id: Option<u64>) {
+        self.dedup_id = id;
+    }
+
+    /// Number of devices the message went through, each one incrementing it before forwarding the message,
+    /// see [DeviceConfig](struct.DeviceConfig.html).
+    /// Like the deadline, it is carried in the extension block of the frame.
+    pub fn get_hop_count(&self) -> usize {
+        self.hop_count
+    }
+
+    pub fn set_hop_count(&mut self, count: usize) {
+        self.hop_count = count;
+    }
+
+    /// Pipe a bus message was received from, so that sending it back on the same socket,
+    /// like a bus device does, does not broadcast it to its originator.
+    /// It is never transmitted to the peers.
+    #[doc(hidden)]
+    pub fn get_originator(&self) -> Option<EndpointId> {
+        self.originator
+    }
+
+    #[doc(hidden)]
+    pub fn set_originator(&mut self, eid: Option<EndpointId>) {
+        self.originator = eid;
+    }
+
+    /// Attaches a reply-to token that the receiver can use to route an answer back,
+    /// replacing the previous one if any.
+    /// Like the deadline, the token is carried in the extension block of the frame, leaving the body untouched,
+    /// so it only reaches the receiver when both sides of the pipe enabled the
+    /// [frame extensions](enum.ConfigOption.html#variant.FrameExtensions).
+    /// Fails when the token is longer than 65535 bytes.
+    pub fn set_reply_to(&mut self, token: &[u8]) -> io::Result<()> {
+        if token.len() > u16::MAX as usize {
+            return Err(invalid_input_io_error("reply-to token is too long"));
+        }
+
+        self.reply_to = Some(token.to_vec());
+
+        Ok(())
+    }
+
+    /// Reply-to token attached by the sender with [set_reply_to](#method.set_reply_to), if any.
+    pub fn reply_to(&self) -> Option<&[u8]> {
+        self.reply_to.as_ref().map(|token| &token[..])
+    }
+
+    /// Removes the reply-to token from the message, returning it.
+    pub fn take_reply_to(&mut self) -> Option<Vec<u8>> {
+        self.reply_to.take()
+    }
 }
 
 impl Into<Vec<u8>> for Message {
@@ -9,14 +9,22 @@ use std::io::Result;
 
 use super::{EndpointTmpl};
 use core::{SocketId, EndpointId, Message};
+use transport::{Stream, PeerCred};
 
 pub trait Network {
     fn connect(&mut self, sid: SocketId, tmpl: &EndpointTmpl) -> Result<EndpointId>;
     fn reconnect(&mut self, sid: SocketId, eid: EndpointId, tmpl: &EndpointTmpl) -> Result<()>;
+    fn connect_stream(&mut self, sid: SocketId, stream: Stream, tmpl: &EndpointTmpl) -> Result<EndpointId>;
+    /// Allocates the id of an endpoint whose connection will only be attempted later, by `reconnect`.
+    fn reserve_endpoint_id(&mut self) -> EndpointId;
     fn bind(&mut self, sid: SocketId, tmpl: &EndpointTmpl) -> Result<EndpointId>;
     fn rebind(&mut self, sid: SocketId, eid: EndpointId, tmpl: &EndpointTmpl) -> Result<()>;
     fn open(&mut self, eid: EndpointId, remote: bool);
     fn close(&mut self, eid: EndpointId, remote: bool);
     fn send(&mut self, eid: EndpointId, msg: Rc<Message>);
     fn recv(&mut self, eid: EndpointId);
+    fn flush(&mut self, eid: EndpointId);
+    fn reject(&mut self, eid: EndpointId);
+    fn pending_pipes(&self, sid: SocketId) -> (usize, usize);
+    fn peer_cred(&self, eid: EndpointId) -> Result<PeerCred>;
 }
\ No newline at end of file
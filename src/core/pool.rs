@@ -0,0 +1,104 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::sync::{Arc, Mutex};
+
+/// Pool of reusable buffers that the pipes read received messages into,
+/// see [Socket::set_recv_pool](struct.Socket.html#method.set_recv_pool).
+/// The pool keeps at most the number of buffers it was created with,
+/// the buffers given back while it is full being freed.
+/// Cloning the pool gives another handle on the same set of buffers,
+/// so it can be shared by several sockets, including ones used from other threads.
+#[derive(Clone)]
+pub struct RecvPool {
+    buffers: Arc<Mutex<Vec<Vec<u8>>>>,
+    max_count: usize
+}
+
+impl RecvPool {
+    /// Creates a pool holding `count` buffers, each allocated with `capacity` bytes.
+    pub fn new(count: usize, capacity: usize) -> RecvPool {
+        let buffers = (0..count).map(|_| Vec::with_capacity(capacity)).collect();
+
+        RecvPool {
+            buffers: Arc::new(Mutex::new(buffers)),
+            max_count: count
+        }
+    }
+
+    /// Returns the number of buffers currently sitting in the pool, waiting to be used.
+    pub fn available(&self) -> usize {
+        self.buffers.lock().map(|buffers| buffers.len()).unwrap_or(0)
+    }
+
+    /// Returns a buffer of `len` zeroed bytes, reusing one of the pool when there is any left.
+    #[doc(hidden)]
+    pub fn take(&self, len: usize) -> Vec<u8> {
+        let mut buffer = self.buffers.lock().ok().and_then(|mut buffers| buffers.pop()).unwrap_or_default();
+
+        buffer.resize(len, 0);
+        buffer
+    }
+
+    #[doc(hidden)]
+    pub fn give_back(&self, mut buffer: Vec<u8>) {
+        if let Ok(mut buffers) = self.buffers.lock() {
+            if buffers.len() < self.max_count {
+                buffer.clear();
+                buffers.push(buffer);
+            }
+        }
+    }
+}
+
+/// Returns a buffer of `len` zeroed bytes, taken from the pool if any.
+#[doc(hidden)]
+pub fn take_buffer(pool: Option<&RecvPool>, len: usize) -> Vec<u8> {
+    match pool {
+        Some(pool) => pool.take(len),
+        None => vec![0u8; len]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn taken_buffer_should_have_the_requested_len() {
+        let pool = RecvPool::new(1, 16);
+        let buffer = pool.take(4);
+
+        assert_eq!(vec![0u8; 4], buffer);
+        assert_eq!(16, buffer.capacity());
+        assert_eq!(0, pool.available());
+    }
+
+    #[test]
+    fn given_back_buffer_should_be_reused() {
+        let pool = RecvPool::new(1, 16);
+        let mut buffer = pool.take(4);
+        let ptr = buffer.as_ptr();
+
+        buffer[0] = 65;
+        pool.give_back(buffer);
+
+        let reused = pool.take(2);
+
+        assert_eq!(ptr, reused.as_ptr());
+        assert_eq!(vec![0u8; 2], reused);
+    }
+
+    #[test]
+    fn full_pool_should_free_given_back_buffers() {
+        let pool = RecvPool::new(1, 16);
+
+        pool.give_back(Vec::with_capacity(8));
+
+        assert_eq!(1, pool.available());
+        assert_eq!(16, pool.take(0).capacity());
+    }
+}
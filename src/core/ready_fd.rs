@@ -0,0 +1,161 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::os::unix::net::UnixStream;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// A file descriptor that external event loops can poll to learn a socket readiness.
+/// It is readable exactly while the readiness is set: a single byte is written
+/// to the other end of a socket pair when the readiness is set, and is read back when it is cleared.
+/// This makes the descriptor level triggered, like the readiness reported by probes.
+pub struct ReadyFd {
+    reader: UnixStream,
+    writer: UnixStream,
+    ready: bool
+}
+
+impl ReadyFd {
+    pub fn new() -> io::Result<ReadyFd> {
+        let (r, w) = UnixStream::pair()?;
+
+        r.set_nonblocking(true)?;
+        w.set_nonblocking(true)?;
+
+        Ok(ReadyFd {
+            reader: r,
+            writer: w,
+            ready: false
+        })
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+
+    pub fn set_ready(&mut self, is_ready: bool) {
+        if self.ready == is_ready {
+            return;
+        }
+
+        if is_ready {
+            let _ = self.writer.write(&[1]);
+        } else {
+            let mut buffer = [0u8; 16];
+
+            while let Ok(x) = self.reader.read(&mut buffer) {
+                if x == 0 {
+                    break;
+                }
+            }
+        }
+
+        self.ready = is_ready;
+    }
+}
+
+/// A file descriptor that is readable while the I/O thread has some requests waiting in its channel.
+/// It is shared by the request senders, which signal it after each request, and the I/O thread,
+/// which clears it once the channel is empty, then looks into the channel again.
+/// A request may thus leave the descriptor readable after it was taken, but never unreadable while it waits.
+/// Only the first signal after a clear writes to the socket pair, so a busy session
+/// does not pay a system call for each request.
+pub struct WakeupFd {
+    reader: UnixStream,
+    writer: UnixStream,
+    signalled: AtomicBool
+}
+
+impl WakeupFd {
+    pub fn new() -> io::Result<WakeupFd> {
+        let (r, w) = UnixStream::pair()?;
+
+        r.set_nonblocking(true)?;
+        w.set_nonblocking(true)?;
+
+        Ok(WakeupFd {
+            reader: r,
+            writer: w,
+            signalled: AtomicBool::new(false)
+        })
+    }
+
+    pub fn as_raw_fd(&self) -> RawFd {
+        self.reader.as_raw_fd()
+    }
+
+    pub fn signal(&self) {
+        if !self.signalled.swap(true, Ordering::AcqRel) {
+            let _ = (&self.writer).write(&[1]);
+        }
+    }
+
+    /// Drains the descriptor before lowering the flag: the other way round, a signal sent in between
+    /// would see the flag lowered, write its byte and have it drained, and the next signals would
+    /// find the flag raised and write nothing, leaving the descriptor unreadable.
+    pub fn clear(&self) {
+        let mut buffer = [0u8; 16];
+
+        while let Ok(x) = (&self.reader).read(&mut buffer) {
+            if x == 0 {
+                break;
+            }
+        }
+
+        self.signalled.store(false, Ordering::Release);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use mio::{Poll, Events, Ready, PollOpt, Token};
+    use mio::unix::EventedFd;
+
+    use super::*;
+
+    fn is_readable(raw_fd: RawFd) -> bool {
+        let poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(1);
+
+        poll.register(&EventedFd(&raw_fd), Token(0), Ready::readable(), PollOpt::level()).unwrap();
+        poll.poll(&mut events, Some(Duration::from_millis(0))).unwrap();
+
+        events.iter().any(|e| e.readiness().is_readable())
+    }
+
+    #[test]
+    fn fd_is_readable_only_while_ready() {
+        let mut fd = ReadyFd::new().unwrap();
+
+        assert!(!is_readable(fd.as_raw_fd()));
+        fd.set_ready(true);
+        fd.set_ready(true);
+        assert!(is_readable(fd.as_raw_fd()));
+        fd.set_ready(false);
+        assert!(!is_readable(fd.as_raw_fd()));
+        fd.set_ready(true);
+        assert!(is_readable(fd.as_raw_fd()));
+    }
+
+    #[test]
+    fn wakeup_fd_is_readable_from_the_first_signal_until_cleared() {
+        let fd = WakeupFd::new().unwrap();
+
+        assert!(!is_readable(fd.as_raw_fd()));
+        fd.signal();
+        fd.signal();
+        assert!(is_readable(fd.as_raw_fd()));
+        fd.clear();
+        assert!(!is_readable(fd.as_raw_fd()));
+        fd.clear();
+        fd.signal();
+        assert!(is_readable(fd.as_raw_fd()));
+    }
+
+}
@@ -5,29 +5,35 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::collections::HashMap;
+use std::rc::Rc;
 use std::sync::mpsc;
 use std::io;
 
 use core::{BuildIdHasher, SocketId, DeviceId, ProbeId, PollReq, socket, device, probe};
+use core::config::{Config, ConfigOption};
 use sequence::Sequence;
+use io_error::*;
 
 pub enum Request {
     CreateSocket(socket::ProtocolCtor),
     CreateDevice(SocketId, SocketId),
     CreateProbe(Vec<PollReq>),
+    Ping,
     Shutdown
 }
 
 pub enum Reply {
     Err(io::Error),
-    SocketCreated(SocketId, mpsc::Receiver<socket::Reply>),
+    SocketCreated(SocketId, mpsc::Receiver<socket::Reply>, socket::PeerCount, socket::PeerProtocols, socket::EndpointInfos),
     DeviceCreated(DeviceId, mpsc::Receiver<device::Reply>),
     ProbeCreated(ProbeId, mpsc::Receiver<probe::Reply>),
+    Pong,
     Shutdown
 }
 
 pub struct Session {
     reply_sender: mpsc::Sender<Reply>,
+    max_sockets: Option<usize>,
     sockets: SocketCollection,
     devices: DeviceCollection,
     probes: ProbeCollection
@@ -35,9 +41,13 @@ pub struct Session {
 
 struct SocketCollection {
     ids: Sequence,
-    sockets: HashMap<SocketId, socket::Socket, BuildIdHasher>
+    sockets: HashMap<SocketId, socket::Socket, BuildIdHasher>,
+    ctors: HashMap<SocketId, Rc<socket::ProtocolCtor>, BuildIdHasher>
 }
 
+/// What the facade needs to drive a newly created socket.
+type SocketParts = (SocketId, mpsc::Receiver<socket::Reply>, socket::PeerCount, socket::PeerProtocols, socket::EndpointInfos);
+
 struct DeviceCollection {
     ids: Sequence,
     mapping: HashMap<SocketId, DeviceId, BuildIdHasher>,
@@ -54,6 +64,7 @@ impl Session {
     pub fn new(seq: Sequence, reply_tx: mpsc::Sender<Reply>) -> Session {
         Session {
             reply_sender: reply_tx,
+            max_sockets: None,
             sockets: SocketCollection::new(seq.clone()),
             devices: DeviceCollection::new(seq.clone()),
             probes: ProbeCollection::new(seq.clone())
@@ -64,6 +75,14 @@ impl Session {
         let _ = self.reply_sender.send(reply);
     }
 
+    pub fn set_max_sockets(&mut self, max: Option<usize>) {
+        self.max_sockets = max;
+    }
+
+    pub fn ping(&self) {
+        self.send_reply(Reply::Pong);
+    }
+
 /*****************************************************************************/
 /*                                                                           */
 /* Sockets                                                                   */
@@ -71,11 +90,46 @@ impl Session {
 /*****************************************************************************/
 
     pub fn add_socket(&mut self, protocol_ctor: socket::ProtocolCtor) {
+        match self.create_socket(Rc::new(protocol_ctor)) {
+            Ok((id, rx, peer_count, peer_protocols, endpoint_infos)) => {
+                self.send_reply(Reply::SocketCreated(id, rx, peer_count, peer_protocols, endpoint_infos))
+            },
+            Err(e) => self.send_reply(Reply::Err(e))
+        }
+    }
+
+    /// Creates a socket with the same protocol and options as the specified one, but no endpoints.
+    /// The outcome is replied to the source socket, as it is the one that requested the sibling.
+    pub fn add_sibling(&mut self, sid: SocketId) {
+        let result = match self.sockets.get_sibling_parts(sid) {
+            Some((protocol_ctor, config, options)) => self.create_socket(protocol_ctor).map(|(id, rx, peer_count, peer_protocols, endpoint_infos)| {
+                if let Some(sibling) = self.sockets.get_socket_mut(id) {
+                    sibling.apply_sibling_config(config, options);
+                }
+
+                socket::Reply::Sibling(id, rx, peer_count, peer_protocols, endpoint_infos)
+            }),
+            None => Err(other_io_error("socket protocol cannot be found to create a sibling"))
+        };
+
+        if let Some(socket) = self.sockets.get_socket_mut(sid) {
+            socket.on_sibling_created(result);
+        }
+    }
+
+    fn create_socket(&mut self, protocol_ctor: Rc<socket::ProtocolCtor>) -> io::Result<SocketParts> {
+        if let Some(max) = self.max_sockets {
+            if self.sockets.len() >= max {
+                return Err(other_io_error(format!("session cannot create more than {} sockets", max)));
+            }
+        }
+
         let (tx, rx) = mpsc::channel();
-        let protocol = protocol_ctor(tx.clone());
-        let id = self.sockets.add(tx, protocol);
+        let (proto_tx, proto_rx) = mpsc::channel();
+        let protocol = protocol_ctor(proto_tx);
+        let (id, peer_count, peer_protocols, endpoint_infos) = self.sockets.add(tx, proto_rx, protocol, protocol_ctor);
 
-        self.send_reply(Reply::SocketCreated(id, rx));
+        Ok((id, rx, peer_count, peer_protocols, endpoint_infos))
     }
 
     pub fn get_socket_mut(&mut self, id: SocketId) -> Option<&mut socket::Socket> {
@@ -147,25 +201,48 @@ impl SocketCollection {
     fn new(seq: Sequence) -> SocketCollection {
         SocketCollection {
             ids: seq,
-            sockets: HashMap::default()
+            sockets: HashMap::default(),
+            ctors: HashMap::default()
         }
     }
 
-    fn add(&mut self, reply_tx: mpsc::Sender<socket::Reply>, proto: Box<dyn socket::Protocol>) -> SocketId {
+    fn add(
+        &mut self,
+        reply_tx: mpsc::Sender<socket::Reply>,
+        proto_reply_rx: mpsc::Receiver<socket::Reply>,
+        proto: Box<dyn socket::Protocol>,
+        proto_ctor: Rc<socket::ProtocolCtor>) -> (SocketId, socket::PeerCount, socket::PeerProtocols, socket::EndpointInfos) {
         let id = SocketId::from(self.ids.next());
-        let socket = socket::Socket::new(id, reply_tx, proto);
+        let socket = socket::Socket::new(id, reply_tx, proto_reply_rx, proto);
+        let peer_count = socket.get_peer_count();
+        let peer_protocols = socket.get_peer_protocols();
+        let endpoint_infos = socket.get_endpoint_infos();
 
         self.sockets.insert(id, socket);
+        self.ctors.insert(id, proto_ctor);
 
-        id
+        (id, peer_count, peer_protocols, endpoint_infos)
     }
 
     fn get_socket_mut(&mut self, id: SocketId) -> Option<&mut socket::Socket> {
         self.sockets.get_mut(&id)
     }
 
+    fn get_sibling_parts(&self, id: SocketId) -> Option<(Rc<socket::ProtocolCtor>, Config, Vec<ConfigOption>)> {
+        let socket = self.sockets.get(&id)?;
+        let protocol_ctor = self.ctors.get(&id)?.clone();
+        let (config, options) = socket.sibling_config();
+
+        Some((protocol_ctor, config, options))
+    }
+
+    fn len(&self) -> usize {
+        self.sockets.len()
+    }
+
     fn remove(&mut self, id: SocketId) {
         self.sockets.remove(&id);
+        self.ctors.remove(&id);
     }
 }
 
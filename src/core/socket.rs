@@ -4,23 +4,61 @@
 // or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
 // This file may not be copied, modified, or distributed except according to those terms.
 
-use std::collections::HashMap;
-use std::sync::mpsc::Sender;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::mpsc::{self, Sender, Receiver};
+use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::io;
-use std::time::Duration;
+use std::mem;
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 
 use super::{BuildIdHasher, SocketId, EndpointId, Message, EndpointTmpl, EndpointSpec, EndpointDesc, Scheduled };
 use super::endpoint::{Pipe, Acceptor};
-use super::config::{Config, ConfigOption};
+use super::config::{Config, ConfigOption, EndpointConfig, EndpointRole, rejected_option_io_error, unsupported_option_io_error};
 use super::context::{Context, Schedulable, Event};
+use super::trace::OpSpan;
+use super::pool::RecvPool;
+use super::event_ring::ErrorCategory;
+#[cfg(unix)]
+use super::ready_fd::ReadyFd;
+use transport::{Stream, Address, PeerBusy, PeerCred, Features, ProtocolVersions};
 use io_error::*;
 
 pub enum Request {
     Connect(String),
+    ConnectBlocking(String, Duration),
+    ConnectStream(Stream),
     Bind(String),
     Send(Message, bool),
+    SendAsync(Message),
+    SendSurvey(Message),
+    CancelRequest,
     Recv(bool),
     SetOption(ConfigOption),
+    SetRecvReducer(RecvReducer, bool),
+    SetRecvMap(RecvMap),
+    SetRecvPool(RecvPool),
+    SetSubscriptionValidator(SubscriptionValidator),
+    PlugDevice,
+    PendingPipes,
+    PendingRetries,
+    DroppedCount,
+    DuplicateCount,
+    ExpiredCount,
+    #[cfg(unix)]
+    RecvFd,
+    #[cfg(unix)]
+    SendFd,
+    SizeHistogram,
+    Capabilities,
+    Stats,
+    NewSibling,
+    ApplyEndpointConfig(EndpointConfig),
+    DumpEndpointConfig,
+    Shutdown(Duration),
+    Clone,
     Close
 }
 
@@ -29,16 +67,214 @@ pub enum Reply {
     Connect(EndpointId),
     Bind(EndpointId),
     Send,
-    Recv(Message),
-    SetOption
+    SendReached(Vec<EndpointId>),
+    CancelRequest,
+    Recv(Message, EndpointId),
+    SetOption,
+    SetRecvReducer,
+    SetRecvMap,
+    SetRecvPool,
+    SetSubscriptionValidator,
+    PlugDevice,
+    PendingPipes(usize, usize),
+    PeerCred(PeerCred),
+    PendingRetries(usize),
+    DroppedCount(usize),
+    DuplicateCount(usize),
+    ExpiredCount(usize),
+    #[cfg(unix)]
+    ReadyFd(RawFd),
+    SizeHistogram(SizeHistogram),
+    Capabilities(Capabilities),
+    Stats(SocketStats),
+    Sibling(SocketId, Receiver<Reply>, PeerCount, PeerProtocols, EndpointInfos),
+    EndpointConfig(Vec<EndpointConfig>),
+    Shutdown,
+    Clone(HandleId, Receiver<Reply>)
+}
+
+/// Identifies a facade socket among the clones sharing a core socket, each having its own reply channel.
+pub type HandleId = usize;
+
+pub type RecvReducer = Box<dyn FnMut(&Message) + Send>;
+
+/// Transform applied by the session to each received message before its delivery,
+/// the messages it fails on being dropped.
+pub type RecvMap = Box<dyn FnMut(Message) -> io::Result<Message> + Send>;
+
+/// Predicate applied by subscriber sockets to each new subscription topic.
+pub type SubscriptionValidator = Box<dyn Fn(&[u8]) -> bool + Send>;
+
+/// Number of pipes that completed the handshake, shared with the facade socket.
+pub type PeerCount = Arc<AtomicUsize>;
+
+/// Protocol id advertised by the peer of each opened pipe, shared with the facade endpoints.
+pub type PeerProtocols = Arc<Mutex<HashMap<EndpointId, u16>>>;
+
+/// Properties of each opened pipe and bound acceptor, shared with the facade endpoints.
+pub type EndpointInfos = Arc<Mutex<HashMap<EndpointId, EndpointInfo>>>;
+
+/// Properties of an endpoint, reported once its pipe is opened or its acceptor is listening,
+/// see [Endpoint::info](struct.Endpoint.html#method.info).
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct EndpointInfo {
+    /// Address of the local side of the endpoint, when reported by the transport.
+    pub local_addr: Option<Address>,
+    /// Address of the peer of a connected endpoint, when reported by the transport.
+    pub peer_addr: Option<Address>,
+    /// Time elapsed from the creation of the pipe to the completion of its handshake,
+    /// connection setup included for a connected endpoint. Always `None` for bound endpoints.
+    pub handshake_duration: Option<Duration>
+}
+
+/// Operations and behaviors supported by the protocol of a socket, as declared by the protocol itself.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// The socket can send messages.
+    pub can_send: bool,
+    /// The socket can receive messages.
+    pub can_recv: bool,
+    /// Received messages are filtered by the topics subscribed with `ConfigOption::Subscribe`.
+    pub has_subscriptions: bool,
+    /// Messages are exchanged as surveys, answered within the `SurveyDeadline` option.
+    pub has_survey: bool,
+    /// Each sent message is delivered to all the connected peers instead of only one of them.
+    pub is_broadcast: bool
+}
+
+/// Cumulative counters of a socket activity since its creation.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SocketStats {
+    /// Number of messages whose send completed.
+    pub messages_sent: u64,
+    /// Number of messages received from the pipes, including the ones the application did not receive yet.
+    pub messages_recv: u64,
+    /// Number of bytes of the messages counted by `messages_sent`, as handed to the socket:
+    /// the header of a message forwarded by a device is included, the one added by the protocol is not.
+    pub bytes_sent: u64,
+    /// Number of bytes of the messages counted by `messages_recv`, as read from the pipes:
+    /// the header of the protocol, such as the backtrace of a request, is included.
+    pub bytes_recv: u64,
+    /// Number of pipes created by connecting that completed the handshake, reconnections included.
+    pub connect_count: u64,
+    /// Number of pipes created by accepting that completed the handshake.
+    pub accept_count: u64,
+    /// Number of pipes currently opened.
+    pub current_connections: usize,
+    /// Number of accepted connections sent the busy signal because the socket had too many accepted pipes,
+    /// see [ConfigOption::MaxAcceptedPipes](enum.ConfigOption.html#variant.MaxAcceptedPipes).
+    pub busy_rejected: u64,
+    /// Number of connections the peer rejected with the busy signal.
+    pub busy_received: u64,
+    /// Number of messages a `Pub` socket did not publish to a pipe whose queue was full,
+    /// see [PubDropPolicy](enum.ConfigOption.html#variant.PubDropPolicy).
+    pub pub_dropped: u64
+}
+
+/// Distribution of the sizes of the messages sent and received by a socket.
+/// Sizes are bucketed by powers of two: bucket `0` counts the empty messages,
+/// and bucket `n` counts the messages whose size is at least `2^(n-1)` and less than `2^n` bytes.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SizeHistogram {
+    buckets: Vec<u64>,
+    total_size: u64
+}
+
+impl SizeHistogram {
+    /// Returns the index of the bucket counting the messages of the specified size.
+    pub fn bucket_of(size: usize) -> usize {
+        (0usize.count_zeros() - size.leading_zeros()) as usize
+    }
+
+    #[doc(hidden)]
+    pub fn add(&mut self, size: usize) {
+        let index = SizeHistogram::bucket_of(size);
+
+        if self.buckets.len() <= index {
+            self.buckets.resize(index + 1, 0);
+        }
+        self.buckets[index] += 1;
+        self.total_size += size as u64;
+    }
+
+    /// Returns the message count of each bucket, up to the last non-empty one.
+    pub fn buckets(&self) -> &[u64] {
+        &self.buckets
+    }
+
+    /// Returns the number of messages counted in the bucket of the specified size.
+    pub fn count_of_size(&self, size: usize) -> u64 {
+        self.buckets.get(SizeHistogram::bucket_of(size)).cloned().unwrap_or(0)
+    }
+
+    /// Returns the number of messages accumulated in the histogram.
+    pub fn count(&self) -> u64 {
+        self.buckets.iter().sum()
+    }
+
+    /// Returns the sum of the sizes of the messages accumulated in the histogram, in bytes.
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Returns the average size of the messages accumulated in the histogram, in bytes.
+    pub fn average_size(&self) -> f64 {
+        match self.count() {
+            0 => 0.0,
+            count => self.total_size as f64 / count as f64
+        }
+    }
 }
 
 pub struct Socket {
     id: SocketId,
-    reply_sender: Sender<Reply>,
+    reply_senders: HashMap<HandleId, Sender<Reply>>,
+    next_handle: HandleId,
+    requestor: HandleId,
+    send_requestor: Option<HandleId>,
+    recv_requestor: Option<HandleId>,
+    protocol_replies: Receiver<Reply>,
     protocol: Box<dyn Protocol>,
     pipes: HashMap<EndpointId, Pipe, BuildIdHasher>,
     acceptors: HashMap<EndpointId, Acceptor, BuildIdHasher>,
+    accepted: HashSet<EndpointId, BuildIdHasher>,
+    opened: HashSet<EndpointId, BuildIdHasher>,
+    draining: HashMap<EndpointId, Scheduled, BuildIdHasher>,
+    connecting: HashMap<EndpointId, (Scheduled, HandleId), BuildIdHasher>,
+    retrying: HashMap<EndpointId, Scheduled, BuildIdHasher>,
+    paused_reconnects: HashMap<EndpointId, Option<EndpointSpec>, BuildIdHasher>,
+    retry_order: VecDeque<EndpointId>,
+    early_readiness: HashMap<EndpointId, Readiness, BuildIdHasher>,
+    endpoint_configs: HashMap<EndpointId, EndpointConfig, BuildIdHasher>,
+    send_queue: VecDeque<(Message, QueuedSend)>,
+    send_in_flight: Option<QueuedSend>,
+    send_blocked: Option<(Message, HandleId)>,
+    send_span: Option<OpSpan>,
+    recv_span: Option<OpSpan>,
+    recv_reducer: Option<RecvReducer>,
+    recv_map: Option<RecvMap>,
+    recv_pool: Option<RecvPool>,
+    recv_forward: bool,
+    recv_blocking: bool,
+    dedup_window: DedupWindow,
+    duplicate_count: usize,
+    expired_count: usize,
+    peer_count: PeerCount,
+    peer_protocols: PeerProtocols,
+    endpoint_infos: EndpointInfos,
+    shutdown_requestor: Option<HandleId>,
+    shutdown_wait: Option<ShutdownWait>,
+    linger: Option<Linger>,
+    size_histogram: SizeHistogram,
+    stats: SocketStats,
+    dropped_count: usize,
+    #[cfg(unix)]
+    recv_fd: Option<ReadyFd>,
+    #[cfg(unix)]
+    send_fd: Option<ReadyFd>,
+    recv_rates: HashMap<EndpointId, RecvRate, BuildIdHasher>,
+    send_size: Option<usize>,
+    protocol_options: Vec<ConfigOption>,
     config: Config
 }
 
@@ -70,17 +306,102 @@ pub trait Protocol {
     fn is_send_ready(&self) -> bool;
     fn is_recv_ready(&self) -> bool;
 
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::default()
+    }
+
     fn set_option(&mut self, _: ConfigOption) -> io::Result<()> {
-        Err(invalid_input_io_error("option not supported"))
+        Err(unsupported_option_io_error("option not supported by this protocol"))
+    }
+    fn set_subscription_validator(&mut self, _: SubscriptionValidator) -> io::Result<()> {
+        Err(unsupported_option_io_error("option not supported by this protocol"))
+    }
+    /// Messages dropped by the protocol itself, reported apart from the ones dropped by the pipes.
+    fn dropped_count(&self) -> usize {
+        0
     }
     fn on_timer_tick(&mut self, _: &mut dyn Context, _: Schedulable) {
     }
     fn on_device_plugged(&mut self, _: &mut dyn Context) {}
+    /// Feature bits advertised in the handshake of the endpoints subsequently added to the socket,
+    /// according to the options of the protocol.
+    fn features(&self) -> u8 {
+        0
+    }
+    /// Called once a protocol option was applied, so that the protocol can act on the pipes it already holds.
+    fn on_option_set(&mut self, _: &mut dyn Context) {}
+    fn cancel_request(&mut self, _: &mut dyn Context) {}
     fn close(&mut self, ctx: &mut dyn Context);
 }
 
 pub type ProtocolCtor = Box<dyn Fn(Sender<Reply>) -> Box<dyn Protocol> + Send>;
 
+/// Readiness reported by a pipe before its handshake was acknowledged by the socket,
+/// replayed once the pipe is handed to the protocol.
+#[derive(Default)]
+struct Readiness {
+    send: bool,
+    recv: bool
+}
+
+/// Messages received from a pipe during the current window of the recv rate limit.
+/// While the pipe is paused, its recv readiness is kept aside, to be replayed when the window ends.
+struct RecvRate {
+    window_start: Instant,
+    count: u32,
+    paused: Option<Scheduled>,
+    ready: bool
+}
+
+/// Pipes still flushing their pending sends while a closed socket lingers,
+/// once the sends queued by the socket itself are done.
+struct Linger {
+    flushing: Option<HashSet<EndpointId>>,
+    deadline: Option<Scheduled>
+}
+
+/// Shutdown waiting for the sends queued by the socket before draining the pipes.
+struct ShutdownWait {
+    drain_timeout: Duration,
+    deadline: Scheduled
+}
+
+/// Dedup ids of the most recently received messages, oldest first.
+#[derive(Default)]
+struct DedupWindow {
+    seen: HashSet<u64>,
+    order: VecDeque<u64>
+}
+
+impl DedupWindow {
+    /// Records the id, evicting the oldest ones beyond the capacity.
+    /// Returns false when the id is already in the window.
+    fn insert(&mut self, id: u64, capacity: usize) -> bool {
+        if self.seen.contains(&id) {
+            return false;
+        }
+
+        while self.order.len() >= capacity {
+            match self.order.pop_front() {
+                Some(oldest) => self.seen.remove(&oldest),
+                None => break
+            };
+        }
+
+        self.seen.insert(id);
+        self.order.push_back(id);
+        true
+    }
+}
+
+/// Kind of a send handed to the protocol by the send queue.
+/// The completion of an async send was already acknowledged to the facade, so it is not forwarded.
+#[derive(Clone, Copy, PartialEq)]
+enum QueuedSend {
+    Async,
+    Blocking(HandleId)
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* Socket                                                                    */
@@ -88,13 +409,60 @@ pub type ProtocolCtor = Box<dyn Fn(Sender<Reply>) -> Box<dyn Protocol> + Send>;
 /*****************************************************************************/
 
 impl Socket {
-    pub fn new(id: SocketId, reply_tx: Sender<Reply>, proto: Box<dyn Protocol>) -> Socket {
+    pub fn new(id: SocketId, reply_tx: Sender<Reply>, proto_reply_rx: Receiver<Reply>, proto: Box<dyn Protocol>) -> Socket {
+        let mut senders = HashMap::new();
+
+        senders.insert(0, reply_tx);
+
         Socket {
             id: id,
-            reply_sender: reply_tx,
+            reply_senders: senders,
+            next_handle: 1,
+            requestor: 0,
+            send_requestor: None,
+            recv_requestor: None,
+            protocol_replies: proto_reply_rx,
             protocol: proto,
             pipes: HashMap::default(),
             acceptors: HashMap::default(),
+            accepted: HashSet::default(),
+            opened: HashSet::default(),
+            draining: HashMap::default(),
+            connecting: HashMap::default(),
+            retrying: HashMap::default(),
+            paused_reconnects: HashMap::default(),
+            retry_order: VecDeque::new(),
+            early_readiness: HashMap::default(),
+            endpoint_configs: HashMap::default(),
+            send_queue: VecDeque::new(),
+            send_in_flight: None,
+            send_blocked: None,
+            send_span: None,
+            recv_span: None,
+            recv_reducer: None,
+            recv_map: None,
+            recv_pool: None,
+            recv_forward: true,
+            recv_blocking: true,
+            dedup_window: DedupWindow::default(),
+            duplicate_count: 0,
+            expired_count: 0,
+            peer_count: PeerCount::default(),
+            peer_protocols: PeerProtocols::default(),
+            endpoint_infos: EndpointInfos::default(),
+            shutdown_requestor: None,
+            shutdown_wait: None,
+            linger: None,
+            size_histogram: SizeHistogram::default(),
+            stats: SocketStats::default(),
+            dropped_count: 0,
+            #[cfg(unix)]
+            recv_fd: None,
+            #[cfg(unix)]
+            send_fd: None,
+            recv_rates: HashMap::default(),
+            send_size: None,
+            protocol_options: Vec::new(),
             config: Config::default()
         }
     }
@@ -107,7 +475,81 @@ impl Socket {
     }
 
     fn send_reply(&self, reply: Reply) {
-        let _ = self.reply_sender.send(reply);
+        self.reply_to(self.requestor, reply);
+    }
+
+    fn reply_to(&self, handle: HandleId, reply: Reply) {
+        if let Some(sender) = self.reply_senders.get(&handle) {
+            let _ = sender.send(reply);
+        }
+    }
+
+/*****************************************************************************/
+/*                                                                           */
+/* handles                                                                   */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Sets the handle the replies to the next request are sent to.
+    pub fn set_requestor(&mut self, handle: HandleId) {
+        self.requestor = handle;
+    }
+
+    /// Creates a new handle with its own reply channel, for a clone of the facade socket.
+    pub fn clone_handle(&mut self, _: &mut dyn Context) {
+        let (tx, rx) = mpsc::channel();
+        let handle = self.next_handle;
+
+        self.next_handle += 1;
+        self.reply_senders.insert(handle, tx);
+        self.send_reply(Reply::Clone(handle, rx));
+    }
+
+    /// Releases the handle of the requestor, closing the socket along with the last one.
+    /// Dropping the reply channel of the handle notifies its facade that the request is complete.
+    pub fn close_handle(&mut self, ctx: &mut dyn Context) {
+        if !self.reply_senders.contains_key(&self.requestor) {
+            return;
+        }
+
+        if self.reply_senders.len() > 1 {
+            self.reply_senders.remove(&self.requestor);
+        } else {
+            self.close_or_linger(ctx);
+        }
+    }
+
+    pub fn get_peer_count(&self) -> PeerCount {
+        self.peer_count.clone()
+    }
+
+    pub fn get_peer_protocols(&self) -> PeerProtocols {
+        self.peer_protocols.clone()
+    }
+
+    pub fn get_endpoint_infos(&self) -> EndpointInfos {
+        self.endpoint_infos.clone()
+    }
+
+    pub fn set_endpoint_info(&self, eid: EndpointId, info: EndpointInfo) {
+        if let Ok(mut endpoint_infos) = self.endpoint_infos.lock() {
+            endpoint_infos.insert(eid, info);
+        }
+    }
+
+    fn clear_endpoint_info(&self, eid: EndpointId) {
+        if let Ok(mut endpoint_infos) = self.endpoint_infos.lock() {
+            endpoint_infos.remove(&eid);
+        }
+    }
+
+    fn set_peer_protocol(&self, eid: EndpointId, peer_proto_id: Option<u16>) {
+        if let Ok(mut peer_protocols) = self.peer_protocols.lock() {
+            match peer_proto_id {
+                Some(pid) => peer_protocols.insert(eid, pid),
+                None => peer_protocols.remove(&eid)
+            };
+        }
     }
 
     pub fn poll(&self, ctx: &mut dyn Context) {
@@ -125,15 +567,34 @@ impl Socket {
         EndpointDesc {
             send_priority: self.config.send_priority,
             recv_priority: self.config.recv_priority,
+            recv_weight: self.config.recv_weight,
             tcp_no_delay: self.config.tcp_no_delay,
-            recv_max_size: self.config.recv_max_size
+            tcp_keepalive: self.config.tcp_keepalive,
+            reuse_addr: self.config.reuse_addr,
+            connect_source_addr: self.config.connect_source_addr,
+            ipc_permissions: self.config.ipc_permissions,
+            recv_max_size: self.config.recv_max_size,
+            send_chunk_size: self.config.send_chunk_size,
+            oversize_policy: self.config.oversize_policy,
+            protocol_versions: self.protocol_versions(),
+            recv_pool: self.recv_pool.clone()
         }
     }
 
+    /// Versions and features advertised in the handshake, the latter depending on both the generic
+    /// and the protocol options.
+    fn protocol_versions(&self) -> ProtocolVersions {
+        let mut versions = self.config.protocol_versions();
+
+        versions.features |= self.protocol.features();
+        versions
+    }
+
     fn create_endpoint_spec(&self, url: String) -> EndpointSpec {
         EndpointSpec {
             url: url,
-            desc: self.create_endpoint_desc()
+            desc: self.create_endpoint_desc(),
+            attempts: 0
         }
     }
 
@@ -144,6 +605,66 @@ impl Socket {
         }
     }
 
+/*****************************************************************************/
+/*                                                                           */
+/* endpoint config                                                           */
+/*                                                                           */
+/*****************************************************************************/
+
+    pub fn apply_endpoint_config(&mut self, ctx: &mut dyn Context, config: EndpointConfig) {
+        let mut tmpl = self.create_endpoint_tmpl(config.url);
+
+        for option in config.options {
+            let name = option.name();
+
+            if let Err(e) = override_endpoint_desc(&mut tmpl.spec.desc, option) {
+                return self.send_reply(Reply::Err(rejected_option_io_error(name, e)));
+            }
+        }
+
+        match config.role {
+            EndpointRole::Connect => self.connect_tmpl(ctx, tmpl),
+            EndpointRole::Bind    => self.bind_tmpl(ctx, tmpl)
+        }
+    }
+
+    pub fn dump_endpoint_config(&mut self, _: &mut dyn Context) {
+        let mut configs: Vec<_> = self.endpoint_configs.iter().
+            map(|(eid, config)| (Into::<usize>::into(eid), config.clone())).
+            collect();
+
+        configs.sort_by_key(|&(id, _)| id);
+
+        let configs = configs.into_iter().map(|(_, config)| config).collect();
+
+        self.send_reply(Reply::EndpointConfig(configs));
+    }
+
+    fn record_endpoint_config(&mut self, eid: EndpointId, endpoint_role: EndpointRole, spec: &EndpointSpec) {
+        let desc = &spec.desc;
+        let config = EndpointConfig {
+            url: spec.url.clone(),
+            role: endpoint_role,
+            options: vec![
+                ConfigOption::SendPriority(desc.send_priority),
+                ConfigOption::RecvPriority(desc.recv_priority),
+                ConfigOption::RecvWeight(desc.recv_weight),
+                ConfigOption::TcpNoDelay(desc.tcp_no_delay),
+                ConfigOption::TcpKeepalive(desc.tcp_keepalive),
+                ConfigOption::ReuseAddr(desc.reuse_addr),
+                ConfigOption::ConnectSourceAddr(desc.connect_source_addr),
+                ConfigOption::IpcSocketPermissions(desc.ipc_permissions),
+                ConfigOption::RecvMaxSize(desc.recv_max_size),
+                ConfigOption::SendChunkSize(desc.send_chunk_size),
+                ConfigOption::OversizePolicy(desc.oversize_policy),
+                ConfigOption::ProtocolVersion(desc.protocol_versions.advertised),
+                ConfigOption::CompatibleProtocolVersions(desc.protocol_versions.compatible.clone())
+            ]
+        };
+
+        self.endpoint_configs.insert(eid, config);
+    }
+
 /*****************************************************************************/
 /*                                                                           */
 /* connect                                                                   */
@@ -153,32 +674,136 @@ impl Socket {
     pub fn connect(&mut self, ctx: &mut dyn Context, url: String) {
         let tmpl = self.create_endpoint_tmpl(url);
 
-        match ctx.connect(self.id, &tmpl) {
-            Ok(id) => self.on_connect_success(ctx, id, tmpl.spec),
+        self.connect_tmpl(ctx, tmpl)
+    }
+
+    fn connect_tmpl(&mut self, ctx: &mut dyn Context, tmpl: EndpointTmpl) {
+        match self.create_connected_endpoint(ctx, tmpl) {
+            Ok(eid) => self.send_reply(Reply::Connect(eid)),
             Err(e) => self.on_connect_error(e)
         };
     }
 
+    /// Adds the endpoint of a connect request to the socket.
+    /// Errors that can be detected synchronously, such as a malformed url or an unknown transport,
+    /// are returned as invalid input. Any other error is considered as a failure of the remote side,
+    /// the endpoint being added anyway and its connection retried later, like when the connection is lost.
+    fn create_connected_endpoint(&mut self, ctx: &mut dyn Context, tmpl: EndpointTmpl) -> io::Result<EndpointId> {
+        match ctx.connect(self.id, &tmpl) {
+            Ok(eid) => {
+                self.on_connect_success(ctx, eid, tmpl.spec);
+                Ok(eid)
+            },
+            Err(e) => {
+                if e.kind() == io::ErrorKind::InvalidInput {
+                    Err(e)
+                } else {
+                    self.defer_connect(ctx, tmpl.spec, e)
+                }
+            }
+        }
+    }
+
     fn on_connect_success(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
+        self.record_endpoint_config(eid, EndpointRole::Connect, &spec);
+
         let pipe = self.connect_pipe(eid, spec);
 
         self.insert_pipe(ctx, eid, pipe);
-        self.send_reply(Reply::Connect(eid));
+    }
+
+    fn defer_connect(&mut self, ctx: &mut dyn Context, spec: EndpointSpec, err: io::Error) -> io::Result<EndpointId> {
+        let eid = ctx.reserve_endpoint_id();
+
+        debug!("[{:?}] connect of ep {:?} failed, will retry: {}", ctx, eid, err);
+        self.record_endpoint_config(eid, EndpointRole::Connect, &spec);
+        self.schedule_reconnect(ctx, eid, spec);
+
+        if self.retrying.contains_key(&eid) {
+            Ok(eid)
+        } else {
+            self.endpoint_configs.remove(&eid);
+            Err(err)
+        }
     }
 
     fn on_connect_error(&mut self, err: io::Error) {
         self.send_reply(Reply::Err(err));
     }
 
+    /// Uses an already connected stream as a pipe.
+    /// Since the connection cannot be established again, the pipe is not reconnected once lost.
+    pub fn connect_stream(&mut self, ctx: &mut dyn Context, stream: Stream) {
+        let tmpl = self.create_endpoint_tmpl(stream.url());
+
+        match ctx.connect_stream(self.id, stream, &tmpl) {
+            Ok(eid) => {
+                let pipe = Pipe::new_accepted(eid, tmpl.spec.desc);
+
+                self.insert_pipe(ctx, eid, pipe);
+                self.send_reply(Reply::Connect(eid));
+            },
+            Err(e) => self.on_connect_error(e)
+        }
+    }
+
+    pub fn connect_blocking(&mut self, ctx: &mut dyn Context, url: String, timeout: Duration) {
+        let tmpl = self.create_endpoint_tmpl(url);
+        let eid = match self.create_connected_endpoint(ctx, tmpl) {
+            Ok(eid) => eid,
+            Err(e) => return self.on_connect_error(e)
+        };
+
+        match ctx.schedule(Schedulable::ConnectTimeout(eid), timeout) {
+            Ok(scheduled) => {
+                self.connecting.insert(eid, (scheduled, self.requestor));
+            },
+            Err(e) => {
+                self.close_pipe(ctx, eid);
+                self.on_connect_error(e);
+            }
+        }
+    }
+
+    pub fn on_connect_timeout(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some((_, handle)) = self.connecting.remove(&eid) {
+            self.close_pipe(ctx, eid);
+            self.reply_to(handle, Reply::Err(timedout_io_error("Connect timed out")));
+        }
+    }
+
+    fn on_connect_handshake(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some((scheduled, handle)) = self.connecting.remove(&eid) {
+            ctx.cancel(scheduled);
+            self.reply_to(handle, Reply::Connect(eid));
+        }
+    }
+
     fn schedule_reconnect(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
-        let task = Schedulable::Reconnect(eid, spec);
-        let delay = self.config.retry_ivl;
-        let _ = ctx.schedule(task, delay); 
-        // TODO maybe we should keep track of the scheduled reconnection
-        // In case the facade wants to close the ep somewhere between the error and the timeout
+        let delay = self.config.retry_delay(spec.attempts);
+
+        self.schedule_reconnect_after(ctx, eid, spec, delay)
+    }
+
+    fn schedule_reconnect_after(&mut self, ctx: &mut dyn Context, eid: EndpointId, mut spec: EndpointSpec, delay: Duration) {
+        if let Some(parked) = self.paused_reconnects.get_mut(&eid) {
+            debug!("[{:?}] reconnect of ep {:?} is paused", ctx, eid);
+            *parked = Some(spec);
+            return;
+        }
+
+        spec.attempts = spec.attempts.saturating_add(1);
+        self.schedule_retry(ctx, eid, Schedulable::Reconnect(eid, spec), delay);
     }
 
     pub fn reconnect(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
+        self.on_retry(eid);
+
+        if let Some(parked) = self.paused_reconnects.get_mut(&eid) {
+            *parked = Some(spec);
+            return;
+        }
+
         let pids = self.get_protocol_ids();
         let tmpl = EndpointTmpl {
             pids: pids,
@@ -187,7 +812,7 @@ impl Socket {
 
         match ctx.reconnect(self.id, eid, &tmpl) {
             Ok(_)  => self.on_reconnect_success(ctx, eid, tmpl.spec),
-            Err(_) => self.on_reconnect_error(ctx, eid, tmpl.spec)
+            Err(e) => self.on_reconnect_error(ctx, eid, tmpl.spec, e)
         }
     }
 
@@ -195,8 +820,154 @@ impl Socket {
         self.insert_pipe(ctx, eid, Pipe::from_spec(eid, spec));
     }
 
-    fn on_reconnect_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
-        self.schedule_reconnect(ctx, eid, spec);
+    fn on_reconnect_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec, err: io::Error) {
+        self.on_connection_lost(ctx, eid, spec, err);
+    }
+
+    /// Reconnects the endpoint according to the category of the error that caused the connection loss.
+    fn on_connection_lost(&mut self, ctx: &mut dyn Context, eid: EndpointId, mut spec: EndpointSpec, err: io::Error) {
+        if PeerBusy::of(&err).is_some() {
+            self.stats.busy_received += 1;
+            let delay = self.config.longest_retry_delay();
+
+            return self.schedule_reconnect_after(ctx, eid, spec, delay);
+        }
+
+        match ErrorCategory::of(&err) {
+            ErrorCategory::PermissionDenied => self.give_up_pipe(ctx, eid, err),
+            ErrorCategory::Reset => {
+                spec.attempts = 0;
+                self.schedule_reconnect(ctx, eid, spec)
+            },
+            _ => self.schedule_reconnect(ctx, eid, spec)
+        }
+    }
+
+    fn give_up_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId, err: io::Error) {
+        warn!("[{:?}] giving up ep {:?}: {}", ctx, eid, err);
+        self.endpoint_configs.remove(&eid);
+        self.paused_reconnects.remove(&eid);
+        ctx.raise(Event::EndpointClosed(eid, err.raw_os_error(), ErrorCategory::of(&err)));
+
+        if let Some((scheduled, handle)) = self.connecting.remove(&eid) {
+            ctx.cancel(scheduled);
+            self.reply_to(handle, Reply::Err(err));
+        }
+    }
+
+    /// Prevents the endpoint from reconnecting once its connection is lost, without closing it.
+    /// A reconnection already scheduled is suppressed when it expires, and kept until `resume_reconnect` is called.
+    pub fn pause_reconnect(&mut self, _: &mut dyn Context, eid: EndpointId) {
+        self.paused_reconnects.entry(eid).or_insert(None);
+    }
+
+    /// Lets the endpoint reconnect again, immediately if its connection was lost while paused.
+    pub fn resume_reconnect(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some(Some(spec)) = self.paused_reconnects.remove(&eid) {
+            self.reconnect(ctx, eid, spec);
+        }
+    }
+
+/*****************************************************************************/
+/*                                                                           */
+/* retry                                                                     */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Schedules a reconnection or a rebind attempt, keeping track of it
+    /// so it can be cancelled when the endpoint is closed in the meantime,
+    /// or when too many endpoints are waiting for their attempt.
+    fn schedule_retry(&mut self, ctx: &mut dyn Context, eid: EndpointId, task: Schedulable, delay: Duration) {
+        if let Ok(scheduled) = ctx.schedule(task, delay) {
+            self.retrying.insert(eid, scheduled);
+            self.retry_order.push_back(eid);
+            self.enforce_max_pending_retries(ctx);
+        }
+    }
+
+    fn enforce_max_pending_retries(&mut self, ctx: &mut dyn Context) {
+        while self.retrying.len() > self.config.max_pending_retries {
+            let oldest = match self.retry_order.pop_front() {
+                Some(eid) => eid,
+                None => break
+            };
+
+            if let Some(scheduled) = self.retrying.remove(&oldest) {
+                debug!("[{:?}] too many pending retries, dropping ep {:?}", ctx, oldest);
+                ctx.cancel(scheduled);
+                self.endpoint_configs.remove(&oldest);
+            }
+        }
+    }
+
+    fn on_retry(&mut self, eid: EndpointId) {
+        if self.retrying.remove(&eid).is_some() {
+            self.retry_order.retain(|id| *id != eid);
+        }
+    }
+
+    fn cancel_retry(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some(scheduled) = self.retrying.remove(&eid) {
+            ctx.cancel(scheduled);
+            self.retry_order.retain(|id| *id != eid);
+        }
+    }
+
+    pub fn pending_retries(&mut self, _: &mut dyn Context) {
+        self.send_reply(Reply::PendingRetries(self.retrying.len()));
+    }
+
+    pub fn dropped_count(&mut self, _: &mut dyn Context) {
+        self.send_reply(Reply::DroppedCount(self.dropped_count));
+    }
+
+    pub fn duplicate_count(&mut self, _: &mut dyn Context) {
+        self.send_reply(Reply::DuplicateCount(self.duplicate_count));
+    }
+
+    pub fn expired_count(&mut self, _: &mut dyn Context) {
+        self.send_reply(Reply::ExpiredCount(self.expired_count));
+    }
+
+    pub fn cancel_request(&mut self, ctx: &mut dyn Context) {
+        self.protocol.cancel_request(ctx);
+        self.send_reply(Reply::CancelRequest);
+    }
+
+/*****************************************************************************/
+/*                                                                           */
+/* readiness file descriptors                                                */
+/*                                                                           */
+/*****************************************************************************/
+
+    #[cfg(unix)]
+    pub fn recv_fd(&mut self, _: &mut dyn Context) {
+        let ready = self.protocol.is_recv_ready();
+        let reply = open_ready_fd(&mut self.recv_fd, ready);
+
+        self.send_reply(reply);
+    }
+
+    #[cfg(unix)]
+    pub fn send_fd(&mut self, _: &mut dyn Context) {
+        let ready = self.protocol.is_send_ready();
+        let reply = open_ready_fd(&mut self.send_fd, ready);
+
+        self.send_reply(reply);
+    }
+
+    #[cfg(unix)]
+    pub fn on_recv_readiness_changed(&mut self, ready: bool) {
+        if let Some(ref mut fd) = self.recv_fd {
+            fd.set_ready(ready);
+        }
+    }
+
+    #[cfg(unix)]
+    pub fn on_send_readiness_changed(&mut self, ready: bool) {
+        if let Some(ref mut fd) = self.send_fd {
+            fd.set_ready(ready);
+        }
     }
 
 /*****************************************************************************/
@@ -208,6 +979,10 @@ impl Socket {
     pub fn bind(&mut self, ctx: &mut dyn Context, url: String) {
         let tmpl = self.create_endpoint_tmpl(url);
 
+        self.bind_tmpl(ctx, tmpl)
+    }
+
+    fn bind_tmpl(&mut self, ctx: &mut dyn Context, tmpl: EndpointTmpl) {
         match ctx.bind(self.id, &tmpl) {
             Ok(id) => self.on_bind_success(ctx, id, tmpl.spec),
             Err(e) => self.on_bind_error(e)
@@ -215,6 +990,8 @@ impl Socket {
     }
 
     fn on_bind_success(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
+        self.record_endpoint_config(eid, EndpointRole::Bind, &spec);
+
         let acceptor = self.connect_acceptor(eid, spec);
 
         acceptor.open(ctx);
@@ -227,15 +1004,16 @@ impl Socket {
         self.send_reply(Reply::Err(err));
     }
 
-    fn schedule_rebind(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
-        let task = Schedulable::Rebind(eid, spec);
-        let delay = self.config.retry_ivl;
-        let _ = ctx.schedule(task, delay); 
-        // TODO maybe we should keep track of the scheduled reconnection
-        // In case the facade wants to close the ep somewhere between the error and the timeout
+    fn schedule_rebind(&mut self, ctx: &mut dyn Context, eid: EndpointId, mut spec: EndpointSpec) {
+        let delay = self.config.retry_delay(spec.attempts);
+
+        spec.attempts = spec.attempts.saturating_add(1);
+        self.schedule_retry(ctx, eid, Schedulable::Rebind(eid, spec), delay);
     }
 
     pub fn rebind(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
+        self.on_retry(eid);
+
         let pids = self.get_protocol_ids();
         let tmpl = EndpointTmpl {
             pids: pids,
@@ -244,18 +1022,44 @@ impl Socket {
 
         match ctx.rebind(self.id, eid, &tmpl) {
             Ok(_)  => self.on_rebind_success(ctx, eid, tmpl.spec),
-            Err(_) => self.on_rebind_error(ctx, eid, tmpl.spec)
+            Err(e) => self.on_rebind_error(ctx, eid, tmpl.spec, e)
         };
     }
 
-    fn on_rebind_success(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
+    fn on_rebind_success(&mut self, ctx: &mut dyn Context, eid: EndpointId, mut spec: EndpointSpec) {
+        spec.attempts = 0;
+
         let acceptor = Acceptor::from_spec(eid, spec);
 
         self.insert_acceptor(ctx, eid, acceptor)
     }
 
-    fn on_rebind_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec) {
-        self.schedule_rebind(ctx, eid, spec);
+    fn on_rebind_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, spec: EndpointSpec, err: io::Error) {
+        let gave_up = self.config.max_rebind_attempts.is_some_and(|max| spec.attempts >= max);
+        let category = ErrorCategory::of(&err);
+
+        if gave_up || category == ErrorCategory::PermissionDenied {
+            warn!("[{:?}] giving up rebind of ep {:?} after {} attempts: {}", ctx, eid, spec.attempts, err);
+            self.endpoint_configs.remove(&eid);
+            ctx.raise(Event::EndpointClosed(eid, err.raw_os_error(), category));
+        } else {
+            debug!("[{:?}] rebind of ep {:?} failed, will retry: {}", ctx, eid, err);
+            self.schedule_rebind(ctx, eid, spec);
+        }
+    }
+
+    pub fn rebind_acceptor(&mut self, ctx: &mut dyn Context, eid: EndpointId, new_url: String) {
+        if let Some(spec) = self.remove_acceptor(ctx, eid) {
+            let new_spec = EndpointSpec {
+                url: new_url,
+                desc: spec.desc,
+                attempts: 0
+            };
+
+            self.record_endpoint_config(eid, EndpointRole::Bind, &new_spec);
+
+            self.schedule_rebind(ctx, eid, new_spec);
+        }
     }
 
 /*****************************************************************************/
@@ -264,25 +1068,122 @@ impl Socket {
 /*                                                                           */
 /*****************************************************************************/
 
-    pub fn on_pipe_opened(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
-        if let Some(pipe) = self.pipes.remove(&eid) {
+    pub fn on_pipe_opened(&mut self, ctx: &mut dyn Context, eid: EndpointId, peer_proto_id: u16, features: Features) {
+        if let Some(mut pipe) = self.pipes.remove(&eid) {
+            if pipe.is_connected() {
+                self.stats.connect_count += 1;
+            } else {
+                self.stats.accept_count += 1;
+            }
+            pipe.reset_attempts();
+            pipe.set_features(features);
             self.protocol.add_pipe(ctx, eid, pipe);
+            self.opened.insert(eid);
+            self.peer_count.fetch_add(1, Ordering::SeqCst);
+            self.set_peer_protocol(eid, Some(peer_proto_id));
+            self.replay_early_readiness(ctx, eid);
+            self.on_connect_handshake(ctx, eid);
+        }
+    }
+
+    fn replay_early_readiness(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some(readiness) = self.early_readiness.remove(&eid) {
+            if readiness.send {
+                self.on_send_ready(ctx, eid, true);
+            }
+            if readiness.recv {
+                self.on_recv_ready(ctx, eid, true);
+            }
         }
     }
 
     pub fn on_pipe_accepted(&mut self, ctx: &mut dyn Context, aid: EndpointId, eid: EndpointId) {
         let pipe = self.accept_pipe(aid, eid);
 
+        if self.config.max_accepted_pipes.is_some_and(|max| self.accepted.len() >= max) {
+            debug!("[{:?}] ep {:?} rejected, too many accepted pipes", ctx, eid);
+            self.stats.busy_rejected += 1;
+            return pipe.reject(ctx);
+        }
+
+        self.accepted.insert(eid);
         self.insert_pipe(ctx, eid, pipe);
     }
 
     pub fn close_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.endpoint_configs.remove(&eid);
+        self.paused_reconnects.remove(&eid);
+        self.cancel_retry(ctx, eid);
+
         let _ = self.remove_pipe(ctx, eid);
     }
 
-    pub fn on_pipe_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, _: io::Error) {
-        if let Some(spec) = self.remove_pipe(ctx, eid) {
-            self.schedule_reconnect(ctx, eid, spec);
+    /// Fails with `NotConnected` when the endpoint has no opened pipe to drain,
+    /// the endpoint being closed right away since a pending reconnection has nothing to flush.
+    pub fn close_pipe_graceful(&mut self, ctx: &mut dyn Context, eid: EndpointId, timeout: Duration) -> io::Result<()> {
+        if self.pipes.contains_key(&eid) {
+            self.close_pipe(ctx, eid);
+            return Ok(());
+        }
+        if !self.opened.contains(&eid) {
+            self.close_pipe(ctx, eid);
+            return Err(not_connected_io_error("no pipe for this endpoint"));
+        }
+        if self.draining.contains_key(&eid) {
+            return Ok(());
+        }
+
+        match ctx.schedule(Schedulable::DrainTimeout(eid), timeout) {
+            Ok(scheduled) => {
+                self.draining.insert(eid, scheduled);
+                self.protocol.on_send_not_ready(ctx, eid);
+                self.protocol.on_recv_not_ready(ctx, eid);
+                ctx.flush(eid);
+            },
+            Err(_) => self.close_pipe(ctx, eid)
+        }
+
+        Ok(())
+    }
+
+    pub fn on_pipe_dropped(&mut self, ctx: &mut dyn Context, eid: EndpointId, count: usize) {
+        debug!("[{:?}] ep {:?} dropped {} oversized messages", ctx, eid, count);
+        self.dropped_count += count;
+    }
+
+    pub fn on_pipe_flushed(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if self.linger.is_some() {
+            return self.on_linger_pipe_done(ctx, eid);
+        }
+        if let Some(scheduled) = self.draining.remove(&eid) {
+            ctx.cancel(scheduled);
+            self.close_pipe(ctx, eid);
+            self.check_shutdown_done(ctx);
+        }
+    }
+
+    pub fn on_drain_timeout(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if self.draining.remove(&eid).is_some() {
+            self.close_pipe(ctx, eid);
+            self.check_shutdown_done(ctx);
+        }
+    }
+
+    fn is_draining(&self, eid: EndpointId) -> bool {
+        self.draining.contains_key(&eid)
+    }
+
+    pub fn on_pipe_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, err: io::Error) {
+        if let Some(Linger { flushing: Some(ref mut eids), .. }) = self.linger {
+            eids.remove(&eid);
+        }
+        if let Some(scheduled) = self.draining.remove(&eid) {
+            ctx.cancel(scheduled);
+            self.close_pipe(ctx, eid);
+            return self.check_shutdown_done(ctx);
+        }
+        if let Some(spec) = self.remove_pipe(ctx, eid) {
+            self.on_connection_lost(ctx, eid, spec, err);
         }
     }
 
@@ -293,10 +1194,20 @@ impl Socket {
     }
 
     fn remove_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> Option<EndpointSpec> {
+        self.accepted.remove(&eid);
+        self.early_readiness.remove(&eid);
+        self.clear_endpoint_info(eid);
+        if let Some(scheduled) = self.recv_rates.remove(&eid).and_then(|rate| rate.paused) {
+            ctx.cancel(scheduled);
+        }
+
         if let Some(pipe) = self.pipes.remove(&eid) {
             return pipe.close(ctx)
         }
         if let Some(pipe) = self.protocol.remove_pipe(ctx, eid) {
+            self.opened.remove(&eid);
+            self.peer_count.fetch_sub(1, Ordering::SeqCst);
+            self.set_peer_protocol(eid, None);
             return pipe.close(ctx)
         }
         None
@@ -307,16 +1218,25 @@ impl Socket {
     }
 
     fn accept_pipe(&self, aid: EndpointId, eid: EndpointId) -> Pipe {
-        let (send_prio, recv_prio) = if let Some(acceptor) = self.acceptors.get(&aid) {
-            (acceptor.get_send_priority(), acceptor.get_recv_priority())
+        let (send_prio, recv_prio, weight) = if let Some(acceptor) = self.acceptors.get(&aid) {
+            (acceptor.get_send_priority(), acceptor.get_recv_priority(), acceptor.get_recv_weight())
         } else {
-            (self.config.send_priority, self.config.recv_priority)
+            (self.config.send_priority, self.config.recv_priority, self.config.recv_weight)
         };
         let desc = EndpointDesc {
             send_priority: send_prio,
             recv_priority: recv_prio,
+            recv_weight: weight,
             tcp_no_delay: self.config.tcp_no_delay,
-            recv_max_size: self.config.recv_max_size
+            tcp_keepalive: self.config.tcp_keepalive,
+            reuse_addr: self.config.reuse_addr,
+            connect_source_addr: self.config.connect_source_addr,
+            ipc_permissions: self.config.ipc_permissions,
+            recv_max_size: self.config.recv_max_size,
+            send_chunk_size: self.config.send_chunk_size,
+            oversize_policy: self.config.oversize_policy,
+            protocol_versions: self.protocol_versions(),
+            recv_pool: self.recv_pool.clone()
         };
 
         Pipe::new_accepted(eid, desc)
@@ -328,13 +1248,20 @@ impl Socket {
 /*                                                                           */
 /*****************************************************************************/
 
-    pub fn on_acceptor_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, _: io::Error) {
+    pub fn on_acceptor_error(&mut self, ctx: &mut dyn Context, eid: EndpointId, err: io::Error) {
         if let Some(spec) = self.remove_acceptor(ctx, eid) {
-            self.schedule_rebind(ctx, eid, spec);
+            if ErrorCategory::of(&err) == ErrorCategory::PermissionDenied {
+                self.on_rebind_error(ctx, eid, spec, err);
+            } else {
+                self.schedule_rebind(ctx, eid, spec);
+            }
         }
     }
 
     pub fn close_acceptor(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.endpoint_configs.remove(&eid);
+        self.cancel_retry(ctx, eid);
+
         let _ = self.remove_acceptor(ctx, eid);
     }
 
@@ -345,6 +1272,7 @@ impl Socket {
     }
 
     fn remove_acceptor(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> Option<EndpointSpec> {
+        self.clear_endpoint_info(eid);
         self.acceptors.remove(&eid).map_or(None, |acceptor| acceptor.close(ctx))
     }
 
@@ -360,22 +1288,62 @@ impl Socket {
 
     pub fn send(&mut self, ctx: &mut dyn Context, msg: Message) {
         #[cfg(debug_assertions)] debug!("[{:?}] send", ctx);
-        if let Some(delay) = self.get_send_timeout() {
-            let task = Schedulable::SendTimeout;
+        if self.is_sending() {
+            self.send_queue.push_back((msg, QueuedSend::Blocking(self.requestor)));
+        } else {
+            self.send_requestor = Some(self.requestor);
 
-            match ctx.schedule(task, delay) {
-                Ok(timeout) => self.protocol.send(ctx, msg, Some(timeout)),
-                Err(e) => self.send_reply(Reply::Err(e))
+            if let Err(e) = self.send_to_protocol(ctx, msg) {
+                self.send_requestor = None;
+                self.send_reply(Reply::Err(e));
             }
+        }
+    }
+
+    /// Sends a survey, or refuses it before sending anything when the protocol does not broadcast surveys,
+    /// since only the `Surveyor` replies with the peers the message reached.
+    pub fn send_survey(&mut self, ctx: &mut dyn Context, msg: Message) {
+        let capabilities = self.protocol.capabilities();
+
+        if capabilities.has_survey && capabilities.is_broadcast {
+            self.send(ctx, msg);
+        } else {
+            self.send_reply(Reply::Err(invalid_input_io_error("surveys can only be sent by a Surveyor socket")));
+        }
+    }
+
+    /// Tells whether a send is in progress, be it started by the queue or directly by a handle.
+    fn is_sending(&self) -> bool {
+        self.send_in_flight.is_some() || self.send_requestor.is_some()
+    }
+
+    fn send_to_protocol(&mut self, ctx: &mut dyn Context, msg: Message) -> io::Result<()> {
+        if self.config.send_requires_peer && self.peer_count.load(Ordering::SeqCst) == 0 {
+            return Err(not_connected_io_error("no connected peer"));
+        }
+
+        self.track_send_size(&msg);
+        if let Some(delay) = self.get_send_timeout() {
+            let timeout = ctx.schedule(Schedulable::SendTimeout, delay)?;
+
+            self.send_span = Some(OpSpan::send(self.id));
+            self.protocol.send(ctx, msg, Some(timeout));
         } else {
+            self.send_span = Some(OpSpan::send(self.id));
             self.protocol.send(ctx, msg, None);
         }
+        self.process_protocol_replies(ctx, true);
+        Ok(())
     }
 
     pub fn try_send(&mut self, ctx: &mut dyn Context, msg: Message) {
         #[cfg(debug_assertions)] debug!("[{:?}] try_send", ctx);
-        if self.protocol.is_send_ready() {
+        if !self.is_sending() && self.protocol.is_send_ready() {
+            self.send_requestor = Some(self.requestor);
+            self.track_send_size(&msg);
+            self.send_span = Some(OpSpan::send(self.id));
             self.protocol.send(ctx, msg, None);
+            self.process_protocol_replies(ctx, true);
         } else {
             let err = would_block_io_error("socket is not send ready");
 
@@ -383,14 +1351,194 @@ impl Socket {
         }
     }
 
+    pub fn send_async(&mut self, ctx: &mut dyn Context, msg: Message) {
+        #[cfg(debug_assertions)] debug!("[{:?}] send_async", ctx);
+        if !self.is_sending() {
+            self.send_reply(Reply::Send);
+            self.start_queued_send(ctx, msg, QueuedSend::Async);
+        } else if self.send_queue.len() < self.config.send_queue_size {
+            self.send_queue.push_back((msg, QueuedSend::Async));
+            self.send_reply(Reply::Send);
+        } else {
+            self.send_blocked = Some((msg, self.requestor));
+        }
+    }
+
+    fn start_queued_send(&mut self, ctx: &mut dyn Context, msg: Message, kind: QueuedSend) {
+        self.send_in_flight = Some(kind);
+
+        if let Err(e) = self.send_to_protocol(ctx, msg) {
+            self.on_queued_send_done(ctx, kind, Reply::Err(e));
+        }
+    }
+
+    fn on_queued_send_done(&mut self, ctx: &mut dyn Context, kind: QueuedSend, reply: Reply) {
+        self.send_in_flight = None;
+
+        if let QueuedSend::Blocking(handle) = kind {
+            self.reply_to(handle, reply);
+        } else if let Reply::Err(e) = reply {
+            debug!("[{:?}] async send failed: {}", ctx, e);
+        }
+
+        if let Some((msg, handle)) = self.send_blocked.take() {
+            self.send_queue.push_back((msg, QueuedSend::Async));
+            self.reply_to(handle, Reply::Send);
+        }
+
+        self.start_next_queued_send(ctx);
+    }
+
+    fn start_next_queued_send(&mut self, ctx: &mut dyn Context) {
+        if let Some((msg, next)) = self.send_queue.pop_front() {
+            self.start_queued_send(ctx, msg, next);
+        }
+    }
+
+    /// Forwards the replies sent by the protocol to the handle that started the operation,
+    /// except the completion of the sends started by the queue on behalf of `send_async`.
+    /// Errors are only considered as a send completion when raised by a send related operation,
+    /// since a pending recv may fail while an async send is in progress.
+    /// The replies are drained before being handled, so that the send started by a completion
+    /// only ever sees its own replies, and not the ones left by the operation that completed it.
+    pub fn process_protocol_replies(&mut self, ctx: &mut dyn Context, send_side: bool) {
+        let replies: Vec<Reply> = self.protocol_replies.try_iter().collect();
+
+        for reply in replies {
+            let is_send_done = match reply {
+                Reply::Send | Reply::SendReached(_) => true,
+                Reply::Err(_) => send_side,
+                _ => false
+            };
+
+            self.close_span(&reply, is_send_done);
+            if is_send_done {
+                self.record_send_size(&reply);
+            }
+
+            match self.send_in_flight {
+                Some(kind) if is_send_done => self.on_queued_send_done(ctx, kind, reply),
+                _ if is_send_done => self.on_direct_send_done(ctx, reply),
+                _ => {
+                    let reply = match self.map_recv(reply) {
+                        Some(reply) => reply,
+                        None => {
+                            self.restart_recv(ctx);
+                            continue;
+                        }
+                    };
+                    let handle = self.recv_requestor.take().unwrap_or(self.requestor);
+
+                    self.reply_to(handle, reply);
+                }
+            }
+        }
+
+        if self.shutdown_wait.is_some() {
+            self.check_shutdown_sends_done(ctx);
+        }
+        if self.linger.is_some() {
+            self.check_linger_done(ctx);
+        }
+    }
+
+    /// Applies the recv map to a received message, returning `None` when the map failed on it.
+    fn map_recv(&mut self, reply: Reply) -> Option<Reply> {
+        let map = match self.recv_map {
+            Some(ref mut map) => map,
+            None => return Some(reply)
+        };
+
+        match reply {
+            Reply::Recv(msg, eid) => match map(msg) {
+                Ok(msg) => Some(Reply::Recv(msg, eid)),
+                Err(e) => {
+                    debug!("[{:?}] recv map dropped a message from ep {:?}: {}", self.id, eid, e);
+                    self.dropped_count += 1;
+                    None
+                }
+            },
+            other => Some(other)
+        }
+    }
+
+    /// Waits for another message once a duplicate was dropped, restarting the recv timeout.
+    fn restart_recv(&mut self, ctx: &mut dyn Context) {
+        if !self.recv_blocking && !self.protocol.is_recv_ready() {
+            let handle = self.recv_requestor.take().unwrap_or(self.requestor);
+
+            return self.reply_to(handle, Reply::Err(would_block_io_error("socket is not recv ready")));
+        }
+
+        let timeout = match self.get_recv_timeout().filter(|_| self.recv_blocking) {
+            Some(delay) => match ctx.schedule(Schedulable::RecvTimeout, delay) {
+                Ok(timeout) => Some(timeout),
+                Err(e) => {
+                    let handle = self.recv_requestor.take().unwrap_or(self.requestor);
+
+                    return self.reply_to(handle, Reply::Err(e));
+                }
+            },
+            None => None
+        };
+
+        self.recv_span = Some(OpSpan::recv(self.id));
+        self.protocol.recv(ctx, timeout);
+    }
+
+    fn on_direct_send_done(&mut self, ctx: &mut dyn Context, reply: Reply) {
+        let handle = self.send_requestor.take().unwrap_or(self.requestor);
+
+        self.reply_to(handle, reply);
+        if self.send_in_flight.is_none() {
+            self.start_next_queued_send(ctx);
+        }
+    }
+
+    /// Ends the span of the operation completed by the reply, if any.
+    fn close_span(&mut self, reply: &Reply, is_send_done: bool) {
+        if is_send_done {
+            self.send_span = None;
+        } else if let Reply::Recv(..) = *reply {
+            self.recv_span = None;
+        } else if let Reply::Err(_) = *reply {
+            if self.recv_span.take().is_none() {
+                self.send_span = None;
+            }
+        }
+    }
+
+    fn track_send_size(&mut self, msg: &Message) {
+        self.send_size = Some(msg.len());
+    }
+
+    fn record_send_size(&mut self, reply: &Reply) {
+        match (self.send_size.take(), reply) {
+            (Some(size), &Reply::Send) |
+            (Some(size), &Reply::SendReached(_)) => {
+                self.stats.messages_sent += 1;
+                self.stats.bytes_sent += size as u64;
+                if self.config.size_histogram {
+                    self.size_histogram.add(size);
+                }
+            },
+            _ => {}
+        }
+    }
+
     pub fn on_send_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
         #[cfg(debug_assertions)] debug!("[{:?}] send ack from ep {:?}", ctx, eid);
+        if let Some(ref span) = self.send_span {
+            span.record_endpoint(eid);
+        }
         self.protocol.on_send_ack(ctx, eid);
+        self.process_protocol_replies(ctx, true);
     }
 
     pub fn on_send_timeout(&mut self, ctx: &mut dyn Context) {
         #[cfg(debug_assertions)] debug!("[{:?}] send timeout", ctx);
         self.protocol.on_send_timeout(ctx);
+        self.process_protocol_replies(ctx, true);
     }
 
     fn get_send_timeout(&self) -> Option<Duration> {
@@ -399,6 +1547,13 @@ impl Socket {
 
     pub fn on_send_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId, ready: bool) {
         #[cfg(debug_assertions)] debug!("[{:?}] ep {:?} send ready: {} ", ctx, eid, ready);
+        if self.is_draining(eid) {
+            return;
+        }
+        if self.pipes.contains_key(&eid) {
+            self.early_readiness.entry(eid).or_default().send = ready;
+            return;
+        }
         if ready {
             self.protocol.on_send_ready(ctx, eid)
         } else {
@@ -414,21 +1569,54 @@ impl Socket {
 
     pub fn recv(&mut self, ctx: &mut dyn Context) {
         #[cfg(debug_assertions)] debug!("[{:?}] recv", ctx);
+        if !self.acquire_recv() {
+            return;
+        }
+        self.recv_blocking = true;
         if let Some(delay) = self.get_recv_timeout() {
             let task = Schedulable::RecvTimeout;
 
             match ctx.schedule(task, delay) {
-                Ok(timeout) => self.protocol.recv(ctx, Some(timeout)),
-                Err(e) => self.send_reply(Reply::Err(e))
+                Ok(timeout) => {
+                    self.recv_span = Some(OpSpan::recv(self.id));
+                    self.protocol.recv(ctx, Some(timeout))
+                },
+                Err(e) => {
+                    self.recv_requestor = None;
+                    self.send_reply(Reply::Err(e))
+                }
             }
         } else {
+            self.recv_span = Some(OpSpan::recv(self.id));
             self.protocol.recv(ctx, None);
         }
     }
 
+    /// Records the requestor as the handle the next received message is for.
+    /// Only one handle at a time can wait for a message, the others being told to retry later.
+    fn acquire_recv(&mut self) -> bool {
+        match self.recv_requestor {
+            Some(handle) if handle != self.requestor => {
+                let err = would_block_io_error("a recv is already in progress on a clone of the socket");
+
+                self.send_reply(Reply::Err(err));
+                false
+            },
+            _ => {
+                self.recv_requestor = Some(self.requestor);
+                true
+            }
+        }
+    }
+
     pub fn try_recv(&mut self, ctx: &mut dyn Context) {
         #[cfg(debug_assertions)] debug!("[{:?}] try_recv", ctx);
         if self.protocol.is_recv_ready() {
+            if !self.acquire_recv() {
+                return;
+            }
+            self.recv_blocking = false;
+            self.recv_span = Some(OpSpan::recv(self.id));
             self.protocol.recv(ctx, None);
         } else {
             let err = would_block_io_error("socket is not recv ready");
@@ -439,7 +1627,107 @@ impl Socket {
 
     pub fn on_recv_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId, msg: Message) {
         #[cfg(debug_assertions)] debug!("[{:?}] recv ack from ep {:?}", ctx, eid);
-        self.protocol.on_recv_ack(ctx, eid, msg);
+        if let Some(ref span) = self.recv_span {
+            span.record_endpoint(eid);
+        }
+        self.stats.messages_recv += 1;
+        self.stats.bytes_recv += msg.len() as u64;
+        if self.config.size_histogram {
+            self.size_histogram.add(msg.len());
+        }
+        if self.is_expired(&msg) {
+            debug!("[{:?}] expired message dropped from ep {:?}", ctx, eid);
+            self.expired_count += 1;
+            return ctx.recv(eid);
+        }
+        if self.is_duplicate(&msg) {
+            debug!("[{:?}] duplicate message dropped from ep {:?}", ctx, eid);
+            self.duplicate_count += 1;
+            return ctx.recv(eid);
+        }
+        if let Some(ref mut reducer) = self.recv_reducer {
+            reducer(&msg);
+        }
+        let paused = self.config.recv_rate_limit > 0 && self.count_recv(ctx, eid);
+        if self.recv_forward {
+            self.protocol.on_recv_ack(ctx, eid, msg);
+            if paused {
+                self.protocol.on_recv_not_ready(ctx, eid);
+            }
+        }
+    }
+
+    /// Whether deadlines are enforced and the deadline of the received message has already passed.
+    /// Dropping such a message before the protocol sees it leaves the recv operation of the protocol
+    /// pending on the pipe, which is then asked for the next message.
+    fn is_expired(&self, msg: &Message) -> bool {
+        self.config.enforce_deadlines && msg.get_deadline().is_some_and(|deadline| deadline < SystemTime::now())
+    }
+
+    /// Whether the dedup window is enabled and already holds the id of the received message,
+    /// recording the id otherwise. Messages without an id are never duplicates.
+    fn is_duplicate(&mut self, msg: &Message) -> bool {
+        let capacity = self.config.dedup_window;
+
+        match msg.get_dedup_id() {
+            Some(id) if capacity > 0 => !self.dedup_window.insert(id, capacity),
+            _ => false
+        }
+    }
+
+    /// Counts a message received from the pipe in the current window of the recv rate limit,
+    /// and pauses the pipe until the end of the window once the limit is reached.
+    /// Returns whether the pipe has just been paused.
+    fn count_recv(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> bool {
+        let limit = self.config.recv_rate_limit;
+        let window = Duration::from_secs(1);
+        let now = ctx.now();
+        let rate = self.recv_rates.entry(eid).or_insert_with(|| RecvRate {
+            window_start: now,
+            count: 0,
+            paused: None,
+            ready: false
+        });
+
+        if rate.paused.is_some() {
+            return false;
+        }
+        if now.duration_since(rate.window_start) >= window {
+            rate.window_start = now;
+            rate.count = 0;
+        }
+
+        rate.count += 1;
+        if rate.count < limit {
+            return false;
+        }
+
+        let remaining = window - now.duration_since(rate.window_start);
+        match ctx.schedule(Schedulable::RecvRateWindow(eid), remaining) {
+            Ok(scheduled) => {
+                rate.paused = Some(scheduled);
+                rate.ready = false;
+                true
+            },
+            Err(_) => false
+        }
+    }
+
+    pub fn on_recv_rate_window(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        let now = ctx.now();
+        let ready = match self.recv_rates.get_mut(&eid) {
+            Some(rate) => {
+                rate.window_start = now;
+                rate.count = 0;
+                rate.paused = None;
+                rate.ready
+            },
+            None => return
+        };
+
+        if ready {
+            self.on_recv_ready(ctx, eid, true);
+        }
     }
 
     pub fn on_recv_timeout(&mut self, ctx: &mut dyn Context) {
@@ -453,6 +1741,26 @@ impl Socket {
 
     pub fn on_recv_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId, ready: bool) {
         #[cfg(debug_assertions)] debug!("[{:?}] ep {:?} recv ready: {}", ctx, eid, ready);
+        if self.is_draining(eid) {
+            return;
+        }
+        if self.pipes.contains_key(&eid) {
+            self.early_readiness.entry(eid).or_default().recv = ready;
+            return;
+        }
+        if let Some(rate) = self.recv_rates.get_mut(&eid) {
+            if rate.paused.is_some() {
+                rate.ready = ready;
+                return;
+            }
+        }
+        if !self.recv_forward {
+            // messages are consumed by the reducer only, so the protocol is bypassed
+            if ready {
+                ctx.recv(eid);
+            }
+            return;
+        }
         if ready {
             self.protocol.on_recv_ready(ctx, eid)
         } else {
@@ -466,14 +1774,101 @@ impl Socket {
 /*                                                                           */
 /*****************************************************************************/
 
-    pub fn set_option(&mut self, _: &mut dyn Context, opt: ConfigOption) {
+    pub fn set_option(&mut self, ctx: &mut dyn Context, opt: ConfigOption) {
+        let name = opt.name();
         let res = if opt.is_generic() {
             self.config.set(opt)
         } else {
-            self.protocol.set_option(opt)
+            self.set_protocol_option(opt).map(|()| self.protocol.on_option_set(ctx))
         };
         let reply = match res {
             Ok(()) => Reply::SetOption,
+            Err(e) => Reply::Err(rejected_option_io_error(name, e))
+        };
+
+        self.send_reply(reply);
+    }
+
+    /// Remembers the protocol options that were successfully applied, so they can be replayed on a sibling.
+    fn set_protocol_option(&mut self, opt: ConfigOption) -> io::Result<()> {
+        self.protocol.set_option(opt.clone())?;
+        self.remember_protocol_option(opt);
+        Ok(())
+    }
+
+    /// An option replaces the one of the same kind applied before, and the subscription changes
+    /// are folded into a single list of topics, so the remembered options do not grow with each call.
+    fn remember_protocol_option(&mut self, opt: ConfigOption) {
+        let remembered = match opt {
+            ConfigOption::Subscribe(topic) => self.fold_subscriptions(vec![topic.into_bytes()], Vec::new()),
+            ConfigOption::Unsubscribe(topic) => self.fold_subscriptions(Vec::new(), vec![topic.into_bytes()]),
+            ConfigOption::SubscribeMany(topics) => self.fold_subscriptions(topics, Vec::new()),
+            ConfigOption::UnsubscribeMany(topics) => self.fold_subscriptions(Vec::new(), topics),
+            other => other
+        };
+        let kind = mem::discriminant(&remembered);
+
+        self.protocol_options.retain(|opt| mem::discriminant(opt) != kind);
+        self.protocol_options.push(remembered);
+    }
+
+    fn fold_subscriptions(&self, added: Vec<Vec<u8>>, removed: Vec<Vec<u8>>) -> ConfigOption {
+        let mut topics = self.protocol_options.iter().filter_map(|opt| match *opt {
+            ConfigOption::Subscriptions(ref topics) => Some(topics.clone()),
+            _ => None
+        }).next().unwrap_or_default();
+
+        topics.retain(|topic| !removed.contains(topic));
+        for topic in added {
+            if !topics.contains(&topic) {
+                topics.push(topic);
+            }
+        }
+
+        ConfigOption::Subscriptions(topics)
+    }
+
+    /// Returns the generic config and the protocol options a sibling of this socket should be created with.
+    pub fn sibling_config(&self) -> (Config, Vec<ConfigOption>) {
+        (self.config.clone(), self.protocol_options.clone())
+    }
+
+    pub fn apply_sibling_config(&mut self, sibling_config: Config, options: Vec<ConfigOption>) {
+        self.config = sibling_config;
+        for opt in options {
+            let _ = self.set_protocol_option(opt);
+        }
+    }
+
+    pub fn on_sibling_created(&mut self, result: io::Result<Reply>) {
+        match result {
+            Ok(reply) => self.send_reply(reply),
+            Err(e) => self.send_reply(Reply::Err(e))
+        }
+    }
+
+    pub fn set_recv_reducer(&mut self, _: &mut dyn Context, reducer: RecvReducer, forward: bool) {
+        self.recv_reducer = Some(reducer);
+        self.recv_forward = forward;
+
+        self.send_reply(Reply::SetRecvReducer);
+    }
+
+    pub fn set_recv_map(&mut self, _: &mut dyn Context, map: RecvMap) {
+        self.recv_map = Some(map);
+
+        self.send_reply(Reply::SetRecvMap);
+    }
+
+    pub fn set_recv_pool(&mut self, _: &mut dyn Context, pool: RecvPool) {
+        self.recv_pool = Some(pool);
+
+        self.send_reply(Reply::SetRecvPool);
+    }
+
+    pub fn set_subscription_validator(&mut self, _: &mut dyn Context, validator: SubscriptionValidator) {
+        let reply = match self.protocol.set_subscription_validator(validator) {
+            Ok(()) => Reply::SetSubscriptionValidator,
             Err(e) => Reply::Err(e)
         };
 
@@ -488,85 +1883,363 @@ impl Socket {
         self.protocol.on_device_plugged(ctx)
     }
 
-    pub fn close(&mut self, ctx: &mut dyn Context) {
-        for (_, pipe) in self.pipes.drain() {
-            pipe.close(ctx);
-        }
-        for (_, acceptor) in self.acceptors.drain() {
-            acceptor.close(ctx);
-        }
+    pub fn plug_device(&mut self, ctx: &mut dyn Context) {
+        self.on_device_plugged(ctx);
+        self.send_reply(Reply::PlugDevice);
+    }
 
-        self.protocol.close(ctx);
+    pub fn pending_pipes(&mut self, ctx: &mut dyn Context) {
+        let (pending_send, pending_recv) = ctx.pending_pipes(self.id);
 
-        ctx.raise(Event::Closed);
+        self.send_reply(Reply::PendingPipes(pending_send, pending_recv));
     }
-}
 
-/*****************************************************************************/
-/*                                                                           */
-/* tests                                                                     */
-/*                                                                           */
-/*****************************************************************************/
+    /// Replies the credentials of the peer process of the pipe to the handle that asked for them.
+    pub fn peer_cred(&mut self, ctx: &mut dyn Context, handle: HandleId, eid: EndpointId) {
+        let reply = match ctx.peer_cred(eid) {
+            Ok(cred) => Reply::PeerCred(cred),
+            Err(e) => Reply::Err(e)
+        };
 
-#[cfg(test)]
-mod tests {
-    use std::fmt;
-    use std::rc::Rc;
-    use std::sync::mpsc;
-    use std::io;
-    use std::time::Duration;
+        self.reply_to(handle, reply);
+    }
 
-    use super::*;
-    use core::network;
-    use core::context::*;
-    use core::{SocketId, EndpointId, Message, EndpointTmpl, Scheduled};
-    use core::endpoint::Pipe;
+    pub fn size_histogram(&mut self, _: &mut dyn Context) {
+        let histogram = self.size_histogram.clone();
 
-    struct TestProto;
+        self.send_reply(Reply::SizeHistogram(histogram));
+    }
 
-    impl Protocol for TestProto {
-        fn id(&self) -> u16 {0}
-        fn peer_id(&self) -> u16 {0}
-        fn add_pipe(&mut self, _: &mut dyn Context, _: EndpointId, _: Pipe) {}
-        fn remove_pipe(&mut self, _: &mut dyn Context, _: EndpointId) -> Option<Pipe> {None}
-        fn send(&mut self, _: &mut dyn Context, _: Message, _: Option<Scheduled>) {}
-        fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {}
-        fn on_send_timeout(&mut self, _: &mut dyn Context) {}
-        fn on_send_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
-        fn on_send_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
-        fn recv(&mut self, _: &mut dyn Context, _: Option<Scheduled>) {}
-        fn on_recv_ack(&mut self, _: &mut dyn Context, _: EndpointId, _: Message) {}
-        fn on_recv_timeout(&mut self, _: &mut dyn Context) {}
-        fn on_recv_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
-        fn on_recv_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
-        fn is_send_ready(&self) -> bool { false }
-        fn is_recv_ready(&self) -> bool { false }
-        fn close(&mut self, _: &mut dyn Context) {}
+    pub fn capabilities(&mut self, _: &mut dyn Context) {
+        let capabilities = self.protocol.capabilities();
+
+        self.send_reply(Reply::Capabilities(capabilities));
     }
 
-    struct FailingNetwork;
+    pub fn stats(&mut self, _: &mut dyn Context) {
+        let mut stats = self.stats;
 
-    impl network::Network for FailingNetwork {
-        fn connect(&mut self, _: SocketId, _: &EndpointTmpl) -> io::Result<EndpointId> {
-            Err(other_io_error("FailingNetwork can only fail"))
+        stats.current_connections = self.peer_count.load(Ordering::SeqCst);
+        stats.pub_dropped = self.protocol.dropped_count() as u64;
+        self.send_reply(Reply::Stats(stats));
+    }
+
+    /// Closes the socket in an orderly way: the acceptors are closed first so no new connection can arrive,
+    /// then the sends queued by the socket are given up to `timeout` to complete,
+    /// then the pipes are drained, each of them being given up to `timeout` to flush its pending messages,
+    /// and finally the socket is torn down.
+    pub fn shutdown(&mut self, ctx: &mut dyn Context, timeout: Duration) {
+        self.shutdown_requestor = Some(self.requestor);
+
+        for (_, acceptor) in self.acceptors.drain() {
+            acceptor.close(ctx);
         }
-        fn reconnect(&mut self, _: SocketId, _: EndpointId, _: &EndpointTmpl) -> io::Result<()> {
-            Err(other_io_error("FailingNetwork can only fail"))
+        for (_, scheduled) in self.retrying.drain() {
+            ctx.cancel(scheduled);
         }
-        fn bind(&mut self, _: SocketId, _: &EndpointTmpl) -> io::Result<EndpointId> {
-            Err(other_io_error("FailingNetwork can only fail"))
+        self.retry_order.clear();
+
+        if !self.is_sending() && self.send_queue.is_empty() {
+            return self.drain_for_shutdown(ctx, timeout);
         }
-        fn rebind(&mut self, _: SocketId, _: EndpointId, _: &EndpointTmpl) -> io::Result<()> {
-            Err(other_io_error("FailingNetwork can only fail"))
+
+        match ctx.schedule(Schedulable::ShutdownTimeout, timeout) {
+            Ok(scheduled) => self.shutdown_wait = Some(ShutdownWait {
+                drain_timeout: timeout,
+                deadline: scheduled
+            }),
+            Err(_) => self.drain_for_shutdown(ctx, timeout)
         }
-        fn open(&mut self, _: EndpointId, _: bool) {
+    }
+
+    fn check_shutdown_sends_done(&mut self, ctx: &mut dyn Context) {
+        if self.is_sending() || !self.send_queue.is_empty() {
+            return;
         }
-        fn close(&mut self, _: EndpointId, _: bool) {
+
+        if let Some(wait) = self.shutdown_wait.take() {
+            ctx.cancel(wait.deadline);
+            self.drain_for_shutdown(ctx, wait.drain_timeout);
+        }
+    }
+
+    pub fn on_shutdown_timeout(&mut self, ctx: &mut dyn Context) {
+        if let Some(wait) = self.shutdown_wait.take() {
+            debug!("[{:?}] shutdown expired, discarding the pending sends", ctx);
+            self.drain_for_shutdown(ctx, wait.drain_timeout);
+        }
+    }
+
+    /// Drains the opened pipes, and closes the ones still in handshake since they have nothing to flush.
+    fn drain_for_shutdown(&mut self, ctx: &mut dyn Context, timeout: Duration) {
+        let mut eids: Vec<EndpointId> = self.pipes.keys().cloned().collect();
+
+        eids.extend(self.opened.iter().cloned());
+        for eid in eids {
+            let _ = self.close_pipe_graceful(ctx, eid, timeout);
+        }
+
+        self.check_shutdown_done(ctx);
+    }
+
+    fn check_shutdown_done(&mut self, ctx: &mut dyn Context) {
+        if !self.draining.is_empty() {
+            return;
+        }
+        if let Some(handle) = self.shutdown_requestor.take() {
+            self.reply_to(handle, Reply::Shutdown);
+            self.close(ctx);
+        }
+    }
+
+    /// Closes the socket once its last handle is closed, unless the linger option
+    /// asks for the pending sends to be completed first.
+    fn close_or_linger(&mut self, ctx: &mut dyn Context) {
+        match self.config.linger {
+            Some(linger) if linger == Duration::from_millis(0) => self.close(ctx),
+            linger => self.start_linger(ctx, linger)
+        }
+    }
+
+    /// Waits for the pending sends until the linger expires, the endpoints being kept alive meanwhile
+    /// so the messages can still reach a peer that connects or reconnects late.
+    /// The queued sends are completed first, then each pipe is asked to flush its own pending send.
+    fn start_linger(&mut self, ctx: &mut dyn Context, linger: Option<Duration>) {
+        let timeout = match linger {
+            Some(delay) => match ctx.schedule(Schedulable::LingerTimeout, delay) {
+                Ok(scheduled) => Some(scheduled),
+                Err(_) => return self.close(ctx)
+            },
+            None => None
+        };
+
+        self.linger = Some(Linger {
+            flushing: None,
+            deadline: timeout
+        });
+        self.check_linger_done(ctx);
+    }
+
+    fn check_linger_done(&mut self, ctx: &mut dyn Context) {
+        if self.is_sending() || !self.send_queue.is_empty() {
+            return;
+        }
+
+        let start_flush = match self.linger {
+            Some(ref linger) => linger.flushing.is_none(),
+            None => return
+        };
+
+        if start_flush {
+            let eids: HashSet<EndpointId> = self.opened.iter().cloned().collect();
+
+            for eid in &eids {
+                ctx.flush(*eid);
+            }
+            if let Some(ref mut linger) = self.linger {
+                linger.flushing = Some(eids);
+            }
+        }
+
+        let done = match self.linger {
+            Some(Linger { flushing: Some(ref eids), .. }) => eids.is_empty(),
+            _ => false
+        };
+
+        if done {
+            self.close(ctx);
+        }
+    }
+
+    fn on_linger_pipe_done(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some(Linger { flushing: Some(ref mut eids), .. }) = self.linger {
+            eids.remove(&eid);
+        }
+
+        self.check_linger_done(ctx);
+    }
+
+    pub fn on_linger_timeout(&mut self, ctx: &mut dyn Context) {
+        if self.linger.take().is_some() {
+            debug!("[{:?}] linger expired, discarding the pending sends", ctx);
+            self.close(ctx);
+        }
+    }
+
+    pub fn close(&mut self, ctx: &mut dyn Context) {
+        if let Some(scheduled) = self.linger.take().and_then(|linger| linger.deadline) {
+            ctx.cancel(scheduled);
+        }
+        if let Some(wait) = self.shutdown_wait.take() {
+            ctx.cancel(wait.deadline);
+        }
+        for (_, acceptor) in self.acceptors.drain() {
+            acceptor.close(ctx);
+        }
+        for (_, scheduled) in self.draining.drain() {
+            ctx.cancel(scheduled);
+        }
+        for (_, (scheduled, _)) in self.connecting.drain() {
+            ctx.cancel(scheduled);
+        }
+        for (_, scheduled) in self.retrying.drain() {
+            ctx.cancel(scheduled);
+        }
+        self.retry_order.clear();
+        self.early_readiness.clear();
+        for (_, rate) in self.recv_rates.drain() {
+            if let Some(scheduled) = rate.paused {
+                ctx.cancel(scheduled);
+            }
+        }
+        self.endpoint_configs.clear();
+        self.send_queue.clear();
+        self.send_in_flight = None;
+        self.send_blocked = None;
+        self.send_requestor = None;
+        self.recv_requestor = None;
+        self.send_span = None;
+        self.recv_span = None;
+        self.paused_reconnects.clear();
+        self.opened.clear();
+        for (_, pipe) in self.pipes.drain() {
+            pipe.close(ctx);
+        }
+
+        self.protocol.close(ctx);
+        self.peer_count.store(0, Ordering::SeqCst);
+        if let Ok(mut peer_protocols) = self.peer_protocols.lock() {
+            peer_protocols.clear();
+        }
+        if let Ok(mut endpoint_infos) = self.endpoint_infos.lock() {
+            endpoint_infos.clear();
+        }
+
+        ctx.raise(Event::Closed);
+    }
+}
+
+#[cfg(unix)]
+fn open_ready_fd(slot: &mut Option<ReadyFd>, ready: bool) -> Reply {
+    if slot.is_none() {
+        match ReadyFd::new() {
+            Ok(fd) => *slot = Some(fd),
+            Err(e) => return Reply::Err(e)
+        }
+    }
+
+    match *slot {
+        Some(ref mut fd) => {
+            fd.set_ready(ready);
+            Reply::ReadyFd(fd.as_raw_fd())
+        },
+        None => Reply::Err(other_io_error("readiness fd not created"))
+    }
+}
+
+fn override_endpoint_desc(desc: &mut EndpointDesc, option: ConfigOption) -> io::Result<()> {
+    match option {
+        ConfigOption::SendPriority(priority) => desc.send_priority = priority,
+        ConfigOption::RecvPriority(priority) => desc.recv_priority = priority,
+        ConfigOption::RecvWeight(weight) => desc.recv_weight = weight,
+        ConfigOption::TcpNoDelay(x) => desc.tcp_no_delay = x,
+        ConfigOption::TcpKeepalive(x) => desc.tcp_keepalive = x,
+        ConfigOption::ReuseAddr(x) => desc.reuse_addr = x,
+        ConfigOption::ConnectSourceAddr(x) => desc.connect_source_addr = x,
+        ConfigOption::IpcSocketPermissions(x) => desc.ipc_permissions = x,
+        ConfigOption::RecvMaxSize(x) => desc.recv_max_size = x,
+        ConfigOption::SendChunkSize(x) => desc.send_chunk_size = x,
+        ConfigOption::OversizePolicy(x) => desc.oversize_policy = x,
+        ConfigOption::ProtocolVersion(x) => desc.protocol_versions.advertised = x,
+        ConfigOption::CompatibleProtocolVersions(x) => desc.protocol_versions.compatible = x,
+        _ => return Err(unsupported_option_io_error("option not supported by endpoints"))
+    }
+    Ok(())
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* tests                                                                     */
+/*                                                                           */
+/*****************************************************************************/
+
+#[cfg(test)]
+mod tests {
+    use std::fmt;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+    use std::sync::mpsc;
+    use std::io;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use core::network;
+    use core::context::*;
+    use core::{SocketId, EndpointId, Message, EndpointTmpl, Scheduled};
+    use core::endpoint::Pipe;
+    use core::tests::*;
+
+    struct TestProto;
+
+    impl Protocol for TestProto {
+        fn id(&self) -> u16 {0}
+        fn peer_id(&self) -> u16 {0}
+        fn add_pipe(&mut self, _: &mut dyn Context, _: EndpointId, _: Pipe) {}
+        fn remove_pipe(&mut self, _: &mut dyn Context, _: EndpointId) -> Option<Pipe> {None}
+        fn send(&mut self, _: &mut dyn Context, _: Message, _: Option<Scheduled>) {}
+        fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_send_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn recv(&mut self, _: &mut dyn Context, _: Option<Scheduled>) {}
+        fn on_recv_ack(&mut self, _: &mut dyn Context, _: EndpointId, _: Message) {}
+        fn on_recv_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_recv_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_recv_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn is_send_ready(&self) -> bool { false }
+        fn is_recv_ready(&self) -> bool { false }
+        fn close(&mut self, _: &mut dyn Context) {}
+    }
+
+    struct FailingNetwork;
+
+    impl network::Network for FailingNetwork {
+        fn connect(&mut self, _: SocketId, _: &EndpointTmpl) -> io::Result<EndpointId> {
+            Err(other_io_error("FailingNetwork can only fail"))
+        }
+        fn reconnect(&mut self, _: SocketId, _: EndpointId, _: &EndpointTmpl) -> io::Result<()> {
+            Err(other_io_error("FailingNetwork can only fail"))
+        }
+        fn connect_stream(&mut self, _: SocketId, _: Stream, _: &EndpointTmpl) -> io::Result<EndpointId> {
+            Err(other_io_error("FailingNetwork can only fail"))
+        }
+        fn reserve_endpoint_id(&mut self) -> EndpointId {
+            EndpointId::from(1)
+        }
+        fn bind(&mut self, _: SocketId, _: &EndpointTmpl) -> io::Result<EndpointId> {
+            Err(other_io_error("FailingNetwork can only fail"))
+        }
+        fn rebind(&mut self, _: SocketId, _: EndpointId, _: &EndpointTmpl) -> io::Result<()> {
+            Err(other_io_error("FailingNetwork can only fail"))
+        }
+        fn open(&mut self, _: EndpointId, _: bool) {
+        }
+        fn close(&mut self, _: EndpointId, _: bool) {
         }
         fn send(&mut self, _: EndpointId, _: Rc<Message>) {
         }
         fn recv(&mut self, _: EndpointId) {
         }
+        fn flush(&mut self, _: EndpointId) {
+        }
+        fn reject(&mut self, _: EndpointId) {
+        }
+        fn pending_pipes(&self, _: SocketId) -> (usize, usize) {
+            (0, 0)
+        }
+        fn peer_cred(&self, _: EndpointId) -> io::Result<PeerCred> {
+            Err(io::Error::new(io::ErrorKind::Other, ""))
+        }
     }
 
     impl Scheduler for FailingNetwork {
@@ -575,6 +2248,9 @@ mod tests {
         }
         fn cancel(&mut self, _: Scheduled){
         }
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
     }
 
     impl fmt::Debug for FailingNetwork {
@@ -594,7 +2270,7 @@ mod tests {
         let (tx, rx) = mpsc::channel();
         let proto = Box::new(TestProto) as Box<Protocol>;
         let mut network = FailingNetwork;
-        let mut socket = Socket::new(id, tx, proto);
+        let mut socket = Socket::new(id, tx, mpsc::channel().1, proto);
 
         socket.connect(&mut network, String::from("test://fake"));
 
@@ -617,6 +2293,12 @@ mod tests {
         fn reconnect(&mut self, _: SocketId, _: EndpointId, _: &EndpointTmpl) -> io::Result<()> {
             Ok(())
         }
+        fn connect_stream(&mut self, _: SocketId, _: Stream, _: &EndpointTmpl) -> io::Result<EndpointId> {
+            Ok(self.0)
+        }
+        fn reserve_endpoint_id(&mut self) -> EndpointId {
+            self.0
+        }
         fn bind(&mut self, _: SocketId, _: &EndpointTmpl) -> io::Result<EndpointId> {
             Ok(self.0)
         }
@@ -627,6 +2309,10 @@ mod tests {
         fn close(&mut self, _: EndpointId, _: bool) {}
         fn send(&mut self, _: EndpointId, _: Rc<Message>) {}
         fn recv(&mut self, _: EndpointId) {}
+        fn flush(&mut self, _: EndpointId) {}
+        fn reject(&mut self, _: EndpointId) {}
+        fn pending_pipes(&self, _: SocketId) -> (usize, usize) { (0, 0) }
+        fn peer_cred(&self, _: EndpointId) -> io::Result<PeerCred> { Err(io::Error::new(io::ErrorKind::Other, "")) }
     }
 
     impl Scheduler for WorkingNetwork {
@@ -635,6 +2321,9 @@ mod tests {
         }
         fn cancel(&mut self, _: Scheduled){
         }
+        fn now(&self) -> Instant {
+            Instant::now()
+        }
     }
 
     impl fmt::Debug for WorkingNetwork {
@@ -655,7 +2344,7 @@ mod tests {
         let (tx, rx) = mpsc::channel();
         let proto = Box::new(TestProto) as Box<Protocol>;
         let mut network = WorkingNetwork(EndpointId::from(1));
-        let mut socket = Socket::new(id, tx, proto);
+        let mut socket = Socket::new(id, tx, mpsc::channel().1, proto);
 
         socket.connect(&mut network, String::from("test://fake"));
 
@@ -670,4 +2359,595 @@ mod tests {
             },
         }
     }
+
+    struct PipeHolderProto(HashMap<EndpointId, Pipe>);
+
+    impl Protocol for PipeHolderProto {
+        fn id(&self) -> u16 {0}
+        fn peer_id(&self) -> u16 {0}
+        fn add_pipe(&mut self, _: &mut dyn Context, eid: EndpointId, pipe: Pipe) { self.0.insert(eid, pipe); }
+        fn remove_pipe(&mut self, _: &mut dyn Context, eid: EndpointId) -> Option<Pipe> { self.0.remove(&eid) }
+        fn send(&mut self, ctx: &mut dyn Context, msg: Message, _: Option<Scheduled>) {
+            if let Some(pipe) = self.0.values().next() {
+                pipe.send(ctx, Rc::new(msg));
+            }
+        }
+        fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_send_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn recv(&mut self, _: &mut dyn Context, _: Option<Scheduled>) {}
+        fn on_recv_ack(&mut self, _: &mut dyn Context, _: EndpointId, _: Message) {}
+        fn on_recv_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_recv_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_recv_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn is_send_ready(&self) -> bool { false }
+        fn is_recv_ready(&self) -> bool { false }
+        fn close(&mut self, _: &mut dyn Context) {}
+    }
+
+    fn new_socket_with_opened_pipe(ctx: &mut TestContext, eid: EndpointId) -> Socket {
+        let (tx, _) = mpsc::channel();
+        let proto = Box::new(PipeHolderProto(HashMap::new())) as Box<dyn Protocol>;
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, proto);
+
+        socket.pipes.insert(eid, new_test_pipe(eid));
+        socket.on_pipe_opened(ctx, eid, 0, Features::default());
+        socket
+    }
+
+    struct ReadinessRecorderProto {
+        pipes: HashMap<EndpointId, Pipe>,
+        send_ready_calls: Rc<RefCell<Vec<(EndpointId, bool)>>>
+    }
+
+    impl Protocol for ReadinessRecorderProto {
+        fn id(&self) -> u16 {0}
+        fn peer_id(&self) -> u16 {0}
+        fn add_pipe(&mut self, _: &mut dyn Context, eid: EndpointId, pipe: Pipe) { self.pipes.insert(eid, pipe); }
+        fn remove_pipe(&mut self, _: &mut dyn Context, eid: EndpointId) -> Option<Pipe> { self.pipes.remove(&eid) }
+        fn send(&mut self, _: &mut dyn Context, _: Message, _: Option<Scheduled>) {}
+        fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_send_ready(&mut self, _: &mut dyn Context, eid: EndpointId) {
+            let is_pipe_known = self.pipes.contains_key(&eid);
+            self.send_ready_calls.borrow_mut().push((eid, is_pipe_known));
+        }
+        fn on_send_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn recv(&mut self, _: &mut dyn Context, _: Option<Scheduled>) {}
+        fn on_recv_ack(&mut self, _: &mut dyn Context, _: EndpointId, _: Message) {}
+        fn on_recv_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_recv_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_recv_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn is_send_ready(&self) -> bool { false }
+        fn is_recv_ready(&self) -> bool { false }
+        fn close(&mut self, _: &mut dyn Context) {}
+    }
+
+    #[test]
+    fn send_readiness_raised_before_the_pipe_is_opened_is_replayed_once_opened() {
+        let eid = EndpointId::from(1);
+        let (tx, _) = mpsc::channel();
+        let send_ready_calls = Rc::new(RefCell::new(Vec::new()));
+        let proto = ReadinessRecorderProto { pipes: HashMap::new(), send_ready_calls: send_ready_calls.clone() };
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(proto));
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+
+        socket.pipes.insert(eid, new_test_pipe(eid));
+        socket.on_send_ready(&mut ctx, eid, true);
+        assert!(send_ready_calls.borrow().is_empty());
+
+        socket.on_pipe_opened(&mut ctx, eid, 0, Features::default());
+        assert_eq!(&[(eid, true)], &send_ready_calls.borrow()[..]);
+    }
+
+    #[test]
+    fn graceful_close_waits_for_the_pipe_to_be_flushed() {
+        let eid = EndpointId::from(1);
+        let scheduled = Scheduled::from(7);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = new_socket_with_opened_pipe(&mut ctx, eid);
+
+        socket.send(&mut ctx, Message::from_body(vec![65]));
+        ctx_sensor.borrow().assert_one_send_to(eid);
+
+        ctx.set_schedule_result(scheduled);
+        socket.close_pipe_graceful(&mut ctx, eid, Duration::from_secs(1)).unwrap();
+
+        assert_eq!(&[eid], ctx_sensor.borrow().get_flush_calls());
+        assert_eq!(0, ctx_sensor.borrow().get_close_calls().len());
+
+        socket.on_send_ack(&mut ctx, eid);
+        assert_eq!(0, ctx_sensor.borrow().get_close_calls().len());
+
+        socket.on_pipe_flushed(&mut ctx, eid);
+        assert_eq!(&[(eid, true)], ctx_sensor.borrow().get_close_calls());
+        ctx_sensor.borrow().assert_one_cancellation(scheduled);
+    }
+
+    #[test]
+    fn graceful_close_gives_up_waiting_on_timeout() {
+        let eid = EndpointId::from(1);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = new_socket_with_opened_pipe(&mut ctx, eid);
+
+        ctx.set_schedule_result(Scheduled::from(7));
+        socket.close_pipe_graceful(&mut ctx, eid, Duration::from_secs(1)).unwrap();
+        socket.on_drain_timeout(&mut ctx, eid);
+
+        assert_eq!(&[(eid, true)], ctx_sensor.borrow().get_close_calls());
+
+        socket.on_pipe_flushed(&mut ctx, eid);
+        assert_eq!(1, ctx_sensor.borrow().get_close_calls().len());
+    }
+
+    #[test]
+    fn graceful_close_of_an_unknown_pipe_fails_without_waiting() {
+        let eid = EndpointId::from(1);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = new_socket_with_opened_pipe(&mut ctx, eid);
+
+        ctx.set_schedule_result(Scheduled::from(7));
+        let err = socket.close_pipe_graceful(&mut ctx, EndpointId::from(2), Duration::from_secs(1)).unwrap_err();
+
+        assert_eq!(io::ErrorKind::NotConnected, err.kind());
+        assert!(ctx_sensor.borrow().get_schedule_delays().is_empty());
+        assert!(ctx_sensor.borrow().get_flush_calls().is_empty());
+        assert!(socket.draining.is_empty());
+    }
+
+    #[test]
+    fn shutdown_closes_acceptors_before_draining_pipes() {
+        let pipe_eid = EndpointId::from(1);
+        let acceptor_eid = EndpointId::from(2);
+        let scheduled = Scheduled::from(7);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = new_socket_with_opened_pipe(&mut ctx, pipe_eid);
+
+        socket.acceptors.insert(acceptor_eid, Acceptor::new(acceptor_eid, String::from("tcp://localhost:5454"), new_test_endpoint_desc()));
+        ctx.set_schedule_result(scheduled);
+        socket.shutdown(&mut ctx, Duration::from_secs(1));
+
+        assert_eq!(&[(acceptor_eid, false)], ctx_sensor.borrow().get_close_calls());
+        assert_eq!(&[pipe_eid], ctx_sensor.borrow().get_flush_calls());
+        assert!(!ctx_sensor.borrow().get_raised_events().contains(&Event::Closed));
+
+        socket.on_pipe_flushed(&mut ctx, pipe_eid);
+
+        assert_eq!(&[(acceptor_eid, false), (pipe_eid, true)], ctx_sensor.borrow().get_close_calls());
+        assert_eq!(Some(&Event::Closed), ctx_sensor.borrow().get_raised_events().last());
+    }
+
+    fn recv_pending_retries(rx: &mpsc::Receiver<Reply>) -> usize {
+        loop {
+            match rx.try_recv() {
+                Ok(Reply::PendingRetries(count)) => return count,
+                Ok(_) => continue,
+                Err(_) => panic!("Socket should have replied the pending retry count")
+            }
+        }
+    }
+
+    #[test]
+    fn pending_retries_beyond_the_limit_drop_the_oldest_endpoint() {
+        let (tx, rx) = mpsc::channel();
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(TestProto));
+
+        socket.set_option(&mut ctx, ConfigOption::MaxPendingRetries(2));
+
+        for i in 1..4 {
+            let eid = EndpointId::from(i);
+            let spec = EndpointSpec {
+                url: format!("tcp://127.0.0.1:{}", 5000 + i),
+                desc: new_test_endpoint_desc(),
+                attempts: 0
+            };
+
+            socket.pipes.insert(eid, Pipe::from_spec(eid, spec));
+            ctx.set_schedule_result(Scheduled::from(i));
+            socket.on_pipe_error(&mut ctx, eid, other_io_error("test"));
+        }
+
+        ctx_sensor.borrow().assert_one_cancellation(Scheduled::from(1));
+        socket.pending_retries(&mut ctx);
+        assert_eq!(2, recv_pending_retries(&rx));
+
+        socket.close_pipe(&mut ctx, EndpointId::from(3));
+        socket.pending_retries(&mut ctx);
+        assert_eq!(1, recv_pending_retries(&rx));
+    }
+
+    fn fail_pipe_after_attempts(socket: &mut Socket, ctx: &mut TestContext, attempts: u32, reset: bool) {
+        let eid = EndpointId::from(1);
+        let spec = EndpointSpec {
+            url: String::from("tcp://127.0.0.1:5454"),
+            desc: new_test_endpoint_desc(),
+            attempts: attempts
+        };
+        let mut pipe = Pipe::from_spec(eid, spec);
+
+        if reset {
+            pipe.reset_attempts();
+        }
+
+        socket.pipes.insert(eid, pipe);
+        ctx.set_schedule_result(Scheduled::from(attempts as usize));
+        socket.on_pipe_error(ctx, eid, other_io_error("test"));
+        socket.cancel_retry(ctx, eid);
+    }
+
+    #[test]
+    fn reconnect_interval_doubles_on_consecutive_failures_up_to_the_max() {
+        let (tx, _) = mpsc::channel();
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(TestProto));
+
+        socket.set_option(&mut ctx, ConfigOption::RetryIvl(Duration::from_millis(100)));
+        socket.set_option(&mut ctx, ConfigOption::RetryIvlMax(Some(Duration::from_millis(350))));
+
+        for attempts in 0..4 {
+            fail_pipe_after_attempts(&mut socket, &mut ctx, attempts, false);
+        }
+        fail_pipe_after_attempts(&mut socket, &mut ctx, 3, true);
+
+        let expected: Vec<Duration> = [100, 200, 350, 350, 100].iter().map(|&ms| Duration::from_millis(ms)).collect();
+        assert_eq!(&expected[..], ctx_sensor.borrow().get_schedule_delays());
+    }
+
+    #[test]
+    fn reconnect_interval_is_constant_when_the_max_is_the_base_interval() {
+        let (tx, _) = mpsc::channel();
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(TestProto));
+
+        socket.set_option(&mut ctx, ConfigOption::RetryIvl(Duration::from_millis(100)));
+        socket.set_option(&mut ctx, ConfigOption::RetryIvlMax(Some(Duration::from_millis(100))));
+
+        for attempts in 0..3 {
+            fail_pipe_after_attempts(&mut socket, &mut ctx, attempts, false);
+        }
+
+        assert!(ctx_sensor.borrow().get_schedule_delays().iter().all(|&d| d == Duration::from_millis(100)));
+    }
+
+    #[test]
+    fn failing_rebind_backs_off_then_gives_up_the_endpoint() {
+        let (tx, _) = mpsc::channel();
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(TestProto));
+        let eid = EndpointId::from(1);
+
+        socket.set_option(&mut ctx, ConfigOption::RetryIvl(Duration::from_millis(100)));
+        socket.set_option(&mut ctx, ConfigOption::RetryIvlMax(Some(Duration::from_millis(1000))));
+        socket.set_option(&mut ctx, ConfigOption::MaxRebindAttempts(Some(3)));
+
+        for attempts in 1..4 {
+            let spec = EndpointSpec {
+                url: String::from("tcp://127.0.0.1:5454"),
+                desc: new_test_endpoint_desc(),
+                attempts: attempts
+            };
+
+            ctx.set_schedule_result(Scheduled::from(attempts as usize));
+            socket.rebind(&mut ctx, eid, spec);
+        }
+
+        let sensor = ctx_sensor.borrow();
+        let expected = vec![Duration::from_millis(200), Duration::from_millis(400)];
+
+        assert_eq!(&expected[..], sensor.get_schedule_delays());
+        assert_eq!(Some(&Event::EndpointClosed(eid, None, ErrorCategory::Other)), sensor.get_raised_events().last());
+        assert!(!socket.retrying.contains_key(&eid));
+    }
+
+    #[test]
+    fn permission_denied_pipe_error_gives_up_the_endpoint_instead_of_retrying() {
+        let (tx, _) = mpsc::channel();
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(TestProto));
+        let eid = EndpointId::from(1);
+        let spec = EndpointSpec {
+            url: String::from("tcp://127.0.0.1:5454"),
+            desc: new_test_endpoint_desc(),
+            attempts: 0
+        };
+
+        socket.pipes.insert(eid, Pipe::from_spec(eid, spec));
+        socket.on_pipe_error(&mut ctx, eid, io::Error::new(io::ErrorKind::PermissionDenied, "test"));
+
+        let sensor = ctx_sensor.borrow();
+
+        assert!(sensor.get_schedule_delays().is_empty());
+        assert_eq!(Some(&Event::EndpointClosed(eid, None, ErrorCategory::PermissionDenied)), sensor.get_raised_events().last());
+        assert!(!socket.retrying.contains_key(&eid));
+    }
+
+    #[test]
+    fn reset_pipe_error_reconnects_without_waiting_for_the_backoff() {
+        let (tx, _) = mpsc::channel();
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(TestProto));
+        let eid = EndpointId::from(1);
+        let spec = EndpointSpec {
+            url: String::from("tcp://127.0.0.1:5454"),
+            desc: new_test_endpoint_desc(),
+            attempts: 3
+        };
+
+        socket.set_option(&mut ctx, ConfigOption::RetryIvl(Duration::from_millis(100)));
+        socket.set_option(&mut ctx, ConfigOption::RetryIvlMax(Some(Duration::from_millis(1000))));
+        socket.pipes.insert(eid, Pipe::from_spec(eid, spec));
+        socket.on_pipe_error(&mut ctx, eid, io::Error::new(io::ErrorKind::ConnectionReset, "test"));
+
+        assert_eq!(&[Duration::from_millis(100)], ctx_sensor.borrow().get_schedule_delays());
+    }
+
+    /// Acknowledges each send, and completes a send while failing the pending recv when a pipe gets ready.
+    struct SendQueueProto(mpsc::Sender<Reply>);
+
+    impl Protocol for SendQueueProto {
+        fn id(&self) -> u16 {0}
+        fn peer_id(&self) -> u16 {0}
+        fn add_pipe(&mut self, _: &mut dyn Context, _: EndpointId, _: Pipe) {}
+        fn remove_pipe(&mut self, _: &mut dyn Context, _: EndpointId) -> Option<Pipe> {None}
+        fn send(&mut self, _: &mut dyn Context, _: Message, _: Option<Scheduled>) {}
+        fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {
+            let _ = self.0.send(Reply::Send);
+        }
+        fn on_send_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_send_ready(&mut self, _: &mut dyn Context, _: EndpointId) {
+            let _ = self.0.send(Reply::Send);
+            let _ = self.0.send(Reply::Err(other_io_error("recv failed")));
+        }
+        fn on_send_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn recv(&mut self, _: &mut dyn Context, _: Option<Scheduled>) {}
+        fn on_recv_ack(&mut self, _: &mut dyn Context, _: EndpointId, _: Message) {}
+        fn on_recv_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_recv_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_recv_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn is_send_ready(&self) -> bool { true }
+        fn is_recv_ready(&self) -> bool { false }
+        fn close(&mut self, _: &mut dyn Context) {}
+    }
+
+    fn new_send_queue_socket(tx: mpsc::Sender<Reply>) -> Socket {
+        let (proto_tx, proto_rx) = mpsc::channel();
+
+        Socket::new(SocketId::from(1), tx, proto_rx, Box::new(SendQueueProto(proto_tx)))
+    }
+
+    #[test]
+    fn send_async_should_block_only_once_the_queue_is_full() {
+        let (tx, rx) = mpsc::channel();
+        let mut socket = new_send_queue_socket(tx);
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+
+        socket.set_option(&mut ctx, ConfigOption::SendQueueSize(2));
+        let _ = rx.try_recv();
+
+        for body in 0..3 {
+            socket.send_async(&mut ctx, Message::from_body(vec![body]));
+            match rx.try_recv() {
+                Ok(Reply::Send) => {},
+                _ => panic!("async send should have been acknowledged once queued")
+            }
+        }
+        assert_eq!(2, socket.send_queue.len());
+
+        socket.send_async(&mut ctx, Message::from_body(vec![3]));
+        assert!(rx.try_recv().is_err());
+        assert!(socket.send_blocked.is_some());
+
+        socket.on_send_ack(&mut ctx, EndpointId::from(1));
+        match rx.try_recv() {
+            Ok(Reply::Send) => {},
+            _ => panic!("blocked async send should have been acknowledged once queued")
+        }
+        assert!(socket.send_blocked.is_none());
+        assert_eq!(2, socket.send_queue.len());
+    }
+
+    #[test]
+    fn recv_error_raised_along_a_send_completion_should_not_complete_the_next_send() {
+        let (tx, rx) = mpsc::channel();
+        let mut socket = new_send_queue_socket(tx);
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+
+        socket.clone_handle(&mut ctx);
+        let recv_rx = match rx.try_recv() {
+            Ok(Reply::Clone(handle, recv_rx)) => {
+                socket.set_requestor(handle);
+                recv_rx
+            },
+            _ => panic!("handle should have been cloned")
+        };
+        socket.recv(&mut ctx);
+        socket.process_protocol_replies(&mut ctx, false);
+
+        socket.set_requestor(0);
+        socket.send_async(&mut ctx, Message::from_body(vec![65]));
+        socket.send_async(&mut ctx, Message::from_body(vec![66]));
+        socket.process_protocol_replies(&mut ctx, false);
+
+        socket.on_send_ready(&mut ctx, EndpointId::from(1), true);
+        socket.process_protocol_replies(&mut ctx, false);
+
+        match recv_rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv error should have been sent to the recv requestor")
+        }
+        assert!(socket.is_sending());
+    }
+
+    #[test]
+    fn remembered_protocol_options_do_not_grow_with_each_subscription_change() {
+        let (tx, _) = mpsc::channel();
+        let (proto_tx, proto_rx) = mpsc::channel();
+        let mut socket = Socket::new(SocketId::from(1), tx, proto_rx, Box::new(::proto::sub::Sub::from(proto_tx)));
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+
+        for _ in 0..10 {
+            socket.set_option(&mut ctx, ConfigOption::Subscribe(String::from("A")));
+            socket.set_option(&mut ctx, ConfigOption::SubscribeMany(vec![b"B".to_vec(), b"C".to_vec()]));
+            socket.set_option(&mut ctx, ConfigOption::Unsubscribe(String::from("A")));
+            socket.set_option(&mut ctx, ConfigOption::UnsubscribeMany(vec![b"C".to_vec()]));
+        }
+
+        match &socket.sibling_config().1[..] {
+            [ConfigOption::Subscriptions(topics)] => assert_eq!(&vec![b"B".to_vec()], topics),
+            _ => panic!("subscriptions should have been folded into a single option")
+        }
+    }
+
+    /// Reports a fixed number of messages dropped by the protocol.
+    struct DroppingProto(usize);
+
+    impl Protocol for DroppingProto {
+        fn id(&self) -> u16 {0}
+        fn peer_id(&self) -> u16 {0}
+        fn add_pipe(&mut self, _: &mut dyn Context, _: EndpointId, _: Pipe) {}
+        fn remove_pipe(&mut self, _: &mut dyn Context, _: EndpointId) -> Option<Pipe> {None}
+        fn send(&mut self, _: &mut dyn Context, _: Message, _: Option<Scheduled>) {}
+        fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_send_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn recv(&mut self, _: &mut dyn Context, _: Option<Scheduled>) {}
+        fn on_recv_ack(&mut self, _: &mut dyn Context, _: EndpointId, _: Message) {}
+        fn on_recv_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_recv_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_recv_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn is_send_ready(&self) -> bool { false }
+        fn is_recv_ready(&self) -> bool { false }
+        fn dropped_count(&self) -> usize { self.0 }
+        fn close(&mut self, _: &mut dyn Context) {}
+    }
+
+    #[test]
+    fn messages_dropped_by_the_protocol_are_counted_apart_from_the_pipe_drops() {
+        let (tx, rx) = mpsc::channel();
+        let mut socket = Socket::new(SocketId::from(1), tx, mpsc::channel().1, Box::new(DroppingProto(3)));
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+
+        socket.on_pipe_dropped(&mut ctx, EndpointId::from(1), 2);
+
+        socket.dropped_count(&mut ctx);
+        match rx.try_recv() {
+            Ok(Reply::DroppedCount(count)) => assert_eq!(2, count),
+            _ => panic!("socket should have replied the dropped count")
+        }
+
+        socket.stats(&mut ctx);
+        match rx.try_recv() {
+            Ok(Reply::Stats(stats)) => assert_eq!(3, stats.pub_dropped),
+            _ => panic!("socket should have replied the stats")
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    struct AckingProto(mpsc::Sender<Reply>);
+
+    #[cfg(feature = "tracing")]
+    impl Protocol for AckingProto {
+        fn id(&self) -> u16 {0}
+        fn peer_id(&self) -> u16 {0}
+        fn add_pipe(&mut self, _: &mut dyn Context, _: EndpointId, _: Pipe) {}
+        fn remove_pipe(&mut self, _: &mut dyn Context, _: EndpointId) -> Option<Pipe> {None}
+        fn send(&mut self, _: &mut dyn Context, _: Message, _: Option<Scheduled>) {}
+        fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {
+            let _ = self.0.send(Reply::Send);
+        }
+        fn on_send_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_send_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_send_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn recv(&mut self, _: &mut dyn Context, _: Option<Scheduled>) {}
+        fn on_recv_ack(&mut self, _: &mut dyn Context, _: EndpointId, _: Message) {}
+        fn on_recv_timeout(&mut self, _: &mut dyn Context) {}
+        fn on_recv_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn on_recv_not_ready(&mut self, _: &mut dyn Context, _: EndpointId) {}
+        fn is_send_ready(&self) -> bool { true }
+        fn is_recv_ready(&self) -> bool { false }
+        fn close(&mut self, _: &mut dyn Context) {}
+    }
+
+    /// Name, endpoint field and closed flag of a created span.
+    #[cfg(feature = "tracing")]
+    type RecordedSpan = (&'static str, Option<u64>, bool);
+
+    #[cfg(feature = "tracing")]
+    #[derive(Clone, Default)]
+    struct SpanRecorder {
+        spans: ::std::sync::Arc<::std::sync::Mutex<Vec<RecordedSpan>>>
+    }
+
+    #[cfg(feature = "tracing")]
+    struct EndpointVisitor<'a>(&'a mut Option<u64>);
+
+    #[cfg(feature = "tracing")]
+    impl<'a> ::tracing::field::Visit for EndpointVisitor<'a> {
+        fn record_u64(&mut self, field: &::tracing::field::Field, value: u64) {
+            if field.name() == "endpoint" {
+                *self.0 = Some(value);
+            }
+        }
+        fn record_debug(&mut self, _: &::tracing::field::Field, _: &dyn fmt::Debug) {}
+    }
+
+    #[cfg(feature = "tracing")]
+    impl ::tracing::Subscriber for SpanRecorder {
+        fn enabled(&self, _: &::tracing::Metadata) -> bool { true }
+        fn new_span(&self, attrs: &::tracing::span::Attributes) -> ::tracing::Id {
+            let mut spans = self.spans.lock().unwrap();
+            spans.push((attrs.metadata().name(), None, false));
+            ::tracing::Id::from_u64(spans.len() as u64)
+        }
+        fn record(&self, id: &::tracing::Id, values: &::tracing::span::Record) {
+            let mut spans = self.spans.lock().unwrap();
+            let span = &mut spans[id.into_u64() as usize - 1];
+            values.record(&mut EndpointVisitor(&mut span.1));
+        }
+        fn record_follows_from(&self, _: &::tracing::Id, _: &::tracing::Id) {}
+        fn event(&self, _: &::tracing::Event) {}
+        fn enter(&self, _: &::tracing::Id) {}
+        fn exit(&self, _: &::tracing::Id) {}
+        fn try_close(&self, id: ::tracing::Id) -> bool {
+            self.spans.lock().unwrap()[id.into_u64() as usize - 1].2 = true;
+            true
+        }
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn completed_send_is_covered_by_a_span() {
+        let eid = EndpointId::from(3);
+        let recorder = SpanRecorder::default();
+        let (tx, rx) = mpsc::channel();
+        let (proto_tx, proto_rx) = mpsc::channel();
+        let mut socket = Socket::new(SocketId::from(1), tx, proto_rx, Box::new(AckingProto(proto_tx)));
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+
+        ::tracing::subscriber::with_default(recorder.clone(), || {
+            socket.send(&mut ctx, Message::new());
+            assert_eq!(&[("send", None, false)], &recorder.spans.lock().unwrap()[..]);
+
+            socket.on_send_ack(&mut ctx, eid);
+        });
+
+        assert_eq!(&[("send", Some(3), true)], &recorder.spans.lock().unwrap()[..]);
+        match rx.try_recv() {
+            Ok(Reply::Send) => {},
+            _ => panic!("send should have been acknowledged")
+        }
+    }
 }
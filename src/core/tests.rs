@@ -8,12 +8,13 @@ use std::fmt;
 use std::rc::Rc;
 use std::cell::RefCell;
 use std::io::Result;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use super::{SocketId, EndpointId, Message, EndpointTmpl, EndpointDesc, Scheduled};
 use super::endpoint::Pipe;
 use super::context::{Context, Scheduler, Schedulable, Event};
 use super::network::Network;
+use transport::{Stream, ProtocolVersions, OversizePolicy, PeerCred};
 use io_error;
 
 pub fn new_test_pipe(id: EndpointId) -> Pipe {
@@ -24,8 +25,17 @@ pub fn new_test_endpoint_desc() -> EndpointDesc {
     EndpointDesc {
         send_priority: 0,
         recv_priority: 0,
+        recv_weight: 1,
         tcp_no_delay: false,
-        recv_max_size: 1024
+        tcp_keepalive: None,
+        reuse_addr: true,
+        connect_source_addr: None,
+        ipc_permissions: None,
+        recv_max_size: 1024,
+        oversize_policy: OversizePolicy::ClosePipe,
+        send_chunk_size: 0,
+        protocol_versions: ProtocolVersions::default(),
+        recv_pool: None
     }
 }
 
@@ -34,7 +44,9 @@ pub struct TestContextSensor {
     close_calls: Vec<(EndpointId, bool)>,
     send_calls: Vec<(EndpointId, Rc<Message>)>,
     recv_calls: Vec<EndpointId>,
+    flush_calls: Vec<EndpointId>,
     raised_events: Vec<Event>,
+    schedule_delays: Vec<Duration>,
     schedule_cancellations: Vec<Scheduled>
 }
 
@@ -44,7 +56,9 @@ impl Default for TestContextSensor {
             close_calls: Vec::new(),
             send_calls: Vec::new(),
             recv_calls: Vec::new(),
+            flush_calls: Vec::new(),
             raised_events: Vec::new(),
+            schedule_delays: Vec::new(),
             schedule_cancellations: Vec::new()
         }
     }
@@ -95,6 +109,14 @@ impl TestContextSensor {
         assert_eq!(0, self.raised_events.len());
     }
 
+    fn push_schedule_delay(&mut self, delay: Duration) {
+        self.schedule_delays.push(delay)
+    }
+
+    pub fn get_schedule_delays(&self) -> &[Duration] {
+        &self.schedule_delays
+    }
+
     fn push_schedule_cancellation(&mut self, scheduled: Scheduled) {
         self.schedule_cancellations.push(scheduled)
     }
@@ -110,6 +132,10 @@ impl TestContextSensor {
         self.recv_calls.push(eid)
     }
 
+    pub fn get_recv_calls(&self) -> &[EndpointId] {
+        &self.recv_calls
+    }
+
     pub fn assert_no_recv_call(&self) {
         assert_eq!(0, self.recv_calls.len());
     }
@@ -120,6 +146,14 @@ impl TestContextSensor {
         let id = &self.recv_calls[0];
         assert_eq!(eid, *id);
     }
+
+    fn push_flush_call(&mut self, eid: EndpointId) {
+        self.flush_calls.push(eid)
+    }
+
+    pub fn get_flush_calls(&self) -> &[EndpointId] {
+        &self.flush_calls
+    }
 }
 
 pub struct TestContext {
@@ -140,6 +174,10 @@ impl TestContext {
             schedule_result: None
         }
     }
+
+    pub fn set_schedule_result(&mut self, scheduled: Scheduled) {
+        self.schedule_result = Some(scheduled);
+    }
 }
 
 impl Network for TestContext {
@@ -149,11 +187,17 @@ impl Network for TestContext {
     fn reconnect(&mut self, _: SocketId, _: EndpointId, _: &EndpointTmpl) -> Result<()> {
         unimplemented!();
     }
+    fn connect_stream(&mut self, _: SocketId, _: Stream, _: &EndpointTmpl) -> Result<EndpointId> {
+        unimplemented!();
+    }
+    fn reserve_endpoint_id(&mut self) -> EndpointId {
+        unimplemented!();
+    }
     fn bind(&mut self, _: SocketId, _: &EndpointTmpl) -> Result<EndpointId> {
         unimplemented!();
     }
     fn rebind(&mut self, _: SocketId, _: EndpointId, _: &EndpointTmpl) -> Result<()> {
-        unimplemented!();
+        Err(io_error::other_io_error("test"))
     }
     fn open(&mut self, _: EndpointId, _: bool) {
         unimplemented!();
@@ -167,10 +211,24 @@ impl Network for TestContext {
     fn recv(&mut self, eid: EndpointId) {
         self.sensor.borrow_mut().push_recv_call(eid)
     }
+    fn flush(&mut self, eid: EndpointId) {
+        self.sensor.borrow_mut().push_flush_call(eid)
+    }
+    fn reject(&mut self, _: EndpointId) {
+        unimplemented!();
+    }
+    fn pending_pipes(&self, _: SocketId) -> (usize, usize) {
+        unimplemented!();
+    }
+    fn peer_cred(&self, _: EndpointId) -> Result<PeerCred> {
+        unimplemented!();
+    }
 }
 
 impl Scheduler for TestContext {
-    fn schedule(&mut self, _: Schedulable, _: Duration) -> Result<Scheduled> {
+    fn schedule(&mut self, _: Schedulable, delay: Duration) -> Result<Scheduled> {
+        self.sensor.borrow_mut().push_schedule_delay(delay);
+
         if let Some(scheduled) = self.schedule_result.take() {
             Ok(scheduled)
         } else {
@@ -180,6 +238,9 @@ impl Scheduler for TestContext {
     fn cancel(&mut self, scheduled: Scheduled) {
         self.sensor.borrow_mut().push_schedule_cancellation(scheduled)
     }
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
 }
 impl Context for TestContext {
     fn raise(&mut self, evt: Event) {
@@ -0,0 +1,49 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+//! Spans covering the lifecycle of a send or a recv operation in the reactor.
+//! They are only emitted when the `tracing` feature is enabled,
+//! otherwise `OpSpan` is an empty type and all its methods compile to nothing.
+
+use super::{SocketId, EndpointId};
+
+#[cfg(feature = "tracing")]
+pub struct OpSpan(::tracing::Span);
+
+#[cfg(not(feature = "tracing"))]
+pub struct OpSpan;
+
+#[cfg(feature = "tracing")]
+impl OpSpan {
+    pub fn send(sid: SocketId) -> OpSpan {
+        OpSpan(::tracing::trace_span!("send", socket = sid.0 as u64, endpoint = ::tracing::field::Empty))
+    }
+
+    pub fn recv(sid: SocketId) -> OpSpan {
+        OpSpan(::tracing::trace_span!("recv", socket = sid.0 as u64, endpoint = ::tracing::field::Empty))
+    }
+
+    pub fn record_endpoint(&self, eid: EndpointId) {
+        self.0.record("endpoint", eid.0 as u64);
+    }
+}
+
+#[cfg(not(feature = "tracing"))]
+impl OpSpan {
+    #[inline(always)]
+    pub fn send(_: SocketId) -> OpSpan {
+        OpSpan
+    }
+
+    #[inline(always)]
+    pub fn recv(_: SocketId) -> OpSpan {
+        OpSpan
+    }
+
+    #[inline(always)]
+    pub fn record_endpoint(&self, _: EndpointId) {
+    }
+}
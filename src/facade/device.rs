@@ -10,7 +10,7 @@ use std::io;
 
 use super::*;
 use reactor;
-use core::DeviceId;
+use core::{DeviceId, Message};
 use core::device::{Request, Reply};
 use io_error::*;
 
@@ -23,6 +23,28 @@ pub trait Device : Send {
     fn run(self: Box<Self>) -> io::Result<()>;
 }
 
+/// Settings of a device, see [create_bridge_device_with_config](struct.Session.html#method.create_bridge_device_with_config)
+/// and [create_relay_device_with_config](struct.Session.html#method.create_relay_device_with_config).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceConfig {
+    /// Maximum number of devices a message can go through, beyond which it is dropped and logged
+    /// instead of being forwarded, which breaks the loops created by devices wired into a cycle, whatever the protocol.
+    /// Each device increments the [hop count](struct.Message.html#method.get_hop_count) of the messages it forwards.
+    /// The count is carried in the extension block of the frames, so the device enables
+    /// [frame extensions](enum.ConfigOption.html#variant.FrameExtensions) on its sockets,
+    /// and it is only transmitted on the endpoints added to them afterwards, whose peer advertised the extensions too.
+    /// Default value is 8.
+    pub max_hops: usize
+}
+
+impl Default for DeviceConfig {
+    fn default() -> DeviceConfig {
+        DeviceConfig {
+            max_hops: 8
+        }
+    }
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* RELAY DEVICE                                                              */
@@ -31,20 +53,28 @@ pub trait Device : Send {
 
 #[doc(hidden)]
 pub struct Relay {
-    socket: Option<socket::Socket>
+    socket: Option<socket::Socket>,
+    max_hops: usize
 }
 
 impl Relay {
-    pub fn new(s: socket::Socket) -> Relay {
-        Relay { socket: Some(s) }
+    pub fn new(s: socket::Socket, config: DeviceConfig) -> Relay {
+        Relay {
+            socket: Some(s),
+            max_hops: config.max_hops
+        }
     }
 }
 
 impl Device for Relay {
     fn run(mut self: Box<Self>) -> io::Result<()> {
         let mut socket = self.socket.take().unwrap();
+        let max_hops = self.max_hops;
+
         loop {
-            socket.recv_msg().and_then(|msg| socket.send_msg(msg))?;
+            let msg = socket.recv_msg()?;
+
+            send_within_hops(&mut socket, msg, max_hops)?;
         }
     }
 }
@@ -72,7 +102,7 @@ impl RequestSender {
         }
     }
     fn send(&self, req: Request) -> io::Result<()> {
-        self.req_tx.send(reactor::Request::Device(self.device_id, req)).map_err(from_send_error)
+        self.req_tx.send(reactor::Request::Device(self.device_id, req))
     }
 }
 
@@ -81,7 +111,8 @@ pub struct Bridge {
     request_sender: RequestSender,
     reply_receiver: ReplyReceiver,
     left: Option<socket::Socket>,
-    right: Option<socket::Socket>
+    right: Option<socket::Socket>,
+    max_hops: usize
 }
 
 impl Bridge {
@@ -90,13 +121,15 @@ impl Bridge {
         request_tx: RequestSender, 
         reply_rx: ReplyReceiver,
         left: socket::Socket,
-        right: socket::Socket) -> Bridge {
+        right: socket::Socket,
+        config: DeviceConfig) -> Bridge {
 
         Bridge {
             request_sender: request_tx,
             reply_receiver: reply_rx,
             left: Some(left),
-            right: Some(right)
+            right: Some(right),
+            max_hops: config.max_hops
         }
     }
 
@@ -115,9 +148,9 @@ impl Bridge {
     fn run_once(&mut self, left: &mut socket::Socket, right: &mut socket::Socket) -> io::Result<()> {
         if let Reply::Check(l, r) = self.execute_request(Request::Check)? {
             match (l, r) {
-                (true, true) => exchange_msg(left, right),
-                (true, _)    => forward_msg(left, right),
-                (_, true)    => forward_msg(right, left),
+                (true, true) => exchange_msg(left, right, self.max_hops),
+                (true, _)    => forward_msg(left, right, self.max_hops),
+                (_, true)    => forward_msg(right, left, self.max_hops),
                 (_, _)       => Ok(())
             }
         } else {
@@ -138,15 +171,27 @@ impl Device for Bridge {
     }
 }
 
-fn forward_msg(from: &mut socket::Socket, to: &mut socket::Socket) -> io::Result<()> {
-    from.recv_msg().and_then(|msg| to.send_msg(msg))
+fn forward_msg(from: &mut socket::Socket, to: &mut socket::Socket, max_hops: usize) -> io::Result<()> {
+    from.recv_msg().and_then(|msg| send_within_hops(to, msg, max_hops))
 }
 
-fn exchange_msg(left: &mut socket::Socket, right: &mut socket::Socket) -> io::Result<()> {
+fn exchange_msg(left: &mut socket::Socket, right: &mut socket::Socket, max_hops: usize) -> io::Result<()> {
     let from_left = left.recv_msg()?;
     let from_right = right.recv_msg()?;
 
-    right.send_msg(from_left).and_then(|_| left.send_msg(from_right))
+    send_within_hops(right, from_left, max_hops).and_then(|_| send_within_hops(left, from_right, max_hops))
+}
+
+fn send_within_hops(to: &mut socket::Socket, mut msg: Message, max_hops: usize) -> io::Result<()> {
+    let hops = msg.get_hop_count() + 1;
+
+    if hops > max_hops {
+        warn!("dropping a message that made {} hops, more than the maximum of {}", hops, max_hops);
+        Ok(())
+    } else {
+        msg.set_hop_count(hops);
+        to.send_msg(msg)
+    }
 }
 
 impl Drop for Bridge {
@@ -5,11 +5,14 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::io;
+use std::time::Duration;
 
 use super::*;
 use reactor;
 use core::{SocketId, EndpointId};
 use core::endpoint::Request;
+use core::socket::{PeerProtocols, EndpointInfos, EndpointInfo};
+use transport::{TransportInfo, Address};
 use io_error::*;
 
 #[doc(hidden)]
@@ -28,7 +31,7 @@ impl RequestSender {
         }
     }
     fn send(&self, req: Request) -> io::Result<()> {
-        self.req_tx.send(reactor::Request::Endpoint(self.socket_id, self.id, req)).map_err(from_send_error)
+        self.req_tx.send(reactor::Request::Endpoint(self.socket_id, self.id, req))
     }
 }
 
@@ -40,19 +43,125 @@ impl RequestSender {
 /// Note that `drop(Endpoint)` will **NOT** close it.
 pub struct Endpoint {
     request_sender: RequestSender,
-    remote: bool
+    remote: bool,
+    transport_info: TransportInfo,
+    peer_protocols: PeerProtocols,
+    endpoint_infos: EndpointInfos
 }
 
 impl Endpoint {
     #[doc(hidden)]
-    pub fn new(request_tx: RequestSender, remote: bool) -> Endpoint {
+    pub fn new(
+        request_tx: RequestSender,
+        remote: bool,
+        info: TransportInfo,
+        protocols: PeerProtocols,
+        infos: EndpointInfos) -> Endpoint {
         Endpoint {
             request_sender: request_tx,
-            remote: remote
+            remote: remote,
+            transport_info: info,
+            peer_protocols: protocols,
+            endpoint_infos: infos
         }
     }
 
+    /// Returns the id of the endpoint, which appears in the logs of the session.
+    pub fn id(&self) -> usize {
+        self.request_sender.id.into()
+    }
+
+    /// Returns the static properties of the transport used by this endpoint,
+    /// such as the write size it is best suited for when choosing how to chunk messages.
+    pub fn transport_info(&self) -> TransportInfo {
+        self.transport_info
+    }
+
+    /// Returns the protocol id advertised by the peer during the handshake,
+    /// see [the proto module](proto/index.html) for the values of the built-in protocols.
+    /// Returns `None` until the handshake of a connected endpoint is completed, 
+    /// once the connection is lost, and always for bound endpoints,
+    /// since they can accept several peers.
+    pub fn peer_protocol_id(&self) -> Option<u16> {
+        self.peer_protocols.lock().ok().and_then(|peers| peers.get(&self.request_sender.id).cloned())
+    }
+
+    /// Returns the address of the local side of the endpoint,
+    /// which is the address actually listened on for a bound endpoint, port included.
+    /// Returns `None` until a connected endpoint completes its handshake or a bound endpoint starts listening,
+    /// once the endpoint is closed, and when the transport does not report it,
+    /// like the unnamed local side of an ipc connection.
+    pub fn local_addr(&self) -> Option<Address> {
+        self.info().and_then(|info| info.local_addr)
+    }
+
+    /// Returns the address of the peer of a connected endpoint,
+    /// under the same conditions as [local_addr](#method.local_addr).
+    /// Always returns `None` for bound endpoints, since they can accept several peers.
+    pub fn peer_addr(&self) -> Option<Address> {
+        self.info().and_then(|info| info.peer_addr)
+    }
+
+    /// Returns the properties of the endpoint, addresses and handshake duration included,
+    /// under the same conditions as [local_addr](#method.local_addr).
+    /// Since a connected endpoint reports them once its handshake is completed,
+    /// this can be used to measure the connection setup latency of each peer.
+    pub fn info(&self) -> Option<EndpointInfo> {
+        self.endpoint_infos.lock().ok().and_then(|infos| infos.get(&self.request_sender.id).cloned())
+    }
+
+    /// Closes the endpoint while the socket stays alive.
+    /// For a connected endpoint, the pipe is removed from the socket,
+    /// which updates its readiness, and any pending reconnection is cancelled.
+    /// For a bound endpoint, the listener is closed, any pending rebind is cancelled,
+    /// and the connections it already accepted are kept alive.
     pub fn close(self) -> io::Result<()> {
         self.request_sender.send(Request::Close(self.remote))
     }
+
+    /// Closes the endpoint once its in-flight operations are completed.
+    /// The endpoint is no longer selected for new sends or receives,
+    /// and is removed as soon as its pending send and recv are finished,
+    /// or when the specified timeout expires.
+    /// Bound endpoints have nothing to drain and are closed immediately,
+    /// and so are connected endpoints whose connection is lost, pending reconnection included.
+    pub fn close_graceful(self, timeout: Duration) -> io::Result<()> {
+        self.request_sender.send(Request::CloseGraceful(self.remote, timeout))
+    }
+
+    /// Moves a bound endpoint to a new address, for example after an IP change.
+    /// The current listener is closed and the new address is bound after the retry interval,
+    /// and again on each retry interval until it succeeds.
+    /// Connections already accepted by the endpoint are kept alive.
+    /// Fails with `InvalidInput` when called on a connected endpoint.
+    pub fn rebind(&self, url: &str) -> io::Result<()> {
+        if self.remote {
+            return Err(invalid_input_io_error("rebind is only supported on bound endpoints"));
+        }
+
+        self.request_sender.send(Request::Rebind(From::from(url)))
+    }
+
+    /// Stops a connected endpoint from reconnecting once its connection is lost, without closing it,
+    /// for example during the maintenance of the peer.
+    /// The current connection, if any, is kept alive.
+    /// Fails with `InvalidInput` when called on a bound endpoint.
+    pub fn pause_reconnect(&self) -> io::Result<()> {
+        if !self.remote {
+            return Err(invalid_input_io_error("pause_reconnect is only supported on connected endpoints"));
+        }
+
+        self.request_sender.send(Request::PauseReconnect)
+    }
+
+    /// Lets a connected endpoint reconnect again after [pause_reconnect](#method.pause_reconnect).
+    /// If the connection was lost in the meantime, a reconnection is attempted immediately.
+    /// Fails with `InvalidInput` when called on a bound endpoint.
+    pub fn resume_reconnect(&self) -> io::Result<()> {
+        if !self.remote {
+            return Err(invalid_input_io_error("resume_reconnect is only supported on connected endpoints"));
+        }
+
+        self.request_sender.send(Request::ResumeReconnect)
+    }
 }
\ No newline at end of file
@@ -9,12 +9,11 @@ pub mod socket;
 pub mod endpoint;
 pub mod device;
 pub mod probe;
+pub mod server;
 
 use std::sync::mpsc;
 use std::io;
 
-use mio_extras;
-
 use reactor;
 use io_error::*;
 
@@ -26,9 +25,9 @@ impl<T> Receiver<T> for mpsc::Receiver<T> {
     fn receive(&self) -> io::Result<T> {
         match mpsc::Receiver::recv(self) {
             Ok(t)  => Ok(t),
-            Err(_) => Err(other_io_error("evt channel closed")),
+            Err(_) => Err(closed_io_error("session closed")),
         }
     }
 }
 
-pub type EventLoopRequestSender = mio_extras::channel::Sender<reactor::Request>;
+pub type EventLoopRequestSender = reactor::RequestSender;
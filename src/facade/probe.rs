@@ -37,7 +37,7 @@ impl RequestSender {
         }
     }
     fn send(&self, req: Request) -> io::Result<()> {
-        self.req_tx.send(reactor::Request::Probe(self.probe_id, req)).map_err(from_send_error)
+        self.req_tx.send(reactor::Request::Probe(self.probe_id, req))
     }
 }
 
@@ -0,0 +1,119 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::sync::{mpsc, Arc, Mutex, PoisonError};
+use std::panic::{self, AssertUnwindSafe};
+use std::thread;
+use std::io;
+
+use super::socket::Socket;
+use core::Message;
+use io_error::*;
+
+/// Helper to serve requests received by a [Rep](proto/rep/struct.Rep.html) socket
+/// with a bounded pool of worker threads.
+pub struct RepServer;
+
+impl RepServer {
+    /// Receives the requests from the specified `Rep` socket and hands them to `concurrency` worker threads,
+    /// each request being passed to the `handler` function and the result sent back to the requester.
+    /// At most `concurrency` requests are processed at the same time,
+    /// further requests wait in the socket until a worker becomes available.
+    /// The socket is switched to the raw mode, so the replies can be sent in any order
+    /// while still reaching the peer that sent the matching request,
+    /// each worker sending its replies through its own clone of the socket.
+    /// Should the handler panic, the worker survives and the request is left unanswered,
+    /// the requester resending it after its resend interval.
+    /// This function loops until it hits an error.
+    /// To break the loop and make the `serve` function exit,
+    /// drop the session that created the socket.
+    pub fn serve<F>(mut socket: Socket, concurrency: usize, handler: F) -> io::Result<()>
+    where F : Fn(Message) -> Message + Send + Sync + 'static {
+        if concurrency == 0 {
+            return Err(invalid_input_io_error("concurrency must be greater than zero"));
+        }
+
+        socket.plug_device()?;
+
+        let (job_tx, job_rx) = mpsc::channel();
+        let (done_tx, done_rx) = mpsc::channel();
+        let job_rx = Arc::new(Mutex::new(job_rx));
+        let handler = Arc::new(handler);
+        let workers: Vec<_> = (0..concurrency).map(|_| {
+            let jobs = job_rx.clone();
+            let done = done_tx.clone();
+            let handler = handler.clone();
+            let mut replier = socket.clone();
+
+            thread::spawn(move || work(&jobs, &done, &mut replier, &*handler))
+        }).collect();
+
+        // only the workers can tell that a job is done, the dispatcher must not keep the channel open
+        drop(done_tx);
+
+        let res = dispatch(&mut socket, concurrency, &job_tx, &done_rx);
+
+        drop(job_tx);
+        for worker in workers {
+            let _ = worker.join();
+        }
+
+        res
+    }
+}
+
+fn dispatch(
+    socket: &mut Socket,
+    concurrency: usize,
+    jobs: &mpsc::Sender<Message>,
+    done: &mpsc::Receiver<io::Result<()>>) -> io::Result<()> {
+
+    let mut in_progress = 0;
+
+    loop {
+        while let Ok(res) = done.try_recv() {
+            in_progress -= 1;
+            res?;
+        }
+
+        if in_progress == concurrency {
+            let res = done.recv().map_err(|_| other_io_error("worker pool stopped"))?;
+
+            in_progress -= 1;
+            res?;
+            continue;
+        }
+
+        match socket.recv_msg() {
+            Ok(request) => {
+                jobs.send(request).map_err(|_| other_io_error("worker pool stopped"))?;
+                in_progress += 1;
+            },
+            Err(ref e) if e.kind() == io::ErrorKind::TimedOut => continue,
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+fn work<F>(jobs: &Mutex<mpsc::Receiver<Message>>, done: &mpsc::Sender<io::Result<()>>, replier: &mut Socket, handler: &F)
+where F : Fn(Message) -> Message {
+    loop {
+        let job = jobs.lock().unwrap_or_else(PoisonError::into_inner).recv();
+        let request = match job {
+            Ok(msg) => msg,
+            Err(_) => return
+        };
+        let (header, body) = request.split();
+        let res = match panic::catch_unwind(AssertUnwindSafe(|| handler(Message::from_body(body)))) {
+            Ok(reply) => replier.send_msg(Message::from_header_and_body(header, reply.into())),
+            Err(_) => Ok(())
+        };
+
+        if done.send(res).is_err() {
+            return;
+        }
+    }
+}
@@ -6,10 +6,11 @@
 
 use std::collections::HashMap;
 use std::io;
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 use std::thread;
-use std::sync::mpsc;
-
-use mio_extras;
+use std::sync::{mpsc, Arc};
+use std::time::Duration;
 
 use super::*;
 use transport::Transport;
@@ -17,12 +18,18 @@ use reactor;
 use reactor::dispatcher;
 use core::session::{Request, Reply};
 use core::socket::{Protocol, ProtocolCtor};
+use core::config::ConfigOption;
+use core::EndpointIdAllocator;
+use core::clock::{Clock, SystemClock};
+use core::event_ring::{EventRing, ReactorEvent};
 use core;
 use io_error::*;
 
 #[doc(hidden)]
 type ReplyReceiver = mpsc::Receiver<Reply>;
 
+type ProtocolDefaults = HashMap<u16, Vec<ConfigOption>>;
+
 #[doc(hidden)]
 struct RequestSender {
     req_tx: EventLoopRequestSender
@@ -42,21 +49,33 @@ impl RequestSender {
         probe::RequestSender::new(self.req_tx.clone(), probe_id)
     }
     fn send(&self, req: Request) -> io::Result<()> {
-        self.req_tx.send(reactor::Request::Session(req)).map_err(from_send_error)
+        self.req_tx.send(reactor::Request::Session(req))
     }
 }
 
 /// Creates the session and starts the I/O thread.
 #[derive(Default)]
 pub struct SessionBuilder {
-    transports: HashMap<String, Box<dyn Transport + Send>, core::BuildIdHasher>
+    transports: HashMap<String, Box<dyn Transport + Send>, core::BuildIdHasher>,
+    defaults: ProtocolDefaults,
+    spin_budget: Duration,
+    max_sockets: Option<usize>,
+    id_allocator: Option<Box<dyn EndpointIdAllocator + Send>>,
+    event_ring_capacity: Option<usize>,
+    clock: Option<Box<dyn Clock + Send>>
 }
 
 impl SessionBuilder {
 
     pub fn new() -> SessionBuilder {
         SessionBuilder {
-            transports: HashMap::with_hasher(core::BuildIdHasher)
+            transports: HashMap::with_hasher(core::BuildIdHasher),
+            defaults: HashMap::new(),
+            spin_budget: Duration::from_millis(0),
+            max_sockets: None,
+            id_allocator: None,
+            event_ring_capacity: None,
+            clock: None
         }
     }
 
@@ -66,13 +85,81 @@ impl SessionBuilder {
         self
     }
 
+    /// Registers a default value for a protocol specific option.
+    /// The option is applied to every socket created by the session 
+    /// whose protocol id matches `proto_id` (see the constants of [the proto module](proto/index.html)),
+    /// before the socket is handed to the application, which can still override it.
+    /// Options not supported by the protocol are ignored.
+    pub fn with_default(mut self, proto_id: u16, option: ConfigOption) -> SessionBuilder {
+        self.defaults.entry(proto_id).or_default().push(option);
+        self
+    }
+
+    /// Sets how long the I/O thread keeps polling without blocking after it handled some events.
+    /// Under bursty load, this saves the cost of waking the thread up for each message of the burst,
+    /// at the expense of burning some CPU during the budget. 
+    /// Once no event was found for the whole budget, the thread blocks again, so an idle session does not spin.
+    /// Default value is zero, meaning the thread always blocks.
+    pub fn with_spin_budget(mut self, budget: Duration) -> SessionBuilder {
+        self.spin_budget = budget;
+        self
+    }
+
+    /// Caps the number of sockets the session can hold at the same time, to catch leaks.
+    /// Once the cap is reached, creating a socket fails until another one is closed.
+    /// By default, the number of sockets is unbounded.
+    pub fn with_max_sockets(mut self, max: usize) -> SessionBuilder {
+        self.max_sockets = Some(max);
+        self
+    }
+
+    /// Replaces the counter used to choose the ids of the endpoints with the specified allocator,
+    /// to give them ids that are meaningful in the logs, by encoding the socket id for example.
+    /// The ids are those returned by [Endpoint::id](struct.Endpoint.html#method.id).
+    pub fn with_endpoint_id_allocator<A>(mut self, allocator: A) -> SessionBuilder
+    where A : EndpointIdAllocator + Send + 'static {
+        self.id_allocator = Some(Box::new(allocator));
+        self
+    }
+
+    /// Makes the I/O thread record the most recent endpoint events, like pipes being opened, 
+    /// accepted, failing or closed, in a ring holding at most `capacity` events.
+    /// Recording is cheap enough to be left enabled in production: 
+    /// the ring is allocated once and written without locking.
+    /// The events can then be retrieved with [Session::recent_events](struct.Session.html#method.recent_events).
+    /// By default, no event is recorded.
+    pub fn with_event_ring(mut self, capacity: usize) -> SessionBuilder {
+        self.event_ring_capacity = Some(capacity);
+        self
+    }
+
+    /// Replaces the time source deciding when the timeouts, retries and other scheduled tasks of the sockets are due.
+    /// Mostly useful to tests, that can move a [TestClock](struct.TestClock.html) forward
+    /// instead of waiting for the system clock, provided the `testing` feature is enabled.
+    /// By default, the [SystemClock](struct.SystemClock.html) is used.
+    pub fn with_clock<C>(mut self, clock: C) -> SessionBuilder
+    where C : Clock + Send + 'static {
+        self.clock = Some(Box::new(clock));
+        self
+    }
+
     pub fn build(self) -> io::Result<Session> {
 
         let (reply_tx, reply_rx) = mpsc::channel();
-        let (request_tx, request_rx) = mio_extras::channel::channel();
-        let session = Session::new(RequestSender::new(request_tx), reply_rx);
-
-        thread::spawn(move || dispatcher::Dispatcher::dispatch(self.transports, request_rx, reply_tx));
+        let (request_tx, request_rx) = reactor::request_channel()?;
+        let infos = self.transports.iter().map(|(scheme, t)| (scheme.clone(), t.info())).collect();
+        let ring = self.event_ring_capacity.map(|capacity| Arc::new(EventRing::new(capacity)));
+        let session = Session::new(RequestSender::new(request_tx), reply_rx, self.defaults, Arc::new(infos), ring.clone());
+        let transports = self.transports;
+        let settings = dispatcher::Settings {
+            spin_budget: self.spin_budget,
+            max_sockets: self.max_sockets,
+            id_allocator: self.id_allocator,
+            event_ring: ring,
+            clock: self.clock.unwrap_or_else(|| Box::new(SystemClock))
+        };
+
+        thread::spawn(move || dispatcher::Dispatcher::dispatch(transports, request_rx, reply_tx, settings));
 
         Ok(session)
     }
@@ -81,15 +168,26 @@ impl SessionBuilder {
 /// Creates sockets and devices.
 pub struct Session {
     request_sender: RequestSender,
-    reply_receiver: ReplyReceiver
+    reply_receiver: ReplyReceiver,
+    defaults: ProtocolDefaults,
+    transport_infos: socket::TransportInfos,
+    event_ring: Option<Arc<EventRing>>
 }
 
 impl Session {
 
-    fn new(request_tx: RequestSender, reply_rx: ReplyReceiver) -> Session {
+    fn new(
+        request_tx: RequestSender,
+        reply_rx: ReplyReceiver,
+        protocol_defaults: ProtocolDefaults,
+        infos: socket::TransportInfos,
+        ring: Option<Arc<EventRing>>) -> Session {
         Session {
             request_sender: request_tx,
-            reply_receiver: reply_rx
+            reply_receiver: reply_rx,
+            defaults: protocol_defaults,
+            transport_infos: infos,
+            event_ring: ring
         }
     }
 
@@ -107,25 +205,51 @@ impl Session {
     pub fn create_socket<T>(&mut self) -> io::Result<socket::Socket>
     where T : Protocol + From<mpsc::Sender<core::socket::Reply>> + 'static
     {
-        let protocol_ctor = Session::create_protocol_ctor::<T>();
+        let protocol_ctor = self.create_protocol_ctor::<T>();
         let request = Request::CreateSocket(protocol_ctor);
 
         self.call(request, |reply| self.on_create_socket_reply(reply))
     }
 
-    fn create_protocol_ctor<T>() -> ProtocolCtor 
+    /// Creates a socket with the specified protocol, wrapped by the `decorator` function.
+    /// The decorator is given the protocol instance and returns the one the socket will actually use,
+    /// so it can interpose on the operations of the protocol, for example to inject faults in tests.
+    pub fn create_socket_with_decorator<T, D>(&mut self, decorator: D) -> io::Result<socket::Socket>
+    where T : Protocol + From<mpsc::Sender<core::socket::Reply>> + 'static,
+          D : Fn(Box<dyn Protocol>) -> Box<dyn Protocol> + Send + 'static
+    {
+        let protocol_ctor = self.create_protocol_ctor::<T>();
+        let decorated_ctor: ProtocolCtor = Box::new(move |sender: mpsc::Sender<core::socket::Reply>| {
+            decorator(protocol_ctor(sender))
+        });
+        let request = Request::CreateSocket(decorated_ctor);
+
+        self.call(request, |reply| self.on_create_socket_reply(reply))
+    }
+
+    fn create_protocol_ctor<T>(&self) -> ProtocolCtor 
     where T : Protocol + From<mpsc::Sender<core::socket::Reply>> + 'static
     {
+        let defaults = self.defaults.clone();
+
         Box::new(move |sender: mpsc::Sender<core::socket::Reply>| {
-            Box::new(T::from(sender)) as Box<dyn Protocol>
+            let mut protocol = T::from(sender);
+
+            if let Some(options) = defaults.get(&protocol.id()) {
+                for option in options {
+                    let _ = protocol.set_option(option.clone());
+                }
+            }
+
+            Box::new(protocol) as Box<dyn Protocol>
         })
     }
 
     fn on_create_socket_reply(&self, reply: Reply) -> io::Result<socket::Socket> {
         match reply {
-            Reply::SocketCreated(id, rx) => {
+            Reply::SocketCreated(id, rx, peer_count, peer_protocols, endpoint_infos) => {
                 let sender = self.request_sender.socket_sender(id);
-                let sock = socket::Socket::new(sender, rx);
+                let sock = socket::Socket::new(sender, rx, peer_count, peer_protocols, endpoint_infos, self.transport_infos.clone());
                 
                 Ok(sock)
             },
@@ -142,22 +266,50 @@ impl Session {
 
     /// Creates a loopback device that loops and sends any messages received from the socket back to itself.
     pub fn create_relay_device(&self, socket: socket::Socket) -> io::Result<Box<dyn device::Device>> {
-        Ok(Box::new(device::Relay::new(socket)))
+        self.create_relay_device_with_config(socket, device::DeviceConfig::default())
+    }
+
+    /// Creates a loopback device that loops and sends any messages received from the socket back to itself,
+    /// using the specified settings.
+    pub fn create_relay_device_with_config(
+        &self,
+        mut socket: socket::Socket,
+        config: device::DeviceConfig) -> io::Result<Box<dyn device::Device>> {
+        socket.set_option(ConfigOption::FrameExtensions(true))?;
+
+        Ok(Box::new(device::Relay::new(socket, config)))
     }
 
     /// Creates a bridge device to forward messages between two sockets. 
     /// It loops and sends any messages received from `left` to `right` and vice versa.
     pub fn create_bridge_device(&mut self, left: socket::Socket, right: socket::Socket) -> io::Result<Box<dyn device::Device>> {
+        self.create_bridge_device_with_config(left, right, device::DeviceConfig::default())
+    }
+
+    /// Creates a bridge device to forward messages between two sockets, using the specified settings.
+    pub fn create_bridge_device_with_config(
+        &mut self,
+        mut left: socket::Socket,
+        mut right: socket::Socket,
+        config: device::DeviceConfig) -> io::Result<Box<dyn device::Device>> {
+        left.set_option(ConfigOption::FrameExtensions(true))?;
+        right.set_option(ConfigOption::FrameExtensions(true))?;
+
         let request = Request::CreateDevice(left.id(), right.id());
 
-        self.call(request, |reply| self.on_create_device_reply(reply, left, right))
+        self.call(request, |reply| self.on_create_device_reply(reply, left, right, config))
     }
 
-    fn on_create_device_reply(&self, reply: Reply, left: socket::Socket, right: socket::Socket) -> io::Result<Box<dyn device::Device>> {
+    fn on_create_device_reply(
+        &self,
+        reply: Reply,
+        left: socket::Socket,
+        right: socket::Socket,
+        config: device::DeviceConfig) -> io::Result<Box<dyn device::Device>> {
         match reply {
             Reply::DeviceCreated(id, rx) => {
                 let sender = self.request_sender.device_sender(id);
-                let bridge = device::Bridge::new(sender, rx, left, right);
+                let bridge = device::Bridge::new(sender, rx, left, right, config);
                 
                 Ok(Box::new(bridge))
             },
@@ -192,6 +344,54 @@ impl Session {
         }
     }
 
+/*****************************************************************************/
+/*                                                                           */
+/* Supervision                                                               */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Checks whether the I/O thread is still running and responsive,
+    /// by sending it a ping and waiting for the answer during the specified `timeout`.
+    /// A supervisor can then decide to drop the session and build a new one.
+    pub fn is_healthy_within(&self, timeout: Duration) -> bool {
+        if self.send_request(Request::Ping).is_err() {
+            return false;
+        }
+
+        loop {
+            match self.reply_receiver.recv_timeout(timeout) {
+                Ok(Reply::Pong) => return true,
+                Ok(_) => continue,
+                Err(_) => return false
+            }
+        }
+    }
+
+    /// Checks whether the I/O thread is still running and responsive, waiting at most 1 second for it to answer.
+    /// See [is_healthy_within](#method.is_healthy_within).
+    pub fn is_healthy(&self) -> bool {
+        self.is_healthy_within(Duration::from_secs(1))
+    }
+
+    /// Returns the most recent endpoint events recorded by the I/O thread, oldest first.
+    /// This does not involve the I/O thread, so it can be called even if it is stuck or gone.
+    /// Always empty unless the session was built [with_event_ring](struct.SessionBuilder.html#method.with_event_ring).
+    pub fn recent_events(&self) -> Vec<ReactorEvent> {
+        self.event_ring.as_ref().map_or_else(Vec::new, |ring| ring.snapshot())
+    }
+
+    /// Returns a file descriptor that is readable while requests sent by the session, its sockets, 
+    /// endpoints, devices or probes are waiting for the I/O thread to pick them up.
+    /// The session has no manual stepping mode: the I/O thread drives the sockets on its own,
+    /// so the descriptor is mostly useful to an external event loop or a watchdog wanting
+    /// to notice when that thread lags behind, by polling the descriptor with level triggered readiness.
+    /// To wait for messages, [Socket::recv_fd](struct.Socket.html#method.recv_fd) is the better fit.
+    /// The descriptor must only be polled, never read nor closed, and it stays valid as long as the session is alive.
+    #[cfg(unix)]
+    pub fn wakeup_fd(&self) -> RawFd {
+        self.request_sender.req_tx.wakeup().as_raw_fd()
+    }
+
 /*****************************************************************************/
 /*                                                                           */
 /* backend                                                                   */
@@ -215,7 +415,13 @@ impl Session {
     }
 
     fn recv_reply(&self) -> io::Result<Reply> {
-        self.reply_receiver.receive()
+        loop {
+            match self.reply_receiver.receive()? {
+                // answer to a ping that timed out
+                Reply::Pong => continue,
+                reply => return Ok(reply)
+            }
+        }
     }
 }
 
@@ -4,39 +4,154 @@
 // or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
 // This file may not be copied, modified, or distributed except according to those terms.
 
-use std::sync::mpsc;
+use std::collections::HashMap;
+use std::{error, fmt, mem};
+use std::sync::{mpsc, Arc};
+use std::sync::atomic::Ordering;
 use std::io;
-use std::time::Duration;
+use std::ops::{Deref, Range};
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
+
+use byteorder::*;
 
 use super::*;
 use reactor;
-use core::{SocketId, Message, PollReq};
-use core::socket::{Request, Reply};
-use core::config::ConfigOption;
+use core::{SocketId, EndpointId, Message, PollReq};
+use core::socket::{Request, Reply, RecvReducer, RecvMap, SubscriptionValidator, PeerCount, PeerProtocols, EndpointInfos, SizeHistogram, Capabilities, SocketStats, HandleId};
+use core::config::{ConfigOption, EndpointConfig, EndpointRole};
+use core::pool::RecvPool;
 use core;
+use transport::{TransportInfo, Stream, PeerCred};
 use io_error::*;
 
 #[doc(hidden)]
 pub type ReplyReceiver = mpsc::Receiver<Reply>;
 
+#[doc(hidden)]
+pub type TransportInfos = Arc<HashMap<String, TransportInfo>>;
+
 #[doc(hidden)]
 pub struct RequestSender {
     req_tx: EventLoopRequestSender,
-    socket_id: SocketId
+    socket_id: SocketId,
+    handle: HandleId
 }
 
 impl RequestSender {
     pub fn new(tx: EventLoopRequestSender, id: SocketId) -> RequestSender {
         RequestSender {
             req_tx: tx,
-            socket_id: id
+            socket_id: id,
+            handle: 0
+        }
+    }
+    fn clone_sender(&self, handle_id: HandleId) -> RequestSender {
+        RequestSender {
+            req_tx: self.req_tx.clone(),
+            socket_id: self.socket_id,
+            handle: handle_id
         }
     }
+    fn sibling_sender(&self, id: SocketId) -> RequestSender {
+        RequestSender::new(self.req_tx.clone(), id)
+    }
     fn child_sender(&self, eid: core::EndpointId) -> endpoint::RequestSender {
         endpoint::RequestSender::new(self.req_tx.clone(), self.socket_id, eid)
     }
     fn send(&self, req: Request) -> io::Result<()> {
-        self.req_tx.send(reactor::Request::Socket(self.socket_id, req)).map_err(from_send_error)
+        self.req_tx.send(reactor::Request::Socket(self.socket_id, self.handle, req))
+    }
+    fn send_to_endpoint(&self, eid: core::EndpointId, req: core::endpoint::Request) -> io::Result<()> {
+        self.req_tx.send(reactor::Request::Endpoint(self.socket_id, eid, req))
+    }
+}
+
+/// Error carried by the `io::Error` returned by [Socket::recv_exact](struct.Socket.html#method.recv_exact)
+/// when the body of the received message does not have the expected length.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LengthMismatch {
+    pub expected: usize,
+    pub actual: usize
+}
+
+impl LengthMismatch {
+    /// Extracts the length mismatch carried by the specified error, if any.
+    pub fn of(err: &io::Error) -> Option<LengthMismatch> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<LengthMismatch>()).cloned()
+    }
+}
+
+impl fmt::Display for LengthMismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "expected a body of {} bytes, received {} bytes", self.expected, self.actual)
+    }
+}
+
+impl error::Error for LengthMismatch {}
+
+/// Message lent by [Socket::recv_borrowed](struct.Socket.html#method.recv_borrowed).
+/// It dereferences to the body of the message, and borrows the socket mutably,
+/// so it must be dropped before the socket can receive again:
+///
+/// ```compile_fail,E0499
+/// # fn recv_twice(socket: &mut scaproust::Socket) {
+/// let first = socket.recv_borrowed().unwrap();
+/// let second = socket.recv_borrowed().unwrap();
+///
+/// assert_eq!(&first[..], &second[..]);
+/// # }
+/// ```
+pub struct BorrowedMessage<'a> {
+    msg: &'a Message
+}
+
+impl<'a> BorrowedMessage<'a> {
+    pub fn get_header(&self) -> &[u8] {
+        self.msg.get_header()
+    }
+
+    pub fn get_body(&self) -> &[u8] {
+        self.msg.get_body()
+    }
+}
+
+impl<'a> Deref for BorrowedMessage<'a> {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        self.msg.get_body()
+    }
+}
+
+/// Message body received by [Socket::recv_pooled](struct.Socket.html#method.recv_pooled).
+/// It dereferences to the body, stored in the buffer the pipe read the message into,
+/// and gives that buffer back to the pool of the socket when dropped.
+pub struct PooledMessage {
+    buffer: Vec<u8>,
+    pool: Option<RecvPool>
+}
+
+impl PooledMessage {
+    pub fn get_body(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Deref for PooledMessage {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        &self.buffer
+    }
+}
+
+impl Drop for PooledMessage {
+    fn drop(&mut self) {
+        if let Some(pool) = self.pool.take() {
+            pool.give_back(mem::take(&mut self.buffer));
+        }
     }
 }
 
@@ -44,17 +159,40 @@ impl RequestSender {
 ///   
 /// It is an abstraction of an application's "connection" to a messaging topology.
 /// Applications can have more than one Socket open at a time.
+///
+/// Cloning a socket gives another handle on the same underlying socket, that can be moved to another thread.
+/// The clones can send concurrently, their messages being queued one after the other,
+/// while only one of them at a time can wait for a message, the others failing with a `WouldBlock` error.
+/// The socket is closed when the last clone is dropped.
 pub struct Socket {
     request_sender: RequestSender,
-    reply_receiver: ReplyReceiver
+    reply_receiver: ReplyReceiver,
+    peer_count: PeerCount,
+    peer_protocols: PeerProtocols,
+    endpoint_infos: EndpointInfos,
+    transport_infos: TransportInfos,
+    lent: Option<Message>,
+    recv_pool: Option<RecvPool>
 }
 
 impl Socket {
     #[doc(hidden)]
-    pub fn new(request_tx: RequestSender, reply_rx: ReplyReceiver) -> Socket {
+    pub fn new(
+        request_tx: RequestSender,
+        reply_rx: ReplyReceiver,
+        peers: PeerCount,
+        protocols: PeerProtocols,
+        endpoints: EndpointInfos,
+        infos: TransportInfos) -> Socket {
         Socket {
             request_sender: request_tx,
-            reply_receiver: reply_rx
+            reply_receiver: reply_rx,
+            peer_count: peers,
+            peer_protocols: protocols,
+            endpoint_infos: endpoints,
+            transport_infos: infos,
+            lent: None,
+            recv_pool: None
         }
     }
 
@@ -63,6 +201,13 @@ impl Socket {
         self.request_sender.socket_id
     }
 
+    /// Returns whether at least one pipe of the socket has completed its handshake with a peer.
+    /// The value is maintained by the event loop as pipes are opened and closed,
+    /// so this check never blocks but may lag slightly behind the actual state.
+    pub fn is_connected(&self) -> bool {
+        self.peer_count.load(Ordering::SeqCst) > 0
+    }
+
     /// Creates a poll request that can be used to initialize a probe.
     /// The probe will then allow polling a group of sockets
     pub fn create_poll_req(&self, recv: bool, send: bool) -> PollReq {
@@ -87,17 +232,70 @@ impl Socket {
     /// Note that bind and connect may be called multiple times on the same socket,
     /// thus allowing the socket to communicate with multiple heterogeneous endpoints.
     /// On success, returns an [Endpoint](struct.Endpoint.html) that can be later used to remove the endpoint from the socket.
+    /// Fails immediately with an `InvalidInput` error when the url is malformed or its transport is unknown,
+    /// while a remote endpoint that cannot be reached yet is added anyway, the connection being retried later.
     pub fn connect(&mut self, url: &str) -> io::Result<endpoint::Endpoint> {
         let request = Request::Connect(From::from(url));
 
-        self.call(request, |reply| self.on_connect_reply(reply))
+        self.call(request, |reply| self.on_connect_reply(reply, url))
+    }
+
+    /// Adds a remote endpoint to the socket and waits for the connection to be established.
+    /// Returns once the handshake with the peer of this endpoint is completed,
+    /// or fails with a `TimedOut` error if it is not within the specified timeout,
+    /// in which case the endpoint is removed from the socket.
+    /// Reconnection attempts made before the timeout expires are waited for.
+    pub fn connect_blocking(&mut self, url: &str, timeout: Duration) -> io::Result<endpoint::Endpoint> {
+        let request = Request::ConnectBlocking(From::from(url), timeout);
+
+        self.call(request, |reply| self.on_connect_reply(reply, url))
+    }
+
+    /// Adds a remote endpoint to the socket, using an already connected stream instead of a url.
+    /// This is useful when the connection is established by other means, 
+    /// an HTTP upgrade for example: the SP handshake is run over the stream, 
+    /// but since the connection is not owned by a transport, it is not reestablished once lost.
+    pub fn connect_stream<S: Into<Stream>>(&mut self, stream: S) -> io::Result<endpoint::Endpoint> {
+        let stream = stream.into();
+        let url = stream.url();
+        let request = Request::ConnectStream(stream);
+
+        self.call(request, |reply| self.on_connect_reply(reply, &url))
+    }
+
+    /// Adds `count` remote endpoints to the socket, all connected to the same address,
+    /// so that a load balancing protocol like Push spreads the sends across parallel connections,
+    /// which helps to aggregate throughput over high latency links.
+    /// Each connection is reestablished on its own once lost.
+    /// Fails with `InvalidInput` when count is zero, and if one of the endpoints can not be added,
+    /// the ones already added are closed before the error is returned.
+    pub fn connect_n(&mut self, url: &str, count: usize) -> io::Result<Vec<endpoint::Endpoint>> {
+        if count == 0 {
+            return Err(invalid_input_io_error("connection count must be greater than zero"));
+        }
+
+        let mut endpoints = Vec::with_capacity(count);
+
+        for _ in 0..count {
+            match self.connect(url) {
+                Ok(ep) => endpoints.push(ep),
+                Err(e) => {
+                    for ep in endpoints {
+                        let _ = ep.close();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+
+        Ok(endpoints)
     }
 
-    fn on_connect_reply(&self, reply: Reply) -> io::Result<endpoint::Endpoint> {
+    fn on_connect_reply(&self, reply: Reply, url: &str) -> io::Result<endpoint::Endpoint> {
         match reply {
             Reply::Connect(id) => {
                 let request_tx = self.request_sender.child_sender(id);
-                let ep = endpoint::Endpoint::new(request_tx, true);
+                let ep = endpoint::Endpoint::new(request_tx, true, self.transport_info(url), self.peer_protocols.clone(), self.endpoint_infos.clone());
                 
                 Ok(ep)
             },
@@ -106,6 +304,13 @@ impl Socket {
         }
     }
 
+    fn transport_info(&self, url: &str) -> TransportInfo {
+        url.find("://")
+            .and_then(|index| self.transport_infos.get(&url[..index]))
+            .cloned()
+            .unwrap_or_default()
+    }
+
 /*****************************************************************************/
 /*                                                                           */
 /* bind                                                                      */
@@ -122,14 +327,38 @@ impl Socket {
     pub fn bind(&mut self, url: &str) -> io::Result<endpoint::Endpoint> {
         let request = Request::Bind(From::from(url));
 
-        self.call(request, |reply| self.on_bind_reply(reply))
+        self.call(request, |reply| self.on_bind_reply(reply, url))
+    }
+
+    /// Adds a local endpoint to the socket, bound to the first free port of the specified range.
+    /// The addr argument is an url without port, like `tcp://127.0.0.1`.
+    /// Ports are tried in order until one can be bound, only the ports already in use are skipped,
+    /// any other error is returned right away.
+    /// On success, returns the [Endpoint](struct.Endpoint.html) along with the chosen port.
+    pub fn bind_in_range(&mut self, addr: &str, ports: Range<u16>) -> io::Result<(endpoint::Endpoint, u16)> {
+        let mut last_err = invalid_input_io_error("empty port range");
+
+        for port in ports {
+            let url = format!("{}:{}", addr, port);
+
+            match self.bind(&url) {
+                Ok(ep) => return Ok((ep, port)),
+                Err(e) => if e.kind() == io::ErrorKind::AddrInUse {
+                    last_err = e
+                } else {
+                    return Err(e)
+                }
+            }
+        }
+
+        Err(last_err)
     }
 
-    fn on_bind_reply(&self, reply: Reply) -> io::Result<endpoint::Endpoint> {
+    fn on_bind_reply(&self, reply: Reply, url: &str) -> io::Result<endpoint::Endpoint> {
         match reply {
             Reply::Bind(id) => {
                 let request_tx = self.request_sender.child_sender(id);
-                let ep = endpoint::Endpoint::new(request_tx, false);
+                let ep = endpoint::Endpoint::new(request_tx, false, self.transport_info(url), self.peer_protocols.clone(), self.endpoint_infos.clone());
                 
                 Ok(ep)
             },
@@ -138,6 +367,57 @@ impl Socket {
         }
     }
 
+/*****************************************************************************/
+/*                                                                           */
+/* endpoint config                                                           */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Adds all the described endpoints to the socket, connecting or binding each of them
+    /// with its own options, which override the socket values for this endpoint only.
+    /// Stops at the first endpoint that can not be added, the previous ones being kept.
+    /// On success, returns the [Endpoints](struct.Endpoint.html) in the order of the configs.
+    pub fn apply_endpoint_config(&mut self, configs: &[EndpointConfig]) -> io::Result<Vec<endpoint::Endpoint>> {
+        let mut endpoints = Vec::with_capacity(configs.len());
+
+        for config in configs {
+            let url = config.url.clone();
+            let remote = config.role == EndpointRole::Connect;
+            let request = Request::ApplyEndpointConfig(config.clone());
+            let ep = self.call(request, |reply| self.on_apply_endpoint_config_reply(reply, &url, remote))?;
+
+            endpoints.push(ep);
+        }
+
+        Ok(endpoints)
+    }
+
+    fn on_apply_endpoint_config_reply(&self, reply: Reply, url: &str, remote: bool) -> io::Result<endpoint::Endpoint> {
+        if remote {
+            self.on_connect_reply(reply, url)
+        } else {
+            self.on_bind_reply(reply, url)
+        }
+    }
+
+    /// Describes the endpoints added to the socket by `connect`, `bind` and their variants,
+    /// in the order they were added, so they can be applied later to another socket.
+    /// The options of each endpoint are listed exhaustively, 
+    /// and endpoints closed by the application are not reported.
+    pub fn dump_endpoint_config(&mut self) -> io::Result<Vec<EndpointConfig>> {
+        let request = Request::DumpEndpointConfig;
+
+        self.call(request, |reply| self.on_dump_endpoint_config_reply(reply))
+    }
+
+    fn on_dump_endpoint_config_reply(&self, reply: Reply) -> io::Result<Vec<EndpointConfig>> {
+        match reply {
+            Reply::EndpointConfig(configs) => Ok(configs),
+            Reply::Err(e)                  => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
 /*****************************************************************************/
 /*                                                                           */
 /* send                                                                      */
@@ -158,6 +438,39 @@ impl Socket {
         self.call(request, |reply| self.on_send_reply(reply))
     }
 
+    /// Sends a frame that is already encoded for the wire, such as one received by a relay,
+    /// which the transports write as is instead of encoding the message length before the body.
+    /// The frame must start with the length of the rest of the frame as a big endian `u64`,
+    /// which is the only check made here: the caller is responsible for the rest of the framing,
+    /// including the protocol header, since the one the protocol would add is ignored.
+    /// This is therefore only suitable for protocols that send messages unchanged, like pair, push, pub or bus.
+    /// The frame is shared, not copied, so the same one can be sent by several sockets.
+    pub fn send_prewrapped(&mut self, frame: Arc<[u8]>) -> io::Result<()> {
+        if frame.len() < 8 || BigEndian::read_u64(&frame[..8]) != (frame.len() - 8) as u64 {
+            return Err(invalid_input_io_error("frame length prefix does not match its size"));
+        }
+
+        self.send_msg(Message::from_frame(frame))
+    }
+
+    /// Queues a buffer for sending and returns without waiting for it to be sent.
+    /// See [send_async_msg](#method.send_async_msg).
+    pub fn send_async(&mut self, buffer: Vec<u8>) -> io::Result<()> {
+        self.send_async_msg(Message::from_body(buffer))
+    }
+
+    /// Queues a message for sending and returns without waiting for it to be sent.
+    /// Queued messages are handed to the protocol one at a time, in order, 
+    /// each one being subject to the send timeout.
+    /// The call only blocks when the [send queue](#method.set_send_queue_size) is full.
+    /// Since the outcome of the actual send is not reported, 
+    /// a message that could not be sent is silently dropped.
+    pub fn send_async_msg(&mut self, msg: Message) -> io::Result<()> {
+        let request = Request::SendAsync(msg);
+
+        self.call(request, |reply| self.on_send_reply(reply))
+    }
+
     /// Non-blocking version of the send method.
     pub fn try_send(&mut self, buffer: Vec<u8>) -> io::Result<()> {
         self.try_send_msg(Message::from_body(buffer))
@@ -170,9 +483,76 @@ impl Socket {
         self.call(request, |reply| self.on_send_reply(reply))
     }
 
+    /// Sends a message that the receiver should discard if it arrives after the specified deadline.
+    /// The deadline is converted to an absolute wall clock time and carried in the extension block of the frame,
+    /// so it only reaches the peers with which the [frame extensions](enum.ConfigOption.html#variant.FrameExtensions)
+    /// were negotiated, and is only checked by a socket having enabled 
+    /// [deadline enforcement](#method.set_enforce_deadlines). The payload is never modified.
+    /// Since the sender and receiver clocks are compared, expiration is best-effort:
+    /// any clock skew between the two hosts shifts the deadline by the same amount.
+    pub fn send_with_deadline(&mut self, mut msg: Message, deadline: Instant) -> io::Result<()> {
+        let now = Instant::now();
+        let wall_deadline = if deadline > now {
+            SystemTime::now() + (deadline - now)
+        } else {
+            SystemTime::now() - (now - deadline)
+        };
+
+        msg.set_deadline(Some(wall_deadline));
+        self.send_msg(msg)
+    }
+
+    /// Sends a message that the receiver should deliver only once, even if it is sent again with the same id.
+    /// The id is carried in the extension block of the frame, so it only reaches the peers with which the
+    /// [frame extensions](enum.ConfigOption.html#variant.FrameExtensions) were negotiated,
+    /// and is only checked by a socket having enabled the [DedupWindow](enum.ConfigOption.html#variant.DedupWindow) option.
+    /// The payload is never modified.
+    pub fn send_with_dedup_id(&mut self, mut msg: Message, id: u64) -> io::Result<()> {
+        msg.set_dedup_id(Some(id));
+        self.send_msg(msg)
+    }
+
+    /// Sends a survey and returns the number of respondents it was handed to when it was sent.
+    /// Respondents whose pipe was not ready to send at that time are not counted,
+    /// even if the [resend](enum.ConfigOption.html#variant.SurveyResendIvl) reaches them later,
+    /// so quorum logic can account for them without waiting for the survey deadline.
+    /// Only supported by the `Surveyor` socket, other protocols fail with an `InvalidInput` error
+    /// without sending anything.
+    pub fn send_survey(&mut self, msg: Message) -> io::Result<usize> {
+        let request = Request::SendSurvey(msg);
+
+        self.call(request, |reply| self.on_send_survey_reply(reply))
+    }
+
+    fn on_send_survey_reply(&self, reply: Reply) -> io::Result<usize> {
+        match reply {
+            Reply::SendReached(reached) => Ok(reached.len()),
+            Reply::Err(e) => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Abandons the request sent by a `Req` socket, whose reply is no longer expected.
+    /// The request is not resent anymore and a pending recv, if any, fails.
+    /// On the protocols that have no request to cancel, this does nothing.
+    pub fn cancel_request(&mut self) -> io::Result<()> {
+        let request = Request::CancelRequest;
+
+        self.call(request, |reply| self.on_cancel_request_reply(reply))
+    }
+
+    fn on_cancel_request_reply(&self, reply: Reply) -> io::Result<()> {
+        match reply {
+            Reply::CancelRequest => Ok(()),
+            Reply::Err(e) => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
     fn on_send_reply(&self, reply: Reply) -> io::Result<()> {
         match reply {
             Reply::Send => Ok(()),
+            Reply::SendReached(_) => Ok(()),
             Reply::Err(e) => Err(e),
             _ => self.unexpected_reply()
         }
@@ -191,11 +571,93 @@ impl Socket {
 
     /// Receives a message.
     pub fn recv_msg(&mut self) -> io::Result<Message> {
+        self.recv_from().map(|(msg, _)| msg)
+    }
+
+    /// Receives a message, along with the id of the endpoint it arrived on,
+    /// which is the one of the pipe the message was actually read from,
+    /// including for the protocols that fair-queue their peers.
+    /// For a bound endpoint, this is the id of the accepted connection, not the one of the listener.
+    pub fn recv_from(&mut self) -> io::Result<(Message, EndpointId)> {
         let request = Request::Recv(false);
 
         self.call(request, |reply| self.on_recv_reply(reply))
     }
 
+    /// Receives a message, along with the time spent waiting for it.
+    pub fn recv_timed(&mut self) -> io::Result<(Message, Duration)> {
+        let started = Instant::now();
+
+        self.recv_msg().map(|msg| (msg, started.elapsed()))
+    }
+
+    /// Receives a message whose body is expected to be exactly `len` bytes long.
+    /// When it is not, the message is consumed anyway and an `InvalidData` error is returned,
+    /// carrying a [LengthMismatch](struct.LengthMismatch.html).
+    pub fn recv_exact(&mut self, len: usize) -> io::Result<Message> {
+        let msg = self.recv_msg()?;
+        let body_len = msg.get_body().len();
+
+        if body_len == len {
+            Ok(msg)
+        } else {
+            Err(invalid_data_io_error(LengthMismatch { expected: len, actual: body_len }))
+        }
+    }
+
+    /// Receives a message and lends it, instead of handing its ownership to the caller.
+    /// The message is kept by the socket until the next call to this method,
+    /// the borrow checker ensuring the returned view is not used past that point.
+    /// The buffer of the previously lent message then goes back to the [recv pool](#method.set_recv_pool)
+    /// of the socket, if any, so the pipes read the next message into it instead of allocating a new one.
+    /// This suits applications that only parse the received bytes and never need to keep them.
+    pub fn recv_borrowed(&mut self) -> io::Result<BorrowedMessage<'_>> {
+        if let Some(msg) = self.lent.take() {
+            if let Some(ref pool) = self.recv_pool {
+                pool.give_back(msg.into());
+            }
+        }
+
+        let msg = self.recv_msg()?;
+        let lent = self.lent.get_or_insert(msg);
+
+        Ok(BorrowedMessage { msg: lent })
+    }
+
+    /// Makes the pipes of the socket read the received messages into buffers taken from the pool,
+    /// instead of allocating a new buffer for each of them.
+    /// Like the transport options, the pool only applies to the endpoints added afterwards,
+    /// so it should be set before connecting or binding the socket.
+    /// When the pool is empty, the pipes allocate new buffers.
+    pub fn set_recv_pool(&mut self, pool: RecvPool) -> io::Result<()> {
+        let request = Request::SetRecvPool(pool.clone());
+
+        self.call(request, |reply| self.on_set_recv_pool_reply(reply))?;
+        self.recv_pool = Some(pool);
+        Ok(())
+    }
+
+    fn on_set_recv_pool_reply(&self, reply: Reply) -> io::Result<()> {
+        match reply {
+            Reply::SetRecvPool => Ok(()),
+            Reply::Err(e)      => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Receives a message whose body is handed over in the buffer the pipe read it into,
+    /// which goes back to the pool of the socket when the returned message is dropped.
+    /// The protocols that move a header out of the body, like `Req` or `Rep`, copy the body into a new buffer,
+    /// so the pool mostly saves allocations for the protocols without header.
+    pub fn recv_pooled(&mut self) -> io::Result<PooledMessage> {
+        let msg = self.recv_msg()?;
+
+        Ok(PooledMessage {
+            buffer: msg.into(),
+            pool: self.recv_pool.clone()
+        })
+    }
+
     /// Non-blocking version of the recv method.
     pub fn try_recv(&mut self) -> io::Result<Vec<u8>> {
         self.try_recv_msg().map(|msg| msg.into())
@@ -205,17 +667,46 @@ impl Socket {
     pub fn try_recv_msg(&mut self) -> io::Result<Message> {
         let request = Request::Recv(true);
 
-        self.call(request, |reply| self.on_recv_reply(reply))
+        self.call(request, |reply| self.on_recv_reply(reply)).map(|(msg, _)| msg)
     }
 
-    fn on_recv_reply(&self, reply: Reply) -> io::Result<Message> {
+    fn on_recv_reply(&self, reply: Reply) -> io::Result<(Message, EndpointId)> {
         match reply {
-            Reply::Recv(msg) => Ok(msg),
+            Reply::Recv(msg, eid) => Ok((msg, eid)),
             Reply::Err(e) => Err(e),
             _ => self.unexpected_reply()
         }
     }
 
+/*****************************************************************************/
+/*                                                                           */
+/* deadline                                                                  */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Enables or disables the enforcement of the deadlines set by [send_with_deadline](#method.send_with_deadline),
+    /// see [ConfigOption::EnforceDeadlines](enum.ConfigOption.html#variant.EnforceDeadlines).
+    /// Must be enabled before the endpoints are added, so that they negotiate the frame extensions.
+    pub fn set_enforce_deadlines(&mut self, enforce: bool) -> io::Result<()> {
+        self.set_option(ConfigOption::EnforceDeadlines(enforce))
+    }
+
+    /// Returns the number of received messages that were dropped because their deadline had expired,
+    /// by any handle of the socket.
+    pub fn get_expired_count(&mut self) -> io::Result<usize> {
+        let request = Request::ExpiredCount;
+
+        self.call(request, |reply| self.on_expired_count_reply(reply))
+    }
+
+    fn on_expired_count_reply(&self, reply: Reply) -> io::Result<usize> {
+        match reply {
+            Reply::ExpiredCount(count) => Ok(count),
+            Reply::Err(e)              => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
 /*****************************************************************************/
 /*                                                                           */
 /* options                                                                   */
@@ -264,8 +755,25 @@ impl Socket {
         self.set_option(ConfigOption::TcpNoDelay(value))
     }
 
+    /// Sets the maximum number of messages accepted by [send_async](#method.send_async)
+    /// that can wait for the message being sent.
+    /// Once the queue is full, `send_async` blocks until a queued message is handed to the protocol.
+    /// Default value is 16.
+    pub fn set_send_queue_size(&mut self, size: usize) -> io::Result<()> {
+        self.set_option(ConfigOption::SendQueueSize(size))
+    }
+
+    /// Replaces all the subscriptions of a `Sub` socket with the specified topics.
+    /// The change is applied in a single step, so a message received afterward
+    /// is never matched against a previous topic that is not part of the new set.
+    pub fn set_subscriptions(&mut self, topics: Vec<Vec<u8>>) -> io::Result<()> {
+        self.set_option(ConfigOption::Subscriptions(topics))
+    }
+
     /// Sets a socket option.
     /// See [ConfigOption](core/config/enum.ConfigOption.html) to get the list of options.
+    /// A rejected option fails with an error carrying an [OptionRejected](struct.OptionRejected.html),
+    /// telling whether the option is not supported or has an invalid value.
     pub fn set_option(&mut self, cfg_opt: ConfigOption) -> io::Result<()> {
         let request = Request::SetOption(cfg_opt);
 
@@ -280,6 +788,320 @@ impl Socket {
         }
     }
 
+/*****************************************************************************/
+/*                                                                           */
+/* recv reducer                                                              */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Installs a function invoked by the session on each message received by the socket,
+    /// before the message is delivered to the application.
+    /// This allows to aggregate or count messages without transferring them to the caller thread.
+    /// When `forward` is `false`, the socket receives messages on its own and hands them only to the reducer,
+    /// bypassing the protocol: the `recv` methods will then not return any message.
+    /// The reducer should be installed before adding endpoints to the socket.
+    pub fn set_recv_reducer(&mut self, reducer: RecvReducer, forward: bool) -> io::Result<()> {
+        let request = Request::SetRecvReducer(reducer, forward);
+
+        self.call(request, |reply| self.on_set_recv_reducer_reply(reply))
+    }
+
+    fn on_set_recv_reducer_reply(&self, reply: Reply) -> io::Result<()> {
+        match reply {
+            Reply::SetRecvReducer => Ok(()),
+            Reply::Err(e)         => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+/*****************************************************************************/
+/*                                                                           */
+/* recv map                                                                  */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Installs a function invoked by the session on each message received by the socket,
+    /// whose result is delivered to the application instead of the received message.
+    /// This offloads cheap transforms, like decompressing or stripping an application framing,
+    /// from the thread calling the `recv` methods.
+    /// A message the map fails on is dropped and counted by [dropped_count](#method.dropped_count),
+    /// the pending recv operation then waiting for the next message.
+    pub fn set_recv_map(&mut self, map: RecvMap) -> io::Result<()> {
+        let request = Request::SetRecvMap(map);
+
+        self.call(request, |reply| self.on_set_recv_map_reply(reply))
+    }
+
+    fn on_set_recv_map_reply(&self, reply: Reply) -> io::Result<()> {
+        match reply {
+            Reply::SetRecvMap => Ok(()),
+            Reply::Err(e)     => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+/*****************************************************************************/
+/*                                                                           */
+/* subscription validator                                                    */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Installs a predicate checked by subscriber sockets each time a topic is subscribed,
+    /// either with the `Subscribe` or the `Subscriptions` option.
+    /// Setting an option with a topic rejected by the validator fails with an `InvalidInput` error,
+    /// leaving the subscriptions unchanged. Subscriptions made before the validator is installed are kept.
+    /// Returns an error for the protocols that have no subscriptions.
+    pub fn set_subscription_validator(&mut self, validator: SubscriptionValidator) -> io::Result<()> {
+        let request = Request::SetSubscriptionValidator(validator);
+
+        self.call(request, |reply| self.on_set_subscription_validator_reply(reply))
+    }
+
+    fn on_set_subscription_validator_reply(&self, reply: Reply) -> io::Result<()> {
+        match reply {
+            Reply::SetSubscriptionValidator => Ok(()),
+            Reply::Err(e)                   => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+/*****************************************************************************/
+/*                                                                           */
+/* pending pipes                                                             */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Returns the number of pipes of the socket with pending I/O, as a `(send, recv)` pair.
+    /// The send count is the number of pipes still writing a message to the transport,
+    /// the recv count is the number of pipes that have a message available but not yet received.
+    /// A pipe holds at most one message in each direction, so these are pipe counts:
+    /// the messages waiting in the socket or protocol queues are not included.
+    pub fn pending_pipes(&mut self) -> io::Result<(usize, usize)> {
+        let request = Request::PendingPipes;
+
+        self.call(request, |reply| self.on_pending_pipes_reply(reply))
+    }
+
+    fn on_pending_pipes_reply(&self, reply: Reply) -> io::Result<(usize, usize)> {
+        match reply {
+            Reply::PendingPipes(send, recv) => Ok((send, recv)),
+            Reply::Err(e)                   => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns the credentials of the process on the other end of a pipe,
+    /// identified by the endpoint id returned by [recv_from](#method.recv_from) for an accepted connection,
+    /// or by [Endpoint::id](struct.Endpoint.html#method.id) for a connected endpoint.
+    /// Only unix ipc pipes on Linux report them, other transports fail with `Unsupported`,
+    /// and endpoints without an active pipe fail with `NotConnected`.
+    pub fn peer_cred(&mut self, endpoint: EndpointId) -> io::Result<PeerCred> {
+        let request = core::endpoint::Request::PeerCred(self.request_sender.handle);
+
+        self.request_sender.send_to_endpoint(endpoint, request)?;
+        self.recv_reply().and_then(|reply| self.on_peer_cred_reply(reply))
+    }
+
+    fn on_peer_cred_reply(&self, reply: Reply) -> io::Result<PeerCred> {
+        match reply {
+            Reply::PeerCred(cred) => Ok(cred),
+            Reply::Err(e)         => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns the number of endpoints waiting for a reconnection or a rebind attempt.
+    /// See [MaxPendingRetries](enum.ConfigOption.html#variant.MaxPendingRetries).
+    pub fn pending_retries(&mut self) -> io::Result<usize> {
+        let request = Request::PendingRetries;
+
+        self.call(request, |reply| self.on_pending_retries_reply(reply))
+    }
+
+    fn on_pending_retries_reply(&self, reply: Reply) -> io::Result<usize> {
+        match reply {
+            Reply::PendingRetries(count) => Ok(count),
+            Reply::Err(e)                => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns the number of received messages discarded by the socket pipes,
+    /// because they exceeded `RecvMaxSize` while the [OversizePolicy](enum.ConfigOption.html#variant.OversizePolicy)
+    /// option was `SkipMessage`, or because the [recv map](#method.set_recv_map) failed on them.
+    /// The published messages dropped by a `Pub` socket are counted apart,
+    /// see [SocketStats::pub_dropped](struct.SocketStats.html#structfield.pub_dropped).
+    pub fn dropped_count(&mut self) -> io::Result<usize> {
+        let request = Request::DroppedCount;
+
+        self.call(request, |reply| self.on_dropped_count_reply(reply))
+    }
+
+    fn on_dropped_count_reply(&self, reply: Reply) -> io::Result<usize> {
+        match reply {
+            Reply::DroppedCount(count) => Ok(count),
+            Reply::Err(e)              => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns the number of received messages dropped because their dedup id was still in the
+    /// [DedupWindow](enum.ConfigOption.html#variant.DedupWindow).
+    pub fn duplicate_count(&mut self) -> io::Result<usize> {
+        let request = Request::DuplicateCount;
+
+        self.call(request, |reply| self.on_duplicate_count_reply(reply))
+    }
+
+    fn on_duplicate_count_reply(&self, reply: Reply) -> io::Result<usize> {
+        match reply {
+            Reply::DuplicateCount(count) => Ok(count),
+            Reply::Err(e)                => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns a file descriptor that is readable while the socket can receive a message,
+    /// so that the socket can be watched by an external event loop, like nanomsg `NN_RCVFD`.
+    /// The descriptor is level triggered, consistent with what a [Probe](struct.Probe.html) reports.
+    /// It must only be polled, never read nor closed, and it stays valid as long as the socket is alive.
+    #[cfg(unix)]
+    pub fn recv_fd(&mut self) -> io::Result<RawFd> {
+        let request = Request::RecvFd;
+
+        self.call(request, |reply| self.on_ready_fd_reply(reply))
+    }
+
+    /// Returns a file descriptor that is readable while the socket can send a message,
+    /// like nanomsg `NN_SNDFD`. See [recv_fd](#method.recv_fd).
+    #[cfg(unix)]
+    pub fn send_fd(&mut self) -> io::Result<RawFd> {
+        let request = Request::SendFd;
+
+        self.call(request, |reply| self.on_ready_fd_reply(reply))
+    }
+
+    #[cfg(unix)]
+    fn on_ready_fd_reply(&self, reply: Reply) -> io::Result<RawFd> {
+        match reply {
+            Reply::ReadyFd(fd) => Ok(fd),
+            Reply::Err(e)      => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns the distribution of the sizes of the messages sent and received by the socket.
+    /// Messages are accumulated only while the [SizeHistogram](enum.ConfigOption.html#variant.SizeHistogram) option is enabled.
+    pub fn size_histogram(&mut self) -> io::Result<SizeHistogram> {
+        let request = Request::SizeHistogram;
+
+        self.call(request, |reply| self.on_size_histogram_reply(reply))
+    }
+
+    fn on_size_histogram_reply(&self, reply: Reply) -> io::Result<SizeHistogram> {
+        match reply {
+            Reply::SizeHistogram(histogram) => Ok(histogram),
+            Reply::Err(e)                   => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns the operations and behaviors supported by the protocol of this socket,
+    /// so generic code can adapt to the socket it is given without knowing its protocol.
+    pub fn capabilities(&mut self) -> io::Result<Capabilities> {
+        let request = Request::Capabilities;
+
+        self.call(request, |reply| self.on_capabilities_reply(reply))
+    }
+
+    fn on_capabilities_reply(&self, reply: Reply) -> io::Result<Capabilities> {
+        match reply {
+            Reply::Capabilities(capabilities) => Ok(capabilities),
+            Reply::Err(e)                     => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Returns the message, byte and connection counters of this socket, cumulated since its creation.
+    /// The counters are shared by all the clones of this socket.
+    pub fn stats(&self) -> io::Result<SocketStats> {
+        let request = Request::Stats;
+
+        self.call(request, |reply| self.on_stats_reply(reply))
+    }
+
+    fn on_stats_reply(&self, reply: Reply) -> io::Result<SocketStats> {
+        match reply {
+            Reply::Stats(stats) => Ok(stats),
+            Reply::Err(e)       => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Creates a new socket with the same protocol and options as this one, but without any endpoint.
+    /// Unlike a clone, the sibling is a distinct socket, with its own connections and message queues.
+    /// The options set on either socket afterwards are not shared with the other one.
+    /// The recv reducer and the subscription validator, if any, are not copied.
+    pub fn new_sibling(&self) -> io::Result<Socket> {
+        let request = Request::NewSibling;
+
+        self.call(request, |reply| self.on_new_sibling_reply(reply))
+    }
+
+    fn on_new_sibling_reply(&self, reply: Reply) -> io::Result<Socket> {
+        match reply {
+            Reply::Sibling(id, rx, peers, protocols, infos) => {
+                let sender = self.request_sender.sibling_sender(id);
+                Ok(Socket::new(sender, rx, peers, protocols, infos, self.transport_infos.clone()))
+            },
+            Reply::Err(e) => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+    /// Closes the socket in an orderly way, and waits for it to be done.
+    /// This closes the underlying socket for all the clones of this socket.
+    /// The bound endpoints are closed first so no new connection is accepted,
+    /// then the messages queued by [send_async](#method.send_async) are given up to the specified timeout to be sent,
+    /// then each connection is given up to the specified timeout to flush the messages it has in flight,
+    /// and finally the socket is closed.
+    pub fn shutdown(self, timeout: Duration) -> io::Result<()> {
+        let request = Request::Shutdown(timeout);
+
+        self.call(request, |reply| self.on_shutdown_reply(reply))
+    }
+
+    fn on_shutdown_reply(&self, reply: Reply) -> io::Result<()> {
+        match reply {
+            Reply::Shutdown => Ok(()),
+            Reply::Err(e)   => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
+/*****************************************************************************/
+/*                                                                           */
+/* device                                                                    */
+/*                                                                           */
+/*****************************************************************************/
+
+    /// Switches the socket to the raw mode used by devices,
+    /// where messages keep their protocol header when received, and are routed by it when sent.
+    #[doc(hidden)]
+    pub fn plug_device(&mut self) -> io::Result<()> {
+        let request = Request::PlugDevice;
+
+        self.call(request, |reply| self.on_plug_device_reply(reply))
+    }
+
+    fn on_plug_device_reply(&self, reply: Reply) -> io::Result<()> {
+        match reply {
+            Reply::PlugDevice => Ok(()),
+            Reply::Err(e)     => Err(e),
+            _ => self.unexpected_reply()
+        }
+    }
+
 /*****************************************************************************/
 /*                                                                           */
 /* backend                                                                   */
@@ -307,6 +1129,28 @@ impl Socket {
     }
 }
 
+impl Clone for Socket {
+    /// Registers a new handle on the underlying socket, with its own reply channel.
+    /// Should the event loop be gone, the returned clone fails every operation.
+    fn clone(&self) -> Socket {
+        let (handle, reply_rx) = match self.execute_request(Request::Clone) {
+            Ok(Reply::Clone(handle, rx)) => (handle, rx),
+            _ => (HandleId::MAX, mpsc::channel().1)
+        };
+
+        Socket {
+            request_sender: self.request_sender.clone_sender(handle),
+            reply_receiver: reply_rx,
+            peer_count: self.peer_count.clone(),
+            peer_protocols: self.peer_protocols.clone(),
+            endpoint_infos: self.endpoint_infos.clone(),
+            transport_infos: self.transport_infos.clone(),
+            lent: None,
+            recv_pool: self.recv_pool.clone()
+        }
+    }
+}
+
 impl Drop for Socket {
     fn drop(&mut self) {
         let _ = self.send_request(Request::Close);
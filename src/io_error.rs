@@ -29,9 +29,33 @@ pub fn timedout_io_error<E>(msg: E) -> io::Error where E: Into<Box<dyn error::Er
     io::Error::new(io::ErrorKind::TimedOut, msg)
 }
 
+pub fn eof_io_error<E>(msg: E) -> io::Error where E: Into<Box<dyn error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::UnexpectedEof, msg)
+}
+
+pub fn closed_io_error<E>(msg: E) -> io::Error where E: Into<Box<dyn error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::BrokenPipe, msg)
+}
+
+pub fn not_connected_io_error<E>(msg: E) -> io::Error where E: Into<Box<dyn error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::NotConnected, msg)
+}
+
+pub fn refused_io_error<E>(msg: E) -> io::Error where E: Into<Box<dyn error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::ConnectionRefused, msg)
+}
+
+pub fn write_zero_io_error<E>(msg: E) -> io::Error where E: Into<Box<dyn error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::WriteZero, msg)
+}
+
+pub fn unsupported_io_error<E>(msg: E) -> io::Error where E: Into<Box<dyn error::Error + Send + Sync>> {
+    io::Error::new(io::ErrorKind::Unsupported, msg)
+}
+
 pub fn from_send_error<T>(send_error: mio_extras::channel::SendError<T>) -> io::Error {
     match send_error {
         mio_extras::channel::SendError::Io(e) => e,
-        mio_extras::channel::SendError::Disconnected(_) => other_io_error("channel closed")
+        mio_extras::channel::SendError::Disconnected(_) => closed_io_error("session closed")
     }
 }
@@ -62,6 +62,11 @@ extern crate mio;
 extern crate mio_uds;
 extern crate mio_extras;
 extern crate iovec;
+extern crate socket2;
+#[cfg(unix)]
+extern crate libc;
+#[cfg(feature = "tracing")]
+extern crate tracing;
 
 #[cfg(windows)]
 extern crate mio_named_pipes;
@@ -85,27 +90,63 @@ mod io_error;
 pub use facade::session::SessionBuilder;
 pub use facade::session::Session;
 pub use facade::socket::Socket;
+pub use facade::socket::LengthMismatch;
+pub use facade::socket::BorrowedMessage;
+pub use facade::socket::PooledMessage;
 pub use facade::device::Device;
+pub use facade::device::DeviceConfig;
 pub use facade::probe::Probe;
+pub use facade::server::RepServer;
 pub use facade::endpoint::Endpoint;
 pub use core::Message;
 pub use core::PollReq;
 pub use core::PollRes;
+pub use core::EndpointId;
+pub use core::EndpointIdAllocator;
+pub use core::pool::RecvPool;
+pub use core::clock::Clock;
+pub use core::clock::ClockWaker;
+pub use core::clock::SystemClock;
+#[cfg(feature = "testing")]
+pub use core::clock::TestClock;
+pub use core::event_ring::ReactorEvent;
+pub use core::event_ring::ReactorEventKind;
+pub use core::event_ring::ErrorCategory;
 pub use core::config::ConfigOption;
+pub use core::config::EndpointConfig;
+pub use core::config::EndpointRole;
+pub use core::config::PubDropPolicy;
+pub use core::config::OptionRejected;
+pub use core::config::RejectReason;
+pub use core::socket::SizeHistogram;
+pub use core::socket::Capabilities;
+pub use core::socket::SocketStats;
+pub use core::socket::EndpointInfo;
 
 pub use transport::tcp::Tcp;
 pub use transport::ipc::Ipc;
+pub use transport::ws::Ws;
+pub use transport::TransportInfo;
+pub use transport::OversizePolicy;
+pub use transport::MessageTooLong;
+pub use transport::PeerBusy;
+pub use transport::Address;
+pub use transport::PeerCred;
+pub use transport::TransportKind;
+pub use transport::Stream;
 
 pub use proto::pair::Pair;
 pub use proto::publ::Pub;
 pub use proto::sub::Sub;
 pub use proto::req::Req;
 pub use proto::rep::Rep;
+pub use proto::rep::RepError;
 pub use proto::push::Push;
 pub use proto::pull::Pull;
 pub use proto::surv::Surveyor;
 pub use proto::resp::Respondent;
 pub use proto::bus::Bus;
+pub use proto::echo::Echo;
 
 #[cfg(test)]
 mod tests {
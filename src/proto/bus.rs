@@ -8,14 +8,13 @@ use std::collections::HashSet;
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
 
-use byteorder::*;
-
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
+use core::socket::{Protocol, Reply, Capabilities};
 use core::endpoint::Pipe;
 use core::context::Context;
 use super::priolist::Priolist;
 use super::pipes::PipeCollection;
+use super::late::LateRecv;
 use super::{Timeout, BUS};
 use super::policy::{broadcast, fair_queue};
 use io_error::*;
@@ -35,7 +34,8 @@ struct Inner {
     reply_tx: Sender<Reply>,
     pipes: PipeCollection,
     bc: HashSet<EndpointId>,
-    fq: Priolist
+    fq: Priolist,
+    late: LateRecv
 }
 
 /*****************************************************************************/
@@ -74,7 +74,8 @@ impl From<Sender<Reply>> for Bus {
                 reply_tx: tx,
                 pipes: PipeCollection::new(),
                 bc: HashSet::new(),
-                fq: Priolist::new()
+                fq: Priolist::new(),
+                late: LateRecv::new()
             },
             state: Some(State::Idle)
         }
@@ -149,6 +150,15 @@ impl Protocol for Bus {
     fn is_recv_ready(&self) -> bool {
         self.inner.is_recv_ready()
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: true,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: true
+        }
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -216,26 +226,37 @@ impl State {
 /*****************************************************************************/
 
     fn recv(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout) -> State {
-        inner.recv(ctx).map_or_else(
-            |   | State::RecvOnHold(timeout),
-            |eid| State::Receiving(eid, timeout))
+        match inner.late.take() {
+            Some((eid, Some(msg))) => State::Receiving(eid, timeout).on_recv_ack(ctx, inner, eid, msg),
+            Some((eid, None)) => State::Receiving(eid, timeout),
+            None => inner.recv(ctx).map_or_else(
+                |   | State::RecvOnHold(timeout),
+                |eid| State::Receiving(eid, timeout))
+        }
     }
     fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message) -> State {
         match self {
             State::Receiving(id, timeout) => {
                 if id == eid {
-                    inner.on_recv_ack(ctx, timeout, msg);
+                    inner.on_recv_ack(ctx, timeout, eid, msg);
                     State::Idle
                 } else {
                     State::Receiving(id, timeout)
                 }
             },
-            any => any
+            any => {
+                inner.late.on_recv_ack(eid, msg);
+                any
+            }
         }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
         inner.on_recv_timeout();
 
+        if let State::Receiving(eid, _) = self {
+            inner.late.on_recv_timeout(eid);
+        }
+
         State::Idle
     }
     fn on_recv_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
@@ -260,12 +281,13 @@ impl State {
 
 impl Inner {
     fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
-        self.fq.insert(eid, pipe.get_recv_priority());
+        self.fq.insert_weighted(eid, pipe.get_recv_priority(), pipe.get_recv_weight());
         self.pipes.insert(eid, pipe);
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
         self.bc.remove(&eid);
         self.fq.remove(&eid);
+        self.late.on_pipe_removed(eid);
         self.pipes.remove(&eid)
     }
 
@@ -286,7 +308,7 @@ impl Inner {
         }
     }
     fn send_to_all(&mut self, ctx: &mut dyn Context, msg: Rc<Message>) {
-        broadcast::send_to_all(&mut self.bc, &mut self.pipes, ctx, msg)
+        broadcast::send_to_all(&mut self.bc, &mut self.pipes, ctx, msg);
     }
     fn send_to_all_except(&mut self, ctx: &mut dyn Context, msg: Rc<Message>, except: EndpointId) {
         broadcast::send_to_all_except(&mut self.bc, &mut self.pipes, ctx, msg, except)
@@ -310,14 +332,14 @@ impl Inner {
     fn on_recv_not_ready(&mut self, eid: EndpointId) {
         self.fq.deactivate(&eid)
     }
-    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, msg: Message) {
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, msg: Message) {
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
     }
     fn is_recv_ready(&self) -> bool {
-        self.fq.peek()
+        self.fq.peek() || self.late.is_recv_ready()
     }
     fn on_recv_timeout(&self) {
         let error = timedout_io_error("Recv timed out");
@@ -334,29 +356,21 @@ impl Inner {
 /*                                                                           */
 /*****************************************************************************/
 
+// The originator is kept in a dedicated field of the message rather than in its header,
+// so an application header is never mistaken for routing information.
 fn decode(raw_msg: Message, eid: EndpointId) -> Message {
-    let originator: usize = eid.into();
     let mut msg = raw_msg;
-    let mut originator_bytes: [u8; 4] = [0; 4];
-
-    BigEndian::write_u32(&mut originator_bytes[..], originator as u32);
 
-    msg.header.reserve(4);
-    msg.header.extend_from_slice(&originator_bytes);
+    msg.set_originator(Some(eid));
     msg
 }
 
-fn encode(msg: Message) -> (Message, Option<EndpointId>) {
-    if msg.get_header().len() < 4 {
-        return (msg, None);
-    }
+fn encode(mut msg: Message) -> (Message, Option<EndpointId>) {
+    let originator = msg.get_originator();
 
-    let (mut header, body) = msg.split();
-    let remaining_header = header.split_off(4);
-    let originator = BigEndian::read_u32(&header) as usize;
-    let raw_msg = Message::from_header_and_body(remaining_header, body);
+    msg.set_originator(None);
 
-    (raw_msg, Some(EndpointId::from(originator)))
+    (msg, originator)
 }
 
 /*****************************************************************************/
@@ -401,6 +415,45 @@ mod tests {
         sensor.assert_one_cancellation(timeout);
     }
 
+    #[test]
+    fn message_received_after_a_recv_timeout_is_delivered_once_to_the_next_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut bus = Bus::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+        let timeout = Scheduled::from(2);
+
+        bus.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        bus.on_recv_ready(&mut ctx, eid);
+        bus.recv(&mut ctx, Some(Scheduled::from(1)));
+        bus.on_recv_timeout(&mut ctx);
+        bus.on_recv_ack(&mut ctx, eid, Message::from_body(vec![65, 66, 67]));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have timed out")
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(bus.is_recv_ready());
+
+        bus.recv(&mut ctx, Some(timeout));
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, id)) => {
+                assert_eq!(vec![65, 66, 67], msg.body);
+                assert_eq!(eid, id);
+            },
+            _ => panic!("recv should have delivered the late message")
+        }
+
+        bus.recv(&mut ctx, None);
+        assert!(rx.try_recv().is_err());
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_recv_from(eid);
+        sensor.assert_one_cancellation(timeout);
+    }
+
     #[test]
     fn send_broadcast_to_all_ready_pipes() {
         let (tx, _) = mpsc::channel();
@@ -568,4 +621,38 @@ mod tests {
         assert_eq!(Event::CanRecv(true), raised_evts[0]);
         assert_eq!(Event::CanRecv(false), raised_evts[1]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn originator_header_round_trips_but_application_header_is_kept() {
+        let eid = EndpointId::from(1);
+        let received = decode(Message::from_body(vec![65]), eid);
+        let (raw_msg, oid) = encode(received);
+
+        assert_eq!(Some(eid), oid);
+        assert!(raw_msg.get_header().is_empty());
+
+        for first in 0x80..0x100 {
+            let app_header = vec![first as u8, 0, 0, 1];
+            let app_msg = Message::from_header_and_body(app_header.clone(), vec![65]);
+            let (raw_msg, oid) = encode(app_msg);
+
+            assert_eq!(None, oid);
+            assert_eq!(&app_header[..], raw_msg.get_header());
+        }
+    }
+
+    #[test]
+    fn application_header_of_a_received_message_is_kept_when_sent_back() {
+        let eid = EndpointId::from(3);
+        let app_header = vec![0xFF, 0xFF, 0xFF, 0xFF, 0x80];
+        let received = decode(Message::from_header_and_body(app_header.clone(), vec![65]), eid);
+
+        assert_eq!(&app_header[..], received.get_header());
+
+        let (raw_msg, oid) = encode(received);
+
+        assert_eq!(Some(eid), oid);
+        assert_eq!(&app_header[..], raw_msg.get_header());
+        assert_eq!(None, raw_msg.get_originator());
+    }
+}
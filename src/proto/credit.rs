@@ -0,0 +1,97 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+//! Control frames sent by `Pull` sockets to `Push` sockets, for the optional flow control and acknowledgements.
+//! A credit frame is a message made of a reserved 8 bytes header and an empty body:
+//! a 3 bytes marker, a capability byte, and the number of granted messages.
+//! An ack frame has the same header, holding the number of acknowledged messages,
+//! followed by their 4 bytes sequence ids.
+
+use byteorder::*;
+
+use core::Message;
+
+const MARKER: [u8; 3] = [0xFF, b'C', b'R'];
+const CAPABILITY_CREDIT: u8 = 0x01;
+const CAPABILITY_ACK: u8 = 0x02;
+
+pub fn encode(credits: u32) -> Message {
+    let mut header = vec![MARKER[0], MARKER[1], MARKER[2], CAPABILITY_CREDIT, 0, 0, 0, 0];
+
+    BigEndian::write_u32(&mut header[4..8], credits);
+
+    Message::from_header_and_body(header, Vec::new())
+}
+
+pub fn decode(msg: &Message) -> Option<u32> {
+    let frame = msg.get_body();
+
+    if frame.len() != 8 || frame[0..3] != MARKER || frame[3] & CAPABILITY_CREDIT == 0 {
+        None
+    } else {
+        Some(BigEndian::read_u32(&frame[4..8]))
+    }
+}
+
+pub fn encode_ack(seq_ids: &[u32]) -> Message {
+    let mut header = vec![MARKER[0], MARKER[1], MARKER[2], CAPABILITY_ACK, 0, 0, 0, 0];
+    let mut body = vec![0; seq_ids.len() * 4];
+
+    BigEndian::write_u32(&mut header[4..8], seq_ids.len() as u32);
+    for (i, seq_id) in seq_ids.iter().enumerate() {
+        BigEndian::write_u32(&mut body[i * 4..i * 4 + 4], *seq_id);
+    }
+
+    Message::from_header_and_body(header, body)
+}
+
+pub fn decode_ack(msg: &Message) -> Option<Vec<u32>> {
+    let frame = msg.get_body();
+
+    if frame.len() < 8 || frame[0..3] != MARKER || frame[3] & CAPABILITY_ACK == 0 {
+        return None;
+    }
+
+    let count = BigEndian::read_u32(&frame[4..8]) as usize;
+    let seq_ids = &frame[8..];
+
+    if seq_ids.len() != count * 4 {
+        None
+    } else {
+        Some(seq_ids.chunks(4).map(BigEndian::read_u32).collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::Message;
+
+    use super::*;
+
+    #[test]
+    fn decode_reads_what_encode_wrote() {
+        let (header, body) = encode(42).split();
+        let received = Message::from_body([header, body].concat());
+
+        assert_eq!(Some(42), decode(&received));
+    }
+
+    #[test]
+    fn decode_ignores_regular_messages() {
+        let received = Message::from_body(vec![65, 66, 67, 68, 0, 0, 0, 1]);
+
+        assert_eq!(None, decode(&received));
+    }
+
+    #[test]
+    fn decode_ack_reads_what_encode_ack_wrote() {
+        let (header, body) = encode_ack(&[0x8000_0001, 0x8000_0002]).split();
+        let received = Message::from_body([header, body].concat());
+
+        assert_eq!(Some(vec![0x8000_0001, 0x8000_0002]), decode_ack(&received));
+        assert_eq!(None, decode(&received));
+    }
+}
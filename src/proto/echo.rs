@@ -0,0 +1,236 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::collections::HashMap;
+use std::rc::Rc;
+use std::sync::mpsc::Sender;
+
+use core::{EndpointId, Message};
+use core::socket::{Protocol, Reply, Capabilities};
+use core::endpoint::Pipe;
+use core::context::Context;
+use super::{Timeout, PAIR};
+use io_error::*;
+
+/// Sends back each message it receives to the peer it came from, for connectivity tests.
+/// It speaks the pair protocol, so a `Pair` socket can connect to it,
+/// and it serves each of its peers independently.
+/// Messages are echoed without the application being involved,
+/// so the send and recv operations are not supported.
+/// Two echo sockets must not be connected to each other, since they would echo the same message forever.
+pub struct Echo {
+    reply_tx: Sender<Reply>,
+    pipes: HashMap<EndpointId, EchoPipe>
+}
+
+struct EchoPipe {
+    pipe: Pipe,
+    send_ready: bool,
+    recv_ready: bool,
+    echoing: Option<Rc<Message>>,
+    receiving: bool
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Echo                                                                      */
+/*                                                                           */
+/*****************************************************************************/
+
+impl Echo {
+    fn with_pipe<F>(&mut self, ctx: &mut dyn Context, eid: EndpointId, f: F) where F : FnOnce(&mut EchoPipe) {
+        if let Some(echo_pipe) = self.pipes.get_mut(&eid) {
+            f(echo_pipe);
+            echo_pipe.resume(ctx);
+        }
+    }
+
+    fn reply_unsupported(&self, ctx: &mut dyn Context, op: &str, timeout: Timeout) {
+        let error = other_io_error(format!("{} is not supported by echo protocol", op));
+        let _ = self.reply_tx.send(Reply::Err(error));
+        if let Some(sched) = timeout {
+            ctx.cancel(sched);
+        }
+    }
+}
+
+#[doc(hidden)]
+impl From<Sender<Reply>> for Echo {
+    fn from(tx: Sender<Reply>) -> Echo {
+        Echo {
+            reply_tx: tx,
+            pipes: HashMap::new()
+        }
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Protocol                                                                  */
+/*                                                                           */
+/*****************************************************************************/
+
+impl Protocol for Echo {
+    fn id(&self)      -> u16 { PAIR }
+    fn peer_id(&self) -> u16 { PAIR }
+
+    fn add_pipe(&mut self, _: &mut dyn Context, eid: EndpointId, pipe: Pipe) {
+        self.pipes.insert(eid, EchoPipe::new(pipe));
+    }
+    fn remove_pipe(&mut self, _: &mut dyn Context, eid: EndpointId) -> Option<Pipe> {
+        self.pipes.remove(&eid).map(|echo_pipe| echo_pipe.pipe)
+    }
+    fn send(&mut self, ctx: &mut dyn Context, _: Message, timeout: Timeout) {
+        self.reply_unsupported(ctx, "Send", timeout)
+    }
+    fn on_send_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.with_pipe(ctx, eid, |p| p.echoing = None)
+    }
+    fn on_send_timeout(&mut self, _: &mut dyn Context) {
+    }
+    fn on_send_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.with_pipe(ctx, eid, |p| p.send_ready = true)
+    }
+    fn on_send_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.with_pipe(ctx, eid, |p| p.send_ready = false)
+    }
+    fn recv(&mut self, ctx: &mut dyn Context, timeout: Timeout) {
+        self.reply_unsupported(ctx, "Recv", timeout)
+    }
+    fn on_recv_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId, msg: Message) {
+        self.with_pipe(ctx, eid, |p| {
+            p.receiving = false;
+            p.echoing = Some(Rc::new(msg));
+        })
+    }
+    fn on_recv_timeout(&mut self, _: &mut dyn Context) {
+    }
+    fn on_recv_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.with_pipe(ctx, eid, |p| p.recv_ready = true)
+    }
+    fn on_recv_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.with_pipe(ctx, eid, |p| p.recv_ready = false)
+    }
+    fn is_send_ready(&self) -> bool {
+        false
+    }
+    fn is_recv_ready(&self) -> bool {
+        false
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: false,
+            can_recv: false,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: false
+        }
+    }
+    fn close(&mut self, ctx: &mut dyn Context) {
+        for (_, echo_pipe) in self.pipes.drain() {
+            echo_pipe.pipe.close(ctx);
+        }
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* EchoPipe                                                                  */
+/*                                                                           */
+/*****************************************************************************/
+
+impl EchoPipe {
+    fn new(echoed_pipe: Pipe) -> EchoPipe {
+        EchoPipe {
+            pipe: echoed_pipe,
+            send_ready: false,
+            recv_ready: false,
+            echoing: None,
+            receiving: false
+        }
+    }
+
+    /// Sends back the received message once the pipe can,
+    /// and receives the next one only when the previous one is echoed.
+    fn resume(&mut self, ctx: &mut dyn Context) {
+        if let Some(ref msg) = self.echoing {
+            if self.send_ready {
+                self.send_ready = false;
+                self.pipe.send(ctx, msg.clone());
+            }
+        } else if self.recv_ready && !self.receiving {
+            self.recv_ready = false;
+            self.receiving = true;
+            self.pipe.recv(ctx);
+        }
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* tests                                                                     */
+/*                                                                           */
+/*****************************************************************************/
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+
+    use core::{EndpointId, Message};
+    use core::socket::{Protocol, Reply};
+    use core::tests::*;
+
+    use super::*;
+
+    #[test]
+    fn received_message_is_sent_back_once_the_pipe_is_send_ready() {
+        let (tx, _) = mpsc::channel();
+        let mut echo = Echo::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+
+        echo.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        echo.on_recv_ready(&mut ctx, eid);
+        assert_eq!(1, ctx_sensor.borrow().get_recv_calls().len());
+
+        echo.on_recv_ack(&mut ctx, eid, Message::from_body(vec![65, 66, 67]));
+        assert_eq!(0, ctx_sensor.borrow().get_send_calls().len());
+
+        echo.on_send_ready(&mut ctx, eid);
+        echo.on_recv_ready(&mut ctx, eid);
+        {
+            let sensor = ctx_sensor.borrow();
+            let send_calls = sensor.get_send_calls();
+
+            assert_eq!(1, send_calls.len());
+            assert_eq!(eid, send_calls[0].0);
+            assert_eq!(&[65, 66, 67], send_calls[0].1.get_body());
+            assert_eq!(1, sensor.get_recv_calls().len());
+        }
+
+        echo.on_send_ack(&mut ctx, eid);
+        assert_eq!(2, ctx_sensor.borrow().get_recv_calls().len());
+    }
+
+    #[test]
+    fn recv_is_not_supported() {
+        let (tx, rx) = mpsc::channel();
+        let mut echo = Echo::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor);
+
+        echo.recv(&mut ctx, None);
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have failed")
+        }
+    }
+}
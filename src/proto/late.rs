@@ -0,0 +1,115 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use core::{EndpointId, Message};
+
+/// Pipe recv left in flight by a socket recv that timed out.
+/// The pipe still delivers its message later on, so the next socket recv
+/// must wait for it, or take it if it already came, instead of asking
+/// another pipe and losing that message.
+pub struct LateRecv<T = Message> {
+    orphan: Option<(EndpointId, Option<T>)>
+}
+
+impl<T> Default for LateRecv<T> {
+    fn default() -> LateRecv<T> {
+        LateRecv::new()
+    }
+}
+
+impl<T> LateRecv<T> {
+    pub fn new() -> LateRecv<T> {
+        LateRecv {
+            orphan: None
+        }
+    }
+
+    /// Remembers the pipe the timed out socket recv was waiting for.
+    pub fn on_recv_timeout(&mut self, eid: EndpointId) {
+        self.orphan = Some((eid, None));
+    }
+
+    /// Keeps what the pipe delivered if it answers the orphaned recv, drops it otherwise.
+    pub fn on_recv_ack(&mut self, eid: EndpointId, item: T) {
+        if let Some((id, ref mut stash)) = self.orphan {
+            if id == eid && stash.is_none() {
+                *stash = Some(item);
+            }
+        }
+    }
+
+    pub fn on_pipe_removed(&mut self, eid: EndpointId) {
+        if self.orphan.iter().any(|&(id, _)| id == eid) {
+            self.orphan = None;
+        }
+    }
+
+    /// Hands the orphaned recv over to a new socket recv: the pipe to wait for,
+    /// along with what it delivered in the meantime if anything.
+    pub fn take(&mut self) -> Option<(EndpointId, Option<T>)> {
+        self.orphan.take()
+    }
+
+    pub fn is_recv_ready(&self) -> bool {
+        self.orphan.iter().any(|orphan| orphan.1.is_some())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::{EndpointId, Message};
+
+    use super::*;
+
+    #[test]
+    fn message_of_the_orphaned_pipe_should_be_stashed() {
+        let mut late = LateRecv::new();
+        let eid = EndpointId::from(1);
+
+        late.on_recv_timeout(eid);
+        assert!(!late.is_recv_ready());
+
+        late.on_recv_ack(eid, Message::from_body(vec![65]));
+        assert!(late.is_recv_ready());
+
+        match late.take() {
+            Some((id, Some(msg))) => {
+                assert_eq!(eid, id);
+                assert_eq!(vec![65], msg.body);
+            },
+            _ => panic!("the stashed message should have been taken")
+        }
+        assert!(late.take().is_none());
+    }
+
+    #[test]
+    fn message_of_another_pipe_should_be_dropped() {
+        let mut late = LateRecv::new();
+
+        late.on_recv_timeout(EndpointId::from(1));
+        late.on_recv_ack(EndpointId::from(2), Message::from_body(vec![65]));
+
+        assert!(!late.is_recv_ready());
+        match late.take() {
+            Some((id, None)) => assert_eq!(EndpointId::from(1), id),
+            _ => panic!("the orphaned pipe should still be waited for")
+        }
+    }
+
+    #[test]
+    fn removing_the_orphaned_pipe_should_forget_it() {
+        let mut late: LateRecv = LateRecv::new();
+        let eid = EndpointId::from(1);
+
+        late.on_recv_timeout(eid);
+        late.on_pipe_removed(EndpointId::from(2));
+        assert!(late.take().is_some());
+
+        late.on_recv_timeout(eid);
+        late.on_pipe_removed(eid);
+        assert!(late.take().is_none());
+    }
+}
@@ -16,10 +16,13 @@ pub mod sub;
 pub mod surv;
 pub mod resp;
 pub mod bus;
+pub mod echo;
 
 mod pipes;
 mod priolist;
 mod policy;
+mod credit;
+mod late;
 
 use core::Scheduled;
 
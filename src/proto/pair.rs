@@ -8,9 +8,10 @@ use std::rc::Rc;
 use std::sync::mpsc::Sender;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
+use core::socket::{Protocol, Reply, Capabilities};
 use core::endpoint::Pipe;
 use core::context::Context;
+use super::late::LateRecv;
 use super::{Timeout, PAIR};
 use io_error::*;
 
@@ -31,7 +32,8 @@ struct Inner {
     reply_tx: Sender<Reply>,
     pipe: Option<(EndpointId, Pipe)>,
     send_ready: bool,
-    recv_ready: bool
+    recv_ready: bool,
+    late: LateRecv
 }
 
 /*****************************************************************************/
@@ -46,10 +48,10 @@ impl Pair {
         if let Some(old_state) = self.state.take() {
             #[cfg(debug_assertions)] let old_name = old_state.name();
             let was_send_ready = self.inner.send_ready;
-            let was_recv_ready = self.inner.recv_ready;
+            let was_recv_ready = self.inner.is_recv_ready();
             let new_state = transition(old_state, ctx, &mut self.inner);
             let is_send_ready = self.inner.send_ready;
-            let is_recv_ready = self.inner.recv_ready;
+            let is_recv_ready = self.inner.is_recv_ready();
             #[cfg(debug_assertions)] let new_name = new_state.name();
 
             self.state = Some(new_state);
@@ -71,7 +73,8 @@ impl From<Sender<Reply>> for Pair {
                 reply_tx: tx,
                 pipe: None,
                 send_ready: false,
-                recv_ready: false
+                recv_ready: false,
+                late: LateRecv::new()
             },
             state: Some(State::Idle)
         }
@@ -134,7 +137,16 @@ impl Protocol for Pair {
         self.inner.send_ready
     }
     fn is_recv_ready(&self) -> bool {
-        self.inner.recv_ready
+        self.inner.is_recv_ready()
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: true,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: false
+        }
     }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
@@ -161,7 +173,7 @@ impl State {
     }
 
     fn on_pipe_removed(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
-        inner.on_pipe_removed(ctx);
+        inner.on_pipe_removed(ctx, eid);
 
         match self {
             State::Sending(id, msg, timeout) => {
@@ -209,9 +221,14 @@ impl State {
         }
     }
     fn on_send_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
-        inner.on_send_timeout();
+        match self {
+            State::Sending(..) | State::SendOnHold(..) => {
+                inner.on_send_timeout();
 
-        State::Idle
+                State::Idle
+            },
+            any => any
+        }
     }
     fn on_send_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
         inner.on_send_ready(eid);
@@ -233,26 +250,37 @@ impl State {
 /*****************************************************************************/
 
     fn recv(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout) -> State {
-        inner.recv(ctx).map_or_else(
-            |   | State::RecvOnHold(timeout),
-            |eid| State::Receiving(eid, timeout))
+        match inner.late.take() {
+            Some((eid, Some(msg))) => State::Receiving(eid, timeout).on_recv_ack(ctx, inner, eid, msg),
+            Some((eid, None)) => State::Receiving(eid, timeout),
+            None => inner.recv(ctx).map_or_else(
+                |   | State::RecvOnHold(timeout),
+                |eid| State::Receiving(eid, timeout))
+        }
     }
     fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message) -> State {
         match self {
             State::Receiving(id, timeout) => {
                 if id == eid {
-                    inner.on_recv_ack(ctx, timeout, msg);
+                    inner.on_recv_ack(ctx, timeout, eid, msg);
                     State::Idle
                 } else {
                     State::Receiving(id, timeout)
                 }
             },
-            any => any
+            any => {
+                inner.late.on_recv_ack(eid, msg);
+                any
+            }
         }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
         inner.on_recv_timeout();
 
+        if let State::Receiving(eid, _) = self {
+            inner.late.on_recv_timeout(eid);
+        }
+
         State::Idle
     }
     fn on_recv_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
@@ -294,9 +322,10 @@ impl Inner {
 
         None
     }
-    fn on_pipe_removed(&mut self, _: &mut dyn Context) {
+    fn on_pipe_removed(&mut self, _: &mut dyn Context, eid: EndpointId) {
         self.send_ready = false;
         self.recv_ready = false;
+        self.late.on_pipe_removed(eid);
     }
     fn send(&mut self, ctx: &mut dyn Context, msg: Rc<Message>) -> Option<EndpointId> {
         if self.send_ready == false {
@@ -351,8 +380,11 @@ impl Inner {
             self.recv_ready = false;
         }
     }
-    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, msg: Message) {
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+    fn is_recv_ready(&self) -> bool {
+        self.recv_ready || self.late.is_recv_ready()
+    }
+    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, msg: Message) {
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -529,7 +561,7 @@ mod tests {
 
         let reply = rx.recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
@@ -690,4 +722,38 @@ mod tests {
         assert_eq!(Event::CanRecv(true), raised_evts[0]);
         assert_eq!(Event::CanRecv(false), raised_evts[1]);
     }
+
+    #[test]
+    fn message_received_after_a_recv_timeout_is_delivered_once_to_the_next_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut pair = Pair::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+        let timeout = Scheduled::from(2);
+
+        pair.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        pair.on_recv_ready(&mut ctx, eid);
+        pair.recv(&mut ctx, Some(Scheduled::from(1)));
+        pair.on_recv_timeout(&mut ctx);
+        pair.on_recv_ack(&mut ctx, eid, Message::from_body(vec![65, 66, 67]));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have timed out")
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(pair.is_recv_ready());
+
+        pair.recv(&mut ctx, Some(timeout));
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, _)) => assert_eq!(vec![65, 66, 67], msg.body),
+            _ => panic!("recv should have delivered the late message")
+        }
+        assert!(!pair.is_recv_ready());
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_recv_from(eid);
+        sensor.assert_one_cancellation(timeout);
+    }
 }
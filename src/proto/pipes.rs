@@ -36,6 +36,14 @@ impl PipeCollection {
         self.pipes.remove(id)
     }
 
+    pub fn is_empty(&self) -> bool {
+        self.pipes.is_empty()
+    }
+
+    pub fn contains(&self, id: &EndpointId) -> bool {
+        self.pipes.contains_key(id)
+    }
+
     pub fn get_mut(&mut self, id: &EndpointId) -> Option<&mut Pipe> {
         self.pipes.get_mut(id)
     }
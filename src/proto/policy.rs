@@ -17,11 +17,18 @@ pub mod broadcast {
         bc: &mut HashSet<EndpointId>, 
         pipes: &mut PipeCollection,
         ctx: &mut dyn Context, 
-        msg: Rc<Message>) {
+        msg: Rc<Message>) -> Vec<EndpointId> {
+
+        let mut reached = Vec::with_capacity(bc.len());
 
         for id in bc.drain() {
-            pipes.get_mut(&id).map(|pipe| pipe.send(ctx, msg.clone()));
+            if let Some(pipe) = pipes.get_mut(&id) {
+                pipe.send(ctx, msg.clone());
+                reached.push(id);
+            }
         }
+
+        reached
     }
     pub fn send_to_all_except(
         bc: &mut HashSet<EndpointId>, 
@@ -45,6 +45,11 @@ Priolist needs to support:
  - Remove from storage
  - if removed item was current, select another item to be the current
 
+### WEIGHT
+ - an item of weight N is returned N times in a row by NEXT before the others of the same priority,
+   provided it is activated again in the meantime
+ - the item keeps its remaining credit while inactive, and loses it when another item is returned
+
 */
 
 use std::ops::Range;
@@ -53,12 +58,14 @@ use core::EndpointId;
 
 pub struct Priolist {
     items: Vec<Item>,
-    current: Option<(usize, u8)>
+    current: Option<(usize, u8)>,
+    credited: Option<(EndpointId, u32)>
 }
 
 struct Item {
     value: EndpointId,
     priority: u8,
+    weight: u32,
     active: bool,
 }
 
@@ -67,12 +74,17 @@ impl Priolist {
     pub fn new() -> Priolist {
         Priolist {
             items: Vec::new(),
-            current: None
+            current: None,
+            credited: None
         }
     }
 
     pub fn insert(&mut self, id: EndpointId, prio: u8) {
-        self.items.push(Item::new(id, prio))
+        self.insert_weighted(id, prio, 1)
+    }
+
+    pub fn insert_weighted(&mut self, id: EndpointId, prio: u8, weight: u32) {
+        self.items.push(Item::new(id, prio, weight))
     }
 
     pub fn remove(&mut self, id: &EndpointId) {
@@ -85,6 +97,10 @@ impl Priolist {
         let item = self.items.swap_remove(index);
         let priority = item.priority;
 
+        if self.is_credited(&item.value) {
+            self.credited = None;
+        }
+
         if self.current == Some((index, priority)) {
             self.compute_next(index, priority);
         }
@@ -106,7 +122,7 @@ impl Priolist {
         self.set_index_active(index, true);
 
         if let Some((cur_idx, cur_prio)) = self.current.take() {
-            if priority < cur_prio {
+            if priority < cur_prio || (priority == cur_prio && self.is_credited(&self.items[index].value)) {
                 self.set_current(index, priority);
             } else {
                 self.set_current(cur_idx, cur_prio);
@@ -127,6 +143,7 @@ impl Priolist {
     pub fn pop(&mut self) -> Option<EndpointId> {
         if let Some((index, priority)) = self.current.take() {
             self.set_index_active(index, false);
+            self.consume_credit(index);
             self.compute_next(index, priority);
 
             Some(self.items[index].value)
@@ -179,6 +196,24 @@ impl Priolist {
         self.unset_current();
     }
 
+    fn is_credited(&self, id: &EndpointId) -> bool {
+        self.credited.is_some_and(|(credited_id, _)| credited_id == *id)
+    }
+
+    fn consume_credit(&mut self, index: usize) {
+        let item = &self.items[index];
+        let credit = match self.credited {
+            Some((id, credit)) if id == item.value => credit,
+            _ => item.weight.max(1)
+        };
+
+        self.credited = if credit > 1 {
+            Some((item.value, credit - 1))
+        } else {
+            None
+        };
+    }
+
     fn set_current(&mut self, index: usize, priority: u8) {
         self.current = Some((index, priority));
     }
@@ -218,10 +253,11 @@ impl Priolist {
 }
 
 impl Item {
-    fn new(id: EndpointId, prio: u8) -> Item {
+    fn new(id: EndpointId, prio: u8, item_weight: u32) -> Item {
         Item {
             value: id,
             priority: prio,
+            weight: item_weight,
             active: false
         }
     }
@@ -486,4 +522,52 @@ mod tests {
         priolist.deactivate(&three);
         assert_eq!(Some(four), priolist.pop());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn weighted_items_are_serviced_in_proportion_of_their_weight() {
+        let mut priolist = Priolist::new();
+        let heavy = EndpointId::from(0);
+        let light = EndpointId::from(1);
+        let mut heavy_count = 0;
+        let mut light_count = 0;
+
+        priolist.insert_weighted(heavy, 8, 3);
+        priolist.insert_weighted(light, 8, 1);
+        priolist.activate(&heavy);
+        priolist.activate(&light);
+
+        for _ in 0..400 {
+            let eid = priolist.pop().unwrap();
+
+            if eid == heavy {
+                heavy_count += 1;
+            } else {
+                light_count += 1;
+            }
+
+            priolist.activate(&eid);
+        }
+
+        assert_eq!(300, heavy_count);
+        assert_eq!(100, light_count);
+    }
+
+    #[test]
+    fn weighted_item_loses_its_credit_when_another_item_is_serviced() {
+        let mut priolist = Priolist::new();
+        let heavy = EndpointId::from(0);
+        let light = EndpointId::from(1);
+
+        priolist.insert_weighted(heavy, 8, 3);
+        priolist.insert_weighted(light, 8, 1);
+        priolist.activate(&heavy);
+        priolist.activate(&light);
+
+        assert_eq!(Some(heavy), priolist.pop());
+        assert_eq!(Some(light), priolist.pop());
+        priolist.activate(&light);
+        priolist.activate(&heavy);
+        assert_eq!(Some(light), priolist.pop());
+        assert_eq!(Some(heavy), priolist.pop());
+    }
+}
@@ -4,12 +4,14 @@
 // or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
 // This file may not be copied, modified, or distributed except according to those terms.
 
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
+use std::io;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
+use core::socket::{Protocol, Reply, Capabilities};
+use core::config::{ConfigOption, PubDropPolicy, unsupported_option_io_error};
 use core::endpoint::Pipe;
 use core::context::{Context, Event};
 use super::pipes::PipeCollection;
@@ -20,7 +22,20 @@ use io_error::*;
 pub struct Pub {
     reply_tx: Sender<Reply>,
     pipes: PipeCollection,
-    bc: HashSet<EndpointId>
+    bc: HashSet<EndpointId>,
+    retain_last: bool,
+    retain_topic_len: usize,
+    retained: HashMap<Vec<u8>, (u64, Rc<Message>)>,
+    retained_seq: u64,
+    history_len: usize,
+    history: VecDeque<Rc<Message>>,
+    fresh: HashSet<EndpointId>,
+    replaying: HashMap<EndpointId, VecDeque<Rc<Message>>>,
+    send_buffer_size: usize,
+    drop_policy: PubDropPolicy,
+    pending: HashMap<EndpointId, VecDeque<Rc<Message>>>,
+    blocked: Option<(Rc<Message>, Timeout)>,
+    dropped: usize
 }
 
 /*****************************************************************************/
@@ -34,11 +49,131 @@ impl From<Sender<Reply>> for Pub {
         Pub {
             reply_tx: tx,
             pipes: PipeCollection::new(),
-            bc: HashSet::new()
+            bc: HashSet::new(),
+            retain_last: false,
+            retain_topic_len: 0,
+            retained: HashMap::new(),
+            retained_seq: 0,
+            history_len: 0,
+            history: VecDeque::new(),
+            fresh: HashSet::new(),
+            replaying: HashMap::new(),
+            send_buffer_size: 0,
+            drop_policy: PubDropPolicy::DropNewest,
+            pending: HashMap::new(),
+            blocked: None,
+            dropped: 0
         }
     }
 }
 
+impl Pub {
+    /// Maximum number of messages replayed to a newly opened pipe.
+    fn replay_len(&self) -> usize {
+        self.history_len + self.retained.len()
+    }
+
+    fn retain(&mut self, msg: &Rc<Message>) {
+        if self.retain_last {
+            let topic = topic_of(msg, self.retain_topic_len).to_vec();
+
+            self.retained_seq += 1;
+            self.retained.insert(topic, (self.retained_seq, msg.clone()));
+        }
+
+        let capacity = self.replay_len();
+
+        push_bounded(&mut self.history, msg.clone(), self.history_len);
+        for queue in self.replaying.values_mut() {
+            push_bounded(queue, msg.clone(), capacity);
+        }
+    }
+
+    /// Lists the messages to replay to a newly opened pipe in publication order,
+    /// the retained messages that are not part of the history coming first.
+    fn replay(&self) -> VecDeque<Rc<Message>> {
+        let mut retained: Vec<_> = self.retained.values().
+            filter(|kept| !self.history.iter().any(|past| Rc::ptr_eq(past, &kept.1))).
+            collect();
+
+        retained.sort_by_key(|kept| kept.0);
+
+        retained.into_iter().map(|kept| kept.1.clone()).chain(self.history.iter().cloned()).collect()
+    }
+
+    fn publish(&mut self, ctx: &mut dyn Context, msg: Rc<Message>, timeout: Timeout) {
+        self.retain(&msg);
+        self.enqueue(&msg);
+
+        broadcast::send_to_all(&mut self.bc, &mut self.pipes, ctx, msg);
+        ctx.raise(Event::CanSend(false));
+
+        let _ = self.reply_tx.send(Reply::Send);
+        if let Some(sched) = timeout {
+            ctx.cancel(sched);
+        }
+    }
+
+    /// Queues the message for the pipes that are busy, applying the drop policy to the full queues.
+    fn enqueue(&mut self, msg: &Rc<Message>) {
+        if self.send_buffer_size == 0 {
+            return;
+        }
+
+        for (eid, queue) in self.pending.iter_mut() {
+            if self.bc.contains(eid) {
+                continue;
+            }
+            if queue.len() >= self.send_buffer_size {
+                if self.drop_policy != PubDropPolicy::DropOldest {
+                    self.dropped += 1;
+                    continue;
+                }
+                while queue.len() >= self.send_buffer_size {
+                    queue.pop_front();
+                    self.dropped += 1;
+                }
+            }
+            queue.push_back(msg.clone());
+        }
+    }
+
+    fn is_any_queue_full(&self) -> bool {
+        self.send_buffer_size > 0 && self.pending.values().any(|queue| queue.len() >= self.send_buffer_size)
+    }
+
+    fn resume_blocked(&mut self, ctx: &mut dyn Context) {
+        if self.is_any_queue_full() {
+            return;
+        }
+        if let Some((msg, timeout)) = self.blocked.take() {
+            self.publish(ctx, msg, timeout);
+        }
+    }
+
+    fn truncate_history(&mut self) {
+        while self.history.len() > self.history_len {
+            self.history.pop_front();
+        }
+    }
+}
+
+fn topic_of(msg: &Message, len: usize) -> &[u8] {
+    let body = msg.get_body();
+
+    &body[..len.min(body.len())]
+}
+
+fn push_bounded(queue: &mut VecDeque<Rc<Message>>, msg: Rc<Message>, capacity: usize) {
+    if capacity == 0 {
+        return;
+    }
+    if queue.len() == capacity {
+        queue.pop_front();
+    }
+    queue.push_back(msg);
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* Protocol                                                                  */
@@ -51,34 +186,67 @@ impl Protocol for Pub {
 
     fn add_pipe(&mut self, _: &mut dyn Context, eid: EndpointId, pipe: Pipe) {
         self.pipes.insert(eid, pipe);
+        self.fresh.insert(eid);
     }
     fn remove_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> Option<Pipe> {
+        self.fresh.remove(&eid);
+        self.replaying.remove(&eid);
+        self.pending.remove(&eid);
         self.bc.remove(&eid);
         if self.bc.is_empty() {
             ctx.raise(Event::CanSend(false));
         }
+        self.resume_blocked(ctx);
         self.pipes.remove(&eid)
     }
     fn send(&mut self, ctx: &mut dyn Context, msg: Message, timeout: Timeout) {
         let msg = Rc::new(msg);
 
-        broadcast::send_to_all(&mut self.bc, &mut self.pipes, ctx, msg);
-        ctx.raise(Event::CanSend(false));
-
-        let _ = self.reply_tx.send(Reply::Send);
-        if let Some(sched) = timeout {
-            ctx.cancel(sched);
+        if self.drop_policy == PubDropPolicy::Block && self.is_any_queue_full() {
+            self.blocked = Some((msg, timeout));
+        } else {
+            self.publish(ctx, msg, timeout);
         }
     }
     fn on_send_ack(&mut self, _: &mut dyn Context, _: EndpointId) {
     }
     fn on_send_timeout(&mut self, _: &mut dyn Context) {
+        if self.blocked.take().is_some() {
+            let error = timedout_io_error("Send timed out");
+            let _ = self.reply_tx.send(Reply::Err(error));
+        }
     }
     fn on_send_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if self.fresh.remove(&eid) {
+            let replay = self.replay();
+
+            if !replay.is_empty() {
+                self.replaying.insert(eid, replay);
+            }
+        }
+        let replayed = self.replaying.get_mut(&eid).map(|queue| queue.pop_front());
+        match replayed {
+            Some(Some(msg)) => {
+                // the pipe will be send ready again once the replayed message is written
+                self.pipes.send_to(ctx, msg, eid);
+                return;
+            },
+            Some(None) => {
+                self.replaying.remove(&eid);
+            },
+            None => {}
+        }
+        let pending = self.pending.entry(eid).or_default().pop_front();
+        if let Some(msg) = pending {
+            self.pipes.send_to(ctx, msg, eid);
+            self.resume_blocked(ctx);
+            return;
+        }
         if self.bc.is_empty() {
             ctx.raise(Event::CanSend(true));
         }
         self.bc.insert(eid);
+        self.resume_blocked(ctx);
     }
     fn on_send_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
         let was_empty = self.bc.is_empty();
@@ -111,7 +279,210 @@ impl Protocol for Pub {
     fn is_recv_ready(&self) -> bool {
         false
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: false,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: true
+        }
+    }
+    fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
+        match opt {
+            ConfigOption::PubRetainLast(x) => {
+                self.retain_last = x;
+                if !x {
+                    self.retained.clear();
+                }
+                Ok(())
+            },
+            ConfigOption::PubRetainTopicLen(x) => {
+                self.retain_topic_len = x;
+                self.retained.clear();
+                Ok(())
+            },
+            ConfigOption::PubHistory(x) => {
+                self.history_len = x;
+                self.truncate_history();
+                Ok(())
+            },
+            ConfigOption::SendBufferSize(x) => {
+                self.send_buffer_size = x;
+                Ok(())
+            },
+            ConfigOption::PubDropPolicy(x) => {
+                self.drop_policy = x;
+                Ok(())
+            },
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
+        }
+    }
+    fn dropped_count(&self) -> usize {
+        self.dropped
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.pipes.close_all(ctx)
     }
 }
+
+/*****************************************************************************/
+/*                                                                           */
+/* tests                                                                     */
+/*                                                                           */
+/*****************************************************************************/
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+
+    use core::{EndpointId, Message};
+    use core::socket::{Protocol, Reply};
+    use core::config::{ConfigOption, PubDropPolicy};
+    use core::tests::*;
+
+    use super::*;
+
+    fn publish(publ: &mut Pub, ctx: &mut TestContext, body: u8) {
+        publ.send(ctx, Message::from_body(vec![body]), None);
+    }
+
+    fn sent_bodies(sensor: &TestContextSensor) -> Vec<u8> {
+        sensor.get_send_calls().iter().map(|call| call.1.get_body()[0]).collect()
+    }
+
+    fn with_busy_pipe(policy: PubDropPolicy) -> (Pub, mpsc::Receiver<Reply>, Rc<RefCell<TestContextSensor>>, TestContext, EndpointId) {
+        let (tx, rx) = mpsc::channel();
+        let mut publ = Pub::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+
+        publ.set_option(ConfigOption::SendBufferSize(2)).unwrap();
+        publ.set_option(ConfigOption::PubDropPolicy(policy)).unwrap();
+        publ.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        publ.on_send_ready(&mut ctx, eid);
+        publish(&mut publ, &mut ctx, 0);
+
+        (publ, rx, ctx_sensor, ctx, eid)
+    }
+
+    #[test]
+    fn busy_pipe_queue_drops_the_newest_messages_by_default() {
+        let (mut publ, _, ctx_sensor, mut ctx, eid) = with_busy_pipe(PubDropPolicy::DropNewest);
+
+        for body in 1..5 {
+            publish(&mut publ, &mut ctx, body);
+        }
+        assert_eq!(2, publ.dropped_count());
+
+        for _ in 0..3 {
+            publ.on_send_ready(&mut ctx, eid);
+        }
+        assert_eq!(vec![0, 1, 2], sent_bodies(&ctx_sensor.borrow()));
+    }
+
+    #[test]
+    fn busy_pipe_queue_can_drop_the_oldest_messages() {
+        let (mut publ, _, ctx_sensor, mut ctx, eid) = with_busy_pipe(PubDropPolicy::DropOldest);
+
+        for body in 1..5 {
+            publish(&mut publ, &mut ctx, body);
+        }
+        assert_eq!(2, publ.dropped_count());
+
+        for _ in 0..3 {
+            publ.on_send_ready(&mut ctx, eid);
+        }
+        assert_eq!(vec![0, 3, 4], sent_bodies(&ctx_sensor.borrow()));
+    }
+
+    #[test]
+    fn busy_pipe_queue_can_block_the_send_until_there_is_room() {
+        let (mut publ, rx, ctx_sensor, mut ctx, eid) = with_busy_pipe(PubDropPolicy::Block);
+
+        publish(&mut publ, &mut ctx, 1);
+        publish(&mut publ, &mut ctx, 2);
+        assert_eq!(3, rx.try_iter().count());
+
+        publish(&mut publ, &mut ctx, 3);
+        assert!(rx.try_recv().is_err(), "send should be blocked !");
+
+        publ.on_send_ready(&mut ctx, eid);
+        match rx.try_recv() {
+            Ok(Reply::Send) => {},
+            _ => panic!("blocked send should have completed !")
+        }
+
+        publ.on_send_ready(&mut ctx, eid);
+        publ.on_send_ready(&mut ctx, eid);
+        assert_eq!(vec![0, 1, 2, 3], sent_bodies(&ctx_sensor.borrow()));
+        assert_eq!(0, publ.dropped_count());
+    }
+
+    #[test]
+    fn nothing_is_queued_without_send_buffer() {
+        let (tx, _) = mpsc::channel();
+        let mut publ = Pub::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+
+        publ.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        publ.on_send_ready(&mut ctx, eid);
+        publish(&mut publ, &mut ctx, 0);
+        publish(&mut publ, &mut ctx, 1);
+        publ.on_send_ready(&mut ctx, eid);
+
+        assert_eq!(vec![0], sent_bodies(&ctx_sensor.borrow()));
+        assert_eq!(0, publ.dropped_count());
+    }
+
+    fn replayed_to_new_pipe(publ: &mut Pub, bodies: &[&[u8]]) -> Vec<Vec<u8>> {
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+
+        for body in bodies {
+            publ.send(&mut ctx, Message::from_body(body.to_vec()), None);
+        }
+
+        publ.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        for _ in 0..bodies.len() {
+            publ.on_send_ready(&mut ctx, eid);
+        }
+
+        let sensor = ctx_sensor.borrow();
+        sensor.get_send_calls().iter().map(|call| call.1.get_body().to_vec()).collect()
+    }
+
+    #[test]
+    fn last_message_of_each_topic_is_replayed_in_publication_order() {
+        let (tx, _) = mpsc::channel();
+        let mut publ = Pub::from(tx);
+
+        publ.set_option(ConfigOption::PubRetainLast(true)).unwrap();
+        publ.set_option(ConfigOption::PubRetainTopicLen(1)).unwrap();
+
+        let replayed = replayed_to_new_pipe(&mut publ, &[b"A1", b"B1", b"A2", b"C"]);
+
+        assert_eq!(vec![b"B1".to_vec(), b"A2".to_vec(), b"C".to_vec()], replayed);
+    }
+
+    #[test]
+    fn retained_messages_are_replayed_before_the_history() {
+        let (tx, _) = mpsc::channel();
+        let mut publ = Pub::from(tx);
+
+        publ.set_option(ConfigOption::PubRetainLast(true)).unwrap();
+        publ.set_option(ConfigOption::PubRetainTopicLen(1)).unwrap();
+        publ.set_option(ConfigOption::PubHistory(2)).unwrap();
+
+        let replayed = replayed_to_new_pipe(&mut publ, &[b"A1", b"B1", b"A2", b"A3"]);
+
+        assert_eq!(vec![b"B1".to_vec(), b"A2".to_vec(), b"A3".to_vec()], replayed);
+    }
+}
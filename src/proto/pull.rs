@@ -5,16 +5,24 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::rc::Rc;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::mpsc::Sender;
+use std::io;
+
+use byteorder::*;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
+use core::socket::{Protocol, Reply, Capabilities};
 use core::endpoint::Pipe;
 use core::context::Context;
+use core::config::{ConfigOption, unsupported_option_io_error};
 use super::priolist::Priolist;
 use super::pipes::PipeCollection;
+use super::late::LateRecv;
 use super::{Timeout, PUSH, PULL};
 use super::policy::fair_queue;
+use super::credit;
+use transport::{FEATURE_CREDIT, FEATURE_SEQUENCED};
 use io_error::*;
 
 pub struct Pull {
@@ -31,7 +39,33 @@ enum State {
 struct Inner {
     reply_tx: Sender<Reply>,
     pipes: PipeCollection,
-    fq: Priolist
+    fq: Priolist,
+    credit_window: u32,
+    grants: HashMap<EndpointId, Grant>,
+    credit_peers: HashSet<EndpointId>,
+    writing: HashSet<EndpointId>,
+    require_ack: bool,
+    sequenced: HashSet<EndpointId>,
+    acks: HashMap<EndpointId, Vec<u32>>,
+    received: HashMap<EndpointId, SeqWindow>,
+    eof_on_no_peers: bool,
+    had_peers: bool,
+    late: LateRecv
+}
+
+#[derive(Default)]
+struct Grant {
+    consumed: u32
+}
+
+/// Number of sequence ids remembered for each pipe to detect the resent messages.
+const SEQ_WINDOW_SIZE: usize = 1024;
+
+/// Sequence ids of the last messages received from a pipe.
+#[derive(Default)]
+struct SeqWindow {
+    ids: HashSet<u32>,
+    order: VecDeque<u32>
 }
 
 /*****************************************************************************/
@@ -66,7 +100,18 @@ impl From<Sender<Reply>> for Pull {
             inner: Inner {
                 reply_tx: tx,
                 pipes: PipeCollection::new(),
-                fq: Priolist::new()
+                fq: Priolist::new(),
+                credit_window: 0,
+                grants: HashMap::new(),
+                credit_peers: HashSet::new(),
+                writing: HashSet::new(),
+                require_ack: false,
+                sequenced: HashSet::new(),
+                acks: HashMap::new(),
+                received: HashMap::new(),
+                eof_on_no_peers: false,
+                had_peers: false,
+                late: LateRecv::new()
             },
             state: Some(State::Idle)
         }
@@ -83,8 +128,8 @@ impl Protocol for Pull {
     fn id(&self)      -> u16 { PULL }
     fn peer_id(&self) -> u16 { PUSH }
 
-    fn add_pipe(&mut self, _: &mut dyn Context, eid: EndpointId, pipe: Pipe) {
-        self.inner.add_pipe(eid, pipe)
+    fn add_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId, pipe: Pipe) {
+        self.inner.add_pipe(ctx, eid, pipe)
     }
     fn remove_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> Option<Pipe> {
         let was_recv_ready = self.is_recv_ready();
@@ -135,6 +180,38 @@ impl Protocol for Pull {
     fn is_recv_ready(&self) -> bool {
         self.inner.is_recv_ready()
     }
+    fn features(&self) -> u8 {
+        self.inner.features()
+    }
+    fn on_option_set(&mut self, ctx: &mut dyn Context) {
+        self.inner.on_option_set(ctx)
+    }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: false,
+            can_recv: true,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: false
+        }
+    }
+    fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
+        match opt {
+            ConfigOption::CreditFlowControl(window) => {
+                self.inner.credit_window = window;
+                Ok(())
+            },
+            ConfigOption::RequireAck(x) => {
+                self.inner.require_ack = x;
+                Ok(())
+            },
+            ConfigOption::RecvEofOnNoPeers(x) => {
+                self.inner.eof_on_no_peers = x;
+                Ok(())
+            },
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
+        }
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -166,6 +243,14 @@ impl State {
                     State::Receiving(id, timeout)
                 }
             },
+            State::RecvOnHold(timeout) => {
+                if inner.is_eof() {
+                    inner.on_eof(ctx, timeout);
+                    State::Idle
+                } else {
+                    State::RecvOnHold(timeout)
+                }
+            },
             any => any
         }
     }
@@ -180,7 +265,8 @@ impl State {
         inner.send(ctx, timeout);
         self
     }
-    fn on_send_ack(self, _: &mut dyn Context, _: &mut Inner, _: EndpointId) -> State {
+    fn on_send_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
+        inner.on_frame_sent(ctx, eid);
         self
     }
     fn on_send_timeout(self, _: &mut dyn Context, _: &mut Inner) -> State {
@@ -200,26 +286,48 @@ impl State {
 /*****************************************************************************/
 
     fn recv(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout) -> State {
-        inner.recv(ctx).map_or_else(
-            |   | State::RecvOnHold(timeout),
-            |eid| State::Receiving(eid, timeout))
+        if inner.is_eof() {
+            inner.on_eof(ctx, timeout);
+            return State::Idle;
+        }
+
+        match inner.late.take() {
+            Some((eid, Some(msg))) => State::Receiving(eid, timeout).on_recv_ack(ctx, inner, eid, msg),
+            Some((eid, None)) => State::Receiving(eid, timeout),
+            None => inner.recv(ctx).map_or_else(
+                |   | State::RecvOnHold(timeout),
+                |eid| State::Receiving(eid, timeout))
+        }
     }
     fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message) -> State {
         match self {
             State::Receiving(id, timeout) => {
                 if id == eid {
-                    inner.on_recv_ack(ctx, timeout, msg);
-                    State::Idle
+                    if let Some(msg) = inner.acknowledge(ctx, eid, msg) {
+                        inner.on_recv_ack(ctx, timeout, eid, msg);
+                        inner.on_msg_consumed(ctx, eid);
+                        State::Idle
+                    } else {
+                        inner.on_msg_consumed(ctx, eid);
+                        State::Idle.recv(ctx, inner, timeout)
+                    }
                 } else {
                     State::Receiving(id, timeout)
                 }
             },
-            any => any
+            any => {
+                inner.late.on_recv_ack(eid, msg);
+                any
+            }
         }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
         inner.on_recv_timeout();
 
+        if let State::Receiving(eid, _) = self {
+            inner.late.on_recv_timeout(eid);
+        }
+
         State::Idle
     }
     fn on_recv_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
@@ -243,14 +351,120 @@ impl State {
 /*****************************************************************************/
 
 impl Inner {
-    fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
-        self.fq.insert(eid, pipe.get_recv_priority());
+    fn add_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId, pipe: Pipe) {
+        self.fq.insert_weighted(eid, pipe.get_recv_priority(), pipe.get_recv_weight());
+        let features = pipe.get_features();
+
+        if features.peer_has(FEATURE_CREDIT) {
+            self.credit_peers.insert(eid);
+        }
+        if features.both_have(FEATURE_SEQUENCED) {
+            self.sequenced.insert(eid);
+        }
         self.pipes.insert(eid, pipe);
+        self.had_peers = true;
+        self.start_grants(ctx, eid);
+    }
+    fn features(&self) -> u8 {
+        let credit = if self.credit_window > 0 { FEATURE_CREDIT } else { 0 };
+        let sequenced = if self.require_ack { FEATURE_SEQUENCED } else { 0 };
+
+        credit | sequenced
+    }
+    /// Grants the initial window to a pipe whose peer takes part in the flow control, if not done yet.
+    /// The peers that did not advertise it in their handshake are never sent any credit frame.
+    fn start_grants(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if self.credit_window == 0 || !self.credit_peers.contains(&eid) || self.grants.contains_key(&eid) {
+            return;
+        }
+
+        let window = self.credit_window;
+
+        self.grants.insert(eid, Grant::default());
+        self.grant(ctx, eid, window);
+    }
+    fn on_option_set(&mut self, ctx: &mut dyn Context) {
+        let eids: Vec<EndpointId> = self.credit_peers.iter().cloned().collect();
+
+        for eid in eids {
+            self.start_grants(ctx, eid);
+        }
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
         self.fq.remove(&eid);
+        self.grants.remove(&eid);
+        self.credit_peers.remove(&eid);
+        self.sequenced.remove(&eid);
+        self.writing.remove(&eid);
+        self.acks.remove(&eid);
+        self.received.remove(&eid);
+        self.late.on_pipe_removed(eid);
         self.pipes.remove(&eid)
     }
+    fn grant(&mut self, ctx: &mut dyn Context, eid: EndpointId, credits: u32) {
+        if let Some(grant) = self.grants.get_mut(&eid) {
+            grant.consumed = 0;
+        }
+
+        self.writing.insert(eid);
+        self.pipes.send_to(ctx, Rc::new(credit::encode(credits)), eid);
+    }
+    fn check_grant(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        let threshold = ::std::cmp::max(1, self.credit_window / 2);
+        let consumed = match self.grants.get(&eid) {
+            Some(grant) if !self.writing.contains(&eid) => grant.consumed,
+            _ => return
+        };
+
+        if consumed >= threshold {
+            self.grant(ctx, eid, consumed);
+        }
+    }
+    fn on_msg_consumed(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some(grant) = self.grants.get_mut(&eid) {
+            grant.consumed += 1;
+        }
+
+        self.check_grant(ctx, eid);
+    }
+    fn on_frame_sent(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        self.writing.remove(&eid);
+        self.send_acks(ctx, eid);
+        self.check_grant(ctx, eid);
+    }
+    /// Strips and acknowledges the sequence id that starts each message received from a pipe
+    /// whose both sides agreed on the acknowledged delivery, the messages of the other pipes being left untouched.
+    /// Returns `None` when the message is a duplicate, or is too short to hold a sequence id.
+    fn acknowledge(&mut self, ctx: &mut dyn Context, eid: EndpointId, mut msg: Message) -> Option<Message> {
+        if !self.sequenced.contains(&eid) {
+            return Some(msg);
+        }
+        if msg.get_body().len() < 4 {
+            debug!("[{:?}] message without sequence id dropped from ep {:?}", ctx, eid);
+            return None;
+        }
+
+        let seq_id = BigEndian::read_u32(&msg.get_body()[0..4]);
+
+        self.acks.entry(eid).or_default().push(seq_id);
+        self.send_acks(ctx, eid);
+
+        if self.received.entry(eid).or_default().insert(seq_id) {
+            msg.body.drain(0..4);
+            Some(msg)
+        } else {
+            None
+        }
+    }
+    fn send_acks(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if self.writing.contains(&eid) {
+            return;
+        }
+        if let Some(seq_ids) = self.acks.remove(&eid) {
+            self.writing.insert(eid);
+            self.pipes.send_to(ctx, Rc::new(credit::encode_ack(&seq_ids)), eid);
+        }
+    }
     fn send(&mut self, ctx: &mut dyn Context, timeout: Timeout) {
         let error = other_io_error("Send is not supported by pull protocol");
         let _ = self.reply_tx.send(Reply::Err(error));
@@ -268,8 +482,18 @@ impl Inner {
     fn on_recv_not_ready(&mut self, eid: EndpointId) {
         self.fq.deactivate(&eid)
     }
-    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, msg: Message) {
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, msg: Message) {
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
+        if let Some(sched) = timeout {
+            ctx.cancel(sched);
+        }
+    }
+    fn is_eof(&self) -> bool {
+        self.eof_on_no_peers && self.had_peers && self.pipes.is_empty()
+    }
+    fn on_eof(&self, ctx: &mut dyn Context, timeout: Timeout) {
+        let error = eof_io_error("All peers are gone");
+        let _ = self.reply_tx.send(Reply::Err(error));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -279,13 +503,29 @@ impl Inner {
         let _ = self.reply_tx.send(Reply::Err(error));
     }
     fn is_recv_ready(&self) -> bool {
-        self.fq.peek()
+        self.fq.peek() || self.late.is_recv_ready()
     }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.pipes.close_all(ctx)
     }
 }
 
+impl SeqWindow {
+    fn insert(&mut self, seq_id: u32) -> bool {
+        if !self.ids.insert(seq_id) {
+            return false;
+        }
+
+        self.order.push_back(seq_id);
+        if self.order.len() > SEQ_WINDOW_SIZE {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* tests                                                                     */
@@ -325,7 +565,7 @@ mod tests {
 
         let reply = rx.recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
@@ -406,4 +646,38 @@ mod tests {
         assert_eq!(Event::CanRecv(true), raised_evts[0]);
         assert_eq!(Event::CanRecv(false), raised_evts[1]);
     }
+
+    #[test]
+    fn message_received_after_a_recv_timeout_is_delivered_once_to_the_next_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut pull = Pull::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+        let timeout = Scheduled::from(2);
+
+        pull.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        pull.on_recv_ready(&mut ctx, eid);
+        pull.recv(&mut ctx, Some(Scheduled::from(1)));
+        pull.on_recv_timeout(&mut ctx);
+        pull.on_recv_ack(&mut ctx, eid, Message::from_body(vec![65, 66, 67]));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have timed out")
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(pull.is_recv_ready());
+
+        pull.recv(&mut ctx, Some(timeout));
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, _)) => assert_eq!(vec![65, 66, 67], msg.body),
+            _ => panic!("recv should have delivered the late message")
+        }
+        assert!(!pull.is_recv_ready());
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_recv_from(eid);
+        sensor.assert_one_cancellation(timeout);
+    }
 }
\ No newline at end of file
@@ -5,16 +5,24 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::rc::Rc;
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::sync::mpsc::Sender;
+use std::time::Duration;
+use std::io;
 
-use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
+use byteorder::*;
+use time;
+
+use core::{EndpointId, Message, Scheduled};
+use core::socket::{Protocol, Reply, Capabilities};
 use core::endpoint::Pipe;
-use core::context::Context;
+use core::context::{Context, Schedulable};
+use core::config::{ConfigOption, unsupported_option_io_error};
 use super::priolist::Priolist;
 use super::pipes::PipeCollection;
 use super::{Timeout, PUSH, PULL};
-use super::policy::load_balancing;
+use super::credit;
+use transport::{FEATURE_CREDIT, FEATURE_SEQUENCED};
 use io_error::*;
 
 pub struct Push {
@@ -31,7 +39,24 @@ enum State {
 struct Inner {
     reply_tx: Sender<Reply>,
     pipes: PipeCollection,
-    lb: Priolist
+    lb: Priolist,
+    credit_flow: bool,
+    credits: HashMap<EndpointId, u32>,
+    writable: HashSet<EndpointId>,
+    require_ack: bool,
+    sequenced: HashSet<EndpointId>,
+    sending: HashMap<EndpointId, (u32, Rc<Message>)>,
+    ack_resend_ivl: Duration,
+    seq_id: u32,
+    unacked: HashMap<u32, Unacked>,
+    resending: HashMap<EndpointId, u32>,
+    resends: VecDeque<u32>
+}
+
+/// Message sent while acknowledged delivery is enabled, kept until the puller acknowledges it.
+struct Unacked {
+    msg: Rc<Message>,
+    timeout: Option<Scheduled>
 }
 
 /*****************************************************************************/
@@ -66,7 +91,18 @@ impl From<Sender<Reply>> for Push {
             inner: Inner {
                 reply_tx: tx,
                 pipes: PipeCollection::default(),
-                lb: Priolist::new()
+                lb: Priolist::new(),
+                credit_flow: false,
+                credits: HashMap::new(),
+                writable: HashSet::new(),
+                require_ack: false,
+                sequenced: HashSet::new(),
+                sending: HashMap::new(),
+                ack_resend_ivl: Duration::from_secs(1),
+                seq_id: time::get_time().nsec as u32,
+                unacked: HashMap::new(),
+                resending: HashMap::new(),
+                resends: VecDeque::new()
             },
             state: Some(State::Idle)
         }
@@ -89,6 +125,7 @@ impl Protocol for Push {
     fn remove_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> Option<Pipe> {
         let was_send_ready = self.is_send_ready();
         let pipe = self.inner.remove_pipe(eid);
+        self.inner.flush_resends(ctx);
         let is_send_ready = self.is_send_ready();
 
         ctx.check_send_ready_change(was_send_ready, is_send_ready);
@@ -103,6 +140,12 @@ impl Protocol for Push {
         self.apply(ctx, |s, ctx, inner| s.send(ctx, inner, Rc::new(msg), timeout))
     }
     fn on_send_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if self.inner.on_resend_ack(ctx, eid) {
+            return;
+        }
+
+        self.inner.on_msg_sent(ctx, eid);
+
         self.apply(ctx, |s, ctx, inner| s.on_send_ack(ctx, inner, eid))
     }
     fn on_send_timeout(&mut self, ctx: &mut dyn Context) {
@@ -132,9 +175,46 @@ impl Protocol for Push {
     fn is_send_ready(&self) -> bool {
         self.inner.is_send_ready()
     }
+    fn features(&self) -> u8 {
+        self.inner.features()
+    }
+    fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
+        match opt {
+            ConfigOption::CreditFlowControl(window) => {
+                self.inner.credit_flow = window > 0;
+                Ok(())
+            },
+            ConfigOption::RequireAck(x) => {
+                self.inner.require_ack = x;
+                Ok(())
+            },
+            ConfigOption::AckResendIvl(ivl) => {
+                self.inner.ack_resend_ivl = ivl;
+                Ok(())
+            },
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
+        }
+    }
+    fn on_timer_tick(&mut self, ctx: &mut dyn Context, task: Schedulable) {
+        if let Schedulable::AckResend(seq_id) = task {
+            let was_send_ready = self.is_send_ready();
+
+            self.inner.on_ack_timeout(ctx, seq_id);
+            ctx.check_send_ready_change(was_send_ready, self.is_send_ready());
+        }
+    }
     fn is_recv_ready(&self) -> bool {
         false
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: false,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: false
+        }
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -197,12 +277,18 @@ impl State {
         }
     }
     fn on_send_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
-        inner.on_send_timeout();
+        match self {
+            State::Sending(..) | State::SendOnHold(..) => {
+                inner.on_send_timeout();
 
-        State::Idle
+                State::Idle
+            },
+            any => any
+        }
     }
     fn on_send_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
         inner.on_send_ready(eid);
+        inner.flush_resends(ctx);
 
         match self {
             State::SendOnHold(msg, timeout) => State::Idle.send(ctx, inner, msg, timeout),
@@ -224,13 +310,21 @@ impl State {
         inner.recv(ctx, timeout);
         self
     }
-    fn on_recv_ack(self, _: &mut dyn Context, _: &mut Inner, _: EndpointId, _: Message) -> State {
-        self
+    fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message) -> State {
+        inner.on_credit_received(eid, &msg);
+        inner.on_ack_received(ctx, &msg);
+        inner.flush_resends(ctx);
+
+        match self {
+            State::SendOnHold(msg, timeout) => State::Idle.send(ctx, inner, msg, timeout),
+            any => any
+        }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, _: &mut Inner) -> State {
         self
     }
-    fn on_recv_ready(self, _: &mut dyn Context, _: &mut Inner, _: EndpointId) -> State {
+    fn on_recv_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
+        inner.on_recv_ready(ctx, eid);
         self
     }
     fn on_recv_not_ready(self, _: &mut dyn Context, _: &mut Inner, _: EndpointId) -> State {
@@ -246,22 +340,164 @@ impl State {
 
 impl Inner {
     fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
+        let features = pipe.get_features();
+
+        if self.credit_flow && features.peer_has(FEATURE_CREDIT) {
+            self.credits.insert(eid, 0);
+        }
+        if features.both_have(FEATURE_SEQUENCED) {
+            self.sequenced.insert(eid);
+        }
         self.lb.insert(eid, pipe.get_send_priority());
         self.pipes.insert(eid, pipe);
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
         self.lb.remove(&eid);
+        self.credits.remove(&eid);
+        self.writable.remove(&eid);
+        self.sequenced.remove(&eid);
+        self.sending.remove(&eid);
+        if let Some(seq_id) = self.resending.remove(&eid) {
+            self.resends.push_front(seq_id);
+        }
         self.pipes.remove(&eid)
     }
+    fn features(&self) -> u8 {
+        let credit = if self.credit_flow { FEATURE_CREDIT } else { 0 };
+        let sequenced = if self.require_ack { FEATURE_SEQUENCED } else { 0 };
+
+        credit | sequenced
+    }
+    /// Sends the message to the next ready pipe, prefixed with a new sequence id
+    /// when both sides of the pipe agreed on the acknowledged delivery.
     fn send(&mut self, ctx: &mut dyn Context, msg: Rc<Message>) -> Option<EndpointId> {
-        load_balancing::send(&mut self.lb, &mut self.pipes, ctx, msg)
+        let eid = self.lb.pop()?;
+
+        if self.sequenced.contains(&eid) {
+            self.seq_id = self.seq_id.wrapping_add(1);
+
+            let seq_id = self.seq_id | 0x8000_0000;
+            let raw_msg = Rc::new(encode(&msg, seq_id));
+
+            self.sending.insert(eid, (seq_id, raw_msg.clone()));
+            self.send_to(ctx, raw_msg, eid)
+        } else {
+            self.send_to(ctx, msg, eid)
+        }
+    }
+    /// Sends again a message that was not acknowledged, without its sequence id
+    /// if the next ready pipe does not take part in the acknowledged delivery.
+    fn resend(&mut self, ctx: &mut dyn Context, raw_msg: Rc<Message>) -> Option<EndpointId> {
+        let eid = self.lb.pop()?;
+
+        if self.sequenced.contains(&eid) {
+            self.send_to(ctx, raw_msg, eid)
+        } else {
+            self.send_to(ctx, Rc::new(decode(&raw_msg)), eid)
+        }
+    }
+    fn send_to(&mut self, ctx: &mut dyn Context, msg: Rc<Message>, eid: EndpointId) -> Option<EndpointId> {
+        let sent = self.pipes.send_to(ctx, msg, eid);
+
+        if let Some(eid) = sent {
+            self.writable.remove(&eid);
+            if let Some(credits) = self.credits.get_mut(&eid) {
+                *credits -= 1;
+            }
+        }
+
+        sent
     }
     fn on_send_ready(&mut self, eid: EndpointId) {
-        self.lb.activate(&eid)
+        self.writable.insert(eid);
+        self.check_send_ready(eid);
     }
     fn on_send_not_ready(&mut self, eid: EndpointId) {
+        self.writable.remove(&eid);
         self.lb.deactivate(&eid)
     }
+    /// Pipes whose peer does not take part in the flow control are never throttled,
+    /// the others are until they are granted credits.
+    fn has_credit(&self, eid: EndpointId) -> bool {
+        self.credits.get(&eid) != Some(&0)
+    }
+    fn check_send_ready(&mut self, eid: EndpointId) {
+        if self.writable.contains(&eid) && self.has_credit(eid) {
+            self.lb.activate(&eid)
+        }
+    }
+    fn on_recv_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if self.credit_flow || self.require_ack || self.credits.contains_key(&eid) || self.sequenced.contains(&eid) {
+            self.pipes.recv_from(ctx, eid);
+        }
+    }
+    /// A grant received on a pipe that was not throttled yet, because the puller enabled
+    /// the flow control after the pipe was added, switches the pipe to the credited mode.
+    fn on_credit_received(&mut self, eid: EndpointId, msg: &Message) {
+        if !self.credit_flow && !self.credits.contains_key(&eid) {
+            return;
+        }
+        if let Some(granted) = credit::decode(msg) {
+            *self.credits.entry(eid).or_insert(0) += granted;
+            self.check_send_ready(eid);
+        }
+    }
+    fn on_msg_sent(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
+        if let Some((seq_id, raw_msg)) = self.sending.remove(&eid) {
+            self.wait_for_ack(ctx, seq_id, raw_msg);
+        }
+    }
+    fn wait_for_ack(&mut self, ctx: &mut dyn Context, seq_id: u32, raw_msg: Rc<Message>) {
+        let resend = ctx.schedule(Schedulable::AckResend(seq_id), self.ack_resend_ivl).ok();
+
+        self.unacked.insert(seq_id, Unacked { msg: raw_msg, timeout: resend });
+    }
+    fn on_ack_received(&mut self, ctx: &mut dyn Context, msg: &Message) {
+        if let Some(seq_ids) = credit::decode_ack(msg) {
+            for seq_id in seq_ids {
+                if let Some(Unacked { timeout: Some(sched), .. }) = self.unacked.remove(&seq_id) {
+                    ctx.cancel(sched);
+                }
+            }
+        }
+    }
+    fn on_ack_timeout(&mut self, ctx: &mut dyn Context, seq_id: u32) {
+        if let Some(unacked) = self.unacked.get_mut(&seq_id) {
+            unacked.timeout = None;
+            self.resends.push_back(seq_id);
+        }
+
+        self.flush_resends(ctx);
+    }
+    fn flush_resends(&mut self, ctx: &mut dyn Context) {
+        while let Some(seq_id) = self.resends.pop_front() {
+            let msg = match self.unacked.get(&seq_id) {
+                Some(unacked) => unacked.msg.clone(),
+                None => continue
+            };
+
+            if let Some(eid) = self.resend(ctx, msg) {
+                self.resending.insert(eid, seq_id);
+            } else {
+                self.resends.push_front(seq_id);
+                return;
+            }
+        }
+    }
+    fn on_resend_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> bool {
+        let seq_id = match self.resending.remove(&eid) {
+            Some(seq_id) => seq_id,
+            None => return false
+        };
+
+        if !self.sequenced.contains(&eid) {
+            self.unacked.remove(&seq_id);
+        } else if let Some(msg) = self.unacked.get(&seq_id).map(|unacked| unacked.msg.clone()) {
+            self.wait_for_ack(ctx, seq_id, msg);
+        }
+
+        true
+    }
     fn on_send_ack(&self, ctx: &mut dyn Context, timeout: Timeout) {
         let _ = self.reply_tx.send(Reply::Send);
         if let Some(sched) = timeout {
@@ -285,10 +521,34 @@ impl Inner {
     }
 
     fn close(&mut self, ctx: &mut dyn Context) {
+        for (_, unacked) in self.unacked.drain() {
+            if let Some(sched) = unacked.timeout {
+                ctx.cancel(sched);
+            }
+        }
         self.pipes.close_all(ctx)
     }
 }
 
+/*****************************************************************************/
+/*                                                                           */
+/* Codec                                                                     */
+/*                                                                           */
+/*****************************************************************************/
+
+fn encode(msg: &Message, seq_id: u32) -> Message {
+    let mut raw_header = vec![0; 4];
+
+    BigEndian::write_u32(&mut raw_header[0..4], seq_id);
+    raw_header.extend_from_slice(msg.get_header());
+
+    msg.with_header(raw_header)
+}
+
+fn decode(raw_msg: &Message) -> Message {
+    raw_msg.with_header(raw_msg.get_header()[4..].to_vec())
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* tests                                                                     */
@@ -338,6 +598,30 @@ mod tests {
         sensor.assert_one_cancellation(timeout);
     }
 
+    #[test]
+    fn when_send_timeout_fires_after_send_ack_no_error_is_notified() {
+        let (tx, rx) = mpsc::channel();
+        let mut push = Push::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+        let pipe = new_test_pipe(eid);
+
+        push.add_pipe(&mut ctx, eid, pipe);
+        push.on_send_ready(&mut ctx, eid);
+        push.send(&mut ctx, Message::new(), Some(Scheduled::from(1)));
+        push.on_send_ack(&mut ctx, eid);
+        push.on_send_timeout(&mut ctx);
+
+        let reply = rx.try_recv().expect("facade should have been sent a reply !");
+        let is_reply_ok = match reply {
+            Reply::Send => true,
+            _ => false
+        };
+        assert!(is_reply_ok);
+        assert!(rx.try_recv().is_err(), "stale send timeout should not be notified !");
+    }
+
     #[test]
     fn when_send_starts_event_is_raised() {
         let (tx, _) = mpsc::channel();
@@ -7,22 +7,35 @@
 use std::collections::HashSet;
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
+use std::{error, fmt, io};
 
 use byteorder::*;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
+use core::socket::{Protocol, Reply, Capabilities};
+use core::config::{ConfigOption, unsupported_option_io_error};
 use core::endpoint::Pipe;
 use core::context::Context;
 use super::priolist::Priolist;
 use super::pipes::PipeCollection;
+use super::late::LateRecv;
 use super::{Timeout, REQ, REP};
 use super::policy::fair_queue;
 use io_error::*;
 
 pub struct Rep {
     inner: Inner,
-    state: Option<State>
+    state: Option<State>,
+    raw_send_state: Option<State>
+}
+
+/// Errors specific to the `Rep` protocol, carried by the `io::Error` returned when sending a reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RepError {
+    /// A reply was sent while no request was being handled.
+    SendWithoutRecv,
+    /// The pipe the request came from was removed before the reply could be sent.
+    RequestorGone
 }
 
 enum State {
@@ -41,7 +54,8 @@ struct Inner {
     sd: HashSet<EndpointId>,
     ttl: u8,
     backtrace: Vec<u8>,
-    is_device_item: bool
+    is_device_item: bool,
+    late: LateRecv
 }
 
 /*****************************************************************************/
@@ -53,35 +67,75 @@ struct Inner {
 impl Rep {
 
     fn apply<F>(&mut self, ctx: &mut dyn Context, transition: F) where F : FnOnce(State, &mut dyn Context, &mut Inner) -> State {
-        if let Some(old_state) = self.state.take() {
-            #[cfg(debug_assertions)] let old_name = old_state.name();
-            let was_send_ready = old_state.is_send_ready(&self.inner);
-            let was_recv_ready = old_state.is_recv_ready(&self.inner);
-            let new_state = transition(old_state, ctx, &mut self.inner);
-            let is_send_ready = new_state.is_send_ready(&self.inner);
-            let is_recv_ready = new_state.is_recv_ready(&self.inner);
-            #[cfg(debug_assertions)] let new_name = new_state.name();
-
-            self.state = Some(new_state);
-
-            ctx.check_send_ready_change(was_send_ready, is_send_ready);
-            ctx.check_recv_ready_change(was_recv_ready, is_recv_ready);
+        apply_transition(&mut self.state, &mut self.inner, ctx, transition)
+    }
 
-            #[cfg(debug_assertions)] debug!("[{:?}] switch from {} to {}", ctx, old_name, new_name);
+    /// In raw mode, the replies do not depend on the request being handled,
+    /// so the send side has its own state and a reply can be sent while a recv is pending.
+    fn apply_send<F>(&mut self, ctx: &mut dyn Context, transition: F) where F : FnOnce(State, &mut dyn Context, &mut Inner) -> State {
+        if self.inner.is_device_item {
+            apply_transition(&mut self.raw_send_state, &mut self.inner, ctx, transition)
+        } else {
+            apply_transition(&mut self.state, &mut self.inner, ctx, transition)
         }
     }
 
 }
 
+fn apply_transition<F>(state: &mut Option<State>, inner: &mut Inner, ctx: &mut dyn Context, transition: F)
+where F : FnOnce(State, &mut dyn Context, &mut Inner) -> State {
+    if let Some(old_state) = state.take() {
+        #[cfg(debug_assertions)] let old_name = old_state.name();
+        let was_send_ready = old_state.is_send_ready(inner);
+        let was_recv_ready = old_state.is_recv_ready(inner);
+        let new_state = transition(old_state, ctx, inner);
+        let is_send_ready = new_state.is_send_ready(inner);
+        let is_recv_ready = new_state.is_recv_ready(inner);
+        #[cfg(debug_assertions)] let new_name = new_state.name();
+
+        *state = Some(new_state);
+
+        ctx.check_send_ready_change(was_send_ready, is_send_ready);
+        ctx.check_recv_ready_change(was_recv_ready, is_recv_ready);
+
+        #[cfg(debug_assertions)] debug!("[{:?}] switch from {} to {}", ctx, old_name, new_name);
+    }
+}
+
 impl From<Sender<Reply>> for Rep {
     fn from(tx: Sender<Reply>) -> Rep {
         Rep {
             inner: Inner::new(tx),
-            state: Some(State::Idle)
+            state: Some(State::Idle),
+            raw_send_state: Some(State::Idle)
         }
     }
 }
 
+/*****************************************************************************/
+/*                                                                           */
+/* RepError                                                                  */
+/*                                                                           */
+/*****************************************************************************/
+
+impl RepError {
+    /// Extracts the `Rep` specific error carried by the specified error, if any.
+    pub fn of(err: &io::Error) -> Option<RepError> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<RepError>()).cloned()
+    }
+}
+
+impl fmt::Display for RepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            RepError::SendWithoutRecv => write!(f, "Can't send: no active request"),
+            RepError::RequestorGone   => write!(f, "Can't send: the requestor is gone")
+        }
+    }
+}
+
+impl error::Error for RepError {}
+
 /*****************************************************************************/
 /*                                                                           */
 /* Protocol                                                                  */
@@ -104,6 +158,9 @@ impl Protocol for Rep {
 
         if pipe.is_some() {
             self.apply(ctx, |s, ctx, inner| s.on_pipe_removed(ctx, inner, eid));
+            if self.inner.is_device_item {
+                self.apply_send(ctx, |s, ctx, inner| s.on_pipe_removed(ctx, inner, eid));
+            }
         }
 
         ctx.check_send_ready_change(was_send_ready, is_send_ready);
@@ -113,24 +170,29 @@ impl Protocol for Rep {
     }
     fn send(&mut self, ctx: &mut dyn Context, msg: Message, timeout: Timeout) {
         if let Some((raw_msg, eid)) = self.inner.msg_to_raw_msg(msg) {
-            self.apply(ctx, |s, ctx, inner| s.send(ctx, inner, Rc::new(raw_msg), timeout, eid))
-        } else {
+            self.apply_send(ctx, |s, ctx, inner| s.send(ctx, inner, Rc::new(raw_msg), timeout, eid))
+        } else if self.inner.is_device_item {
             self.inner.on_send_malformed(ctx, timeout);
+        } else {
+            self.apply(ctx, |_, ctx, inner| {
+                inner.send_when_inactive(ctx, timeout);
+                State::Idle
+            })
         }
     }
     fn on_send_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
         self.inner.clear_backtrace();
         
-        self.apply(ctx, |s, ctx, inner| s.on_send_ack(ctx, inner, eid))
+        self.apply_send(ctx, |s, ctx, inner| s.on_send_ack(ctx, inner, eid))
     }
     fn on_send_timeout(&mut self, ctx: &mut dyn Context) {
-        self.apply(ctx, |s, ctx, inner| s.on_send_timeout(ctx, inner))
+        self.apply_send(ctx, |s, ctx, inner| s.on_send_timeout(ctx, inner))
     }
     fn on_send_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
-        self.apply(ctx, |s, ctx, inner| s.on_send_ready(ctx, inner, eid))
+        self.apply_send(ctx, |s, ctx, inner| s.on_send_ready(ctx, inner, eid))
     }
     fn on_send_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
-        self.apply(ctx, |s, ctx, inner| s.on_send_not_ready(ctx, inner, eid))
+        self.apply_send(ctx, |s, ctx, inner| s.on_send_not_ready(ctx, inner, eid))
     }
     fn recv(&mut self, ctx: &mut dyn Context, timeout: Timeout) {
         self.apply(ctx, |s, ctx, inner| s.recv(ctx, inner, timeout))
@@ -151,6 +213,12 @@ impl Protocol for Rep {
     fn on_recv_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
         self.apply(ctx, |s, ctx, inner| s.on_recv_not_ready(ctx, inner, eid))
     }
+    fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
+        match opt {
+            ConfigOption::MaxTtl(ttl) => self.inner.set_ttl(ttl),
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
+        }
+    }
     fn on_device_plugged(&mut self, _: &mut dyn Context) {
         self.inner.is_device_item = true;
     }
@@ -168,6 +236,15 @@ impl Protocol for Rep {
             false
         }
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: true,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: false
+        }
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -202,6 +279,10 @@ impl State {
                     State::Receiving(id, timeout)
                 }
             },
+            State::Sending(id, _, timeout) | State::SendOnHold(id, _, timeout) if id == eid => {
+                inner.send_when_requestor_gone(ctx, timeout);
+                State::Idle
+            },
             any => any
         }
     }
@@ -222,7 +303,11 @@ impl State {
                 State::Idle
             }
         } else if let State::Active(_) = self {
-            if inner.is_send_ready_to(&eid) {
+            if !inner.pipes.contains(&eid) {
+                inner.send_when_requestor_gone(ctx, timeout);
+
+                State::Idle
+            } else if inner.is_send_ready_to(&eid) {
                 State::Idle.send_reply_to(ctx, inner, msg, timeout, eid)
             } else {
                 State::SendOnHold(eid, msg, timeout)
@@ -254,9 +339,14 @@ impl State {
         }
     }
     fn on_send_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
-        inner.on_send_timeout();
+        match self {
+            State::Sending(..) | State::SendOnHold(..) => {
+                inner.on_send_timeout();
 
-        State::Idle
+                State::Idle
+            },
+            any => any
+        }
     }
     fn on_send_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
         inner.on_send_ready(eid);
@@ -294,15 +384,19 @@ impl State {
 /*****************************************************************************/
 
     fn recv(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout) -> State {
-        inner.recv(ctx).map_or_else(
-            |   | State::RecvOnHold(timeout),
-            |eid| State::Receiving(eid, timeout))
+        match inner.late.take() {
+            Some((eid, Some(msg))) => State::Receiving(eid, timeout).on_recv_ack(ctx, inner, eid, msg),
+            Some((eid, None)) => State::Receiving(eid, timeout),
+            None => inner.recv(ctx).map_or_else(
+                |   | State::RecvOnHold(timeout),
+                |eid| State::Receiving(eid, timeout))
+        }
     }
     fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message) -> State {
         match self {
             State::Receiving(id, timeout) => {
                 if id == eid {
-                    inner.on_recv_ack(ctx, timeout, msg);
+                    inner.on_recv_ack(ctx, timeout, eid, msg);
                     if inner.is_device_item {
                         State::Idle
                     } else {
@@ -312,12 +406,19 @@ impl State {
                     State::Receiving(id, timeout)
                 }
             },
-            any => any
+            any => {
+                inner.late.on_recv_ack(eid, msg);
+                any
+            }
         }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
         inner.on_recv_timeout();
 
+        if let State::Receiving(eid, _) = self {
+            inner.late.on_recv_timeout(eid);
+        }
+
         State::Idle
     }
     fn on_recv_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
@@ -352,16 +453,18 @@ impl Inner {
             sd: HashSet::new(),
             ttl: 8,
             backtrace: Vec::new(),
-            is_device_item: false
+            is_device_item: false,
+            late: LateRecv::new()
         }
     }
     fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
-        self.fq.insert(eid, pipe.get_recv_priority());
+        self.fq.insert_weighted(eid, pipe.get_recv_priority(), pipe.get_recv_weight());
         self.pipes.insert(eid, pipe);
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
         self.fq.remove(&eid);
         self.sd.remove(&eid);
+        self.late.on_pipe_removed(eid);
         self.pipes.remove(&eid)
     }
     fn on_send_malformed(&mut self, ctx: &mut dyn Context, timeout: Timeout) {
@@ -382,8 +485,17 @@ impl Inner {
         }
     }
     fn send_when_inactive(&mut self, ctx: &mut dyn Context, timeout: Timeout) {
-        let error = other_io_error("Can't send: no active request");
+        let error = invalid_data_io_error(RepError::SendWithoutRecv);
         let _ = self.reply_tx.send(Reply::Err(error));
+        self.clear_backtrace();
+        if let Some(sched) = timeout {
+            ctx.cancel(sched);
+        }
+    }
+    fn send_when_requestor_gone(&mut self, ctx: &mut dyn Context, timeout: Timeout) {
+        let error = closed_io_error(RepError::RequestorGone);
+        let _ = self.reply_tx.send(Reply::Err(error));
+        self.clear_backtrace();
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -414,12 +526,12 @@ impl Inner {
     fn on_recv_not_ready(&mut self, eid: EndpointId) {
         self.fq.deactivate(&eid)
     }
-    fn on_recv_ack(&mut self, ctx: &mut dyn Context, timeout: Timeout, mut msg: Message) {
+    fn on_recv_ack(&mut self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, mut msg: Message) {
         if !self.is_device_item {
             self.set_backtrace(&msg.header);
             msg.header.clear();
         }
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -433,10 +545,19 @@ impl Inner {
         let _ = self.reply_tx.send(Reply::Err(error));
     }
     fn is_recv_ready(&self) -> bool {
-        self.fq.peek()
+        self.fq.peek() || self.late.is_recv_ready()
     }
  
+    fn set_ttl(&mut self, ttl: u8) -> io::Result<()> {
+        if ttl == 0 {
+            return Err(invalid_input_io_error("invalid value, max ttl must be at least 1"));
+        }
+        self.ttl = ttl;
+        Ok(())
+    }
+
     fn raw_msg_to_msg(&self, raw_msg: Message, eid: EndpointId) -> Option<Message> {
+        let hop_count = raw_msg.get_hop_count();
         let (mut header, mut body) = raw_msg.split();
         let mut hops = 0;
         let mut eid_bytes: [u8; 4] = [0; 4];
@@ -463,30 +584,34 @@ impl Inner {
 
             let position = header.len() - 4;
             if header[position] & 0x80 != 0 {
-                return Some(Message::from_header_and_body(header, tail));
+                let mut msg = Message::from_header_and_body(header, tail);
+
+                // kept for the device forwarding the request, if any
+                msg.set_hop_count(hop_count);
+                return Some(msg);
             }
             body = tail;
         }
     }
-    fn msg_to_raw_msg(&self, msg: Message) -> Option<(Message, EndpointId)> {
-        let (mut header, body) = msg.split();
-
+    fn msg_to_raw_msg(&self, mut msg: Message) -> Option<(Message, EndpointId)> {
         if !self.is_device_item {
             let backtrace = self.get_backtrace();
 
-            header.clear();
-            header.extend_from_slice(backtrace);
+            msg.header.clear();
+            msg.header.extend_from_slice(backtrace);
         }
 
-        if header.len() < 4 {
+        if msg.header.len() < 4 {
             return None;
         }
 
-        let tail = header.split_off(4);
-        let eid_u32 = BigEndian::read_u32(&header);
+        let tail = msg.header.split_off(4);
+        let eid_u32 = BigEndian::read_u32(&msg.header);
         let eid = EndpointId::from(eid_u32 as usize);
-        
-        Some((Message::from_header_and_body(tail, body), eid))
+
+        msg.header = tail;
+
+        Some((msg, eid))
     }
     fn set_backtrace(&mut self, bt: &[u8]) {
         self.backtrace.clear();
@@ -548,7 +673,7 @@ mod tests {
 
         let reply = rx.recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
@@ -558,6 +683,43 @@ mod tests {
         sensor.assert_one_cancellation(timeout);
     }
 
+    #[test]
+    fn request_received_after_a_recv_timeout_is_delivered_once_to_the_next_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+        let timeout = Scheduled::from(2);
+        let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
+
+        BigEndian::write_u32(&mut body[0..4], 666 | 0x80000000);
+
+        rep.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        rep.on_recv_ready(&mut ctx, eid);
+        rep.recv(&mut ctx, Some(Scheduled::from(1)));
+        rep.on_recv_timeout(&mut ctx);
+        rep.on_recv_ack(&mut ctx, eid, Message::from_body(body));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have timed out")
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(rep.is_recv_ready());
+
+        rep.recv(&mut ctx, Some(timeout));
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, _)) => assert_eq!(vec![4, 2, 1], msg.body),
+            _ => panic!("recv should have delivered the late request")
+        }
+        assert!(!rep.is_recv_ready());
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_recv_from(eid);
+        sensor.assert_one_cancellation(timeout);
+    }
+
     #[test]
     fn send_before_recv_notifies_an_error() {
         let (tx, rx) = mpsc::channel();
@@ -587,6 +749,98 @@ mod tests {
         sensor.assert_one_cancellation(timeout);
     }
 
+    fn new_request() -> Message {
+        let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
+
+        BigEndian::write_u32(&mut body[0..4], 666 | 0x80000000);
+
+        Message::from_body(body)
+    }
+
+    fn assert_rep_error(expected: RepError, reply: Reply) {
+        match reply {
+            Reply::Err(ref e) => assert_eq!(Some(expected), RepError::of(e)),
+            _ => panic!("facade should have been sent an error !")
+        }
+    }
+
+    fn assert_recv_reply(reply: Reply) {
+        match reply {
+            Reply::Recv(_, _) => {},
+            _ => panic!("facade should have been sent a message !")
+        }
+    }
+
+    #[test]
+    fn send_before_recv_reports_a_typed_error_and_next_recv_works() {
+        let (tx, rx) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+
+        rep.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        rep.on_send_ready(&mut ctx, eid);
+        rep.send(&mut ctx, Message::new(), Some(Scheduled::from(1)));
+        assert_rep_error(RepError::SendWithoutRecv, rx.recv().unwrap());
+        ctx_sensor.borrow().assert_one_cancellation(Scheduled::from(1));
+
+        rep.on_recv_ready(&mut ctx, eid);
+        rep.recv(&mut ctx, None);
+        rep.on_recv_ack(&mut ctx, eid, new_request());
+        assert_recv_reply(rx.recv().unwrap());
+    }
+
+    #[test]
+    fn send_after_the_requestor_pipe_is_removed_reports_a_typed_error() {
+        let (tx, rx) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let gone = EndpointId::from(0);
+        let other = EndpointId::from(1);
+
+        rep.add_pipe(&mut ctx, gone, new_test_pipe(gone));
+        rep.add_pipe(&mut ctx, other, new_test_pipe(other));
+        rep.on_recv_ready(&mut ctx, gone);
+        rep.recv(&mut ctx, None);
+        rep.on_recv_ack(&mut ctx, gone, new_request());
+        assert_recv_reply(rx.recv().unwrap());
+
+        rep.remove_pipe(&mut ctx, gone);
+        rep.send(&mut ctx, Message::new(), Some(Scheduled::from(2)));
+        assert_rep_error(RepError::RequestorGone, rx.recv().unwrap());
+        ctx_sensor.borrow().assert_one_cancellation(Scheduled::from(2));
+
+        rep.send(&mut ctx, Message::new(), None);
+        assert_rep_error(RepError::SendWithoutRecv, rx.recv().unwrap());
+
+        rep.on_recv_ready(&mut ctx, other);
+        rep.recv(&mut ctx, None);
+        rep.on_recv_ack(&mut ctx, other, new_request());
+        assert_recv_reply(rx.recv().unwrap());
+    }
+
+    #[test]
+    fn send_pending_when_the_requestor_pipe_is_removed_reports_a_typed_error() {
+        let (tx, rx) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+
+        rep.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        rep.on_recv_ready(&mut ctx, eid);
+        rep.recv(&mut ctx, None);
+        rep.on_recv_ack(&mut ctx, eid, new_request());
+        assert_recv_reply(rx.recv().unwrap());
+
+        rep.send(&mut ctx, Message::new(), Some(Scheduled::from(3)));
+        rep.remove_pipe(&mut ctx, eid);
+        assert_rep_error(RepError::RequestorGone, rx.recv().unwrap());
+        ctx_sensor.borrow().assert_one_cancellation(Scheduled::from(3));
+    }
+
     #[test]
     fn when_send_succeed_it_is_notified_and_timeout_is_cancelled() {
         let (tx, rx) = mpsc::channel();
@@ -727,7 +981,7 @@ mod tests {
         rep.on_recv_ack(&mut ctx, eid, msg);
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.unwrap();
@@ -758,7 +1012,7 @@ mod tests {
         rep.on_recv_ack(&mut ctx, eid, msg);
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.unwrap();
@@ -788,7 +1042,7 @@ mod tests {
         rep.on_recv_ack(&mut ctx, eid, msg);
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.unwrap();
@@ -864,4 +1118,98 @@ mod tests {
 
         ctx_sensor.borrow().assert_no_send_call();
     }
+
+    #[test]
+    fn when_in_raw_mode_send_will_not_cancel_the_pending_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+        let pipe = new_test_pipe(eid);
+        let reply_msg = Message::from_header_and_body(vec![0, 0, 0, 1], vec![6, 6, 6, 6, 4, 2, 1]);
+
+        rep.on_device_plugged(&mut ctx);
+        rep.add_pipe(&mut ctx, eid, pipe);
+        rep.on_recv_ready(&mut ctx, eid);
+        rep.on_send_ready(&mut ctx, eid);
+        rep.recv(&mut ctx, None);
+        rep.send(&mut ctx, reply_msg, None);
+        rep.on_send_ack(&mut ctx, eid);
+        rep.on_recv_ack(&mut ctx, eid, request_with_hops(1));
+
+        match rx.try_recv().expect("facade should have been sent a reply !") {
+            Reply::Send => {},
+            _ => panic!("send should have completed first !")
+        }
+        match rx.try_recv().expect("facade should have been sent a reply !") {
+            Reply::Recv(msg, from) => {
+                assert_eq!(eid, from);
+                assert_eq!(&[4, 2, 1], msg.get_body());
+            },
+            _ => panic!("pending recv should have completed !")
+        }
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_send_to(eid);
+        sensor.assert_one_recv_from(eid);
+    }
+
+    fn request_with_hops(hops: usize) -> Message {
+        let mut body = vec![0u8; 4 * hops];
+
+        for hop in 0..hops {
+            BigEndian::write_u32(&mut body[hop * 4..hop * 4 + 4], hop as u32);
+        }
+        body[(hops - 1) * 4] |= 0x80;
+        body.extend_from_slice(&[4, 2, 1]);
+
+        Message::from_body(body)
+    }
+
+    #[test]
+    fn request_with_more_hops_than_max_ttl_is_rejected() {
+        let (tx, rx) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+        let eid = EndpointId::from(0);
+
+        rep.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        rep.on_recv_ready(&mut ctx, eid);
+        rep.recv(&mut ctx, None);
+        rep.on_recv_ack(&mut ctx, eid, request_with_hops(9));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have rejected the request")
+        }
+    }
+
+    #[test]
+    fn request_with_more_hops_than_the_default_is_delivered_once_max_ttl_is_raised() {
+        let (tx, rx) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+        let eid = EndpointId::from(0);
+
+        rep.set_option(ConfigOption::MaxTtl(16)).unwrap();
+        rep.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        rep.on_recv_ready(&mut ctx, eid);
+        rep.recv(&mut ctx, None);
+        rep.on_recv_ack(&mut ctx, eid, request_with_hops(9));
+
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, _)) => assert_eq!(vec![4, 2, 1], msg.body),
+            _ => panic!("recv should have delivered the request")
+        }
+    }
+
+    #[test]
+    fn max_ttl_of_zero_is_rejected() {
+        let (tx, _) = mpsc::channel();
+        let mut rep = Rep::from(tx);
+
+        assert!(rep.set_option(ConfigOption::MaxTtl(0)).is_err());
+        assert!(rep.set_option(ConfigOption::MaxTtl(1)).is_ok());
+    }
 }
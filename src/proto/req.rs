@@ -15,8 +15,8 @@ use time;
 use byteorder::*;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
-use core::config::ConfigOption;
+use core::socket::{Protocol, Reply, Capabilities};
+use core::config::{ConfigOption, unsupported_option_io_error};
 use core::endpoint::Pipe;
 use core::context::{Context, Schedulable};
 use super::priolist::Priolist;
@@ -24,6 +24,7 @@ use super::pipes::PipeCollection;
 use super::{Timeout, REQ, REP};
 use super::policy::{load_balancing, fair_queue};
 use io_error::*;
+use sequence::WireIdSeq;
 
 pub struct Req {
     inner: Inner,
@@ -45,7 +46,7 @@ struct Inner {
     lb: Priolist,
     fq: Priolist,
     rv: HashSet<EndpointId>,
-    req_id_seq: u32,
+    req_ids: WireIdSeq,
     is_device_item: bool,
     resend_ivl: Duration
 }
@@ -161,7 +162,7 @@ impl Protocol for Req {
     fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
         match opt {
             ConfigOption::ReqResendIvl(ivl) => Ok(self.inner.set_resend_ivl(ivl)),
-            _ => Err(invalid_input_io_error("option not supported"))
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
         }
     }
     fn on_timer_tick(&mut self, ctx: &mut dyn Context, task: Schedulable) {
@@ -172,6 +173,9 @@ impl Protocol for Req {
     fn on_device_plugged(&mut self, _: &mut dyn Context) {
         self.inner.is_device_item = true;
     }
+    fn cancel_request(&mut self, ctx: &mut dyn Context) {
+        self.apply(ctx, |s, ctx, inner| s.cancel_request(ctx, inner))
+    }
     fn is_send_ready(&self) -> bool {
         if let Some(ref state) = self.state {
             state.is_send_ready(&self.inner)
@@ -186,6 +190,15 @@ impl Protocol for Req {
             false
         }
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: true,
+            has_subscriptions: false,
+            has_survey: false,
+            is_broadcast: false
+        }
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -270,9 +283,14 @@ impl State {
         }
     }
     fn on_send_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
-        inner.on_send_timeout();
+        match self {
+            State::Sending(..) | State::SendOnHold(..) => {
+                inner.on_send_timeout();
 
-        State::Idle
+                State::Idle
+            },
+            any => any
+        }
     }
     fn on_send_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
         inner.on_send_ready(eid);
@@ -320,7 +338,7 @@ impl State {
         match self {
             State::Receiving(id, None, timeout) => {
                 if eid == id {
-                    inner.on_recv_ack(ctx, timeout, msg, None);
+                    inner.on_recv_ack(ctx, timeout, eid, msg, None);
                     State::Idle
                 } else {
                     State::Receiving(id, None, timeout)
@@ -329,7 +347,7 @@ impl State {
             State::Receiving(id, Some(p), timeout) => {
                 if eid == id {
                     if inner.cur_req_id() == req_id {
-                        inner.on_recv_ack(ctx, timeout, msg, p.retry_timeout);
+                        inner.on_recv_ack(ctx, timeout, eid, msg, p.retry_timeout);
                         State::Idle
                     } else {
                         State::Idle.recv_reply_for(ctx, inner, timeout, id, p)
@@ -372,6 +390,16 @@ impl State {
         inner.on_recv_not_ready(eid);
         self
     }
+    fn cancel_request(self, ctx: &mut dyn Context, inner: &mut Inner) -> State {
+        match self {
+            State::Active(_, p) => inner.cancel(ctx, p),
+            State::Receiving(_, Some(p), timeout) |
+            State::RecvOnHold(_, Some(p), timeout) => inner.on_recv_cancelled(ctx, timeout, p),
+            any => return any
+        }
+
+        State::Idle
+    }
     fn on_retry_timeout(self, ctx: &mut dyn Context, inner: &mut Inner) -> State {
         if let State::Active(_, p) = self {
             State::Idle.send(ctx, inner, p.req, None, true)
@@ -404,14 +432,14 @@ impl Inner {
             lb: Priolist::new(),
             fq: Priolist::new(),
             rv: HashSet::new(),
-            req_id_seq: time::get_time().nsec as u32,
+            req_ids: WireIdSeq::new(time::get_time().nsec as u32),
             is_device_item: false,
             resend_ivl: Duration::from_secs(60)
         }
     }
     fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
         self.lb.insert(eid, pipe.get_send_priority());
-        self.fq.insert(eid, pipe.get_recv_priority());
+        self.fq.insert_weighted(eid, pipe.get_recv_priority(), pipe.get_recv_weight());
         self.pipes.insert(eid, pipe);
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
@@ -469,8 +497,8 @@ impl Inner {
             ctx.cancel(sched);
         }
     }
-    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, msg: Message, retry_timeout: Timeout) {
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, msg: Message, retry_timeout: Timeout) {
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -485,6 +513,14 @@ impl Inner {
             ctx.cancel(sched);
         }
     }
+    fn on_recv_cancelled(&self, ctx: &mut dyn Context, timeout: Timeout, p: PendingRequest) {
+        let error = other_io_error("Recv cancelled: the request was cancelled");
+        let _ = self.reply_tx.send(Reply::Err(error));
+        if let Some(sched) = timeout {
+            ctx.cancel(sched);
+        }
+        self.cancel(ctx, p);
+    }
     fn on_recv_ack_malformed(&self, _: &mut dyn Context) {
         let error = invalid_data_io_error("Received reply without req id");
         let _ = self.reply_tx.send(Reply::Err(error));
@@ -522,12 +558,11 @@ impl Inner {
     }
 
     fn cur_req_id(&self) -> u32 {
-        self.req_id_seq | 0x80000000
+        self.req_ids.current().value
     }
 
     fn next_req_id(&mut self) -> u32 {
-        self.req_id_seq += 1;
-        self.req_id_seq | 0x80000000
+        self.req_ids.next().value
     }
     fn set_resend_ivl(&mut self, ivl: Duration) {
         self.resend_ivl = ivl;
@@ -553,6 +588,7 @@ fn decode(raw_msg: Message) -> Option<(Message, u32)> {
         return None;
     }
 
+    let hop_count = raw_msg.get_hop_count();
     let (mut header, mut payload) = raw_msg.split();
     let body = payload.split_off(4);
     let req_id = BigEndian::read_u32(&payload);
@@ -563,7 +599,12 @@ fn decode(raw_msg: Message) -> Option<(Message, u32)> {
         header.extend_from_slice(&payload);
     }
 
-    Some((Message::from_header_and_body(header, body), req_id))
+    let mut msg = Message::from_header_and_body(header, body);
+
+    // kept for the device forwarding the reply, if any
+    msg.set_hop_count(hop_count);
+
+    Some((msg, req_id))
 }
 
 /*****************************************************************************/
@@ -617,6 +658,30 @@ mod tests {
         sensor.assert_one_cancellation(timeout);
     }
 
+    #[test]
+    fn when_send_timeout_fires_after_send_ack_no_error_is_notified() {
+        let (tx, rx) = mpsc::channel();
+        let mut req = Req::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+        let pipe = new_test_pipe(eid);
+
+        req.add_pipe(&mut ctx, eid, pipe);
+        req.on_send_ready(&mut ctx, eid);
+        req.send(&mut ctx, Message::new(), Some(Scheduled::from(1)));
+        req.on_send_ack(&mut ctx, eid);
+        req.on_send_timeout(&mut ctx);
+
+        let reply = rx.try_recv().expect("facade should have been sent a reply !");
+        let is_reply_ok = match reply {
+            Reply::Send => true,
+            _ => false
+        };
+        assert!(is_reply_ok);
+        assert!(rx.try_recv().is_err(), "stale send timeout should not be notified !");
+    }
+
     #[test]
     fn when_send_starts_event_is_raised() {
         let (tx, _) = mpsc::channel();
@@ -792,7 +857,7 @@ mod tests {
         req.on_send_ack(&mut ctx, eid);
         let _ = rx.try_recv().expect("facade should have been sent a reply !");
 
-        let bad_request_id = (req.inner.req_id_seq - 1) | 0x80000000;
+        let bad_request_id = (req.inner.req_ids.value - 1) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], bad_request_id);
@@ -819,7 +884,7 @@ mod tests {
         req.on_send_ack(&mut ctx, eid);
         let _ = rx.try_recv().expect("facade should have been sent a reply !");
 
-        let good_request_id = (req.inner.req_id_seq) | 0x80000000;
+        let good_request_id = (req.inner.req_ids.value) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], good_request_id);
@@ -831,12 +896,49 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
     }
 
+    #[test]
+    fn when_in_regular_mode_request_id_wraps_around_without_matching_stale_replies() {
+        let (tx, rx) = mpsc::channel();
+        let mut req = Req::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+        let pipe = new_test_pipe(eid);
+
+        req.inner.req_ids.value = 0x7FFF_FFFF;
+        req.add_pipe(&mut ctx, eid, pipe);
+        req.on_send_ready(&mut ctx, eid);
+        req.send(&mut ctx, Message::new(), None);
+        req.on_send_ack(&mut ctx, eid);
+        let _ = rx.try_recv().expect("facade should have been sent a reply !");
+
+        let sent_request_id = BigEndian::read_u32(ctx_sensor.borrow().get_send_calls()[0].1.get_header());
+        assert_eq!(0x80000000, sent_request_id);
+
+        req.on_recv_ready(&mut ctx, eid);
+        req.recv(&mut ctx, None);
+        for request_id in &[0xFFFF_FFFFu32, 0x8000_0000] {
+            let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
+
+            BigEndian::write_u32(&mut body[0..4], *request_id);
+            req.on_recv_ack(&mut ctx, eid, Message::from_body(body));
+        }
+
+        let reply = rx.try_recv().expect("facade should have been sent a reply !");
+        let received_request_id = match reply {
+            Reply::Recv(msg, _) => BigEndian::read_u32(msg.get_header()),
+            _ => 0
+        };
+        assert_eq!(0x80000000, received_request_id);
+        assert!(rx.try_recv().is_err());
+    }
+
     #[test]
     fn when_in_regular_mode_recv_moves_the_request_id_from_the_body_to_the_header() {
         let (tx, rx) = mpsc::channel();
@@ -852,7 +954,7 @@ mod tests {
         req.on_send_ack(&mut ctx, eid);
         let _ = rx.try_recv().expect("facade should have been sent a reply !");
 
-        let good_request_id = (req.inner.req_id_seq) | 0x80000000;
+        let good_request_id = (req.inner.req_ids.value) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], good_request_id);
@@ -864,7 +966,7 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.unwrap();
@@ -888,7 +990,7 @@ mod tests {
         req.on_send_ack(&mut ctx, eid);
         let _ = rx.try_recv().expect("facade should have been sent a reply !");
 
-        let bad_request_id = (req.inner.req_id_seq + 666) | 0x80000000;
+        let bad_request_id = (req.inner.req_ids.value + 666) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], bad_request_id);
@@ -900,7 +1002,7 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
@@ -915,7 +1017,7 @@ mod tests {
         let eid = EndpointId::from(0);
         let pipe = new_test_pipe(eid);
 
-        let request_id = (req.inner.req_id_seq + 666) | 0x80000000;
+        let request_id = (req.inner.req_ids.value + 666) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], request_id);
@@ -930,7 +1032,7 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
@@ -945,7 +1047,7 @@ mod tests {
         let eid = EndpointId::from(0);
         let pipe = new_test_pipe(eid);
 
-        let request_id = (req.inner.req_id_seq + 666) | 0x80000000;
+        let request_id = (req.inner.req_ids.value + 666) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], request_id);
@@ -960,7 +1062,7 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.unwrap();
@@ -992,11 +1094,80 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.unwrap();
         assert_eq!(4, app_msg.get_header().len());
         assert_eq!(2, app_msg.get_body().len());
      }
+
+    #[test]
+    fn when_active_request_is_cancelled_it_is_not_resent_anymore() {
+        let (tx, _) = mpsc::channel();
+        let mut req = Req::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+        let retry_timeout = Scheduled::from(2);
+
+        req.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        req.on_send_ready(&mut ctx, eid);
+        req.send(&mut ctx, Message::new(), None);
+        ctx.set_schedule_result(retry_timeout);
+        req.on_send_ack(&mut ctx, eid);
+        req.on_send_ready(&mut ctx, eid);
+        req.on_recv_ready(&mut ctx, eid);
+        assert!(req.is_recv_ready());
+
+        req.cancel_request(&mut ctx);
+        assert!(!req.is_recv_ready());
+        assert_eq!(Some(&Event::CanRecv(false)), ctx_sensor.borrow().get_raised_events().last());
+
+        req.on_timer_tick(&mut ctx, Schedulable::ReqResend);
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_send_to(eid);
+        sensor.assert_one_cancellation(retry_timeout);
+    }
+
+    #[test]
+    fn when_request_is_cancelled_while_receiving_the_pending_recv_fails() {
+        let (tx, rx) = mpsc::channel();
+        let mut req = Req::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+
+        req.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        req.on_send_ready(&mut ctx, eid);
+        req.send(&mut ctx, Message::new(), None);
+        req.on_send_ack(&mut ctx, eid);
+        let _ = rx.try_recv().expect("facade should have been sent a reply !");
+
+        req.on_recv_ready(&mut ctx, eid);
+        req.recv(&mut ctx, None);
+        req.cancel_request(&mut ctx);
+
+        let reply = rx.try_recv().expect("facade should have been sent a reply !");
+        let is_reply_err = match reply {
+            Reply::Err(_) => true,
+            _ => false
+        };
+        assert!(is_reply_err);
+        assert!(!req.is_recv_ready());
+    }
+
+    #[test]
+    fn when_no_request_is_pending_cancel_does_nothing() {
+        let (tx, rx) = mpsc::channel();
+        let mut req = Req::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+
+        req.cancel_request(&mut ctx);
+
+        assert!(rx.try_recv().is_err());
+        assert_eq!(0, ctx_sensor.borrow().get_raised_events().len());
+    }
 }
\ No newline at end of file
@@ -7,15 +7,18 @@
 use std::collections::HashSet;
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
+use std::io;
 
 use byteorder::*;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
+use core::socket::{Protocol, Reply, Capabilities};
+use core::config::{ConfigOption, unsupported_option_io_error};
 use core::endpoint::Pipe;
 use core::context::{Context, Event};
 use super::priolist::Priolist;
 use super::pipes::PipeCollection;
+use super::late::LateRecv;
 use super::{Timeout, SURVEYOR, RESPONDENT};
 use super::policy::fair_queue;
 use io_error::*;
@@ -41,7 +44,8 @@ struct Inner {
     sd: HashSet<EndpointId>,
     ttl: u8,
     backtrace: Vec<u8>,
-    is_device_item: bool
+    is_device_item: bool,
+    late: LateRecv
 }
 
 /*****************************************************************************/
@@ -151,6 +155,12 @@ impl Protocol for Respondent {
     fn on_recv_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) {
         self.apply(ctx, |s, ctx, inner| s.on_recv_not_ready(ctx, inner, eid))
     }
+    fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
+        match opt {
+            ConfigOption::MaxTtl(ttl) => self.inner.set_ttl(ttl),
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
+        }
+    }
     fn on_device_plugged(&mut self, _: &mut dyn Context) {
         self.inner.is_device_item = true;
     }
@@ -168,6 +178,15 @@ impl Protocol for Respondent {
             false
         }
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: true,
+            has_subscriptions: false,
+            has_survey: true,
+            is_broadcast: false
+        }
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -254,9 +273,14 @@ impl State {
         }
     }
     fn on_send_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
-        inner.on_send_timeout();
+        match self {
+            State::Sending(..) | State::SendOnHold(..) => {
+                inner.on_send_timeout();
 
-        State::Idle
+                State::Idle
+            },
+            any => any
+        }
     }
     fn on_send_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
         inner.on_send_ready(eid);
@@ -294,15 +318,19 @@ impl State {
 /*****************************************************************************/
 
     fn recv(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout) -> State {
-        inner.recv(ctx).map_or_else(
-            |   | State::RecvOnHold(timeout),
-            |eid| State::Receiving(eid, timeout))
+        match inner.late.take() {
+            Some((eid, Some(msg))) => State::Receiving(eid, timeout).on_recv_ack(ctx, inner, eid, msg),
+            Some((eid, None)) => State::Receiving(eid, timeout),
+            None => inner.recv(ctx).map_or_else(
+                |   | State::RecvOnHold(timeout),
+                |eid| State::Receiving(eid, timeout))
+        }
     }
     fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message) -> State {
         match self {
             State::Receiving(id, timeout) => {
                 if id == eid {
-                    inner.on_recv_ack(ctx, timeout, msg);
+                    inner.on_recv_ack(ctx, timeout, eid, msg);
                     if inner.is_device_item {
                         State::Idle
                     } else {
@@ -312,12 +340,19 @@ impl State {
                     State::Receiving(id, timeout)
                 }
             },
-            any => any
+            any => {
+                inner.late.on_recv_ack(eid, msg);
+                any
+            }
         }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
         inner.on_recv_timeout();
 
+        if let State::Receiving(eid, _) = self {
+            inner.late.on_recv_timeout(eid);
+        }
+
         State::Idle
     }
     fn on_recv_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
@@ -355,16 +390,18 @@ impl Inner {
             sd: HashSet::new(),
             ttl: 8,
             backtrace: Vec::new(),
-            is_device_item: false
+            is_device_item: false,
+            late: LateRecv::new()
         }
     }
     fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
-        self.fq.insert(eid, pipe.get_recv_priority());
+        self.fq.insert_weighted(eid, pipe.get_recv_priority(), pipe.get_recv_weight());
         self.pipes.insert(eid, pipe);
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
         self.fq.remove(&eid);
         self.sd.remove(&eid);
+        self.late.on_pipe_removed(eid);
         self.pipes.remove(&eid)
     }
 
@@ -418,12 +455,12 @@ impl Inner {
     fn on_recv_not_ready(&mut self, eid: EndpointId) {
         self.fq.deactivate(&eid)
     }
-    fn on_recv_ack(&mut self, ctx: &mut dyn Context, timeout: Timeout, mut msg: Message) {
+    fn on_recv_ack(&mut self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, mut msg: Message) {
         if !self.is_device_item {
             self.set_backtrace(&msg.header);
             msg.header.clear();
         }
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -437,10 +474,19 @@ impl Inner {
         let _ = self.reply_tx.send(Reply::Err(error));
     }
     fn is_recv_ready(&self) -> bool {
-        self.fq.peek()
+        self.fq.peek() || self.late.is_recv_ready()
+    }
+
+    fn set_ttl(&mut self, ttl: u8) -> io::Result<()> {
+        if ttl == 0 {
+            return Err(invalid_input_io_error("invalid value, max ttl must be at least 1"));
+        }
+        self.ttl = ttl;
+        Ok(())
     }
 
     fn raw_msg_to_msg(&self, raw_msg: Message, eid: EndpointId) -> Option<Message> {
+        let hop_count = raw_msg.get_hop_count();
         let (mut header, mut body) = raw_msg.split();
         let mut hops = 0;
         let mut eid_bytes: [u8; 4] = [0; 4];
@@ -467,12 +513,17 @@ impl Inner {
 
             let position = header.len() - 4;
             if header[position] & 0x80 != 0 {
-                return Some(Message::from_header_and_body(header, tail));
+                let mut msg = Message::from_header_and_body(header, tail);
+
+                // kept for the device forwarding the survey, if any
+                msg.set_hop_count(hop_count);
+                return Some(msg);
             }
             body = tail;
         }
     }
     fn msg_to_raw_msg(&self, msg: Message) -> Option<(Message, EndpointId)> {
+        let hop_count = msg.get_hop_count();
         let (mut header, body) = msg.split();
 
         if !self.is_device_item {
@@ -490,7 +541,11 @@ impl Inner {
         let eid_u32 = BigEndian::read_u32(&header);
         let eid = EndpointId::from(eid_u32 as usize);
         
-        Some((Message::from_header_and_body(tail, body), eid))
+        let mut raw_msg = Message::from_header_and_body(tail, body);
+
+        raw_msg.set_hop_count(hop_count);
+
+        Some((raw_msg, eid))
     }
     fn set_backtrace(&mut self, bt: &[u8]) {
         self.backtrace.clear();
@@ -506,3 +561,79 @@ impl Inner {
         self.pipes.close_all(ctx)
     }
 }
+
+#[cfg(test)]
+mod tests {
+
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::mpsc;
+    use std::io;
+
+    use byteorder::*;
+
+    use core::{EndpointId, Message};
+    use core::socket::{Protocol, Reply};
+    use core::config::ConfigOption;
+    use core::tests::*;
+
+    use super::*;
+
+    fn survey_with_hops(hops: usize) -> Message {
+        let mut body = vec![0u8; 4 * hops];
+
+        for hop in 0..hops {
+            BigEndian::write_u32(&mut body[hop * 4..hop * 4 + 4], hop as u32);
+        }
+        body[(hops - 1) * 4] |= 0x80;
+        body.extend_from_slice(&[4, 2, 1]);
+
+        Message::from_body(body)
+    }
+
+    #[test]
+    fn survey_with_more_hops_than_max_ttl_fails_the_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut resp = Respondent::from(tx);
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+        let eid = EndpointId::from(0);
+
+        resp.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        resp.on_recv_ready(&mut ctx, eid);
+        resp.recv(&mut ctx, None);
+        resp.on_recv_ack(&mut ctx, eid, survey_with_hops(9));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(e)) => assert_eq!(io::ErrorKind::InvalidData, e.kind()),
+            _ => panic!("recv should have rejected the survey")
+        }
+    }
+
+    #[test]
+    fn survey_with_more_hops_than_the_default_is_delivered_once_max_ttl_is_raised() {
+        let (tx, rx) = mpsc::channel();
+        let mut resp = Respondent::from(tx);
+        let mut ctx = TestContext::with_sensor(Rc::new(RefCell::new(TestContextSensor::default())));
+        let eid = EndpointId::from(0);
+
+        resp.set_option(ConfigOption::MaxTtl(16)).unwrap();
+        resp.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        resp.on_recv_ready(&mut ctx, eid);
+        resp.recv(&mut ctx, None);
+        resp.on_recv_ack(&mut ctx, eid, survey_with_hops(9));
+
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, _)) => assert_eq!(vec![4, 2, 1], msg.body),
+            _ => panic!("recv should have delivered the survey")
+        }
+    }
+
+    #[test]
+    fn max_ttl_of_zero_is_rejected() {
+        let (tx, _) = mpsc::channel();
+        let mut resp = Respondent::from(tx);
+
+        assert!(resp.set_option(ConfigOption::MaxTtl(0)).is_err());
+        assert!(resp.set_option(ConfigOption::MaxTtl(1)).is_ok());
+    }
+}
@@ -4,18 +4,19 @@
 // or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
 // This file may not be copied, modified, or distributed except according to those terms.
 
-use std::collections::HashSet;
+use std::collections::HashMap;
 use std::rc::Rc;
 use std::sync::mpsc::Sender;
 use std::io;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
-use core::config::ConfigOption;
+use core::socket::{Protocol, Reply, Capabilities, SubscriptionValidator};
+use core::config::{ConfigOption, unsupported_option_io_error};
 use core::endpoint::Pipe;
 use core::context::Context;
 use super::priolist::Priolist;
 use super::pipes::PipeCollection;
+use super::late::LateRecv;
 use super::{Timeout, PUB, SUB};
 use super::policy::fair_queue;
 use io_error::*;
@@ -35,7 +36,9 @@ struct Inner {
     reply_tx: Sender<Reply>,
     pipes: PipeCollection,
     fq: Priolist,
-    subscriptions: HashSet<Vec<u8>>
+    subscriptions: SubscriptionTrie,
+    validator: Option<SubscriptionValidator>,
+    late: LateRecv
 }
 
 /*****************************************************************************/
@@ -71,7 +74,9 @@ impl From<Sender<Reply>> for Sub {
                 reply_tx: tx,
                 pipes: PipeCollection::new(),
                 fq: Priolist::new(),
-                subscriptions: HashSet::new()
+                subscriptions: SubscriptionTrie::default(),
+                validator: None,
+                late: LateRecv::new()
             },
             state: Some(State::Idle)
         }
@@ -140,13 +145,32 @@ impl Protocol for Sub {
     fn is_recv_ready(&self) -> bool {
         self.inner.is_recv_ready()
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: false,
+            can_recv: true,
+            has_subscriptions: true,
+            has_survey: false,
+            is_broadcast: false
+        }
+    }
     fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
         match opt {
-            ConfigOption::Subscribe(x)   => Ok(self.inner.subscribe(x)),
+            ConfigOption::Subscribe(x)   => self.inner.subscribe(x),
             ConfigOption::Unsubscribe(x) => Ok(self.inner.unsubscribe(x)),
-            _ => Err(invalid_input_io_error("option not supported"))
+            ConfigOption::SubscribeMany(x)   => self.inner.subscribe_many(x),
+            ConfigOption::UnsubscribeMany(x) => {
+                self.inner.unsubscribe_many(x);
+                Ok(())
+            },
+            ConfigOption::Subscriptions(x) => self.inner.replace_subscriptions(x),
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
         }
     }
+    fn set_subscription_validator(&mut self, validator: SubscriptionValidator) -> io::Result<()> {
+        self.inner.validator = Some(validator);
+        Ok(())
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -212,16 +236,20 @@ impl State {
 /*****************************************************************************/
 
     fn recv(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout) -> State {
-        inner.recv(ctx).map_or_else(
-            |   | State::RecvOnHold(timeout),
-            |eid| State::Receiving(eid, timeout))
+        match inner.late.take() {
+            Some((eid, Some(msg))) => State::Receiving(eid, timeout).on_recv_ack(ctx, inner, eid, msg),
+            Some((eid, None)) => State::Receiving(eid, timeout),
+            None => inner.recv(ctx).map_or_else(
+                |   | State::RecvOnHold(timeout),
+                |eid| State::Receiving(eid, timeout))
+        }
     }
     fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message) -> State {
         match self {
             State::Receiving(id, timeout) => {
                 if id == eid {
                     if inner.accept(&msg) {
-                        inner.on_recv_ack(ctx, timeout, msg);
+                        inner.on_recv_ack(ctx, timeout, eid, msg);
                         State::Idle
                     } else {
                         State::Idle.recv(ctx, inner, timeout)
@@ -230,12 +258,19 @@ impl State {
                     State::Receiving(id, timeout)
                 }
             },
-            any => any
+            any => {
+                inner.late.on_recv_ack(eid, msg);
+                any
+            }
         }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
         inner.on_recv_timeout();
 
+        if let State::Receiving(eid, _) = self {
+            inner.late.on_recv_timeout(eid);
+        }
+
         State::Idle
     }
     fn on_recv_ready(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
@@ -260,11 +295,12 @@ impl State {
 
 impl Inner {
     fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
-        self.fq.insert(eid, pipe.get_recv_priority());
+        self.fq.insert_weighted(eid, pipe.get_recv_priority(), pipe.get_recv_weight());
         self.pipes.insert(eid, pipe);
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
         self.fq.remove(&eid);
+        self.late.on_pipe_removed(eid);
         self.pipes.remove(&eid)
     }
     fn send(&mut self, ctx: &mut dyn Context, timeout: Timeout) {
@@ -284,8 +320,8 @@ impl Inner {
     fn on_recv_not_ready(&mut self, eid: EndpointId) {
         self.fq.deactivate(&eid)
     }
-    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, msg: Message) {
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, msg: Message) {
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -295,27 +331,144 @@ impl Inner {
         let _ = self.reply_tx.send(Reply::Err(error));
     }
     fn is_recv_ready(&self) -> bool {
-        self.fq.peek()
+        self.fq.peek() || self.late.is_recv_ready()
+    }
+
+    fn validate(&self, subscription: &[u8]) -> io::Result<()> {
+        match self.validator {
+            Some(ref is_valid) if !is_valid(subscription) => Err(invalid_input_io_error("subscription rejected by validator")),
+            _ => Ok(())
+        }
     }
 
-    fn subscribe(&mut self, subscription :String) {
-        self.subscriptions.insert(subscription.into_bytes());
+    fn subscribe(&mut self, subscription :String) -> io::Result<()> {
+        self.validate(subscription.as_bytes())?;
+        self.subscriptions.insert(subscription.as_bytes());
+        Ok(())
     }
 
     fn unsubscribe(&mut self, subscription :String) {
-        self.subscriptions.remove(&subscription.into_bytes());
+        self.subscriptions.remove(subscription.as_bytes());
+    }
+
+    fn subscribe_many(&mut self, subscriptions: Vec<Vec<u8>>) -> io::Result<()> {
+        for subscription in &subscriptions {
+            self.validate(subscription)?;
+        }
+
+        for subscription in &subscriptions {
+            self.subscriptions.insert(subscription);
+        }
+        Ok(())
+    }
+
+    fn unsubscribe_many(&mut self, subscriptions: Vec<Vec<u8>>) {
+        for subscription in &subscriptions {
+            self.subscriptions.remove(subscription);
+        }
+    }
+
+    fn replace_subscriptions(&mut self, subscriptions: Vec<Vec<u8>>) -> io::Result<()> {
+        for subscription in &subscriptions {
+            self.validate(subscription)?;
+        }
+
+        self.subscriptions.clear();
+        for subscription in &subscriptions {
+            self.subscriptions.insert(subscription);
+        }
+        Ok(())
     }
 
     fn accept(&self, msg: &Message) -> bool {
-        let payload = msg.get_body();
-        
-        self.subscriptions.iter().any(|s| payload.starts_with(s))
+        self.subscriptions.matches(msg.get_body())
     }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.pipes.close_all(ctx)
     }
 }
 
+/*****************************************************************************/
+/*                                                                           */
+/* SubscriptionTrie                                                          */
+/*                                                                           */
+/*****************************************************************************/
+
+/// Set of subscribed topics, stored byte by byte so that finding whether a message
+/// starts with one of them only walks the message prefix, whatever the number of topics.
+#[derive(Default)]
+struct SubscriptionTrie {
+    root: TrieNode
+}
+
+#[derive(Default)]
+struct TrieNode {
+    subscribed: bool,
+    children: HashMap<u8, TrieNode>
+}
+
+impl SubscriptionTrie {
+    fn insert(&mut self, topic: &[u8]) {
+        let mut node = &mut self.root;
+
+        for byte in topic {
+            node = node.children.entry(*byte).or_default();
+        }
+
+        node.subscribed = true;
+    }
+
+    fn remove(&mut self, topic: &[u8]) {
+        self.root.remove(topic);
+    }
+
+    fn clear(&mut self) {
+        self.root = TrieNode::default();
+    }
+
+    /// Tells whether the payload starts with one of the topics, the empty one matching any payload.
+    fn matches(&self, payload: &[u8]) -> bool {
+        let mut node = &self.root;
+
+        for byte in payload {
+            if node.subscribed {
+                return true;
+            }
+
+            node = match node.children.get(byte) {
+                Some(child) => child,
+                None => return false
+            };
+        }
+
+        node.subscribed
+    }
+}
+
+impl TrieNode {
+    fn remove(&mut self, topic: &[u8]) {
+        let (byte, rest) = match topic.split_first() {
+            Some(split) => split,
+            None => {
+                self.subscribed = false;
+                return;
+            }
+        };
+
+        let prune = match self.children.get_mut(byte) {
+            Some(child) => {
+                child.remove(rest);
+                !child.subscribed && child.children.is_empty()
+            },
+            None => false
+        };
+
+        if prune {
+            self.children.remove(byte);
+        }
+    }
+}
+
 /*****************************************************************************/
 /*                                                                           */
 /* tests                                                                     */
@@ -357,7 +510,7 @@ mod tests {
 
         let reply = rx.recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
@@ -392,6 +545,69 @@ mod tests {
         assert_eq!(Event::CanRecv(true), raised_evts[2]);
     }
 
+    #[test]
+    fn subscribe_many_and_unsubscribe_many_update_the_subscriptions_at_once() {
+        let (tx, _) = mpsc::channel();
+        let mut sub = Sub::from(tx);
+
+        sub.set_option(ConfigOption::Subscribe(String::from("A"))).unwrap();
+        sub.set_option(ConfigOption::SubscribeMany(vec![b"A".to_vec(), b"B".to_vec(), b"C".to_vec()])).unwrap();
+        assert!(sub.inner.subscriptions.matches(b"A1"));
+        assert!(sub.inner.subscriptions.matches(b"B1"));
+        assert!(sub.inner.subscriptions.matches(b"C1"));
+
+        sub.set_option(ConfigOption::UnsubscribeMany(vec![b"A".to_vec(), b"B".to_vec(), b"Z".to_vec()])).unwrap();
+        assert!(!sub.inner.subscriptions.matches(b"A1"));
+        assert!(!sub.inner.subscriptions.matches(b"B1"));
+        assert!(sub.inner.subscriptions.matches(b"C1"));
+
+        sub.set_subscription_validator(Box::new(|topic| topic != b"X")).unwrap();
+        assert!(sub.set_option(ConfigOption::SubscribeMany(vec![b"D".to_vec(), b"X".to_vec()])).is_err());
+        assert!(!sub.inner.subscriptions.matches(b"D1"));
+    }
+
+    #[test]
+    fn trie_matches_overlapping_prefixes() {
+        let mut trie = SubscriptionTrie::default();
+
+        trie.insert(b"sport");
+        assert!(trie.matches(b"sport/tennis"));
+        assert!(trie.matches(b"sport"));
+        assert!(!trie.matches(b"sp"));
+        assert!(!trie.matches(b"spam"));
+
+        trie.insert(b"sp");
+        assert!(trie.matches(b"sp"));
+        assert!(trie.matches(b"spam"));
+        assert!(trie.matches(b"sport/tennis"));
+        assert!(!trie.matches(b"s"));
+
+        trie.remove(b"sp");
+        assert!(!trie.matches(b"spam"));
+        assert!(trie.matches(b"sport/tennis"));
+
+        trie.remove(b"sport");
+        assert!(!trie.matches(b"sport/tennis"));
+        assert!(trie.root.children.is_empty());
+    }
+
+    #[test]
+    fn trie_empty_topic_matches_everything() {
+        let mut trie = SubscriptionTrie::default();
+
+        assert!(!trie.matches(b""));
+        assert!(!trie.matches(b"any"));
+
+        trie.insert(b"news");
+        trie.insert(b"");
+        assert!(trie.matches(b""));
+        assert!(trie.matches(b"any"));
+
+        trie.remove(b"");
+        assert!(!trie.matches(b"any"));
+        assert!(trie.matches(b"news"));
+    }
+
     /*//#[test]
     fn when_recv_ack_event_is_raised_if_there_is_another_pipe_ready() {
         let (tx, _) = mpsc::channel();
@@ -439,4 +655,39 @@ mod tests {
         assert_eq!(Event::CanRecv(true), raised_evts[0]);
         assert_eq!(Event::CanRecv(false), raised_evts[1]);
     }*/
+
+    #[test]
+    fn message_received_after_a_recv_timeout_is_delivered_once_to_the_next_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut sub = Sub::from(tx);
+        let _ = sub.set_option(ConfigOption::Subscribe(String::from("")));
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+        let timeout = Scheduled::from(2);
+
+        sub.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        sub.on_recv_ready(&mut ctx, eid);
+        sub.recv(&mut ctx, Some(Scheduled::from(1)));
+        sub.on_recv_timeout(&mut ctx);
+        sub.on_recv_ack(&mut ctx, eid, Message::from_body(vec![65, 66, 67]));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have timed out")
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(sub.is_recv_ready());
+
+        sub.recv(&mut ctx, Some(timeout));
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, _)) => assert_eq!(vec![65, 66, 67], msg.body),
+            _ => panic!("recv should have delivered the late message")
+        }
+        assert!(!sub.is_recv_ready());
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_recv_from(eid);
+        sensor.assert_one_cancellation(timeout);
+    }
 }
\ No newline at end of file
@@ -15,15 +15,17 @@ use time;
 use byteorder::*;
 
 use core::{EndpointId, Message};
-use core::socket::{Protocol, Reply};
-use core::config::ConfigOption;
+use core::socket::{Protocol, Reply, Capabilities};
+use core::config::{ConfigOption, unsupported_option_io_error};
 use core::endpoint::Pipe;
 use core::context::{Context, Schedulable};
 use super::priolist::Priolist;
 use super::pipes::PipeCollection;
+use super::late::LateRecv;
 use super::{Timeout, SURVEYOR, RESPONDENT};
 use super::policy::{broadcast, fair_queue};
 use io_error::*;
+use sequence::{WireId, WireIdSeq};
 
 pub struct Surveyor {
     inner: Inner,
@@ -42,14 +44,19 @@ struct Inner {
     pipes: PipeCollection,
     bc: HashSet<EndpointId>,
     fq: Priolist,
-    survey_id_seq: u32,
+    survey_ids: WireIdSeq,
     is_device_item: bool,
-    deadline: Duration
+    deadline: Duration,
+    resend_ivl: Option<Duration>,
+    late: LateRecv<(Message, WireId)>
 }
 
 struct PendingSurvey {
-    id: u32,
-    timeout: Timeout
+    id: WireId,
+    timeout: Timeout,
+    msg: Rc<Message>,
+    reached: HashSet<EndpointId>,
+    resend: Timeout
 }
 
 /*****************************************************************************/
@@ -158,12 +165,18 @@ impl Protocol for Surveyor {
     fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> {
         match opt {
             ConfigOption::SurveyDeadline(ivl) => Ok(self.inner.set_survey_deadline(ivl)),
-            _ => Err(invalid_input_io_error("option not supported"))
+            ConfigOption::SurveyResendIvl(ivl) => {
+                self.inner.set_survey_resend_ivl(ivl);
+                Ok(())
+            },
+            _ => Err(unsupported_option_io_error("option not supported by this protocol"))
         }
     }
     fn on_timer_tick(&mut self, ctx: &mut dyn Context, task: Schedulable) {
-        if let Schedulable::SurveyCancel = task {
-            self.apply(ctx, |s, ctx, inner| s.on_survey_timeout(ctx, inner))
+        match task {
+            Schedulable::SurveyCancel => self.apply(ctx, |s, ctx, inner| s.on_survey_timeout(ctx, inner)),
+            Schedulable::SurveyResend => self.apply(ctx, |s, ctx, inner| s.on_survey_resend(ctx, inner)),
+            _ => {}
         }
     }
     fn on_device_plugged(&mut self, _: &mut dyn Context) {
@@ -183,6 +196,15 @@ impl Protocol for Surveyor {
             false
         }
     }
+    fn capabilities(&self) -> Capabilities {
+        Capabilities {
+            can_send: true,
+            can_recv: true,
+            has_subscriptions: false,
+            has_survey: true,
+            is_broadcast: true
+        }
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.inner.close(ctx)
     }
@@ -206,7 +228,20 @@ impl State {
         }
     }
 
-    fn on_pipe_removed(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
+    fn pending_survey_mut(&mut self) -> Option<&mut PendingSurvey> {
+        match *self {
+            State::Active(ref mut p)               |
+            State::Receiving(_, Some(ref mut p), _) |
+            State::RecvOnHold(Some(ref mut p), _)   => Some(p),
+            _ => None
+        }
+    }
+
+    fn on_pipe_removed(mut self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId) -> State {
+        if let Some(p) = self.pending_survey_mut() {
+            p.reached.remove(&eid);
+        }
+
         match self {
             State::Receiving(id, p, timeout) => {
                 if id == eid {
@@ -225,7 +260,10 @@ impl State {
 /*                                                                           */
 /*****************************************************************************/
 
-    fn send(self, ctx: &mut dyn Context, inner: &mut Inner, msg: Rc<Message>, timeout: Timeout) -> State {
+    fn send(mut self, ctx: &mut dyn Context, inner: &mut Inner, msg: Rc<Message>, timeout: Timeout) -> State {
+        if let Some(p) = self.pending_survey_mut() {
+            inner.stop_resend(ctx, p);
+        }
         if let State::Active(p) = self {
             inner.cancel(ctx, p);
         }
@@ -260,29 +298,31 @@ impl State {
 
     fn recv(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout) -> State {
         if inner.is_device_item {
-            inner.recv(ctx).map_or_else(
-                |   | State::RecvOnHold(None, timeout),
-                |eid| State::Receiving(eid, None, timeout))
+            State::Idle.recv_reply_for(ctx, inner, timeout, None)
         } else if let State::Active(p) = self {
-            State::Idle.recv_reply_for(ctx, inner, timeout, p)
+            State::Idle.recv_reply_for(ctx, inner, timeout, Some(p))
         } else {
             inner.recv_when_inactive(ctx, timeout);
 
             State::Idle
         }
     }
-    fn recv_reply_for(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout, p: PendingSurvey) -> State {
-        if let Some(eid) = inner.recv(ctx) {
-            State::Receiving(eid, Some(p), timeout)
-        } else {
-            State::RecvOnHold(Some(p), timeout)
+    /// Receives a reply for the pending survey, or any message when there is none in raw mode.
+    fn recv_reply_for(self, ctx: &mut dyn Context, inner: &mut Inner, timeout: Timeout, p: Option<PendingSurvey>) -> State {
+        match inner.late.take() {
+            Some((eid, Some((msg, survey_id)))) => State::Receiving(eid, p, timeout).on_recv_ack(ctx, inner, eid, msg, survey_id),
+            Some((eid, None)) => State::Receiving(eid, p, timeout),
+            None => match inner.recv(ctx) {
+                Some(eid) => State::Receiving(eid, p, timeout),
+                None => State::RecvOnHold(p, timeout)
+            }
         }
     }
-    fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message, survey_id: u32) -> State {
+    fn on_recv_ack(self, ctx: &mut dyn Context, inner: &mut Inner, eid: EndpointId, msg: Message, survey_id: WireId) -> State {
         match self {
             State::Receiving(id, None, timeout) => {
                 if id == eid {
-                    inner.on_recv_ack(ctx, timeout, msg);
+                    inner.on_recv_ack(ctx, timeout, eid, msg);
                     State::Idle
                 } else {
                     State::Receiving(id, None, timeout)
@@ -291,21 +331,28 @@ impl State {
             State::Receiving(id, Some(p), timeout) => {
                 if id == eid {
                     if p.id == survey_id {
-                        inner.on_recv_ack(ctx, timeout, msg);
+                        inner.on_recv_ack(ctx, timeout, eid, msg);
                         State::Active(p)
                     } else {
-                        State::Idle.recv_reply_for(ctx, inner, timeout, p)
+                        State::Idle.recv_reply_for(ctx, inner, timeout, Some(p))
                     }
                 } else {
                     State::Receiving(id, Some(p), timeout)
                 }
             },
-            any => any
+            any => {
+                inner.late.on_recv_ack(eid, (msg, survey_id));
+                any
+            }
         }
     }
     fn on_recv_timeout(self, _: &mut dyn Context, inner: &mut Inner) -> State {
         inner.on_recv_timeout();
 
+        if let State::Receiving(eid, _, _) = self {
+            inner.late.on_recv_timeout(eid);
+        }
+
         match self {
             State::Receiving(_, Some(p), _) |
             State::RecvOnHold(Some(p), _)   => State::Active(p),
@@ -327,13 +374,24 @@ impl State {
         inner.on_recv_not_ready(eid);
         self
     }
-    fn on_survey_timeout(self, _: &mut dyn Context, _: &mut Inner) -> State {
+    fn on_survey_timeout(mut self, ctx: &mut dyn Context, inner: &mut Inner) -> State {
+        if let Some(p) = self.pending_survey_mut() {
+            inner.stop_resend(ctx, p);
+        }
+
         if let State::Active(_) = self {
             State::Idle
         } else {
             self
         }
     }
+    fn on_survey_resend(mut self, ctx: &mut dyn Context, inner: &mut Inner) -> State {
+        if let Some(p) = self.pending_survey_mut() {
+            inner.resend(ctx, p);
+        }
+
+        self
+    }
     fn is_recv_ready(&self, inner: &Inner) -> bool {
         if inner.is_device_item {
             inner.is_recv_ready()
@@ -359,31 +417,71 @@ impl Inner {
             pipes: PipeCollection::new(),
             bc: HashSet::new(),
             fq: Priolist::new(),
-            survey_id_seq: time::get_time().nsec as u32,
+            survey_ids: WireIdSeq::new(time::get_time().nsec as u32),
             is_device_item: false,
-            deadline: Duration::from_secs(1)
+            deadline: Duration::from_secs(1),
+            resend_ivl: None,
+            late: LateRecv::new()
         }
     }
     fn add_pipe(&mut self, eid: EndpointId, pipe: Pipe) {
-        self.fq.insert(eid, pipe.get_recv_priority());
+        self.fq.insert_weighted(eid, pipe.get_recv_priority(), pipe.get_recv_weight());
         self.pipes.insert(eid, pipe);
     }
     fn remove_pipe(&mut self, eid: EndpointId) -> Option<Pipe> {
         self.bc.remove(&eid);
         self.fq.remove(&eid);
+        self.late.on_pipe_removed(eid);
         self.pipes.remove(&eid)
     }
-    fn send(&mut self, ctx: &mut dyn Context, msg: Rc<Message>, timeout: Timeout) -> PendingSurvey {
-        broadcast::send_to_all(&mut self.bc, &mut self.pipes, ctx, msg);
+    fn send(&mut self, ctx: &mut dyn Context, raw_msg: Rc<Message>, timeout: Timeout) -> PendingSurvey {
+        let reached = broadcast::send_to_all(&mut self.bc, &mut self.pipes, ctx, raw_msg.clone());
+        let reached_set = reached.iter().cloned().collect();
+        let resend_timeout = self.schedule_resend(ctx);
 
-        let _ = self.reply_tx.send(Reply::Send);
+        let _ = self.reply_tx.send(Reply::SendReached(reached));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
 
         PendingSurvey {
             id: self.cur_survey_id(),
-            timeout: ctx.schedule(Schedulable::SurveyCancel, self.deadline).ok()
+            timeout: ctx.schedule(Schedulable::SurveyCancel, self.deadline).ok(),
+            msg: raw_msg,
+            reached: reached_set,
+            resend: resend_timeout
+        }
+    }
+    fn schedule_resend(&self, ctx: &mut dyn Context) -> Timeout {
+        if self.is_device_item {
+            return None;
+        }
+
+        self.resend_ivl.and_then(|ivl| ctx.schedule(Schedulable::SurveyResend, ivl).ok())
+    }
+    fn resend(&mut self, ctx: &mut dyn Context, pending_survey: &mut PendingSurvey) {
+        if pending_survey.resend.take().is_none() {
+            return;
+        }
+
+        let missed: Vec<EndpointId> = self.bc.iter()
+            .filter(|eid| !pending_survey.reached.contains(eid))
+            .cloned()
+            .collect();
+
+        for eid in missed {
+            if let Some(pipe) = self.pipes.get_mut(&eid) {
+                self.bc.remove(&eid);
+                pipe.send(ctx, pending_survey.msg.clone());
+                pending_survey.reached.insert(eid);
+            }
+        }
+
+        pending_survey.resend = self.schedule_resend(ctx);
+    }
+    fn stop_resend(&self, ctx: &mut dyn Context, pending_survey: &mut PendingSurvey) {
+        if let Some(resend) = pending_survey.resend.take() {
+            ctx.cancel(resend);
         }
     }
     fn on_send_ready(&mut self, eid: EndpointId) {
@@ -413,10 +511,10 @@ impl Inner {
         self.fq.deactivate(&eid)
     }
     fn is_recv_ready(&self) -> bool {
-        self.fq.peek()
+        self.fq.peek() || self.late.is_recv_ready()
     }
-    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, msg: Message) {
-        let _ = self.reply_tx.send(Reply::Recv(msg));
+    fn on_recv_ack(&self, ctx: &mut dyn Context, timeout: Timeout, eid: EndpointId, msg: Message) {
+        let _ = self.reply_tx.send(Reply::Recv(msg, eid));
         if let Some(sched) = timeout {
             ctx.cancel(sched);
         }
@@ -434,6 +532,7 @@ impl Inner {
         if let Some(timeout) = pending_survey.timeout.take() {
             ctx.cancel(timeout);
         }
+        self.stop_resend(ctx, &mut pending_survey);
     }
 
     fn msg_to_raw_msg(&mut self, msg: Message) -> Message {
@@ -444,27 +543,29 @@ impl Inner {
         }
     }
 
-    fn raw_msg_to_msg(&self, raw_msg: Message) -> Option<(Message, u32)> {
+    fn raw_msg_to_msg(&self, raw_msg: Message) -> Option<(Message, WireId)> {
         if self.is_device_item {
             let cur_survey_id = self.cur_survey_id();
             decode(raw_msg).map(|(msg, _)| (msg, cur_survey_id))
         } else {
-            decode(raw_msg).map(|(msg, id)| (msg.without_header(), id))
+            decode(raw_msg).map(|(msg, id)| (msg.without_header(), self.survey_ids.received(id)))
         }
     }
 
-    fn cur_survey_id(&self) -> u32 {
-        self.survey_id_seq | 0x80000000
+    fn cur_survey_id(&self) -> WireId {
+        self.survey_ids.current()
     }
 
     fn next_survey_id(&mut self) -> u32 {
-        self.survey_id_seq += 1;
-        self.survey_id_seq | 0x80000000
+        self.survey_ids.next().value
     }
 
     fn set_survey_deadline(&mut self, ivl: Duration) {
         self.deadline = ivl;
     }
+    fn set_survey_resend_ivl(&mut self, ivl: Duration) {
+        self.resend_ivl = if ivl == Duration::from_secs(0) { None } else { Some(ivl) };
+    }
     fn close(&mut self, ctx: &mut dyn Context) {
         self.pipes.close_all(ctx)
     }
@@ -486,6 +587,7 @@ fn decode(raw_msg: Message) -> Option<(Message, u32)> {
         return None;
     }
 
+    let hop_count = raw_msg.get_hop_count();
     let (mut header, mut payload) = raw_msg.split();
     let body = payload.split_off(4);
     let survey_id = BigEndian::read_u32(&payload);
@@ -496,7 +598,12 @@ fn decode(raw_msg: Message) -> Option<(Message, u32)> {
         header.extend_from_slice(&payload);
     }
 
-    Some((Message::from_header_and_body(header, body), survey_id))
+    let mut msg = Message::from_header_and_body(header, body);
+
+    // kept for the device forwarding the reply, if any
+    msg.set_hop_count(hop_count);
+
+    Some((msg, survey_id))
 }
 
 /*****************************************************************************/
@@ -540,7 +647,7 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Send => true,
+            Reply::SendReached(reached) => reached == vec![eid],
             _ => false
         };
         assert!(is_reply_ok);
@@ -550,6 +657,33 @@ mod tests {
         sensor.assert_one_cancellation(timeout);
     }
 
+    #[test]
+    fn when_send_succeed_peers_not_ready_are_excluded_from_the_reply() {
+        let (tx, rx) = mpsc::channel();
+        let mut surv = Surveyor::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let ready_eid = EndpointId::from(0);
+        let busy_eid = EndpointId::from(1);
+
+        surv.add_pipe(&mut ctx, ready_eid, new_test_pipe(ready_eid));
+        surv.add_pipe(&mut ctx, busy_eid, new_test_pipe(busy_eid));
+        surv.on_send_ready(&mut ctx, ready_eid);
+        surv.send(&mut ctx, Message::new(), None);
+
+        let reply = rx.try_recv().expect("facade should have been sent a reply !");
+        let reached = match reply {
+            Reply::SendReached(reached) => reached,
+            _ => panic!("facade should have been sent the reached peers !")
+        };
+        assert_eq!(1, reached.len());
+        assert!(reached.contains(&ready_eid));
+        assert!(!reached.contains(&busy_eid));
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_send_to(ready_eid);
+    }
+
     #[test]
     fn when_send_starts_event_is_raised() {
         let (tx, _) = mpsc::channel();
@@ -687,7 +821,7 @@ mod tests {
         surv.on_send_ack(&mut ctx, eid);
         let _ = rx.try_recv().expect("facade should have been sent a reply !");
 
-        let bad_survey_id = (surv.inner.survey_id_seq - 1) | 0x80000000;
+        let bad_survey_id = (surv.inner.survey_ids.value - 1) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], bad_survey_id);
@@ -714,7 +848,7 @@ mod tests {
         surv.on_send_ack(&mut ctx, eid);
         let _ = rx.try_recv().expect("facade should have been sent a reply !");
 
-        let good_survey_id = (surv.inner.survey_id_seq) | 0x80000000;
+        let good_survey_id = (surv.inner.survey_ids.value) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], good_survey_id);
@@ -726,12 +860,91 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let is_reply_ok = match reply {
-            Reply::Recv(_) => true,
+            Reply::Recv(_, _) => true,
+            _ => false
+        };
+        assert!(is_reply_ok);
+    }
+
+    #[test]
+    fn when_in_regular_mode_survey_id_wraps_around_without_matching_stale_responses() {
+        let (tx, rx) = mpsc::channel();
+        let mut surv = Surveyor::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+        let pipe = new_test_pipe(eid);
+
+        surv.inner.survey_ids.value = 0x7FFF_FFFF;
+        surv.add_pipe(&mut ctx, eid, pipe);
+        surv.on_send_ready(&mut ctx, eid);
+        surv.send(&mut ctx, Message::new(), None);
+        surv.on_send_ack(&mut ctx, eid);
+        let _ = rx.try_recv().expect("facade should have been sent a reply !");
+
+        let sent_survey_id = BigEndian::read_u32(ctx_sensor.borrow().get_send_calls()[0].1.get_header());
+        assert_eq!(0x80000000, sent_survey_id);
+
+        let stale_survey_id = 0xFFFF_FFFFu32;
+        let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
+
+        BigEndian::write_u32(&mut body[0..4], stale_survey_id);
+        surv.on_recv_ready(&mut ctx, eid);
+        surv.recv(&mut ctx, None);
+        surv.on_recv_ack(&mut ctx, eid, Message::from_body(body));
+        assert!(rx.try_recv().is_err());
+
+        let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
+
+        BigEndian::write_u32(&mut body[0..4], sent_survey_id);
+        surv.on_recv_ready(&mut ctx, eid);
+        surv.on_recv_ack(&mut ctx, eid, Message::from_body(body));
+
+        let reply = rx.try_recv().expect("facade should have been sent a reply !");
+        let is_reply_ok = match reply {
+            Reply::Recv(_, _) => true,
             _ => false
         };
         assert!(is_reply_ok);
     }
 
+    #[test]
+    fn late_reply_kept_across_a_wrap_of_the_survey_ids_does_not_match_the_survey_reusing_its_id() {
+        let (tx, rx) = mpsc::channel();
+        let mut surv = Surveyor::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+
+        surv.inner.survey_ids.value = 0x7FFF_FFFF;
+        surv.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        surv.on_send_ready(&mut ctx, eid);
+        surv.send(&mut ctx, Message::new(), None);
+        surv.on_send_ack(&mut ctx, eid);
+        let _ = rx.try_recv().expect("facade should have been sent a reply !");
+
+        let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
+
+        BigEndian::write_u32(&mut body[0..4], 0x80000000);
+        surv.on_recv_ready(&mut ctx, eid);
+        surv.recv(&mut ctx, Some(Scheduled::from(1)));
+        surv.on_recv_timeout(&mut ctx);
+        surv.on_recv_ack(&mut ctx, eid, Message::from_body(body));
+        let _ = rx.try_recv().expect("recv should have timed out");
+
+        // as if 2^31 surveys were sent meanwhile
+        surv.inner.survey_ids.value = 0x7FFF_FFFF;
+        surv.on_send_ready(&mut ctx, eid);
+        surv.send(&mut ctx, Message::new(), None);
+        let _ = rx.try_recv().expect("facade should have been sent a reply !");
+
+        let sent_survey_id = BigEndian::read_u32(ctx_sensor.borrow().get_send_calls()[1].1.get_header());
+        assert_eq!(0x80000000, sent_survey_id);
+
+        surv.recv(&mut ctx, Some(Scheduled::from(2)));
+        assert!(rx.try_recv().is_err());
+    }
+
     #[test]
     fn when_in_regular_mode_recv_removes_the_survey_id_from_the_body() {
         let (tx, rx) = mpsc::channel();
@@ -747,7 +960,7 @@ mod tests {
         surv.on_send_ack(&mut ctx, eid);
         let _ = rx.try_recv().expect("facade should have been sent a reply !");
 
-        let good_survey_id = (surv.inner.survey_id_seq) | 0x80000000;
+        let good_survey_id = (surv.inner.survey_ids.value) | 0x80000000;
         let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
 
         BigEndian::write_u32(&mut body[0..4], good_survey_id);
@@ -759,7 +972,7 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.unwrap();
@@ -790,7 +1003,7 @@ mod tests {
 
         let reply = rx.try_recv().expect("facade should have been sent a reply !");
         let reply_msg = match reply {
-            Reply::Recv(msg) => Some(msg),
+            Reply::Recv(msg, _) => Some(msg),
             _ => None
         };
         let app_msg = reply_msg.expect("facade should have been sent a Recv reply !");
@@ -798,5 +1011,120 @@ mod tests {
         assert_eq!(3, app_msg.get_body().len());
     }
 
+    #[test]
+    fn when_resend_ivl_is_set_active_survey_is_resent_to_peers_that_missed_it() {
+        let (tx, _) = mpsc::channel();
+        let mut surv = Surveyor::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let early_eid = EndpointId::from(0);
+        let late_eid = EndpointId::from(1);
+
+        surv.set_option(ConfigOption::SurveyResendIvl(Duration::from_millis(100))).unwrap();
+        surv.add_pipe(&mut ctx, early_eid, new_test_pipe(early_eid));
+        surv.on_send_ready(&mut ctx, early_eid);
+        ctx.set_schedule_result(Scheduled::from(1));
+        surv.send(&mut ctx, Message::from_body(vec![1u8, 2, 3]), None);
+        surv.on_send_ack(&mut ctx, early_eid);
+        surv.on_send_ready(&mut ctx, early_eid);
+
+        surv.add_pipe(&mut ctx, late_eid, new_test_pipe(late_eid));
+        surv.on_send_ready(&mut ctx, late_eid);
+        surv.on_timer_tick(&mut ctx, Schedulable::SurveyResend);
+
+        let sensor = ctx_sensor.borrow();
+        let send_calls = sensor.get_send_calls();
+
+        assert_eq!(2, send_calls.len());
+        assert_eq!(early_eid, send_calls[0].0);
+        assert_eq!(late_eid, send_calls[1].0);
+        assert_eq!(send_calls[0].1.get_header(), send_calls[1].1.get_header());
+        assert_eq!(&[Duration::from_millis(100), Duration::from_secs(1), Duration::from_millis(100)], sensor.get_schedule_delays());
+    }
+
+    #[test]
+    fn when_survey_deadline_expires_resend_is_stopped() {
+        let (tx, _) = mpsc::channel();
+        let mut surv = Surveyor::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let early_eid = EndpointId::from(0);
+        let late_eid = EndpointId::from(1);
+        let resend = Scheduled::from(1);
+
+        surv.set_option(ConfigOption::SurveyResendIvl(Duration::from_millis(100))).unwrap();
+        surv.add_pipe(&mut ctx, early_eid, new_test_pipe(early_eid));
+        surv.on_send_ready(&mut ctx, early_eid);
+        ctx.set_schedule_result(resend);
+        surv.send(&mut ctx, Message::new(), None);
+        surv.on_timer_tick(&mut ctx, Schedulable::SurveyCancel);
+
+        surv.add_pipe(&mut ctx, late_eid, new_test_pipe(late_eid));
+        surv.on_send_ready(&mut ctx, late_eid);
+        surv.on_timer_tick(&mut ctx, Schedulable::SurveyResend);
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_send_to(early_eid);
+        sensor.assert_one_cancellation(resend);
+    }
+
+    #[test]
+    fn when_resend_ivl_is_not_set_survey_is_not_resent() {
+        let (tx, _) = mpsc::channel();
+        let mut surv = Surveyor::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(0);
+
+        surv.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        surv.on_send_ready(&mut ctx, eid);
+        surv.send(&mut ctx, Message::new(), None);
+
+        let sensor = ctx_sensor.borrow();
+        assert_eq!(&[Duration::from_secs(1)], sensor.get_schedule_delays());
+    }
+
     // test CanRecv events ...
+
+    #[test]
+    fn reply_received_after_a_recv_timeout_is_delivered_once_to_the_next_recv() {
+        let (tx, rx) = mpsc::channel();
+        let mut surv = Surveyor::from(tx);
+        let ctx_sensor = Rc::new(RefCell::new(TestContextSensor::default()));
+        let mut ctx = TestContext::with_sensor(ctx_sensor.clone());
+        let eid = EndpointId::from(1);
+        let timeout = Scheduled::from(2);
+
+        surv.add_pipe(&mut ctx, eid, new_test_pipe(eid));
+        surv.on_send_ready(&mut ctx, eid);
+        surv.send(&mut ctx, Message::new(), None);
+        let _ = rx.try_recv().expect("facade should have been sent a reply !");
+
+        let mut body: Vec<u8> = vec![0, 0, 0, 0, 4, 2, 1];
+
+        BigEndian::write_u32(&mut body[0..4], surv.inner.survey_ids.value | 0x80000000);
+
+        surv.on_recv_ready(&mut ctx, eid);
+        surv.recv(&mut ctx, Some(Scheduled::from(1)));
+        surv.on_recv_timeout(&mut ctx);
+        surv.on_recv_ack(&mut ctx, eid, Message::from_body(body));
+
+        match rx.try_recv() {
+            Ok(Reply::Err(_)) => {},
+            _ => panic!("recv should have timed out")
+        }
+        assert!(rx.try_recv().is_err());
+        assert!(surv.is_recv_ready());
+
+        surv.recv(&mut ctx, Some(timeout));
+        match rx.try_recv() {
+            Ok(Reply::Recv(msg, _)) => assert_eq!(vec![4, 2, 1], msg.body),
+            _ => panic!("recv should have delivered the late reply")
+        }
+        assert!(!surv.is_recv_ready());
+
+        let sensor = ctx_sensor.borrow();
+        sensor.assert_one_recv_from(eid);
+        sensor.assert_one_cancellation(timeout);
+    }
 }
\ No newline at end of file
@@ -8,17 +8,17 @@ use std::fmt;
 use std::rc::Rc;
 use std::collections::HashMap;
 use std::io;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use mio::{Evented, Token, Ready, PollOpt};
-use mio_extras::timer::{Timer, Timeout};
 
 use core::context;
 use core::device;
 use core::probe;
 use core::network::Network;
-use core::{BuildIdHasher, SocketId, EndpointId, DeviceId, ProbeId, Message, EndpointTmpl, Scheduled};
-use transport::{Transport, Destination};
+use core::socket::EndpointInfo;
+use core::{BuildIdHasher, SocketId, EndpointId, DeviceId, ProbeId, Message, EndpointTmpl, Scheduled, EndpointIdAllocator};
+use transport::{Transport, Destination, Stream, Address, PeerCred};
 use transport::endpoint::*;
 use transport::pipe;
 use transport::acceptor;
@@ -27,6 +27,7 @@ use super::{Signal, Task};
 use sequence::Sequence;
 use io_error::*;
 use super::event_loop::EventLoop;
+use super::timer::{Timer, Timeout};
 
 pub trait Registrar {
     fn register(&mut self, io: &dyn Evented, tok: Token, interest: Ready, opt: PollOpt) -> io::Result<()>;
@@ -64,7 +65,9 @@ pub struct ProbeEventLoopContext<'a> {
 pub struct PipeController {
     socket_id: SocketId,
     endpoint_id: EndpointId,
-    pipe: Box<dyn pipe::Pipe>
+    pipe: Box<dyn pipe::Pipe>,
+    pending_send: bool,
+    pending_recv: bool
 }
 
 pub struct AcceptorController {
@@ -73,8 +76,12 @@ pub struct AcceptorController {
     acceptor: Box<dyn acceptor::Acceptor>
 }
 
+/// Ids below this value can be used by endpoints, the greater ones being the tokens of the event loop inputs.
+const MAX_ENDPOINT_ID: usize = usize::MAX - 16;
+
 pub struct EndpointCollection {
     ids: Sequence,
+    id_allocator: Option<Box<dyn EndpointIdAllocator + Send>>,
     transports: HashMap<String, Box<dyn Transport + Send>, BuildIdHasher>,
     pipes: HashMap<EndpointId, PipeController, BuildIdHasher>,
     acceptors: HashMap<EndpointId, AcceptorController, BuildIdHasher>
@@ -116,8 +123,37 @@ impl PipeController {
         match cmd {
             pipe::Command::Open      => self.pipe.open(&mut ctx),
             pipe::Command::Close     => self.pipe.close(&mut ctx),
-            pipe::Command::Send(msg) => self.pipe.send(&mut ctx, msg),
-            pipe::Command::Recv      => self.pipe.recv(&mut ctx)
+            pipe::Command::Send(msg) => {
+                self.pending_send = true;
+                self.pipe.send(&mut ctx, msg)
+            },
+            pipe::Command::Recv      => self.pipe.recv(&mut ctx),
+            pipe::Command::Flush     => self.pipe.flush(&mut ctx),
+            pipe::Command::Reject    => self.pipe.reject(&mut ctx)
+        }
+    }
+
+    pub fn on_event(&mut self, evt: &pipe::Event) {
+        match *evt {
+            pipe::Event::Sent        => self.pending_send = false,
+            pipe::Event::CanRecv(x)  => self.pending_recv = x,
+            pipe::Event::Received(_) => self.pending_recv = false,
+            pipe::Event::Error(_) |
+            pipe::Event::Closed      => {
+                self.pending_send = false;
+                self.pending_recv = false;
+            },
+            _ => {}
+        }
+    }
+
+    pub fn info(&self) -> EndpointInfo {
+        let (local, peer) = self.pipe.addresses();
+
+        EndpointInfo {
+            local_addr: local,
+            peer_addr: peer,
+            handshake_duration: self.pipe.handshake_duration()
         }
     }
 
@@ -147,6 +183,10 @@ impl AcceptorController {
         }
     }
 
+    pub fn local_addr(&self) -> Option<Address> {
+        self.acceptor.local_addr()
+    }
+
     fn create_context<'a, 'b>(&self, registrar: &'b mut dyn Registrar, signal_bus: &'a mut EventLoopBus<Signal>) -> EndpointEventLoopContext<'a, 'b> {
         EndpointEventLoopContext {
             socket_id: self.socket_id,
@@ -161,6 +201,7 @@ impl EndpointCollection {
     pub fn new(seq: Sequence, transports: HashMap<String, Box<dyn Transport + Send>, BuildIdHasher>) -> EndpointCollection {
         EndpointCollection {
             ids: seq,
+            id_allocator: None,
             transports: transports,
             pipes: HashMap::default(),
             acceptors: HashMap::default()
@@ -177,8 +218,57 @@ impl EndpointCollection {
         self.pipes.get_mut(&eid)
     }
 
+    fn peer_cred(&self, eid: EndpointId) -> io::Result<PeerCred> {
+        match self.pipes.get(&eid) {
+            Some(controller) => controller.pipe.peer_cred(),
+            None => Err(not_connected_io_error("no pipe for this endpoint"))
+        }
+    }
+
+    pub fn set_id_allocator(&mut self, allocator: Box<dyn EndpointIdAllocator + Send>) {
+        self.id_allocator = Some(allocator);
+    }
+
+    fn next_id(&mut self, sid: SocketId) -> EndpointId {
+        let seq = self.ids.next();
+        let id = match self.id_allocator {
+            Some(ref mut allocator) => allocator.allocate(sid.into(), seq),
+            None => return EndpointId::from(seq)
+        };
+        let eid = EndpointId::from(id);
+
+        if id >= MAX_ENDPOINT_ID || self.is_id_used(eid) {
+            let fallback = self.next_unused_id(seq);
+
+            warn!("[{:?}] allocated endpoint id {} is not available, using {:?}", sid, id, fallback);
+            fallback
+        } else {
+            eid
+        }
+    }
+
+    fn is_id_used(&self, eid: EndpointId) -> bool {
+        self.pipes.contains_key(&eid) || self.acceptors.contains_key(&eid)
+    }
+
+    /// Returns the first counter value, starting from `seq`, that is not already the id of an endpoint,
+    /// since the allocator may have picked any of the next values.
+    fn next_unused_id(&mut self, seq: usize) -> EndpointId {
+        let mut eid = EndpointId::from(seq);
+
+        while self.is_id_used(eid) {
+            eid = EndpointId::from(self.ids.next());
+        }
+
+        eid
+    }
+
+    pub fn reserve_id(&mut self, sid: SocketId) -> EndpointId {
+        self.next_id(sid)
+    }
+
     pub fn insert_pipe(&mut self, sid: SocketId, pipe: Box<dyn pipe::Pipe>) -> EndpointId {
-        let eid = EndpointId::from(self.ids.next());
+        let eid = self.next_id(sid);
         
         self.insert_pipe_controller(sid, eid, pipe);
 
@@ -189,7 +279,9 @@ impl EndpointCollection {
         let controller = PipeController {
             socket_id: sid,
             endpoint_id: eid,
-            pipe: pipe
+            pipe: pipe,
+            pending_send: false,
+            pending_recv: false
         };
 
         self.pipes.insert(eid, controller);
@@ -199,12 +291,24 @@ impl EndpointCollection {
         self.pipes.remove(&eid);
     }
 
+    fn pending_pipes(&self, sid: SocketId) -> (usize, usize) {
+        let pipes = self.pipes.values().filter(|pipe| pipe.socket_id == sid);
+
+        pipes.fold((0, 0), |(sending, receiving), pipe| (
+            sending + pipe.pending_send as usize,
+            receiving + pipe.pending_recv as usize))
+    }
+
     pub fn get_acceptor_mut(&mut self, eid: EndpointId) -> Option<&mut AcceptorController> {
         self.acceptors.get_mut(&eid)
     }
 
+    pub fn remove_acceptor(&mut self, eid: EndpointId) {
+        self.acceptors.remove(&eid);
+    }
+
     fn insert_acceptor(&mut self, sid: SocketId, acceptor: Box<dyn acceptor::Acceptor>) -> EndpointId {
-        let eid = EndpointId::from(self.ids.next());
+        let eid = self.next_id(sid);
 
         self.insert_acceptor_controller(sid, eid, acceptor);
 
@@ -301,7 +405,15 @@ impl<'a> SocketEventLoopContext<'a> {
             addr: addr,
             pids: tmpl.pids,
             tcp_no_delay: tmpl.spec.desc.tcp_no_delay,
+            tcp_keepalive: tmpl.spec.desc.tcp_keepalive,
+            reuse_addr: tmpl.spec.desc.reuse_addr,
+            connect_source_addr: tmpl.spec.desc.connect_source_addr,
+            ipc_permissions: tmpl.spec.desc.ipc_permissions,
             recv_max_size: tmpl.spec.desc.recv_max_size,
+            send_chunk_size: tmpl.spec.desc.send_chunk_size,
+            oversize_policy: tmpl.spec.desc.oversize_policy,
+            versions: &tmpl.spec.desc.protocol_versions,
+            recv_pool: tmpl.spec.desc.recv_pool.as_ref()
         };
 
         transport.connect(&dest)
@@ -321,7 +433,15 @@ impl<'a> SocketEventLoopContext<'a> {
             addr: addr,
             pids: tmpl.pids,
             tcp_no_delay: tmpl.spec.desc.tcp_no_delay,
+            tcp_keepalive: tmpl.spec.desc.tcp_keepalive,
+            reuse_addr: tmpl.spec.desc.reuse_addr,
+            connect_source_addr: tmpl.spec.desc.connect_source_addr,
+            ipc_permissions: tmpl.spec.desc.ipc_permissions,
             recv_max_size: tmpl.spec.desc.recv_max_size,
+            send_chunk_size: tmpl.spec.desc.send_chunk_size,
+            oversize_policy: tmpl.spec.desc.oversize_policy,
+            versions: &tmpl.spec.desc.protocol_versions,
+            recv_pool: tmpl.spec.desc.recv_pool.as_ref()
         };
 
         transport.bind(&dest)
@@ -347,6 +467,29 @@ impl<'a> Network for SocketEventLoopContext<'a> {
 
         Ok(self.endpoints.insert_pipe_controller(sid, eid, pipe))
     }
+    fn reserve_endpoint_id(&mut self) -> EndpointId {
+        self.endpoints.reserve_id(self.socket_id)
+    }
+    fn connect_stream(&mut self, sid: SocketId, stream: Stream, tmpl: &EndpointTmpl) -> io::Result<EndpointId> {
+        let dest = Destination {
+            addr: &tmpl.spec.url,
+            pids: tmpl.pids,
+            tcp_no_delay: tmpl.spec.desc.tcp_no_delay,
+            tcp_keepalive: tmpl.spec.desc.tcp_keepalive,
+            reuse_addr: tmpl.spec.desc.reuse_addr,
+            connect_source_addr: tmpl.spec.desc.connect_source_addr,
+            ipc_permissions: tmpl.spec.desc.ipc_permissions,
+            recv_max_size: tmpl.spec.desc.recv_max_size,
+            send_chunk_size: tmpl.spec.desc.send_chunk_size,
+            oversize_policy: tmpl.spec.desc.oversize_policy,
+            versions: &tmpl.spec.desc.protocol_versions,
+            recv_pool: tmpl.spec.desc.recv_pool.as_ref()
+        };
+        let pipe = stream.into_pipe(&dest)?;
+        let eid = self.endpoints.insert_pipe(sid, pipe);
+
+        Ok(eid)
+    }
     fn rebind(&mut self, sid: SocketId, eid: EndpointId, tmpl: &EndpointTmpl) -> io::Result<()> {
         let acceptor = self.bind(tmpl)?;
 
@@ -372,6 +515,18 @@ impl<'a> Network for SocketEventLoopContext<'a> {
     fn recv(&mut self, endpoint_id: EndpointId) {
         self.send_pipe_cmd(endpoint_id, pipe::Command::Recv);
     }
+    fn flush(&mut self, endpoint_id: EndpointId) {
+        self.send_pipe_cmd(endpoint_id, pipe::Command::Flush);
+    }
+    fn reject(&mut self, endpoint_id: EndpointId) {
+        self.send_pipe_cmd(endpoint_id, pipe::Command::Reject);
+    }
+    fn pending_pipes(&self, sid: SocketId) -> (usize, usize) {
+        self.endpoints.pending_pipes(sid)
+    }
+    fn peer_cred(&self, eid: EndpointId) -> io::Result<PeerCred> {
+        self.endpoints.peer_cred(eid)
+    }
 
 }
 
@@ -388,6 +543,9 @@ impl<'a> context::Scheduler for SocketEventLoopContext<'a> {
             self.timer.cancel_timeout(&handle);
         }
     }
+    fn now(&self) -> Instant {
+        self.timer.now()
+    }
 }
 
 impl<'a> fmt::Debug for SocketEventLoopContext<'a> {
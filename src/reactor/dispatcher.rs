@@ -5,19 +5,21 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::collections::HashMap;
+use std::sync::Arc;
 use std::sync::mpsc::Sender;
 use std::io;
 use std::time::Duration;
 
 use mio::{Token, Ready, PollOpt};
-use mio_extras::timer::{Timer, Builder};
-use mio_extras::channel::{Receiver};
 
-use core::{BuildIdHasher, SocketId, EndpointId, DeviceId, ProbeId, session, socket, context, endpoint, device, probe};
+use core::{BuildIdHasher, SocketId, EndpointId, DeviceId, ProbeId, EndpointIdAllocator, session, socket, context, endpoint, device, probe};
+use core::event_ring::{EventRing, ReactorEventKind};
+use core::clock::Clock;
 use transport::{Transport, pipe, acceptor};
-use super::{Signal, Request, Task};
+use super::{Signal, Request, RequestReceiver, Task};
 use super::event_loop::{EventLoop, EventHandler};
 use super::bus::EventLoopBus;
+use super::timer::Timer;
 use super::adapter::{
     EndpointCollection, 
     Schedule, 
@@ -30,48 +32,66 @@ const CHANNEL_TOKEN: Token = Token(::std::usize::MAX - 1);
 const BUS_TOKEN: Token     = Token(::std::usize::MAX - 2);
 const TIMER_TOKEN: Token   = Token(::std::usize::MAX - 3);
 
+/// Settings of the I/O thread, chosen with the session builder.
+pub struct Settings {
+    pub spin_budget: Duration,
+    pub max_sockets: Option<usize>,
+    pub id_allocator: Option<Box<dyn EndpointIdAllocator + Send>>,
+    pub event_ring: Option<Arc<EventRing>>,
+    pub clock: Box<dyn Clock + Send>
+}
+
 pub struct Dispatcher {
     // request inputs
-    channel: Receiver<Request>,
+    channel: RequestReceiver,
     bus: EventLoopBus<Signal>,
     timer: Timer<Task>,
 
     // request handlers
     sockets: session::Session,
     endpoints: EndpointCollection,
-    schedule: Schedule
+    schedule: Schedule,
+
+    spin_budget: Duration,
+    event_ring: Option<Arc<EventRing>>
 }
 
 impl Dispatcher {
     pub fn dispatch(
         transports: HashMap<String, Box<dyn Transport + Send>, BuildIdHasher>,
-        rx: Receiver<Request>,
-        tx: Sender<session::Reply>) -> io::Result<()> {
+        rx: RequestReceiver,
+        tx: Sender<session::Reply>,
+        settings: Settings) -> io::Result<()> {
 
-        let mut dispatcher = Dispatcher::new(transports, rx, tx);
+        let mut dispatcher = Dispatcher::new(transports, rx, tx, settings.clock);
+
+        dispatcher.spin_budget = settings.spin_budget;
+        dispatcher.event_ring = settings.event_ring;
+        dispatcher.sockets.set_max_sockets(settings.max_sockets);
+        if let Some(allocator) = settings.id_allocator {
+            dispatcher.endpoints.set_id_allocator(allocator);
+        }
 
         dispatcher.run()
     }
     pub fn new(
         transports: HashMap<String, Box<dyn Transport + Send>, BuildIdHasher>,
-        rx: Receiver<Request>, 
-        tx: Sender<session::Reply>) -> Dispatcher {
+        rx: RequestReceiver, 
+        tx: Sender<session::Reply>,
+        clock: Box<dyn Clock + Send>) -> Dispatcher {
 
         let id_seq = Sequence::new();
         let timeout_eq = Sequence::new();
-        let clock = Builder::default().
-            tick_duration(Duration::from_millis(25)).
-            num_slots(1_024).
-            capacity(8_192).
-            build();
 
         Dispatcher {
             channel: rx,
             bus: EventLoopBus::new(),
-            timer: clock,
+            timer: Timer::new(clock),
             sockets: session::Session::new(id_seq.clone(), tx),
             endpoints: EndpointCollection::new(id_seq.clone(), transports),
-            schedule: Schedule::new(timeout_eq)
+            schedule: Schedule::new(timeout_eq),
+            spin_budget: Duration::from_millis(0),
+            event_ring: None
         }
 
     }
@@ -84,6 +104,8 @@ impl Dispatcher {
 
     pub fn run(&mut self) -> io::Result<()> {
         let mut event_loop = EventLoop::new()?;
+
+        event_loop.set_spin_budget(self.spin_budget);
         let interest = Ready::readable();
         let opt = PollOpt::edge();
 
@@ -124,7 +146,7 @@ impl Dispatcher {
     fn process_request(&mut self, el: &mut EventLoop, request: Request) {
         match request {
             Request::Session(req) => self.process_session_request(el, req),
-            Request::Socket(id, req) => self.process_socket_request(el, id, req),
+            Request::Socket(id, handle, req) => self.process_socket_request(el, id, handle, req),
             Request::Endpoint(sid, eid, req) => self.process_endpoint_request(el, sid, eid, req),
             Request::Device(id, req) => self.process_device_request(el, id, req),
             Request::Probe(id, req) => self.process_probe_request(el, id, req),
@@ -132,7 +154,7 @@ impl Dispatcher {
     }
     fn process_signal(&mut self, el: &mut EventLoop, signal: Signal) {
         match signal {
-            Signal::PipeCmd(_, eid, cmd)       => self.process_pipe_cmd(el, eid, cmd),
+            Signal::PipeCmd(sid, eid, cmd)     => self.process_pipe_cmd(el, sid, eid, cmd),
             Signal::AcceptorCmd(_, eid, cmd)   => self.process_acceptor_cmd(el, eid, cmd),
             Signal::SocketCmd(sid, cmd)        => self.process_socket_cmd(el, sid, cmd),
             Signal::PipeEvt(sid, eid, evt)     => self.process_pipe_evt(el, sid, eid, evt),
@@ -159,6 +181,11 @@ impl Dispatcher {
             context::Schedulable::Rebind(eid, spec)    => self.apply_on_socket(sid, |socket, ctx| socket.rebind(ctx, eid, spec)),
             context::Schedulable::SendTimeout          => self.apply_on_socket(sid, |socket, ctx| socket.on_send_timeout(ctx)),
             context::Schedulable::RecvTimeout          => self.apply_on_socket(sid, |socket, ctx| socket.on_recv_timeout(ctx)),
+            context::Schedulable::DrainTimeout(eid)    => self.apply_on_socket(sid, |socket, ctx| socket.on_drain_timeout(ctx, eid)),
+            context::Schedulable::LingerTimeout        => self.apply_on_socket(sid, |socket, ctx| socket.on_linger_timeout(ctx)),
+            context::Schedulable::ShutdownTimeout      => self.apply_on_socket(sid, |socket, ctx| socket.on_shutdown_timeout(ctx)),
+            context::Schedulable::ConnectTimeout(eid)  => self.apply_on_socket(sid, |socket, ctx| socket.on_connect_timeout(ctx, eid)),
+            context::Schedulable::RecvRateWindow(eid)  => self.apply_on_socket(sid, |socket, ctx| socket.on_recv_rate_window(ctx, eid)),
             other                                      => self.apply_on_socket(sid, |socket, ctx| socket.on_timer_tick(ctx, other))
         }
     }
@@ -206,29 +233,71 @@ impl Dispatcher {
                 self.sockets.add_device(l, r);
             },
             session::Request::CreateProbe(poll_opts) => self.sockets.add_probe(poll_opts),
+            session::Request::Ping => self.sockets.ping(),
             session::Request::Shutdown => el.shutdown()
         }
     }
-    fn process_socket_request(&mut self, _: &mut EventLoop, id: SocketId, request: socket::Request) {
+    fn process_socket_request(&mut self, _: &mut EventLoop, id: SocketId, handle: socket::HandleId, request: socket::Request) {
+        if let Some(socket) = self.sockets.get_socket_mut(id) {
+            socket.set_requestor(handle);
+        }
         match request {
             socket::Request::Connect(url)     => self.apply_on_socket(id, |socket, ctx| socket.connect(ctx, url)),
+            socket::Request::ConnectBlocking(url, timeout) => self.apply_on_socket(id, |socket, ctx| socket.connect_blocking(ctx, url, timeout)),
+            socket::Request::ConnectStream(stream) => self.apply_on_socket(id, |socket, ctx| socket.connect_stream(ctx, stream)),
             socket::Request::Bind(url)        => self.apply_on_socket(id, |socket, ctx| socket.bind(ctx, url)),
             socket::Request::Send(msg, false) => self.apply_on_socket(id, |socket, ctx| socket.send(ctx, msg)),
             socket::Request::Send(msg, true)  => self.apply_on_socket(id, |socket, ctx| socket.try_send(ctx, msg)),
+            socket::Request::SendAsync(msg)   => self.apply_on_socket(id, |socket, ctx| socket.send_async(ctx, msg)),
+            socket::Request::SendSurvey(msg)  => self.apply_on_socket(id, |socket, ctx| socket.send_survey(ctx, msg)),
+            socket::Request::CancelRequest    => self.apply_on_socket(id, |socket, ctx| socket.cancel_request(ctx)),
             socket::Request::Recv(false)      => self.apply_on_socket(id, |socket, ctx| socket.recv(ctx)),
             socket::Request::Recv(true)       => self.apply_on_socket(id, |socket, ctx| socket.try_recv(ctx)),
             socket::Request::SetOption(x)     => self.apply_on_socket(id, |socket, ctx| socket.set_option(ctx, x)),
-            socket::Request::Close            => self.apply_on_socket(id, |socket, ctx| socket.close(ctx)),
+            socket::Request::SetRecvReducer(r, f) => self.apply_on_socket(id, |socket, ctx| socket.set_recv_reducer(ctx, r, f)),
+            socket::Request::SetRecvMap(m)    => self.apply_on_socket(id, |socket, ctx| socket.set_recv_map(ctx, m)),
+            socket::Request::SetRecvPool(p)   => self.apply_on_socket(id, |socket, ctx| socket.set_recv_pool(ctx, p)),
+            socket::Request::SetSubscriptionValidator(v) => self.apply_on_socket(id, |socket, ctx| socket.set_subscription_validator(ctx, v)),
+            socket::Request::PlugDevice       => self.apply_on_socket(id, |socket, ctx| socket.plug_device(ctx)),
+            socket::Request::PendingPipes     => self.apply_on_socket(id, |socket, ctx| socket.pending_pipes(ctx)),
+            socket::Request::PendingRetries   => self.apply_on_socket(id, |socket, ctx| socket.pending_retries(ctx)),
+            socket::Request::DroppedCount     => self.apply_on_socket(id, |socket, ctx| socket.dropped_count(ctx)),
+            socket::Request::DuplicateCount   => self.apply_on_socket(id, |socket, ctx| socket.duplicate_count(ctx)),
+            socket::Request::ExpiredCount     => self.apply_on_socket(id, |socket, ctx| socket.expired_count(ctx)),
+            #[cfg(unix)]
+            socket::Request::RecvFd           => self.apply_on_socket(id, |socket, ctx| socket.recv_fd(ctx)),
+            #[cfg(unix)]
+            socket::Request::SendFd           => self.apply_on_socket(id, |socket, ctx| socket.send_fd(ctx)),
+            socket::Request::SizeHistogram    => self.apply_on_socket(id, |socket, ctx| socket.size_histogram(ctx)),
+            socket::Request::Capabilities     => self.apply_on_socket(id, |socket, ctx| socket.capabilities(ctx)),
+            socket::Request::Stats            => self.apply_on_socket(id, |socket, ctx| socket.stats(ctx)),
+            socket::Request::NewSibling       => self.sockets.add_sibling(id),
+            socket::Request::ApplyEndpointConfig(x) => self.apply_on_socket(id, |socket, ctx| socket.apply_endpoint_config(ctx, x)),
+            socket::Request::DumpEndpointConfig => self.apply_on_socket(id, |socket, ctx| socket.dump_endpoint_config(ctx)),
+            socket::Request::Shutdown(timeout) => self.apply_on_socket(id, |socket, ctx| socket.shutdown(ctx, timeout)),
+            socket::Request::Clone            => self.apply_on_socket(id, |socket, ctx| socket.clone_handle(ctx)),
+            socket::Request::Close            => self.apply_on_socket(id, |socket, ctx| socket.close_handle(ctx)),
         }
     }
     fn process_endpoint_request(&mut self, _: &mut EventLoop, sid: SocketId, eid: EndpointId, request: endpoint::Request) {
-        let endpoint::Request::Close(remote) = request;
-
-        self.apply_on_socket(sid, |socket, ctx| if remote {
-            socket.close_pipe(ctx, eid)
-        } else {
-            socket.close_acceptor(ctx, eid)
-        });
+        match request {
+            endpoint::Request::Close(remote) => self.apply_on_socket(sid, |socket, ctx| if remote {
+                socket.close_pipe(ctx, eid)
+            } else {
+                socket.close_acceptor(ctx, eid)
+            }),
+            endpoint::Request::CloseGraceful(remote, timeout) => self.apply_on_socket(sid, |socket, ctx| if remote {
+                if let Err(e) = socket.close_pipe_graceful(ctx, eid, timeout) {
+                    debug!("[{:?}] ep {:?} closed without draining: {}", sid, eid, e);
+                }
+            } else {
+                socket.close_acceptor(ctx, eid)
+            }),
+            endpoint::Request::Rebind(url) => self.apply_on_socket(sid, |socket, ctx| socket.rebind_acceptor(ctx, eid, url)),
+            endpoint::Request::PauseReconnect => self.apply_on_socket(sid, |socket, ctx| socket.pause_reconnect(ctx, eid)),
+            endpoint::Request::ResumeReconnect => self.apply_on_socket(sid, |socket, ctx| socket.resume_reconnect(ctx, eid)),
+            endpoint::Request::PeerCred(handle) => self.apply_on_socket(sid, |socket, ctx| socket.peer_cred(ctx, handle, eid))
+        }
     }
     fn process_device_request(&mut self, _: &mut EventLoop, id: DeviceId, request: device::Request) {
         if let device::Request::Check = request { 
@@ -247,7 +316,13 @@ impl Dispatcher {
 /* process signal cmd                                                        */
 /*                                                                           */
 /*****************************************************************************/
-    fn process_pipe_cmd(&mut self, el: &mut EventLoop, eid: EndpointId, cmd: pipe::Command) {
+    fn process_pipe_cmd(&mut self, el: &mut EventLoop, sid: SocketId, eid: EndpointId, cmd: pipe::Command) {
+        // a pipe closed by its socket goes straight to dead without raising the closed event
+        if let pipe::Command::Close = cmd {
+            if self.endpoints.get_pipe_mut(eid).is_some() {
+                self.record_event(ReactorEventKind::PipeClosed, sid, eid, None);
+            }
+        }
         if let Some(pipe) = self.endpoints.get_pipe_mut(eid) {
             pipe.process(el, &mut self.bus, cmd);
         }
@@ -269,44 +344,96 @@ impl Dispatcher {
 /*                                                                           */
 /*****************************************************************************/
     fn process_pipe_evt(&mut self, _: &mut EventLoop, sid: SocketId, eid: EndpointId, evt: pipe::Event) {
+        if let Some(pipe) = self.endpoints.get_pipe_mut(eid) {
+            pipe.on_event(&evt);
+        }
+
         match evt {
-            pipe::Event::Opened        => self.apply_on_socket(sid, |socket, ctx| socket.on_pipe_opened(ctx, eid)),
+            pipe::Event::Opened(pid, features) => {
+                self.record_event(ReactorEventKind::PipeOpened, sid, eid, None);
+                let info = self.endpoints.get_pipe_mut(eid).map_or_else(socket::EndpointInfo::default, |pipe| pipe.info());
+
+                self.apply_on_socket(sid, |socket, ctx| {
+                    socket.set_endpoint_info(eid, info);
+                    socket.on_pipe_opened(ctx, eid, pid, features)
+                })
+            },
             pipe::Event::CanSend(x)    => self.apply_on_socket(sid, |socket, ctx| socket.on_send_ready(ctx, eid, x)),
             pipe::Event::Sent          => self.apply_on_socket(sid, |socket, ctx| socket.on_send_ack(ctx, eid)),
             pipe::Event::CanRecv(x)    => self.apply_on_socket(sid, |socket, ctx| socket.on_recv_ready(ctx, eid, x)),
             pipe::Event::Received(msg) => self.apply_on_socket(sid, |socket, ctx| socket.on_recv_ack(ctx, eid, msg)),
-            pipe::Event::Error(err)    => self.apply_on_socket(sid, |socket, ctx| socket.on_pipe_error(ctx, eid, err)),
-            pipe::Event::Closed        => self.endpoints.remove_pipe(eid)
+            pipe::Event::Dropped(n)    => self.apply_on_socket(sid, |socket, ctx| socket.on_pipe_dropped(ctx, eid, n)),
+            pipe::Event::Flushed       => self.apply_on_socket(sid, |socket, ctx| socket.on_pipe_flushed(ctx, eid)),
+            pipe::Event::Error(err)    => {
+                self.record_event(ReactorEventKind::PipeError, sid, eid, Some(&err));
+                self.apply_on_socket(sid, |socket, ctx| socket.on_pipe_error(ctx, eid, err))
+            },
+            pipe::Event::Closed        => {
+                self.record_event(ReactorEventKind::PipeClosed, sid, eid, None);
+                self.endpoints.remove_pipe(eid)
+            }
         }
     }
     fn process_acceptor_evt(&mut self, _: &mut EventLoop, sid: SocketId, aid: EndpointId, evt: acceptor::Event) {
         match evt {
             // Maybe the controller should be removed from the endpoint collection
-            acceptor::Event::Error(e) => self.apply_on_socket(sid, |socket, ctx| socket.on_acceptor_error(ctx, aid, e)),
+            acceptor::Event::Error(e) => {
+                self.record_event(ReactorEventKind::AcceptorError, sid, aid, Some(&e));
+                self.apply_on_socket(sid, |socket, ctx| socket.on_acceptor_error(ctx, aid, e))
+            },
+            acceptor::Event::Opened => {
+                self.record_event(ReactorEventKind::AcceptorOpened, sid, aid, None);
+                let info = socket::EndpointInfo {
+                    local_addr: self.endpoints.get_acceptor_mut(aid).and_then(|acceptor| acceptor.local_addr()),
+                    peer_addr: None,
+                    handshake_duration: None
+                };
+
+                self.apply_on_socket(sid, |socket, _| socket.set_endpoint_info(aid, info));
+            },
             acceptor::Event::Accepted(pipes) => {
                 for pipe in pipes {
                     let pipe_id = self.endpoints.insert_pipe(sid, pipe);
 
+                    self.record_event(ReactorEventKind::PipeAccepted, sid, pipe_id, None);
                     self.apply_on_socket(sid, |socket, ctx| socket.on_pipe_accepted(ctx, aid, pipe_id));
                 }
             },
-            _ => {}
+            acceptor::Event::Closed => {
+                self.record_event(ReactorEventKind::AcceptorClosed, sid, aid, None);
+                self.endpoints.remove_acceptor(aid)
+            }
         }
     }
     fn process_socket_evt(&mut self, _: &mut EventLoop, sid: SocketId, evt: context::Event) {
         match evt {
             context::Event::CanRecv(x) => {
+                #[cfg(unix)]
+                self.apply_on_socket_readiness(sid, |socket| socket.on_recv_readiness_changed(x));
                 self.apply_on_device_link(sid, |device| device.on_socket_can_recv(sid, x));
                 self.apply_on_probe_link(sid, |probe, ctx| probe.on_socket_can_recv(ctx, sid, x));
             },
             context::Event::CanSend(x) => {
+                #[cfg(unix)]
+                self.apply_on_socket_readiness(sid, |socket| socket.on_send_readiness_changed(x));
                 self.apply_on_probe_link(sid, |probe, ctx| probe.on_socket_can_send(ctx, sid, x));
 
             },
+            context::Event::EndpointClosed(eid, os_error, category) => {
+                if let Some(ref ring) = self.event_ring {
+                    ring.record_error(ReactorEventKind::EndpointClosed, sid, eid, os_error, Some(category));
+                }
+            },
             context::Event::Closed => self.sockets.remove_socket(sid)
         }
     }
 
+    fn record_event(&self, kind: ReactorEventKind, sid: SocketId, eid: EndpointId, err: Option<&io::Error>) {
+        if let Some(ref ring) = self.event_ring {
+            ring.record(kind, sid, eid, err);
+        }
+    }
+
     fn apply_on_socket<F>(&mut self, id: SocketId, f: F) 
     where F : FnOnce(&mut socket::Socket, &mut SocketEventLoopContext) {
         if let Some(socket) = self.sockets.get_socket_mut(id) {
@@ -318,6 +445,15 @@ impl Dispatcher {
                 &mut self.timer);
 
             f(socket, &mut ctx);
+            socket.process_protocol_replies(&mut ctx, false);
+        }
+    }
+
+    #[cfg(unix)]
+    fn apply_on_socket_readiness<F>(&mut self, id: SocketId, f: F) 
+    where F : FnOnce(&mut socket::Socket) {
+        if let Some(socket) = self.sockets.get_socket_mut(id) {
+            f(socket);
         }
     }
 
@@ -366,8 +502,16 @@ impl EventHandler for Dispatcher {
         match token {
             CHANNEL_TOKEN => self.process_channel(el),
             BUS_TOKEN     => self.process_bus(el),
-            TIMER_TOKEN   => self.process_timer(el),
+            TIMER_TOKEN   => self.timer.clear_wakeup(),
             _             => self.process_io(el, token, events)
         }
     }
+
+    fn next_timeout(&self) -> Option<Duration> {
+        self.timer.next_delay()
+    }
+
+    fn polled(&mut self, el: &mut EventLoop) {
+        self.process_timer(el);
+    }
 }
@@ -5,17 +5,28 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::io;
+use std::time::{Duration, Instant};
 
 use mio::{Poll, Token, Ready, Event, Events, Evented, PollOpt};
 
 pub trait EventHandler {
     fn handle(&mut self, el: &mut EventLoop, token: Token, events: Ready);
+
+    /// Returns how long the loop can block before the handler has some work due, `None` meaning as long as needed.
+    fn next_timeout(&self) -> Option<Duration> {
+        None
+    }
+
+    /// Called after each poll, whether some events were found or the poll timed out.
+    fn polled(&mut self, _: &mut EventLoop) {}
 }
 
 pub struct EventLoop {
     events_poller: Poll,
     events: Events,
-    running: bool
+    running: bool,
+    spin_budget: Duration,
+    spin_until: Option<Instant>
 }
 
 impl EventLoop {
@@ -25,12 +36,20 @@ impl EventLoop {
         let event_loop = EventLoop {
             events_poller: poll,
             events: evts,
-            running: false
+            running: false,
+            spin_budget: Duration::from_millis(0),
+            spin_until: None
         };
 
         Ok(event_loop)
     }
 
+    /// Sets how long the loop keeps polling without blocking after it last found some events,
+    /// trading CPU for latency when messages come in bursts. Zero disables hot polling.
+    pub fn set_spin_budget(&mut self, budget: Duration) {
+        self.spin_budget = budget;
+    }
+
     pub fn shutdown(&mut self) {
         self.running = false;
     }
@@ -46,7 +65,8 @@ impl EventLoop {
     }
 
     pub fn run_once<H: EventHandler>(&mut self, event_handler: &mut H) -> io::Result<()> {
-        let event_count = match self.poll_events() {
+        let timeout = shortest(self.poll_timeout(Instant::now()), event_handler.next_timeout());
+        let event_count = match self.poll_events(timeout) {
             Ok(count) => count,
             Err(err) => {
                 if err.kind() == io::ErrorKind::Interrupted {
@@ -57,13 +77,28 @@ impl EventLoop {
             }
         };
 
+        self.on_events_polled(event_count, Instant::now());
         self.process_events(event_handler, event_count);
+        event_handler.polled(self);
 
         Ok(())
     }
 
-    fn poll_events(&mut self) -> io::Result<usize> {
-        self.events_poller.poll(&mut self.events, None)
+    fn poll_timeout(&self, now: Instant) -> Option<Duration> {
+        match self.spin_until {
+            Some(deadline) if now < deadline => Some(Duration::from_millis(0)),
+            _ => None
+        }
+    }
+
+    fn on_events_polled(&mut self, count: usize, now: Instant) {
+        if count > 0 && self.spin_budget > Duration::from_millis(0) {
+            self.spin_until = Some(now + self.spin_budget);
+        }
+    }
+
+    fn poll_events(&mut self, timeout: Option<Duration>) -> io::Result<usize> {
+        self.events_poller.poll(&mut self.events, timeout)
     }
 
     fn process_events<H: EventHandler>(&mut self, event_handler: &mut H, count: usize) {
@@ -82,3 +117,43 @@ impl EventLoop {
         self.events_poller.deregister(io)
     }
 }
+
+fn shortest(a: Option<Duration>, b: Option<Duration>) -> Option<Duration> {
+    match (a, b) {
+        (Some(x), Some(y)) => Some(x.min(y)),
+        (x, None) => x,
+        (None, y) => y
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn without_spin_budget_poll_always_blocks() {
+        let mut event_loop = EventLoop::new().unwrap();
+        let now = Instant::now();
+
+        event_loop.on_events_polled(3, now);
+
+        assert_eq!(None, event_loop.poll_timeout(now));
+    }
+
+    #[test]
+    fn with_spin_budget_poll_is_hot_only_shortly_after_events() {
+        let mut event_loop = EventLoop::new().unwrap();
+        let now = Instant::now();
+
+        event_loop.set_spin_budget(Duration::from_millis(10));
+        assert_eq!(None, event_loop.poll_timeout(now));
+
+        event_loop.on_events_polled(1, now);
+        assert_eq!(Some(Duration::from_millis(0)), event_loop.poll_timeout(now + Duration::from_millis(5)));
+
+        event_loop.on_events_polled(0, now + Duration::from_millis(5));
+        assert_eq!(None, event_loop.poll_timeout(now + Duration::from_millis(10)));
+    }
+}
@@ -8,9 +8,19 @@ pub mod dispatcher;
 mod event_loop;
 mod bus;
 mod adapter;
+mod timer;
 
+use std::io;
+use std::sync::Arc;
+use std::sync::mpsc::TryRecvError;
+
+use mio::{Evented, Poll, Token, Ready, PollOpt};
+use mio_extras::channel::{self, Sender, Receiver};
+
+use core::ready_fd::WakeupFd;
 use core::{SocketId, EndpointId, DeviceId, ProbeId, context, session, socket, endpoint, device, probe};
 use transport::{pipe, acceptor};
+use io_error::from_send_error;
 
 /// Commands and events flowing between the controller and transport or core components.
 /// The controller sends commands, the transport and core raise events.
@@ -26,7 +36,7 @@ pub enum Signal {
 /// Requests flowing to core components via the controller
 pub enum Request {
     Session(session::Request),
-    Socket(SocketId, socket::Request),
+    Socket(SocketId, socket::HandleId, socket::Request),
     Endpoint(SocketId, EndpointId, endpoint::Request),
     Device(DeviceId, device::Request),
     Probe(ProbeId, probe::Request)
@@ -36,3 +46,103 @@ pub enum Task {
     Socket(SocketId, context::Schedulable),
     Probe(ProbeId, probe::Schedulable)
 }
+
+/// Creates the channel carrying the requests to the I/O thread,
+/// along with the descriptor signalled while some requests are waiting in it.
+pub fn request_channel() -> io::Result<(RequestSender, RequestReceiver)> {
+    let (sender_tx, receiver_rx) = channel::channel();
+    let wakeup_fd = Arc::new(WakeupFd::new()?);
+    let sender = RequestSender { tx: sender_tx, wakeup: wakeup_fd.clone() };
+    let receiver = RequestReceiver { rx: receiver_rx, wakeup: wakeup_fd };
+
+    Ok((sender, receiver))
+}
+
+#[derive(Clone)]
+pub struct RequestSender {
+    tx: Sender<Request>,
+    wakeup: Arc<WakeupFd>
+}
+
+impl RequestSender {
+    pub fn send(&self, req: Request) -> io::Result<()> {
+        self.tx.send(req).map_err(from_send_error)?;
+        self.wakeup.signal();
+        Ok(())
+    }
+
+    pub fn wakeup(&self) -> &WakeupFd {
+        &self.wakeup
+    }
+}
+
+pub struct RequestReceiver {
+    rx: Receiver<Request>,
+    wakeup: Arc<WakeupFd>
+}
+
+impl RequestReceiver {
+    /// Clears the wakeup descriptor once the channel is empty, and then looks into the channel again,
+    /// so a request sent meanwhile is either taken or leaves the descriptor signalled.
+    pub fn try_recv(&self) -> Result<Request, TryRecvError> {
+        match self.rx.try_recv() {
+            Err(TryRecvError::Empty) => {
+                self.wakeup.clear();
+                self.rx.try_recv()
+            },
+            other => other
+        }
+    }
+}
+
+impl Evented for RequestReceiver {
+    fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        self.rx.register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        self.rx.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        self.rx.deregister(poll)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::os::unix::io::RawFd;
+    use std::time::Duration;
+
+    use mio::{Poll, Events, Ready, PollOpt, Token};
+    use mio::unix::EventedFd;
+
+    use core::{DeviceId, device};
+
+    use super::*;
+
+    fn is_readable(raw_fd: RawFd) -> bool {
+        let poll = Poll::new().unwrap();
+        let mut events = Events::with_capacity(1);
+
+        poll.register(&EventedFd(&raw_fd), Token(0), Ready::readable(), PollOpt::level()).unwrap();
+        poll.poll(&mut events, Some(Duration::from_millis(0))).unwrap();
+
+        events.iter().any(|e| e.readiness().is_readable())
+    }
+
+    #[test]
+    fn wakeup_should_stay_signalled_until_the_channel_is_empty() {
+        let (tx, rx) = request_channel().unwrap();
+        let wakeup_fd = tx.wakeup().as_raw_fd();
+
+        tx.send(Request::Device(DeviceId::from(1), device::Request::Check)).unwrap();
+        tx.send(Request::Device(DeviceId::from(1), device::Request::Close)).unwrap();
+
+        assert!(rx.try_recv().is_ok());
+        assert!(is_readable(wakeup_fd));
+        assert!(rx.try_recv().is_ok());
+        assert!(rx.try_recv().is_err());
+        assert!(!is_readable(wakeup_fd));
+    }
+}
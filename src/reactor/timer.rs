@@ -0,0 +1,183 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::collections::BTreeMap;
+use std::io;
+use std::time::{Duration, Instant};
+
+use mio::{Evented, Poll, Token, Ready, PollOpt, Registration, SetReadiness};
+
+use core::clock::{Clock, ClockWaker};
+
+/// Handle of a scheduled task, ordered by deadline, then by scheduling order.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Timeout {
+    deadline: Instant,
+    seq: u64
+}
+
+/// Holds the tasks scheduled by the I/O thread until the clock says they are due.
+/// The event loop is expected to block no longer than `next_delay`, and to `poll` the due tasks after each wake up.
+/// Being registered lets the clock wake the event loop up when its time changes.
+pub struct Timer<T> {
+    clock: Box<dyn Clock + Send>,
+    tasks: BTreeMap<Timeout, T>,
+    seq: u64,
+    registration: Registration,
+    readiness: SetReadiness
+}
+
+impl<T> Timer<T> {
+    pub fn new(mut time_source: Box<dyn Clock + Send>) -> Timer<T> {
+        let (reg, set_readiness) = Registration::new2();
+
+        time_source.set_waker(ClockWaker::new(set_readiness.clone()));
+
+        Timer {
+            clock: time_source,
+            tasks: BTreeMap::new(),
+            seq: 0,
+            registration: reg,
+            readiness: set_readiness
+        }
+    }
+
+    pub fn set_timeout(&mut self, delay: Duration, task: T) -> Timeout {
+        let timeout = Timeout {
+            deadline: self.clock.now() + delay,
+            seq: self.seq
+        };
+
+        self.seq += 1;
+        self.tasks.insert(timeout, task);
+        self.clock.on_scheduled(timeout.deadline);
+        timeout
+    }
+
+    pub fn cancel_timeout(&mut self, timeout: &Timeout) -> Option<T> {
+        self.tasks.remove(timeout)
+    }
+
+    /// Removes and returns the first task that is due, if any.
+    pub fn poll(&mut self) -> Option<T> {
+        let now = self.clock.now();
+        let due = match self.tasks.keys().next() {
+            Some(timeout) if timeout.deadline <= now => *timeout,
+            _ => return None
+        };
+
+        self.tasks.remove(&due)
+    }
+
+    /// Returns how long until the first task is due, `None` meaning that there is no task.
+    pub fn next_delay(&self) -> Option<Duration> {
+        let now = self.clock.now();
+
+        self.tasks.keys().next().map(|timeout| if timeout.deadline > now {
+            timeout.deadline - now
+        } else {
+            Duration::from_millis(0)
+        })
+    }
+
+    pub fn now(&self) -> Instant {
+        self.clock.now()
+    }
+
+    /// Acknowledges the wake up requested by the clock.
+    pub fn clear_wakeup(&self) {
+        let _ = self.readiness.set_readiness(Ready::empty());
+    }
+}
+
+impl<T> Evented for Timer<T> {
+    fn register(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        self.registration.register(poll, token, interest, opts)
+    }
+
+    fn reregister(&self, poll: &Poll, token: Token, interest: Ready, opts: PollOpt) -> io::Result<()> {
+        self.registration.reregister(poll, token, interest, opts)
+    }
+
+    fn deregister(&self, poll: &Poll) -> io::Result<()> {
+        poll.deregister(&self.registration)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    struct ManualClock {
+        now: Arc<Mutex<Instant>>
+    }
+
+    impl Clock for ManualClock {
+        fn now(&self) -> Instant {
+            *self.now.lock().unwrap()
+        }
+    }
+
+    fn advance(now: &Arc<Mutex<Instant>>, delay: Duration) {
+        *now.lock().unwrap() += delay;
+    }
+
+    fn new_timer() -> (Timer<u32>, Arc<Mutex<Instant>>) {
+        let now = Arc::new(Mutex::new(Instant::now()));
+        let clock = ManualClock { now: now.clone() };
+
+        (Timer::new(Box::new(clock)), now)
+    }
+
+    #[test]
+    fn tasks_are_due_in_deadline_order_once_the_clock_reaches_them() {
+        let (mut timer, now) = new_timer();
+
+        timer.set_timeout(Duration::from_millis(20), 2);
+        timer.set_timeout(Duration::from_millis(10), 1);
+        timer.set_timeout(Duration::from_millis(30), 3);
+
+        assert_eq!(None, timer.poll());
+        assert_eq!(Some(Duration::from_millis(10)), timer.next_delay());
+
+        advance(&now, Duration::from_millis(25));
+
+        assert_eq!(Some(1), timer.poll());
+        assert_eq!(Some(2), timer.poll());
+        assert_eq!(None, timer.poll());
+        assert_eq!(Some(Duration::from_millis(5)), timer.next_delay());
+    }
+
+    #[test]
+    fn cancelled_task_is_never_due() {
+        let (mut timer, now) = new_timer();
+        let timeout = timer.set_timeout(Duration::from_millis(10), 1);
+
+        assert_eq!(Some(1), timer.cancel_timeout(&timeout));
+
+        advance(&now, Duration::from_millis(10));
+
+        assert_eq!(None, timer.poll());
+        assert_eq!(None, timer.next_delay());
+    }
+
+    #[test]
+    fn tasks_with_the_same_deadline_are_due_in_scheduling_order() {
+        let (mut timer, now) = new_timer();
+
+        timer.set_timeout(Duration::from_millis(10), 1);
+        timer.set_timeout(Duration::from_millis(10), 2);
+
+        advance(&now, Duration::from_millis(10));
+
+        assert_eq!(Some(Duration::from_millis(0)), timer.next_delay());
+        assert_eq!(Some(1), timer.poll());
+        assert_eq!(Some(2), timer.poll());
+    }
+}
@@ -30,3 +30,83 @@ impl Default for Sequence {
         Sequence::new()
     }
 }
+
+/// Returns the sequence value following the specified one, for the request and survey ids.
+/// These ids are sent with their most significant bit set, so only 31 bits are available:
+/// the value wraps to zero past the largest one, instead of overflowing or aliasing
+/// the ids of the lower half once the bit is set.
+pub fn next_wire_id(seq: u32) -> u32 {
+    seq.wrapping_add(1) & 0x7FFF_FFFF
+}
+
+/// Request or survey id as sent on the wire, along with the generation of the sequence it was taken from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct WireId {
+    pub value: u32,
+    pub generation: u32
+}
+
+/// Sequence of the request or survey ids, see [next_wire_id](fn.next_wire_id.html).
+/// Each wrap starts a new generation, so an id kept from before the wrap,
+/// such as the one of a late reply, does not match the id reusing its value afterward.
+pub struct WireIdSeq {
+    pub value: u32,
+    pub generation: u32
+}
+
+impl WireIdSeq {
+    pub fn new(seed: u32) -> WireIdSeq {
+        WireIdSeq {
+            value: seed & 0x7FFF_FFFF,
+            generation: 0
+        }
+    }
+
+    pub fn current(&self) -> WireId {
+        WireId {
+            value: self.value | 0x8000_0000,
+            generation: self.generation
+        }
+    }
+
+    pub fn next(&mut self) -> WireId {
+        self.value = next_wire_id(self.value);
+        if self.value == 0 {
+            self.generation = self.generation.wrapping_add(1);
+        }
+        self.current()
+    }
+
+    /// Tags an id read from the wire with the current generation.
+    pub fn received(&self, id: u32) -> WireId {
+        WireId {
+            value: id,
+            generation: self.generation
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn wire_id_wraps_to_zero_past_31_bits() {
+        assert_eq!(1, next_wire_id(0));
+        assert_eq!(0, next_wire_id(0x7FFF_FFFF));
+        assert_eq!(0, next_wire_id(0xFFFF_FFFF));
+    }
+
+    #[test]
+    fn wrapped_wire_id_should_not_match_the_id_of_the_previous_generation() {
+        let mut seq = WireIdSeq::new(0x7FFF_FFFF);
+        let before_wrap = seq.received(0x8000_0000);
+
+        seq.value = 0x7FFF_FFFF;
+        let after_wrap = seq.next();
+
+        assert_eq!(before_wrap.value, after_wrap.value);
+        assert!(before_wrap != after_wrap);
+        assert_eq!(after_wrap, seq.received(0x8000_0000));
+    }
+}
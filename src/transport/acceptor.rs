@@ -10,6 +10,7 @@ use mio::Ready;
 
 use transport::endpoint::EndpointRegistrar;
 use transport::pipe::Pipe;
+use transport::Address;
 
 pub enum Command {
     Open,
@@ -27,6 +28,9 @@ pub trait Acceptor {
     fn ready(&mut self, ctx: &mut dyn Context, events: Ready);
     fn open(&mut self, ctx: &mut dyn Context);
     fn close(&mut self, ctx: &mut dyn Context);
+    fn local_addr(&self) -> Option<Address> {
+        None
+    }
 }
 
 pub trait Context : EndpointRegistrar {
@@ -14,25 +14,61 @@ use core::Message;
 use transport::async::stub::*;
 use transport::async::state::*;
 use transport::async::dead::Dead; 
+use transport::async::extension;
 use transport::pipe::{Event, Context};
+use transport::{Addresses, PeerCred, Features, FEATURE_EXTENSIONS};
 use io_error::*;
 
 // pipe readiness value is different from the underlying I/O readiness
 // because while an operation is in progress, must be finished
 // before being able to start another one. 
 
+// the stub is registered for a readiness only while it is useful:
+// either the pipe does not know yet that an operation can be started,
+// or an operation is in progress and waits for the stream.
+// Once the readiness has been reported and nothing is pending,
+// the interest is dropped so an idle pipe does not wake the event loop up.
+
 pub struct Active<S> {
     stub: S,
     can_send_msg: bool,
-    can_recv_msg: bool
+    can_recv_msg: bool,
+    flushing: bool,
+    peer_proto_id: u16,
+    features: Features,
+    registered: Ready
 }
 
 impl<S : AsyncPipeStub> Active<S> {
-    pub fn new(s: S) -> Active<S> {
+    pub fn new(s: S, peer_pid: u16, negotiated: Features) -> Active<S> {
         Active {
             stub: s,
             can_send_msg: false,
-            can_recv_msg: false
+            can_recv_msg: false,
+            flushing: false,
+            peer_proto_id: peer_pid,
+            features: negotiated,
+            registered: interest()
+        }
+    }
+    fn desired_interest(&self) -> Ready {
+        let mut desired = Ready::from(UnixReady::hup() | UnixReady::error());
+
+        if !self.can_recv_msg || self.stub.has_pending_recv() {
+            desired |= Ready::readable();
+        }
+        if !self.can_send_msg || self.stub.has_pending_send() {
+            desired |= Ready::writable();
+        }
+
+        desired
+    }
+    fn check_interest(&mut self, ctx: &mut dyn Context) {
+        let desired = self.desired_interest();
+
+        if self.registered != desired {
+            self.registered = desired;
+            ctx.reregister(self.stub.deref(), desired, PollOpt::level());
         }
     }
     fn on_send_progress(&mut self, ctx: &mut dyn Context, progress: Result<bool>) -> Result<()> {
@@ -40,8 +76,13 @@ impl<S : AsyncPipeStub> Active<S> {
     }
     fn on_msg_sent(&mut self, ctx: &mut dyn Context) {
         ctx.raise(Event::Sent);
+        self.check_flushed(ctx);
     }
-    fn writable_changed(&mut self, ctx: &mut dyn Context, events: Ready) -> Result<()> {
+    fn writable_changed(&mut self, ctx: &mut dyn Context, events: Ready, registered: Ready) -> Result<()> {
+        if !registered.is_writable() {
+            return Ok(());
+        }
+
         if events.is_writable() == false {
             return Ok(self.change_can_send(ctx, false));
         }
@@ -65,12 +106,40 @@ impl<S : AsyncPipeStub> Active<S> {
     }
 
     fn on_recv_progress(&mut self, ctx: &mut dyn Context, progress: Result<Option<Message>>) -> Result<()> {
-        progress.map(|recv| if let Some(msg) = recv { self.on_msg_received(ctx, msg) } )
+        let dropped = self.stub.take_dropped();
+
+        if dropped > 0 {
+            ctx.raise(Event::Dropped(dropped));
+        }
+
+        match progress? {
+            Some(msg) => self.decode_extensions(msg).map(|msg| self.on_msg_received(ctx, msg)),
+            None => Ok(())
+        }
+    }
+    fn encode_extensions(&self, msg: Rc<Message>) -> Rc<Message> {
+        if self.features.both_have(FEATURE_EXTENSIONS) {
+            Rc::new(extension::encode(&msg))
+        } else {
+            msg
+        }
+    }
+    fn decode_extensions(&self, msg: Message) -> Result<Message> {
+        if self.features.both_have(FEATURE_EXTENSIONS) {
+            extension::decode(msg)
+        } else {
+            Ok(msg)
+        }
     }
     fn on_msg_received(&mut self, ctx: &mut dyn Context, msg: Message) {
         ctx.raise(Event::Received(msg));
+        self.check_flushed(ctx);
     }
-    fn readable_changed(&mut self, ctx: &mut dyn Context, events: Ready) -> Result<()> {
+    fn readable_changed(&mut self, ctx: &mut dyn Context, events: Ready, registered: Ready) -> Result<()> {
+        if !registered.is_readable() {
+            return Ok(());
+        }
+
         if events.is_readable() == false {
             return Ok(self.change_can_recv(ctx, false));
         }
@@ -93,6 +162,16 @@ impl<S : AsyncPipeStub> Active<S> {
         }
     }
 
+    fn has_pending_operation(&self) -> bool {
+        self.stub.has_pending_send() || self.stub.has_pending_recv()
+    }
+    fn check_flushed(&mut self, ctx: &mut dyn Context) {
+        if self.flushing && !self.has_pending_operation() {
+            self.flushing = false;
+            ctx.raise(Event::Flushed);
+        }
+    }
+
     fn hang_up_changed(&mut self, hup: bool) -> Result<()> {
         if hup {
             self.can_send_msg = false;
@@ -115,9 +194,20 @@ fn interest() -> Ready {
 impl<S : AsyncPipeStub + 'static> PipeState<S> for Active<S> {
     fn name(&self) -> &'static str {"Active"}
 
+    fn is_active(&self) -> bool {
+        true
+    }
+
     fn enter(&mut self, ctx: &mut dyn Context) {
-        ctx.reregister(self.stub.deref(), interest(), PollOpt::level());
-        ctx.raise(Event::Opened);
+        self.registered = self.desired_interest();
+        ctx.reregister(self.stub.deref(), self.registered, PollOpt::level());
+        ctx.raise(Event::Opened(self.peer_proto_id, self.features));
+    }
+    fn addresses(&self) -> Addresses {
+        self.stub.addresses()
+    }
+    fn peer_cred(&self) -> Result<PeerCred> {
+        self.stub.peer_cred()
     }
     fn close(self: Box<Self>, ctx: &mut dyn Context) -> Box<dyn PipeState<S>> {
         ctx.deregister(self.stub.deref());
@@ -127,9 +217,14 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for Active<S> {
     fn send(mut self: Box<Self>, ctx: &mut dyn Context, msg: Rc<Message>) -> Box<dyn PipeState<S>> {
         self.can_send_msg = false;
 
+        let msg = self.encode_extensions(msg);
         let progress = self.stub.start_send(msg);
         let res = self.on_send_progress(ctx, progress);
 
+        if res.is_ok() {
+            self.check_interest(ctx);
+        }
+
         no_transition_if_ok(self, ctx, res)
     }
     fn recv(mut self: Box<Self>, ctx: &mut dyn Context) -> Box<dyn PipeState<S>> {
@@ -138,15 +233,28 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for Active<S> {
         let progress = self.stub.start_recv();
         let res = self.on_recv_progress(ctx, progress);
 
+        if res.is_ok() {
+            self.check_interest(ctx);
+        }
+
         no_transition_if_ok(self, ctx, res)
     }
+    fn flush(&mut self, ctx: &mut dyn Context) {
+        self.flushing = true;
+        self.check_flushed(ctx);
+    }
     fn ready(mut self: Box<Self>, ctx: &mut dyn Context, events: Ready) -> Box<dyn PipeState<S>> {
+        let registered = self.registered;
         let res = 
-            self.readable_changed(ctx, events).and_then(|_|
-            self.writable_changed(ctx, events).and_then(|_| 
+            self.readable_changed(ctx, events, registered).and_then(|_|
+            self.writable_changed(ctx, events, registered).and_then(|_| 
             self.hang_up_changed(UnixReady::from(events).is_hup()))
         );
 
+        if res.is_ok() {
+            self.check_interest(ctx);
+        }
+
         no_transition_if_ok(self, ctx, res)
     }
 }
@@ -171,7 +279,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let mut state = Box::new(Active::new(stub));
+        let mut state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
 
         state.enter(&mut ctx);
@@ -190,7 +298,7 @@ mod tests {
         assert_eq!(1, ctx.get_raised_events().len());
         let evt = &ctx.get_raised_events()[0];
         let is_opened = match *evt {
-            pipe::Event::Opened => true,
+            pipe::Event::Opened(..) => true,
             _ => false,
         };
 
@@ -200,7 +308,7 @@ mod tests {
     #[test]
     fn close_should_deregister_and_cause_a_transition_to_dead() {
         let stub = TestStepStream::new();
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let new_state = state.close(&mut ctx);
 
@@ -216,7 +324,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let payload = vec!(66, 65, 67);
         let msg = Rc::new(Message::from_body(payload));
@@ -238,7 +346,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
 
         sensor.borrow_mut().set_start_send_result(Some(false));
@@ -264,12 +372,60 @@ mod tests {
         assert!(is_sent);
     }
 
+    #[test]
+    fn flush_without_pending_operation_should_raise_an_event() {
+        let stub = TestStepStream::new();
+        let mut state = Box::new(Active::new(stub, 0, Features::default()));
+        let mut ctx = TestPipeContext::new();
+
+        state.flush(&mut ctx);
+
+        assert_eq!(1, ctx.get_raised_events().len());
+        let is_flushed = match ctx.get_raised_events()[0] {
+            pipe::Event::Flushed => true,
+            _ => false,
+        };
+        assert!(is_flushed);
+    }
+
+    #[test]
+    fn flush_with_pending_send_should_raise_an_event_once_sent() {
+        let sensor_srv = TestStepStreamSensor::new();
+        let sensor = Rc::new(RefCell::new(sensor_srv));
+        let stub = TestStepStream::with_sensor(sensor.clone());
+        let state = Box::new(Active::new(stub, 0, Features::default()));
+        let mut ctx = TestPipeContext::new();
+
+        sensor.borrow_mut().set_start_send_result(Some(false));
+        let msg = Rc::new(Message::from_body(vec!(66, 65, 67)));
+        let mut new_state = state.send(&mut ctx, msg);
+
+        new_state.flush(&mut ctx);
+        assert_eq!(0, ctx.get_raised_events().len());
+
+        sensor.borrow_mut().set_resume_send_result(Some(true));
+        let new_state = new_state.ready(&mut ctx, mio::Ready::writable());
+
+        assert_eq!("Active", new_state.name());
+        assert_eq!(2, ctx.get_raised_events().len());
+        let is_sent = match ctx.get_raised_events()[0] {
+            pipe::Event::Sent => true,
+            _ => false,
+        };
+        let is_flushed = match ctx.get_raised_events()[1] {
+            pipe::Event::Flushed => true,
+            _ => false,
+        };
+        assert!(is_sent);
+        assert!(is_flushed);
+    }
+
     #[test]
     fn when_writable_should_raise_an_event() {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let events = mio::Ready::writable();
         let new_state = state.ready(&mut ctx, events);
@@ -290,7 +446,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let events = mio::Ready::writable();
         let new_state = state.ready(&mut ctx, events);
@@ -302,12 +458,58 @@ mod tests {
         assert_eq!(1, ctx.get_raised_events().len());
     }
 
+    #[test]
+    fn when_writable_is_reported_writable_interest_is_dropped() {
+        let stub = TestStepStream::new();
+        let mut state = Box::new(Active::new(stub, 0, Features::default()));
+        let mut ctx = TestPipeContext::new();
+
+        state.enter(&mut ctx);
+        let new_state = state.ready(&mut ctx, mio::Ready::writable());
+
+        assert_eq!("Active", new_state.name());
+        assert_eq!(2, ctx.get_reregistrations().len());
+        let (ref interest, _) = ctx.get_reregistrations()[1];
+        assert!(interest.is_readable());
+        assert!(!interest.is_writable());
+
+        let new_state = new_state.ready(&mut ctx, mio::Ready::readable());
+        assert_eq!("Active", new_state.name());
+        assert_eq!(3, ctx.get_raised_events().len());
+        let is_can_send_kept = ctx.get_raised_events().iter().all(|evt| match *evt {
+            pipe::Event::CanSend(x) => x,
+            _ => true,
+        });
+        assert!(is_can_send_kept);
+    }
+
+    #[test]
+    fn when_send_is_postponed_writable_interest_is_restored() {
+        let sensor_srv = TestStepStreamSensor::new();
+        let sensor = Rc::new(RefCell::new(sensor_srv));
+        let stub = TestStepStream::with_sensor(sensor.clone());
+        let mut state = Box::new(Active::new(stub, 0, Features::default()));
+        let mut ctx = TestPipeContext::new();
+
+        state.enter(&mut ctx);
+        let new_state = state.ready(&mut ctx, mio::Ready::writable());
+
+        sensor.borrow_mut().set_start_send_result(Some(false));
+        let msg = Rc::new(Message::from_body(vec!(66, 65, 67)));
+        let new_state = new_state.send(&mut ctx, msg);
+
+        assert_eq!("Active", new_state.name());
+        assert_eq!(3, ctx.get_reregistrations().len());
+        let (ref interest, _) = ctx.get_reregistrations()[2];
+        assert!(interest.is_writable());
+    }
+
     #[test]
     fn when_writable_hup_should_not_raise_an_event_and_transition_to_dead() {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let hup = UnixReady::hup();
         let writable = mio::Ready::writable();
@@ -330,7 +532,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let payload = vec!(66, 65, 67);
         let msg = Message::from_body(payload);
@@ -354,7 +556,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let payload = vec!(66, 65, 67);
         let msg = Message::from_body(payload);
@@ -384,7 +586,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let events = mio::Ready::readable();
         let new_state = state.ready(&mut ctx, events);
@@ -405,7 +607,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let readable = mio::Ready::readable();
         let hup = UnixReady::hup();
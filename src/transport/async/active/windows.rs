@@ -13,20 +13,28 @@ use core::Message;
 use transport::async::stub::*;
 use transport::async::state::*;
 use transport::async::dead::Dead; 
+use transport::async::extension;
 use transport::pipe::{Event, Context};
+use transport::{Addresses, PeerCred, Features, FEATURE_EXTENSIONS};
 
 pub struct Active<S> {
     stub: S,
     can_send_msg: bool,
-    can_recv_msg: bool
+    can_recv_msg: bool,
+    flushing: bool,
+    peer_proto_id: u16,
+    features: Features
 }
 
 impl<S : AsyncPipeStub> Active<S> {
-    pub fn new(s: S) -> Active<S> {
+    pub fn new(s: S, peer_pid: u16, negotiated: Features) -> Active<S> {
         Active {
             stub: s,
             can_send_msg: false,
-            can_recv_msg: false
+            can_recv_msg: false,
+            flushing: false,
+            peer_proto_id: peer_pid,
+            features: negotiated
         }
     }
     
@@ -43,6 +51,7 @@ impl<S : AsyncPipeStub> Active<S> {
     }
     fn on_msg_sent(&mut self, ctx: &mut dyn Context) {
         self.raise_and_resync_readiness(ctx, Event::Sent);
+        self.check_flushed(ctx);
     }
     fn writable_changed(&mut self, ctx: &mut dyn Context, events: Ready) -> Result<()> {
         if events.is_writable() == false {
@@ -65,10 +74,34 @@ impl<S : AsyncPipeStub> Active<S> {
     }
 
     fn on_recv_progress(&mut self, ctx: &mut dyn Context, progress: Result<Option<Message>>) -> Result<()> {
-        progress.map(|recv| if let Some(msg) = recv { self.on_msg_received(ctx, msg) } )
+        let dropped = self.stub.take_dropped();
+
+        if dropped > 0 {
+            ctx.raise(Event::Dropped(dropped));
+        }
+
+        match progress? {
+            Some(msg) => self.decode_extensions(msg).map(|msg| self.on_msg_received(ctx, msg)),
+            None => Ok(())
+        }
+    }
+    fn encode_extensions(&self, msg: Rc<Message>) -> Rc<Message> {
+        if self.features.both_have(FEATURE_EXTENSIONS) {
+            Rc::new(extension::encode(&msg))
+        } else {
+            msg
+        }
+    }
+    fn decode_extensions(&self, msg: Message) -> Result<Message> {
+        if self.features.both_have(FEATURE_EXTENSIONS) {
+            extension::decode(msg)
+        } else {
+            Ok(msg)
+        }
     }
     fn on_msg_received(&mut self, ctx: &mut dyn Context, msg: Message) {
         self.raise_and_resync_readiness(ctx, Event::Received(msg));
+        self.check_flushed(ctx);
     }
     fn readable_changed(&mut self, ctx: &mut dyn Context, events: Ready) -> Result<()> {
         if events.is_readable() == false {
@@ -88,13 +121,34 @@ impl<S : AsyncPipeStub> Active<S> {
             ctx.raise(Event::CanRecv(can_recv));
         }
     }
+
+    fn has_pending_operation(&self) -> bool {
+        self.stub.has_pending_send() || self.stub.has_pending_recv()
+    }
+    fn check_flushed(&mut self, ctx: &mut dyn Context) {
+        if self.flushing && !self.has_pending_operation() {
+            self.flushing = false;
+            ctx.raise(Event::Flushed);
+        }
+    }
 }
 
 impl<S : AsyncPipeStub + 'static> PipeState<S> for Active<S> {
     fn name(&self) -> &'static str {"Active"}
 
+    fn is_active(&self) -> bool {
+        true
+    }
+
     fn enter(&mut self, ctx: &mut dyn Context) {
-        self.raise_and_resync_readiness(ctx, Event::Opened);
+        let evt = Event::Opened(self.peer_proto_id, self.features);
+        self.raise_and_resync_readiness(ctx, evt);
+    }
+    fn addresses(&self) -> Addresses {
+        self.stub.addresses()
+    }
+    fn peer_cred(&self) -> Result<PeerCred> {
+        self.stub.peer_cred()
     }
     fn close(self: Box<Self>, ctx: &mut dyn Context) -> Box<PipeState<S>> {
         ctx.deregister(self.stub.deref());
@@ -102,6 +156,7 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for Active<S> {
         Box::new(Dead)
     }
     fn send(mut self: Box<Self>, ctx: &mut dyn Context, msg: Rc<Message>) -> Box<PipeState<S>> {
+        let msg = self.encode_extensions(msg);
         let progress = self.stub.start_send(msg);
         let res = self.on_send_progress(ctx, progress);
 
@@ -117,6 +172,10 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for Active<S> {
 
         no_transition_if_ok(self, ctx, res)
     }
+    fn flush(&mut self, ctx: &mut dyn Context) {
+        self.flushing = true;
+        self.check_flushed(ctx);
+    }
     fn ready(mut self: Box<Self>, ctx: &mut dyn Context, events: Ready) -> Box<PipeState<S>> {
         let res = 
             self.readable_changed(ctx, events).and_then(|_|
@@ -146,7 +205,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let mut state = Box::new(Active::new(stub));
+        let mut state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
 
         state.enter(&mut ctx);
@@ -165,7 +224,7 @@ mod tests {
         assert_eq!(1, ctx.get_raised_events().len());
         let evt = &ctx.get_raised_events()[0];
         let is_opened = match *evt {
-            pipe::Event::Opened => true,
+            pipe::Event::Opened(..) => true,
             _ => false,
         };
 
@@ -175,7 +234,7 @@ mod tests {
     #[test]
     fn close_should_deregister_and_cause_a_transition_to_dead() {
         let stub = TestStepStream::new();
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let new_state = state.close(&mut ctx);
 
@@ -191,7 +250,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let payload = vec!(66, 65, 67);
         let msg = Rc::new(Message::from_body(payload));
@@ -214,7 +273,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
 
         sensor.borrow_mut().set_start_send_result(Some(false));
@@ -246,7 +305,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let events = mio::Ready::writable();
         let new_state = state.ready(&mut ctx, events);
@@ -267,7 +326,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let events = mio::Ready::writable();
         let new_state = state.ready(&mut ctx, events);
@@ -284,7 +343,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let payload = vec!(66, 65, 67);
         let msg = Message::from_body(payload);
@@ -308,7 +367,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let payload = vec!(66, 65, 67);
         let msg = Message::from_body(payload);
@@ -338,7 +397,7 @@ mod tests {
         let sensor_srv = TestStepStreamSensor::new();
         let sensor = Rc::new(RefCell::new(sensor_srv));
         let stub = TestStepStream::with_sensor(sensor.clone());
-        let state = Box::new(Active::new(stub));
+        let state = Box::new(Active::new(stub, 0, Features::default()));
         let mut ctx = TestPipeContext::new();
         let events = mio::Ready::readable();
         let new_state = state.ready(&mut ctx, events);
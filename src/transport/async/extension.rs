@@ -0,0 +1,273 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+//! Extension block written at the start of each frame sent on a pipe whose both sides advertised
+//! the `FEATURE_EXTENSIONS` feature, carrying the fields of the message that are not part of its payload.
+//! The block is a flags byte telling which fields follow, each one being 8 bytes long, big-endian:
+//! the deadline, in milliseconds since the unix epoch, then the dedup id, then the hop count.
+//! The reply-to token comes last, as its length on 2 bytes big-endian followed by the token itself.
+//! The block is removed by the receiving pipe, before the protocol sees the message.
+
+use std::io::Result;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use byteorder::{ BigEndian, ByteOrder };
+
+use core::Message;
+use io_error::*;
+
+const FLAG_DEADLINE: u8 = 0x01;
+const FLAG_DEDUP_ID: u8 = 0x02;
+const FLAG_HOP_COUNT: u8 = 0x04;
+const FLAG_REPLY_TO: u8 = 0x08;
+const FIXED_FLAGS: u8 = FLAG_DEADLINE | FLAG_DEDUP_ID | FLAG_HOP_COUNT;
+const KNOWN_FLAGS: u8 = FIXED_FLAGS | FLAG_REPLY_TO;
+
+/// Creates the message actually sent, the extension block being prepended to the header.
+/// A prewrapped frame is unwrapped, its payload becoming the body.
+pub fn encode(msg: &Message) -> Message {
+    let mut flags = 0;
+    let mut block = vec![0u8];
+
+    if let Some(deadline) = msg.get_deadline() {
+        write_field(&mut block, to_millis(deadline));
+        flags |= FLAG_DEADLINE;
+    }
+
+    if let Some(id) = msg.get_dedup_id() {
+        write_field(&mut block, id);
+        flags |= FLAG_DEDUP_ID;
+    }
+
+    if msg.get_hop_count() > 0 {
+        write_field(&mut block, msg.get_hop_count() as u64);
+        flags |= FLAG_HOP_COUNT;
+    }
+
+    if let Some(token) = msg.reply_to() {
+        let mut len = [0u8; 2];
+
+        BigEndian::write_u16(&mut len, token.len() as u16);
+        block.extend_from_slice(&len);
+        block.extend_from_slice(token);
+        flags |= FLAG_REPLY_TO;
+    }
+
+    block[0] = flags;
+
+    match msg.get_frame() {
+        Some(frame) => Message::from_header_and_body(block, frame[8..].to_vec()),
+        None => {
+            block.extend_from_slice(msg.get_header());
+
+            Message::from_header_and_body(block, msg.get_body().to_vec())
+        }
+    }
+}
+
+/// Removes the extension block from the start of the received message, setting the fields it holds.
+pub fn decode(msg: Message) -> Result<Message> {
+    let (header, mut body) = msg.split();
+    let flags = match body.first() {
+        Some(&flags) if flags & !KNOWN_FLAGS == 0 => flags,
+        Some(_) => return Err(invalid_data_io_error("unknown extension field")),
+        None => return Err(invalid_data_io_error("missing extension block"))
+    };
+    let fixed_len = 1 + 8 * ((flags & FIXED_FLAGS).count_ones() as usize);
+    let block_len = if flags & FLAG_REPLY_TO != 0 {
+        fixed_len + 2 + reply_to_len(&body, fixed_len)?
+    } else {
+        fixed_len
+    };
+
+    if body.len() < block_len {
+        return Err(invalid_data_io_error("truncated extension block"));
+    }
+
+    let mut fields = body[1..fixed_len].chunks(8).map(BigEndian::read_u64);
+    let deadline = if flags & FLAG_DEADLINE != 0 {
+        fields.next().map(from_millis)
+    } else {
+        None
+    };
+    let dedup_id = if flags & FLAG_DEDUP_ID != 0 {
+        fields.next()
+    } else {
+        None
+    };
+    let hop_count = if flags & FLAG_HOP_COUNT != 0 {
+        fields.next().unwrap_or(0) as usize
+    } else {
+        0
+    };
+    let reply_to = if flags & FLAG_REPLY_TO != 0 {
+        Some(body[fixed_len + 2..block_len].to_vec())
+    } else {
+        None
+    };
+
+    body.drain(0..block_len);
+
+    let mut decoded = Message::from_header_and_body(header, body);
+
+    decoded.set_deadline(deadline);
+    decoded.set_dedup_id(dedup_id);
+    decoded.set_hop_count(hop_count);
+
+    if let Some(token) = reply_to {
+        decoded.set_reply_to(&token)?;
+    }
+
+    Ok(decoded)
+}
+
+fn reply_to_len(body: &[u8], offset: usize) -> Result<usize> {
+    if body.len() < offset + 2 {
+        Err(invalid_data_io_error("truncated extension block"))
+    } else {
+        Ok(BigEndian::read_u16(&body[offset..offset + 2]) as usize)
+    }
+}
+
+fn write_field(block: &mut Vec<u8>, value: u64) {
+    let mut field = [0u8; 8];
+
+    BigEndian::write_u64(&mut field, value);
+    block.extend_from_slice(&field);
+}
+
+fn to_millis(time: SystemTime) -> u64 {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(elapsed) => elapsed.as_secs() * 1_000 + u64::from(elapsed.subsec_millis()),
+        Err(_) => 0
+    }
+}
+
+fn from_millis(millis: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_millis(millis)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use core::Message;
+
+    use super::*;
+
+    fn transmit(msg: &Message) -> Message {
+        let (header, body) = encode(msg).split();
+
+        Message::from_body([header, body].concat())
+    }
+
+    #[test]
+    fn decode_reads_the_deadline_that_encode_wrote() {
+        let deadline = UNIX_EPOCH + Duration::from_millis(1_500_000_000_123);
+        let mut msg = Message::from_header_and_body(vec![0, 0, 0, 1], vec![65]);
+
+        msg.set_deadline(Some(deadline));
+
+        let received = decode(transmit(&msg)).unwrap();
+
+        assert_eq!(Some(deadline), received.get_deadline());
+        assert_eq!(&[0, 0, 0, 1, 65], received.get_body());
+    }
+
+    #[test]
+    fn decode_reads_the_dedup_id_after_the_deadline() {
+        let mut msg = Message::from_body(vec![65]);
+
+        msg.set_deadline(Some(UNIX_EPOCH + Duration::from_millis(42)));
+        msg.set_dedup_id(Some(7));
+
+        let received = decode(transmit(&msg)).unwrap();
+
+        assert_eq!(Some(7), received.get_dedup_id());
+        assert_eq!(Some(UNIX_EPOCH + Duration::from_millis(42)), received.get_deadline());
+        assert_eq!(&[65], received.get_body());
+    }
+
+    #[test]
+    fn decode_reads_the_hop_count_after_the_other_fields() {
+        let mut msg = Message::from_header_and_body(vec![0, 0, 0, 1], vec![65]);
+
+        msg.set_dedup_id(Some(7));
+        msg.set_hop_count(3);
+
+        let received = decode(transmit(&msg)).unwrap();
+
+        assert_eq!(3, received.get_hop_count());
+        assert_eq!(Some(7), received.get_dedup_id());
+        assert_eq!(&[0, 0, 0, 1, 65], received.get_body());
+    }
+
+    #[test]
+    fn decode_reads_the_reply_to_token_after_the_other_fields() {
+        let mut msg = Message::from_body(vec![65]);
+
+        msg.set_dedup_id(Some(7));
+        msg.set_reply_to(b"tcp://127.0.0.1:5454").unwrap();
+
+        let mut received = decode(transmit(&msg)).unwrap();
+
+        assert_eq!(Some(7), received.get_dedup_id());
+        assert_eq!(Some(&b"tcp://127.0.0.1:5454"[..]), received.reply_to());
+        assert_eq!(Some(b"tcp://127.0.0.1:5454".to_vec()), received.take_reply_to());
+        assert_eq!(None, received.reply_to());
+        assert_eq!(&[65], received.get_body());
+    }
+
+    #[test]
+    fn set_reply_to_replaces_the_previous_token_and_leaves_the_body_alone() {
+        let mut msg = Message::from_body(vec![65]);
+
+        msg.set_reply_to(b"a").unwrap();
+        msg.set_reply_to(b"b").unwrap();
+
+        assert_eq!(Some(&b"b"[..]), msg.reply_to());
+        assert_eq!(&[65], msg.get_body());
+        assert!(msg.set_reply_to(&vec![0; 65536]).is_err());
+    }
+
+    #[test]
+    fn payload_looking_like_a_reply_to_field_is_kept() {
+        let payload = vec![0xFF, b'R', b'T', 0, 1, 65];
+        let mut received = decode(transmit(&Message::from_body(payload.clone()))).unwrap();
+
+        assert_eq!(None, received.take_reply_to());
+        assert_eq!(&payload[..], received.get_body());
+    }
+
+    #[test]
+    fn short_message_without_fields_is_left_untouched() {
+        let received = decode(transmit(&Message::from_body(vec![65]))).unwrap();
+
+        assert_eq!(None, received.get_deadline());
+        assert_eq!(0, received.get_hop_count());
+        assert_eq!(&[65], received.get_body());
+    }
+
+    #[test]
+    fn prewrapped_frame_is_unwrapped_behind_the_block() {
+        let frame: Arc<[u8]> = vec![0, 0, 0, 0, 0, 0, 0, 2, 65, 66].into();
+        let received = decode(transmit(&Message::from_frame(frame))).unwrap();
+
+        assert_eq!(&[65, 66], received.get_body());
+    }
+
+    #[test]
+    fn decode_rejects_a_truncated_block() {
+        let err = decode(Message::from_body(vec![FLAG_DEADLINE, 0, 0])).unwrap_err();
+
+        assert_eq!(::std::io::ErrorKind::InvalidData, err.kind());
+        assert!(decode(Message::new()).is_err());
+        assert!(decode(Message::from_body(vec![0x80])).is_err());
+        assert!(decode(Message::from_body(vec![FLAG_REPLY_TO, 0])).is_err());
+        assert!(decode(Message::from_body(vec![FLAG_REPLY_TO, 0, 2, 65])).is_err());
+    }
+}
@@ -4,7 +4,7 @@
 // or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
 // This file may not be copied, modified, or distributed except according to those terms.
 
-use std::io::Result;
+use std::io::{Result, ErrorKind};
 
 use mio::{Ready, PollOpt};
 
@@ -12,31 +12,52 @@ use transport::async::stub::*;
 use transport::async::state::*;
 use transport::async::active::Active; 
 use transport::async::dead::Dead; 
-use transport::pipe::Context;
+use transport::pipe::{Event, Context};
+use transport::Features;
 
 pub struct HandshakeTx<S : AsyncPipeStub + 'static> {
     stub: S,
-    proto_ids: (u16, u16)
+    proto_ids: (u16, u16),
+    busy: bool
 }
 
 impl<S : AsyncPipeStub> HandshakeTx<S> {
     pub fn new(s: S, pids: (u16, u16)) -> HandshakeTx<S> {
         HandshakeTx { 
             stub: s,
-            proto_ids: pids
+            proto_ids: pids,
+            busy: false
+        }
+    }
+
+    /// Creates the state of a rejected pipe, sending the busy signal instead of the handshake.
+    /// The peer handshake is still read before the pipe is closed, 
+    /// so the busy signal is not lost in a connection reset.
+    pub fn busy(s: S, pids: (u16, u16)) -> HandshakeTx<S> {
+        HandshakeTx { 
+            stub: s,
+            proto_ids: pids,
+            busy: true
         }
     }
 
     fn send_handshake(&mut self) -> Result<()> {
         let pids = self.proto_ids;
 
-        self.stub.send_handshake(pids)
+        if self.busy {
+            self.stub.send_busy_handshake(pids)
+        } else {
+            self.stub.send_handshake(pids)
+        }
     }
 }
 
 impl<S : AsyncPipeStub> Into<HandshakeRx<S>> for HandshakeTx<S> {
     fn into(self) -> HandshakeRx<S> {
-        HandshakeRx::new(self.stub, self.proto_ids)
+        let mut rx = HandshakeRx::new(self.stub, self.proto_ids);
+
+        rx.busy = self.busy;
+        rx
     }
 }
 
@@ -58,6 +79,16 @@ impl<S : AsyncPipeStub> PipeState<S> for HandshakeTx<S> {
         if events.is_writable() {
             let res = self.send_handshake();
 
+            // the stream did not take the whole handshake, the rest is written once it is writable again
+            if let Err(ref e) = res {
+                if e.kind() == ErrorKind::WouldBlock {
+                    return self;
+                }
+            }
+            if self.busy && res.is_err() {
+                return close_rejected(self.stub, ctx);
+            }
+
             transition_if_ok::<HandshakeTx<S>, HandshakeRx<S>, S>(self, ctx, res)
         } else {
             self
@@ -67,27 +98,52 @@ impl<S : AsyncPipeStub> PipeState<S> for HandshakeTx<S> {
 
 pub struct HandshakeRx<S> {
     stub: S,
-    proto_ids: (u16, u16)
+    proto_ids: (u16, u16),
+    peer_proto_id: u16,
+    features: Features,
+    busy: bool,
+    registered: Ready
 }
 
 impl<S: AsyncPipeStub> HandshakeRx<S> {
     pub fn new(s: S, pids: (u16, u16)) -> HandshakeRx<S> {
         HandshakeRx {
             stub: s,
-            proto_ids: pids
+            proto_ids: pids,
+            peer_proto_id: pids.1,
+            features: Features::default(),
+            busy: false,
+            registered: Ready::readable()
+        }
+    }
+
+    /// Waits for the stream to be writable as well while the stub has some handshake bytes left to write.
+    fn check_interest(&mut self, ctx: &mut dyn Context) {
+        let desired = if self.stub.has_pending_send() {
+            Ready::readable() | Ready::writable()
+        } else {
+            Ready::readable()
+        };
+
+        if self.registered != desired {
+            self.registered = desired;
+            ctx.reregister(self.stub.deref(), desired, PollOpt::level());
         }
     }
 
     fn recv_handshake(&mut self) -> Result<()> {
         let pids = self.proto_ids;
+        let (peer_proto_id, features) = self.stub.recv_handshake(pids)?;
 
-        self.stub.recv_handshake(pids)
+        self.peer_proto_id = peer_proto_id;
+        self.features = features;
+        Ok(())
     }
 }
 
 impl<S : AsyncPipeStub> Into<Active<S>> for HandshakeRx<S> {
     fn into(self) -> Active<S> {
-        Active::new(self.stub)
+        Active::new(self.stub, self.peer_proto_id, self.features)
     }
 }
 
@@ -96,7 +152,7 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for HandshakeRx<S> {
     fn name(&self) -> &'static str {"HandshakeRx"}
 
     fn enter(&mut self, ctx: &mut dyn Context) {
-        ctx.reregister(self.stub.deref(), Ready::readable(), PollOpt::level());
+        ctx.reregister(self.stub.deref(), self.registered, PollOpt::level());
     }
     fn close(self: Box<Self>, ctx: &mut dyn Context) -> Box<dyn PipeState<S>> {
         ctx.deregister(self.stub.deref());
@@ -104,9 +160,19 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for HandshakeRx<S> {
         Box::new(Dead)
     }
     fn ready(mut self: Box<Self>, ctx: &mut dyn Context, events: Ready) -> Box<dyn PipeState<S>> {
-        if events.is_readable() {
+        if events.is_readable() || events.is_writable() {
             let res = self.recv_handshake();
-            
+
+            if let Err(ref e) = res {
+                if e.kind() == ErrorKind::WouldBlock {
+                    self.check_interest(ctx);
+                    return self;
+                }
+            }
+            if self.busy {
+                return close_rejected(self.stub, ctx);
+            }
+
             transition_if_ok::<HandshakeRx<S>, Active<S>, S>(self, ctx, res)
         } else {
             self
@@ -114,6 +180,13 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for HandshakeRx<S> {
     }
 }
 
+fn close_rejected<S : AsyncPipeStub + 'static>(stub: S, ctx: &mut dyn Context) -> Box<dyn PipeState<S>> {
+    ctx.deregister(stub.deref());
+    ctx.raise(Event::Closed);
+
+    Box::new(Dead)
+}
+
 #[cfg(test)]
 mod tests {
     use std::rc::Rc;
@@ -121,6 +194,7 @@ mod tests {
 
     use mio;
 
+    use transport::pipe;
     use transport::tests::*;
     use transport::async::state::*;
     use transport::async::tests::*;
@@ -177,6 +251,28 @@ mod tests {
         assert_eq!("HandshakeRx", new_state.name());
     }
 
+    #[test]
+    fn on_writable_the_rest_of_a_blocked_handshake_should_be_sent() {
+        let sensor_srv = TestStepStreamSensor::new();
+        let sensor = Rc::new(RefCell::new(sensor_srv));
+        let mut stub = TestStepStream::with_sensor(sensor.clone());
+        let mut ctx = TestPipeContext::new();
+        let events = mio::Ready::writable();
+
+        stub.block_handshake();
+
+        let state = Box::new(HandshakeTx::new(stub, (4, 2)));
+        let state = state.ready(&mut ctx, events);
+
+        assert_eq!("HandshakeTx", state.name());
+        assert_eq!(0, ctx.get_raised_events().len());
+
+        let state = state.ready(&mut ctx, events);
+
+        assert_eq!(2, sensor.borrow().get_sent_handshakes().len());
+        assert_eq!("HandshakeRx", state.name());
+    }
+
     #[test]
     fn on_enter_rx_should_reregister() {
         let stub = TestStepStream::new();
@@ -211,6 +307,28 @@ mod tests {
         assert_eq!("Dead", new_state.name());
     }
 
+    #[test]
+    fn rx_should_wait_for_writable_while_the_handshake_is_being_written() {
+        let sensor_srv = TestStepStreamSensor::new();
+        let sensor = Rc::new(RefCell::new(sensor_srv));
+        let mut stub = TestStepStream::with_sensor(sensor.clone());
+        let mut ctx = TestPipeContext::new();
+
+        stub.block_handshake();
+
+        let state = Box::new(HandshakeRx::new(stub, (6, 6)));
+        let state = state.ready(&mut ctx, mio::Ready::readable());
+
+        assert_eq!("HandshakeRx", state.name());
+        assert_eq!(1, ctx.get_reregistrations().len());
+        assert_eq!(mio::Ready::readable() | mio::Ready::writable(), ctx.get_reregistrations()[0].0);
+
+        let state = state.ready(&mut ctx, mio::Ready::writable());
+
+        assert_eq!(2, sensor.borrow().get_received_handshakes());
+        assert_eq!("Active", state.name());
+    }
+
     #[test]
     fn readable_the_handshake_should_be_received() {
         let sensor_srv = TestStepStreamSensor::new();
@@ -225,4 +343,31 @@ mod tests {
         assert_eq!(1, sensor.borrow().get_received_handshakes());
         assert_eq!("Active", new_state.name());
     }
+
+    #[test]
+    fn rejected_pipe_should_send_the_busy_signal_then_read_the_peer_handshake_and_close() {
+        let sensor_srv = TestStepStreamSensor::new();
+        let sensor = Rc::new(RefCell::new(sensor_srv));
+        let stub = TestStepStream::with_sensor(sensor.clone());
+        let state = Box::new(HandshakeTx::busy(stub, (4, 2)));
+        let mut ctx = TestPipeContext::new();
+        let state = state.ready(&mut ctx, mio::Ready::writable());
+
+        assert_eq!(1, sensor.borrow().get_sent_busy_handshakes());
+        assert_eq!(0, sensor.borrow().get_sent_handshakes().len());
+        assert_eq!("HandshakeRx", state.name());
+
+        let state = state.ready(&mut ctx, mio::Ready::readable());
+
+        assert_eq!(1, sensor.borrow().get_received_handshakes());
+        assert_eq!(1, ctx.get_deregistrations());
+        assert_eq!("Dead", state.name());
+
+        let raised_events = ctx.get_raised_events();
+        assert_eq!(1, raised_events.len());
+        match raised_events[0] {
+            pipe::Event::Closed => {},
+            _ => panic!("rejected pipe should be closed")
+        }
+    }
 }
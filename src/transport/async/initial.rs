@@ -36,6 +36,12 @@ impl<S : AsyncPipeStub + 'static> PipeState<S> for Initial<S> {
     fn open(self: Box<Self>, ctx: &mut dyn Context) -> Box<dyn PipeState<S>> {
         transition::<Initial<S>, HandshakeTx<S>, S>(self, ctx)
     }
+    fn reject(self: Box<Self>, ctx: &mut dyn Context) -> Box<dyn PipeState<S>> {
+        let mut new_state = HandshakeTx::busy(self.stub, self.proto_ids);
+
+        new_state.enter(ctx);
+        Box::new(new_state)
+    }
 
 }
 
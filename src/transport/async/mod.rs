@@ -12,11 +12,13 @@ mod initial;
 mod handshake;
 mod active;
 mod dead;
+mod extension;
 
 #[cfg(test)]
 mod tests;
 
 use std::rc::Rc;
+use std::time::{Duration, Instant};
 
 use mio;
 
@@ -26,10 +28,13 @@ use transport::pipe::*;
 
 use self::stub::AsyncPipeStub;
 use self::state::PipeState;
+use io_error::*;
 
 pub struct AsyncPipe<S : AsyncPipeStub + 'static> {
 
-    state: Option<Box<dyn PipeState<S>>>
+    state: Option<Box<dyn PipeState<S>>>,
+    created: Instant,
+    handshake_duration: Option<Duration>
 
 }
 
@@ -37,7 +42,11 @@ impl<S : AsyncPipeStub + 'static> AsyncPipe<S> {
     pub fn new(stub: S, pids: (u16, u16)) -> AsyncPipe<S> {
         let initial_state = Box::new(initial::Initial::new(stub, pids));
 
-        AsyncPipe { state: Some(initial_state) }
+        AsyncPipe {
+            state: Some(initial_state),
+            created: Instant::now(),
+            handshake_duration: None
+        }
     }
 
     fn apply<F>(&mut self, ctx: &mut dyn Context, transition: F) 
@@ -47,6 +56,10 @@ impl<S : AsyncPipeStub + 'static> AsyncPipe<S> {
             let new_state = transition(old_state, ctx);
             #[cfg(debug_assertions)] let new_name = new_state.name();
 
+            if new_state.is_active() && self.handshake_duration.is_none() {
+                self.handshake_duration = Some(self.created.elapsed());
+            }
+
             self.state = Some(new_state);
 
             #[cfg(debug_assertions)] debug!("[{:?}] switch from {} to {}", ctx, old_name, new_name);
@@ -75,4 +88,26 @@ impl<S : AsyncPipeStub> pipe::Pipe for AsyncPipe<S> {
     fn recv(&mut self, ctx: &mut dyn Context) {
         self.apply(ctx, |s, ctx| s.recv(ctx))
     }
+
+    fn flush(&mut self, ctx: &mut dyn Context) {
+        if let Some(ref mut state) = self.state {
+            state.flush(ctx)
+        }
+    }
+
+    fn reject(&mut self, ctx: &mut dyn Context) {
+        self.apply(ctx, |s, ctx| s.reject(ctx))
+    }
+
+    fn addresses(&self) -> Addresses {
+        self.state.as_ref().map_or((None, None), |state| state.addresses())
+    }
+
+    fn peer_cred(&self) -> io::Result<PeerCred> {
+        self.state.as_ref().map_or_else(|| Err(not_connected_io_error("pipe is not active")), |state| state.peer_cred())
+    }
+
+    fn handshake_duration(&self) -> Option<Duration> {
+        self.handshake_duration
+    }
 }
@@ -13,6 +13,8 @@ use core::Message;
 use transport::async::stub::*;
 use transport::async::dead::*;
 use transport::pipe::{Event, Context};
+use transport::{Addresses, PeerCred};
+use io_error::*;
 
 pub trait PipeState<S : AsyncPipeStub + 'static> {
 
@@ -25,6 +27,10 @@ pub trait PipeState<S : AsyncPipeStub + 'static> {
         error!("[{:?}] close while {}", ctx, self.name());
         Box::new(Dead)
     }
+    fn reject(self: Box<Self>, ctx: &mut dyn Context) -> Box<dyn PipeState<S>> {
+        error!("[{:?}] reject while {}", ctx, self.name());
+        Box::new(Dead)
+    }
     fn send(self: Box<Self>, ctx: &mut dyn Context, _: Rc<Message>) -> Box<dyn PipeState<S>> {
         error!("[{:?}] send while {}", ctx, self.name());
         Box::new(Dead)
@@ -44,10 +50,22 @@ pub trait PipeState<S : AsyncPipeStub + 'static> {
         error!("[{:?}] ready while {}", ctx, self.name());
         Box::new(Dead)
     }
+    fn flush(&mut self, ctx: &mut dyn Context) {
+        ctx.raise(Event::Flushed);
+    }
     fn enter(&mut self, _: &mut dyn Context) {
     }
     fn leave(&mut self, _: &mut dyn Context) {
     }
+    fn addresses(&self) -> Addresses {
+        (None, None)
+    }
+    fn peer_cred(&self) -> Result<PeerCred> {
+        Err(not_connected_io_error("pipe is not active"))
+    }
+    fn is_active(&self) -> bool {
+        false
+    }
 }
 
 pub fn transition<F, T, S>(mut old_state: Box<F>, ctx: &mut dyn Context) -> Box<T> where
@@ -13,6 +13,7 @@ use byteorder::{ BigEndian, ByteOrder };
 use mio::Evented;
 
 use core::Message;
+use transport::{ProtocolVersions, Features, Addresses, PeerBusy, PeerCred};
 use io_error::*;
 
 pub trait AsyncPipeStub : Sender + Receiver + Handshake + Deref<Target=dyn Evented> {
@@ -20,6 +21,12 @@ pub trait AsyncPipeStub : Sender + Receiver + Handshake + Deref<Target=dyn Event
     fn read_and_write_void(&mut self);
     #[cfg(windows)]
     fn registered(&mut self) {}
+    fn addresses(&self) -> Addresses {
+        (None, None)
+    }
+    fn peer_cred(&self) -> Result<PeerCred> {
+        Err(unsupported_io_error("peer credentials are not supported by this transport"))
+    }
 }
 
 pub trait Sender {
@@ -32,44 +39,112 @@ pub trait Receiver {
     fn start_recv(&mut self) -> Result<Option<Message>>;
     fn resume_recv(&mut self) -> Result<Option<Message>>;
     fn has_pending_recv(&self) -> bool;
+    /// Returns the number of oversized messages discarded since the last call.
+    fn take_dropped(&mut self) -> usize {
+        0
+    }
 }
 
 pub trait Handshake {
     fn send_handshake(&mut self, pids: (u16, u16)) -> Result<()>;
-    fn recv_handshake(&mut self, pids: (u16, u16)) -> Result<()>;
+    /// Returns the protocol id of the peer, along with the features advertised by both sides.
+    fn recv_handshake(&mut self, pids: (u16, u16)) -> Result<(u16, Features)>;
+    /// Sends the handshake telling the peer that its connection is rejected, see `PeerBusy`.
+    fn send_busy_handshake(&mut self, _: (u16, u16)) -> Result<()> {
+        Err(other_io_error("busy signal not supported"))
+    }
+}
+
+pub fn send_and_check_handshake<T:Write>(stream: &mut T, pids: (u16, u16), versions: &ProtocolVersions) -> Result<()> {
+    let (proto_id, _) = pids;
+    let handshake = create_local_handshake(proto_id, versions);
+
+    match stream.write(&handshake)? {
+        8 => Ok(()),
+        _ => Err(other_io_error("failed to send handshake"))
+    }
 }
 
-pub fn send_and_check_handshake<T:Write>(stream: &mut T, pids: (u16, u16)) -> Result<()> {
+pub fn send_busy_handshake<T:Write>(stream: &mut T, pids: (u16, u16), versions: &ProtocolVersions) -> Result<()> {
     let (proto_id, _) = pids;
-    let handshake = create_handshake(proto_id);
+    let handshake = create_busy_handshake(proto_id, versions.advertised);
 
     match stream.write(&handshake)? {
         8 => Ok(()),
-        _ => Err(would_block_io_error("failed to send handshake"))
+        _ => Err(other_io_error("failed to send busy handshake"))
     }
 }
 
-fn create_handshake(protocol_id: u16) -> [u8; 8] {
+pub fn create_handshake(protocol_id: u16, version: u8) -> [u8; 8] {
     // handshake is Zero, 'S', 'P', Version, Proto[2], Rsvd[2]
-    let mut handshake = [0, 83, 80, 0, 0, 0, 0, 0];
+    let mut handshake = [0, 83, 80, version, 0, 0, 0, 0];
     BigEndian::write_u16(&mut handshake[4..6], protocol_id);
     handshake
 }
 
-pub fn recv_and_check_handshake<T:Read>(stream: &mut T, pids: (u16, u16)) -> Result<()> {
-    let mut handshake = [0u8; 8];
+/// Creates the handshake advertising the version and the features of the local side.
+pub fn create_local_handshake(protocol_id: u16, versions: &ProtocolVersions) -> [u8; 8] {
+    let mut handshake = create_handshake(protocol_id, versions.advertised);
+    handshake[7] = versions.features;
+    handshake
+}
 
-    stream.read(&mut handshake).and_then(|_| check_handshake(pids, &handshake))
+/// The busy signal is the regular handshake with the first reserved byte set,
+/// so a peer that does not know about it just sees a bad handshake.
+pub fn create_busy_handshake(protocol_id: u16, version: u8) -> [u8; 8] {
+    let mut handshake = create_handshake(protocol_id, version);
+    handshake[6] = BUSY_FLAG;
+    handshake
 }
 
-fn check_handshake(pids: (u16, u16), handshake: &[u8; 8]) -> Result<()> {
-    let (_, proto_id) = pids;
-    let expected_handshake = create_handshake(proto_id);
+const BUSY_FLAG: u8 = 1;
 
-    if handshake == &expected_handshake {
-        Ok(())
-    } else {
+/// Bytes of the peer handshake received so far.
+/// Reads are bounded to the handshake length so that a message sent right after it
+/// by a fast peer stays in the stream for the active state to consume.
+#[derive(Default)]
+pub struct PartialHandshake {
+    buffer: [u8; 8],
+    read: usize
+}
+
+pub fn recv_and_check_handshake<T:Read>(stream: &mut T, pids: (u16, u16), versions: &ProtocolVersions, partial: &mut PartialHandshake) -> Result<(u16, Features)> {
+    while partial.read < 8 {
+        match stream.read(&mut partial.buffer[partial.read..])? {
+            0 => return Err(eof_io_error("connection closed during handshake")),
+            x => partial.read += x
+        }
+    }
+
+    check_handshake(pids, versions, &partial.buffer)
+}
+
+/// Checks the handshake of the peer, whose last reserved byte holds the features it advertises,
+/// and returns the protocol id it received once checked against the expected peer protocol.
+pub fn check_handshake(pids: (u16, u16), versions: &ProtocolVersions, handshake: &[u8; 8]) -> Result<(u16, Features)> {
+    let (_, expected_proto_id) = pids;
+    let peer_proto_id = BigEndian::read_u16(&handshake[4..6]);
+    let peer_version = handshake[3];
+    let peer_features = handshake[7];
+    let mut well_formed = create_handshake(peer_proto_id, peer_version);
+
+    well_formed[7] = peer_features;
+
+    if handshake == &create_busy_handshake(expected_proto_id, peer_version) {
+        Err(refused_io_error(PeerBusy))
+    } else if !versions.accepts(peer_version) {
+        Err(invalid_data_io_error("received handshake with incompatible version"))
+    } else if handshake != &well_formed {
         Err(invalid_data_io_error("received bad handshake"))
+    } else if peer_proto_id != expected_proto_id {
+        Err(invalid_data_io_error(format!("received handshake of protocol {}, expected {}", peer_proto_id, expected_proto_id)))
+    } else {
+        let features = Features {
+            local: versions.features,
+            peer: peer_features
+        };
+
+        Ok((peer_proto_id, features))
     }
 }
 
@@ -96,6 +171,54 @@ impl<T:Write> WriteBuffer for T {
     }
 }
 
+/// Number of writes in a row that may accept no byte at all before the pipe is considered stalled.
+pub const MAX_ZERO_WRITES: usize = 16;
+
+/// Wraps a stream to retry the writes that made no progress, and to fail once too many of them did in a row.
+/// A writer returning `Ok(0)` instead of `WouldBlock` will not signal it is writable again,
+/// so the send operation would otherwise wait for a progress that never comes.
+pub struct StallGuard<'a, T: 'a> {
+    stream: &'a mut T
+}
+
+impl<'a, T> StallGuard<'a, T> {
+    pub fn new(s: &'a mut T) -> StallGuard<'a, T> {
+        StallGuard { stream: s }
+    }
+
+    /// Applies the specified write to the stream, retrying it as long as it makes no progress
+    /// while there is something to write, up to `MAX_ZERO_WRITES` times.
+    pub fn guard<F>(&mut self, len: usize, mut write: F) -> Result<usize>
+    where F : FnMut(&mut T) -> Result<usize> {
+        for _ in 0..MAX_ZERO_WRITES {
+            match write(self.stream)? {
+                0 if len > 0 => continue,
+                x => return Ok(x)
+            }
+        }
+
+        Err(write_zero_io_error("stalled write"))
+    }
+}
+
+impl<'a, T:Write> Write for StallGuard<'a, T> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        self.guard(buf.len(), |stream| stream.write(buf))
+    }
+    fn flush(&mut self) -> Result<()> {
+        self.stream.flush()
+    }
+}
+
+/// Computes where the write of a buffer should stop, given what has already been written,
+/// so that a single run writes at most `chunk_size` bytes. Zero means unbounded.
+pub fn chunk_end(len: usize, written: usize, chunk_size: usize) -> usize {
+    if chunk_size == 0 {
+        len
+    } else {
+        ::std::cmp::min(len, written + chunk_size)
+    }
+}
 
 pub trait ReadBuffer {
     fn read_buffer(&mut self, buffer: &mut [u8]) -> Result<usize>;
@@ -104,6 +227,9 @@ pub trait ReadBuffer {
 impl<T:Read> ReadBuffer for T {
     fn read_buffer(&mut self, buf: &mut [u8]) -> Result<usize> {
         match self.read(buf) {
+            Ok(0) if !buf.is_empty() => {
+                Err(eof_io_error("connection closed by peer"))
+            },
             Ok(x) => {
                 Ok(x)
             },
@@ -117,3 +243,87 @@ impl<T:Read> ReadBuffer for T {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use super::*;
+
+    #[test]
+    fn handshake_recv_leaves_the_following_message_in_the_stream() {
+        let mut buffer = create_handshake(4, 0).to_vec();
+        buffer.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 3, 65, 66, 67]);
+        let mut stream = io::Cursor::new(buffer);
+        let mut partial = PartialHandshake::default();
+        let (peer_proto_id, _) = recv_and_check_handshake(&mut stream, (2, 4), &ProtocolVersions::default(), &mut partial).
+            expect("handshake should have succeeded");
+        let mut remaining = Vec::new();
+        stream.read_to_end(&mut remaining).unwrap();
+
+        assert_eq!(4, peer_proto_id);
+        assert_eq!(vec![0, 0, 0, 0, 0, 0, 0, 3, 65, 66, 67], remaining);
+    }
+
+    #[test]
+    fn handshake_recv_resumes_after_a_partial_read() {
+        let handshake = create_handshake(4, 0);
+        let mut partial = PartialHandshake::default();
+        let mut head = io::Cursor::new(handshake[..3].to_vec());
+        let mut tail = io::Cursor::new(handshake[3..].to_vec());
+
+        assert!(recv_and_check_handshake(&mut head, (2, 4), &ProtocolVersions::default(), &mut partial).is_err());
+        assert_eq!(4, recv_and_check_handshake(&mut tail, (2, 4), &ProtocolVersions::default(), &mut partial).unwrap().0);
+    }
+
+    #[test]
+    fn handshake_recv_accepts_only_compatible_versions() {
+        let versions = ProtocolVersions { advertised: 1, compatible: vec![2], features: 0 };
+
+        for &(version, accepted) in &[(0, false), (1, true), (2, true), (3, false)] {
+            let mut stream = io::Cursor::new(create_handshake(4, version).to_vec());
+            let mut partial = PartialHandshake::default();
+            let res = recv_and_check_handshake(&mut stream, (2, 4), &versions, &mut partial);
+
+            assert_eq!(accepted, res.is_ok());
+        }
+    }
+
+    #[test]
+    fn handshake_recv_reports_the_features_of_both_sides() {
+        let versions = ProtocolVersions { advertised: 0, compatible: Vec::new(), features: 3 };
+        let mut sent = Vec::new();
+
+        send_and_check_handshake(&mut sent, (4, 2), &versions).unwrap();
+        assert_eq!(3, sent[7]);
+
+        let mut stream = io::Cursor::new(sent);
+        let mut partial = PartialHandshake::default();
+        let (peer_proto_id, features) = recv_and_check_handshake(&mut stream, (2, 4), &ProtocolVersions::default(), &mut partial).unwrap();
+
+        assert_eq!(4, peer_proto_id);
+        assert_eq!(Features { local: 0, peer: 3 }, features);
+        assert!(features.peer_has(2));
+        assert!(!features.both_have(2));
+    }
+
+    #[test]
+    fn handshake_recv_rejects_an_unexpected_protocol() {
+        let mut stream = io::Cursor::new(create_handshake(5, 0).to_vec());
+        let mut partial = PartialHandshake::default();
+        let err = recv_and_check_handshake(&mut stream, (2, 4), &ProtocolVersions::default(), &mut partial).unwrap_err();
+
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert!(err.to_string().contains("protocol 5"), "unexpected error: {}", err);
+    }
+
+    #[test]
+    fn handshake_recv_reports_the_busy_signal() {
+        let mut stream = io::Cursor::new(create_busy_handshake(4, 0).to_vec());
+        let mut partial = PartialHandshake::default();
+        let err = recv_and_check_handshake(&mut stream, (2, 4), &ProtocolVersions::default(), &mut partial).unwrap_err();
+
+        assert_eq!(io::ErrorKind::ConnectionRefused, err.kind());
+        assert_eq!(Some(PeerBusy), PeerBusy::of(&err));
+    }
+}
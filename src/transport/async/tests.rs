@@ -12,11 +12,13 @@ use std::io;
 use mio;
 
 use core::Message;
+use transport::Features;
 use transport::async::*;
 use io_error::*;
 
 pub struct TestStepStreamSensor {
     sent_handshakes: Vec<(u16, u16)>,
+    sent_busy_handshakes: usize,
     received_handshakes: usize,
     start_send_result: Option<bool>,
     resume_send_result: Option<bool>,
@@ -28,6 +30,7 @@ impl TestStepStreamSensor {
     pub fn new() -> TestStepStreamSensor {
         TestStepStreamSensor {
             sent_handshakes: Vec::new(),
+            sent_busy_handshakes: 0,
             received_handshakes: 0,
             start_send_result: Some(true),
             resume_send_result: None,
@@ -44,6 +47,14 @@ impl TestStepStreamSensor {
         self.sent_handshakes.push(sent_handshake);
     }
 
+    pub fn get_sent_busy_handshakes(&self) -> usize {
+        self.sent_busy_handshakes
+    }
+
+    fn push_sent_busy_handshake(&mut self) {
+        self.sent_busy_handshakes += 1;
+    }
+
     pub fn get_received_handshakes(&self) -> usize {
         self.received_handshakes
     }
@@ -89,6 +100,7 @@ pub struct TestStepStream {
     sensor: Rc<RefCell<TestStepStreamSensor>>,
     send_handshake_ok: bool,
     recv_handshake_ok: bool,
+    handshake_blocks: usize,
     pending_send: bool,
     pending_recv: bool
 }
@@ -103,10 +115,22 @@ impl TestStepStream {
             sensor: sensor,
             send_handshake_ok: true,
             recv_handshake_ok: true,
+            handshake_blocks: 0,
             pending_send: false,
             pending_recv: false
         }
     }
+    /// Makes the next handshake operation fail with `WouldBlock`, some of its bytes being left to write.
+    pub fn block_handshake(&mut self) {
+        self.handshake_blocks = 1;
+    }
+    fn handshake_blocked(&mut self) -> bool {
+        self.pending_send = self.handshake_blocks > 0;
+        if self.pending_send {
+            self.handshake_blocks -= 1;
+        }
+        self.pending_send
+    }
 }
 
 impl stub::AsyncPipeStub for TestStepStream {
@@ -136,11 +160,21 @@ impl Deref for TestStepStream {
 impl stub::Handshake for TestStepStream {
     fn send_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
         self.sensor.borrow_mut().push_sent_handshake(pids);
+        if self.handshake_blocked() {
+            return Err(would_block_io_error("test"));
+        }
         if self.send_handshake_ok { Ok(()) } else { Err(other_io_error("test")) }
     }
-    fn recv_handshake(&mut self, _: (u16, u16)) -> io::Result<()> {
+    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<(u16, Features)> {
         self.sensor.borrow_mut().push_received_handshake();
-        if self.recv_handshake_ok { Ok(()) } else { Err(other_io_error("test")) }
+        if self.handshake_blocked() {
+            return Err(would_block_io_error("test"));
+        }
+        if self.recv_handshake_ok { Ok((pids.1, Features::default())) } else { Err(other_io_error("test")) }
+    }
+    fn send_busy_handshake(&mut self, _: (u16, u16)) -> io::Result<()> {
+        self.sensor.borrow_mut().push_sent_busy_handshake();
+        if self.send_handshake_ok { Ok(()) } else { Err(other_io_error("test")) }
     }
 }
 
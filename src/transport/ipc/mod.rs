@@ -9,6 +9,7 @@ mod send;
 mod recv;
 
 #[cfg(unix)] pub use self::unix::Ipc;
+#[cfg(unix)] pub use self::unix::connect_stream;
 #[cfg(unix)] mod unix;
 
 #[cfg(windows)] pub use self::windows::Ipc;
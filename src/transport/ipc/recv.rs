@@ -9,20 +9,33 @@ use std::io;
 use byteorder::{ BigEndian, ByteOrder };
 
 use core::Message;
+use core::pool::{RecvPool, take_buffer};
+use transport::{OversizePolicy, MessageTooLong};
 use transport::async::stub::*;
 use io_error::*;
 
 pub struct RecvOperation {
-    step: Option<RecvOperationStep>
+    step: Option<RecvOperationStep>,
+    skip_oversize: bool,
+    pool: Option<RecvPool>,
+    dropped: usize
 }
 
 impl RecvOperation {
-    pub fn new(recv_max_size: u64) -> RecvOperation {
+    pub fn new(recv_max_size: u64, oversize_policy: OversizePolicy, recv_pool: Option<RecvPool>) -> RecvOperation {
         RecvOperation {
-            step: Some(RecvOperationStep::Header([0; 9], 0, recv_max_size))
+            step: Some(RecvOperationStep::Header([0; 9], 0, recv_max_size)),
+            skip_oversize: oversize_policy == OversizePolicy::SkipMessage,
+            pool: recv_pool,
+            dropped: 0
         }
     }
 
+    /// Returns the number of oversized messages skipped since the last call.
+    pub fn take_dropped(&mut self) -> usize {
+        ::std::mem::replace(&mut self.dropped, 0)
+    }
+
     pub fn run<T:io::Read>(&mut self, stream: &mut T) -> io::Result<Option<Message>> {
         if let Some(step) = self.step.take() {
             self.resume_at(stream, step)
@@ -35,7 +48,7 @@ impl RecvOperation {
         let mut cur_step = step;
 
         loop {
-            let (passed, next_step) = cur_step.advance(stream)?;
+            let (passed, next_step) = cur_step.advance(stream, self.skip_oversize, self.pool.as_ref())?;
 
             if !passed {
                 self.step = Some(next_step);
@@ -44,6 +57,10 @@ impl RecvOperation {
 
             match next_step {
                 RecvOperationStep::Terminal(msg) => return Ok(Some(msg)),
+                RecvOperationStep::Skipped(max_size) => {
+                    self.dropped += 1;
+                    cur_step = RecvOperationStep::Header([0; 9], 0, max_size);
+                },
                 other => cur_step = other
             }
         }
@@ -53,46 +70,71 @@ impl RecvOperation {
 enum RecvOperationStep {
     Header([u8; 9], usize, u64),
     Payload(Vec<u8>, usize),
+    Skip(u64, u64),
+    Skipped(u64),
     Terminal(Message)
 }
 
 impl RecvOperationStep {
-    fn advance<T:io::Read>(self, stream: &mut T) -> io::Result<(bool, RecvOperationStep)> {
+    fn advance<T:io::Read>(self, stream: &mut T, skip_oversize: bool, pool: Option<&RecvPool>) -> io::Result<(bool, RecvOperationStep)> {
         match self {
-            RecvOperationStep::Header(buffer, read, max_size) => read_header(stream, buffer, read, max_size),
+            RecvOperationStep::Header(buffer, read, max_size) => read_header(stream, buffer, read, max_size, skip_oversize, pool),
             RecvOperationStep::Payload(buffer, read) => read_payload(stream, buffer, read),
+            RecvOperationStep::Skip(remaining, max_size) => skip_payload(stream, remaining, max_size),
+            RecvOperationStep::Skipped(_) => Err(other_io_error("Cannot advance skipped step of recv operation")),
             RecvOperationStep::Terminal(_) => Err(other_io_error("Cannot advance terminal step of recv operation"))
         }
     }
 }
 
-fn read_header<T:io::Read>(stream: &mut T, mut buffer: [u8; 9], mut read: usize, max_size: u64) -> io::Result<(bool, RecvOperationStep)> {
+fn read_header<T:io::Read>(stream: &mut T, mut buffer: [u8; 9], mut read: usize, size_limit: u64, skip_oversize: bool, pool: Option<&RecvPool>) -> io::Result<(bool, RecvOperationStep)> {
     read += stream.read_buffer(&mut buffer[read..])?;
 
     if read == 9 {
         let msg_len = BigEndian::read_u64(&buffer[1..]);
-        if max_size > 0 && msg_len > max_size {
-            Err(invalid_data_io_error("message is too long"))
+        if size_limit > 0 && msg_len > size_limit {
+            if skip_oversize {
+                Ok((true, RecvOperationStep::Skip(msg_len, size_limit)))
+            } else {
+                Err(invalid_data_io_error(MessageTooLong { max_size: size_limit, actual: msg_len }))
+            }
         } else {
-            let payload = vec![0u8; msg_len as usize];
+            let payload = take_buffer(pool, msg_len as usize);
 
             Ok((true, RecvOperationStep::Payload(payload, 0)))
         }
     } else {
-        Ok((false, RecvOperationStep::Header(buffer, read, max_size)))
+        Ok((false, RecvOperationStep::Header(buffer, read, size_limit)))
     }
 }
 
 fn read_payload<T:io::Read>(stream: &mut T, mut buffer: Vec<u8>, mut read: usize) -> io::Result<(bool, RecvOperationStep)> {
     read += stream.read_buffer(&mut buffer[read..])?;
 
-    if read == buffer.capacity() {
+    if read == buffer.len() {
         Ok((true, RecvOperationStep::Terminal(Message::from_body(buffer))))
     } else {
         Ok((false, RecvOperationStep::Payload(buffer, read)))
     }
 }
 
+fn skip_payload<T:io::Read>(stream: &mut T, mut remaining: u64, max_size: u64) -> io::Result<(bool, RecvOperationStep)> {
+    let mut scratch = [0u8; 4096];
+
+    while remaining > 0 {
+        let len = ::std::cmp::min(remaining, scratch.len() as u64) as usize;
+        let read = stream.read_buffer(&mut scratch[..len])?;
+
+        if read == 0 {
+            return Ok((false, RecvOperationStep::Skip(remaining, max_size)));
+        }
+
+        remaining -= read as u64;
+    }
+
+    Ok((true, RecvOperationStep::Skipped(max_size)))
+}
+
 #[cfg(test)]
 mod tests {
     use std::io;
@@ -103,7 +145,7 @@ mod tests {
     fn recv_in_one_run() {
         let buffer = vec![1, 0, 0, 0, 0, 0, 0, 0, 8, 1, 4, 3, 2, 65, 66, 67, 69];
         let mut stream = io::Cursor::new(buffer);
-        let mut operation = RecvOperation::new(1024);
+        let mut operation = RecvOperation::new(1024, OversizePolicy::ClosePipe, None);
         let msg = operation.run(&mut stream).
             expect("recv should have succeeded").
             expect("recv should be done");
@@ -111,4 +153,31 @@ mod tests {
 
         assert_eq!(&expected_bytes, msg.get_body());
     }
+
+    #[test]
+    fn recv_after_skipping_oversized_message() {
+        let buffer = vec![
+            1, 0, 0, 0, 0, 0, 0, 0, 6, 1, 2, 3, 4, 5, 6,
+            1, 0, 0, 0, 0, 0, 0, 0, 2, 65, 66];
+        let mut stream = io::Cursor::new(buffer);
+        let mut operation = RecvOperation::new(4, OversizePolicy::SkipMessage, None);
+        let msg = operation.run(&mut stream).
+            expect("recv should have succeeded").
+            expect("recv should be done");
+
+        assert_eq!(&[65, 66], msg.get_body());
+        assert_eq!(1, operation.take_dropped());
+        assert_eq!(0, operation.take_dropped());
+    }
+
+    #[test]
+    fn recv_oversized_message_fails_by_default() {
+        let buffer = vec![1, 0, 0, 0, 0, 0, 0, 0, 6, 1, 2, 3, 4, 5, 6];
+        let mut stream = io::Cursor::new(buffer);
+        let mut operation = RecvOperation::new(4, OversizePolicy::ClosePipe, None);
+        let err = operation.run(&mut stream).err().expect("recv should have failed");
+
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+        assert_eq!(Some(MessageTooLong { max_size: 4, actual: 6 }), MessageTooLong::of(&err));
+    }
 }
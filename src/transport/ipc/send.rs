@@ -14,19 +14,21 @@ use transport::async::stub::*;
 use io_error::*;
 
 pub struct SendOperation {
-    step: Option<SendOperationStep>
+    step: Option<SendOperationStep>,
+    chunk_size: usize
 }
 
 impl SendOperation {
-    pub fn new(msg: Rc<Message>) -> SendOperation {
+    pub fn new(msg: Rc<Message>, max_chunk: usize) -> SendOperation {
         SendOperation { 
-            step: Some(SendOperationStep::TransportHdr(msg, 0))
+            step: Some(SendOperationStep::TransportHdr(msg, 0)),
+            chunk_size: max_chunk
         }
     }
 
     pub fn run<T:io::Write>(&mut self, stream: &mut T) -> io::Result<bool> {
         if let Some(step) = self.step.take() {
-            self.resume_at(stream, step)
+            self.resume_at(&mut StallGuard::new(stream), step)
         } else {
             Err(other_io_error("Cannot resume already finished send operation"))
         }
@@ -36,7 +38,7 @@ impl SendOperation {
         let mut cur_step = step;
 
         loop {
-            let (passed, next_step) = cur_step.advance(stream)?;
+            let (passed, next_step) = cur_step.advance(stream, self.chunk_size)?;
 
             if next_step.is_terminal() {
                 return Ok(true);
@@ -55,17 +57,19 @@ enum SendOperationStep {
     TransportHdr(Rc<Message>, usize),
     ProtocolHdr(Rc<Message>, usize),
     UsrPayload(Rc<Message>, usize),
+    Frame(Rc<Message>, usize),
     Terminal
 }
 
 impl SendOperationStep {
     /// Writes one of the buffers composing the message.
     /// Returns whether the buffer was fully sent, and what is the next step.
-    fn advance<T:io::Write>(self, stream: &mut T) -> io::Result<(bool, SendOperationStep)> {
+    fn advance<T:io::Write>(self, stream: &mut T, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
         match self {
-            SendOperationStep::TransportHdr(msg, written) => write_transport_hdr(stream, msg, written),
+            SendOperationStep::TransportHdr(msg, written) => write_transport_hdr(stream, msg, written, chunk_size),
             SendOperationStep::ProtocolHdr(msg, written) => write_protocol_hdr(stream, msg, written),
-            SendOperationStep::UsrPayload(msg, written) => write_usr_payload(stream, msg, written),
+            SendOperationStep::UsrPayload(msg, written) => write_usr_payload(stream, msg, written, chunk_size),
+            SendOperationStep::Frame(msg, written) => write_frame(stream, msg, written, chunk_size),
             SendOperationStep::Terminal => Err(other_io_error("Cannot advance terminal step of send operation"))
         }
     }
@@ -78,12 +82,27 @@ impl SendOperationStep {
     }
 }
 
-fn write_transport_hdr<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written: usize) -> io::Result<(bool, SendOperationStep)> {
+/// Messages up to this size, transport header included, are written at once from a single buffer,
+/// so they are not split across several writes that the peer could wake up for separately.
+/// This saves system calls, not latency: the pipe already starts writing a message
+/// as soon as it is sent, without waiting for a writable event.
+const SMALL_MSG_SIZE: usize = 4096;
+
+fn write_transport_hdr<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written: usize, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
     let msg_len = msg.len() as u64;
     let mut buffer = [1u8; 9];
 
+    if msg.get_frame().is_some() {
+        return write_frame_type(stream, msg, written);
+    }
+
     BigEndian::write_u64(&mut buffer[1..], msg_len);
 
+    let whole_len = buffer.len() + msg.len();
+    if written == 0 && whole_len <= SMALL_MSG_SIZE && (chunk_size == 0 || msg.get_body().len() <= chunk_size) {
+        return write_small_msg(stream, msg, &buffer);
+    }
+
     let sent = stream.write_buffer(&buffer, &mut written)?;
     if sent {
         Ok((true, SendOperationStep::ProtocolHdr(msg, 0)))
@@ -92,6 +111,29 @@ fn write_transport_hdr<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut writte
     }
 }
 
+fn write_small_msg<T:io::Write>(stream: &mut T, msg: Rc<Message>, transport_hdr: &[u8]) -> io::Result<(bool, SendOperationStep)> {
+    let mut whole = Vec::with_capacity(transport_hdr.len() + msg.len());
+    let mut written = 0;
+
+    whole.extend_from_slice(transport_hdr);
+    whole.extend_from_slice(msg.get_header());
+    whole.extend_from_slice(msg.get_body());
+
+    if stream.write_buffer(&whole, &mut written)? {
+        return Ok((true, SendOperationStep::Terminal));
+    }
+
+    let proto_hdr_limit = transport_hdr.len() + msg.get_header().len();
+
+    if written < transport_hdr.len() {
+        Ok((false, SendOperationStep::TransportHdr(msg, written)))
+    } else if written < proto_hdr_limit {
+        Ok((false, SendOperationStep::ProtocolHdr(msg, written - transport_hdr.len())))
+    } else {
+        Ok((false, SendOperationStep::UsrPayload(msg, written - proto_hdr_limit)))
+    }
+}
+
 fn write_protocol_hdr<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written: usize) -> io::Result<(bool, SendOperationStep)> {
     if msg.get_header().len() == 0 {
         return Ok((true, SendOperationStep::UsrPayload(msg, 0)));
@@ -105,21 +147,49 @@ fn write_protocol_hdr<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written
     }
 }
 
-fn write_usr_payload<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written: usize) -> io::Result<(bool, SendOperationStep)> {
-    if msg.get_body().len() == 0 {
+fn write_usr_payload<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written: usize, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
+    let payload = msg.get_body();
+    if payload.len() == 0 {
         return Ok((true, SendOperationStep::Terminal));
     }
 
-    let sent = stream.write_buffer(msg.get_body(), &mut written)?;
-    if sent {
+    let chunk = &payload[..chunk_end(payload.len(), written, chunk_size)];
+    let sent = stream.write_buffer(chunk, &mut written)?;
+    if sent && written == payload.len() {
         Ok((true, SendOperationStep::Terminal))
     } else {
         Ok((false, SendOperationStep::UsrPayload(msg, written)))
     }
 }
 
+/// Writes the message type that precedes the length prefix already included in a frame.
+fn write_frame_type<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written: usize) -> io::Result<(bool, SendOperationStep)> {
+    let sent = stream.write_buffer(&[1u8], &mut written)?;
+    if sent {
+        Ok((true, SendOperationStep::Frame(msg, 0)))
+    } else {
+        Ok((false, SendOperationStep::TransportHdr(msg, written)))
+    }
+}
+
+fn write_frame<T:io::Write>(stream: &mut T, msg: Rc<Message>, mut written: usize, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
+    let done = {
+        let frame = msg.get_frame().unwrap_or_default();
+        let chunk = &frame[..chunk_end(frame.len(), written, chunk_size)];
+
+        stream.write_buffer(chunk, &mut written)? && written == frame.len()
+    };
+
+    if done {
+        Ok((true, SendOperationStep::Terminal))
+    } else {
+        Ok((false, SendOperationStep::Frame(msg, written)))
+    }
+}
+
 #[cfg(test)]
 mod tests {
+    use std::io;
     use std::ops::Deref;
     use std::rc::Rc;
 
@@ -131,7 +201,7 @@ mod tests {
         let header = vec!(1, 4, 3, 2);
         let payload = vec!(65, 66, 67, 69);
         let msg = Message::from_header_and_body(header, payload);
-        let mut operation = SendOperation::new(Rc::new(msg));
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
         let mut stream = Vec::new();
         let result = operation.run(&mut stream).expect("send should have succeeded");
         let expected_bytes = [1, 0, 0, 0, 0, 0, 0, 0, 8, 1, 4, 3, 2, 65, 66, 67, 69];
@@ -139,4 +209,108 @@ mod tests {
         assert!(result);
         assert_eq!(&expected_bytes, stream.deref());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn send_prewrapped_frame_after_message_type() {
+        let frame: Vec<u8> = vec!(0, 0, 0, 0, 0, 0, 0, 3, 65, 66, 67);
+        let msg = Message::from_frame(frame.into());
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
+        let mut stream = Vec::new();
+        let result = operation.run(&mut stream).expect("send should have succeeded");
+        let expected_bytes = [1, 0, 0, 0, 0, 0, 0, 0, 3, 65, 66, 67];
+
+        assert!(result);
+        assert_eq!(&expected_bytes, stream.deref());
+    }
+
+    #[test]
+    fn send_in_several_runs_when_chunked() {
+        let header = vec!(1, 4, 3, 2);
+        let payload = vec!(65, 66, 67, 69);
+        let msg = Message::from_header_and_body(header, payload);
+        let mut operation = SendOperation::new(Rc::new(msg), 3);
+        let mut stream = Vec::new();
+
+        assert!(!operation.run(&mut stream).expect("first run should have succeeded"));
+        assert_eq!(16, stream.len());
+        assert!(operation.run(&mut stream).expect("second run should have succeeded"));
+
+        let expected_bytes = [1, 0, 0, 0, 0, 0, 0, 0, 8, 1, 4, 3, 2, 65, 66, 67, 69];
+        assert_eq!(&expected_bytes, stream.deref());
+    }
+
+    /// Accepts at most `limit` bytes per write, once `stalls` writes accepted nothing, and counts the writes.
+    struct LimitedStream {
+        bytes: Vec<u8>,
+        writes: usize,
+        limit: usize,
+        stalls: usize
+    }
+
+    impl io::Write for LimitedStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let len = if self.stalls > 0 {
+                self.stalls -= 1;
+                0
+            } else {
+                ::std::cmp::min(buf.len(), self.limit)
+            };
+
+            self.writes += 1;
+            self.bytes.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn send_small_message_in_a_single_write() {
+        let msg = Message::from_header_and_body(vec!(1, 4, 3, 2), vec!(65, 66, 67, 69));
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
+        let mut stream = LimitedStream { bytes: Vec::new(), writes: 0, limit: 1024, stalls: 0 };
+
+        assert!(operation.run(&mut stream).expect("send should have succeeded"));
+        assert_eq!(1, stream.writes);
+
+        let expected_bytes = [1, 0, 0, 0, 0, 0, 0, 0, 8, 1, 4, 3, 2, 65, 66, 67, 69];
+        assert_eq!(&expected_bytes, stream.bytes.deref());
+    }
+
+    #[test]
+    fn send_small_message_partially_written_resumes_where_it_stopped() {
+        let msg = Message::from_header_and_body(vec!(1, 4, 3, 2), vec!(65, 66, 67, 69));
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
+        let mut stream = LimitedStream { bytes: Vec::new(), writes: 0, limit: 11, stalls: 0 };
+
+        assert!(!operation.run(&mut stream).expect("first run should have succeeded"));
+        stream.limit = 1024;
+        assert!(operation.run(&mut stream).expect("second run should have succeeded"));
+
+        let expected_bytes = [1, 0, 0, 0, 0, 0, 0, 0, 8, 1, 4, 3, 2, 65, 66, 67, 69];
+        assert_eq!(&expected_bytes, stream.bytes.deref());
+    }
+
+    #[test]
+    fn send_fails_once_too_many_writes_in_a_row_accepted_nothing() {
+        let msg = Message::from_body(vec!(65, 66, 67, 69));
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
+        let mut stream = LimitedStream { bytes: Vec::new(), writes: 0, limit: 0, stalls: 0 };
+
+        let err = operation.run(&mut stream).expect_err("run should have failed");
+
+        assert_eq!(io::ErrorKind::WriteZero, err.kind());
+        assert_eq!(MAX_ZERO_WRITES, stream.writes);
+    }
+
+    #[test]
+    fn send_retries_the_writes_that_accepted_nothing() {
+        let msg = Message::from_body(vec!(65, 66, 67, 69));
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
+        let mut stream = LimitedStream { bytes: Vec::new(), writes: 0, limit: 1024, stalls: MAX_ZERO_WRITES - 1 };
+
+        assert!(operation.run(&mut stream).expect("run should have succeeded"));
+        assert_eq!(MAX_ZERO_WRITES, stream.writes);
+    }
+}
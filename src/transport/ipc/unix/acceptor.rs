@@ -10,24 +10,33 @@ use mio;
 
 use mio_uds::{UnixListener, UnixStream};
 
+use core::pool::RecvPool;
 use transport::*;
 use transport::acceptor::*;
 use transport::async::AsyncPipe;
-use super::stub::IpcPipeStub;
+use super::stub::{IpcPipeStub, ipc_address};
 
 pub struct IpcAcceptor {
     listener: UnixListener,
     proto_ids: (u16, u16),
-    recv_max_size: u64
+    recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>
 }
 
 impl IpcAcceptor {
 
-    pub fn new(l: UnixListener, pids: (u16, u16), recv_max_size: u64) -> IpcAcceptor {
+    pub fn new(l: UnixListener, pids: (u16, u16), recv_max_size: u64, policy: OversizePolicy, chunk_size: usize, protocol_versions: ProtocolVersions, pool: Option<RecvPool>) -> IpcAcceptor {
         IpcAcceptor {
             listener: l,
             proto_ids: pids,
-            recv_max_size: recv_max_size
+            recv_max_size: recv_max_size,
+            oversize_policy: policy,
+            send_chunk_size: chunk_size,
+            versions: protocol_versions,
+            recv_pool: pool
         }
     }
 
@@ -60,7 +69,7 @@ impl IpcAcceptor {
     }
 
     fn create_pipe(&self, stream: UnixStream) -> Box<dyn pipe::Pipe> {
-        let stub = IpcPipeStub::new(stream, self.recv_max_size);
+        let stub = IpcPipeStub::new(stream, self.recv_max_size, self.oversize_policy, self.send_chunk_size, self.versions.clone(), self.recv_pool.clone());
 
         Box::new(AsyncPipe::new(stub, self.proto_ids))
     }
@@ -82,4 +91,8 @@ impl acceptor::Acceptor for IpcAcceptor {
         ctx.deregister(&self.listener);
         ctx.raise(Event::Closed);
     }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.listener.local_addr().ok().and_then(|addr| ipc_address(&addr))
+    }
 }
\ No newline at end of file
@@ -10,41 +10,196 @@ mod acceptor;
 
 use std::fs;
 use std::io;
+use std::mem;
 use std::path;
-use std::os::unix::fs::FileTypeExt;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::io::{AsRawFd, FromRawFd};
+use std::os::unix::net;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
 
+use libc;
 use mio_uds::{UnixListener, UnixStream};
 
 use self::stub::IpcPipeStub;
 use self::acceptor::IpcAcceptor;
-use transport::{Transport, Destination};
+use transport::{Transport, TransportInfo, TransportKind, Destination};
 use transport::pipe::Pipe;
 use transport::acceptor::Acceptor;
 use transport::async::AsyncPipe;
+use io_error::*;
 
 pub struct Ipc;
 
+/// Creates a pipe running the SP handshake over an already connected stream.
+pub fn connect_stream(stream: net::UnixStream, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
+    let stream = UnixStream::from_stream(stream)?;
+
+    Ok(create_pipe(stream, dest))
+}
+
+fn create_pipe(stream: UnixStream, dest: &Destination) -> Box<dyn Pipe> {
+    let stub = IpcPipeStub::new(stream, dest.recv_max_size, dest.oversize_policy, dest.send_chunk_size, dest.versions.clone(), dest.recv_pool.cloned());
+
+    Box::new(AsyncPipe::new(stub, dest.pids))
+}
+
+/// On Linux, an address starting with `@` names a socket of the abstract namespace,
+/// which has no filesystem entry, so nothing is left behind to clean up.
+#[cfg(target_os = "linux")]
+fn abstract_name(addr: &str) -> Option<&str> {
+    addr.strip_prefix('@')
+}
+
+#[cfg(not(target_os = "linux"))]
+fn abstract_name(_: &str) -> Option<&str> {
+    None
+}
+
+/// Connects a non-blocking socket, so the reactor thread is never blocked.
+/// The connection to a local listener completes immediately, unless its backlog is full,
+/// in which case the pipe fails once it tries to use the socket, and is reconnected.
+#[cfg(target_os = "linux")]
+fn connect_abstract(name: &str) -> io::Result<UnixStream> {
+    let (addr, len) = abstract_sockaddr_un(name)?;
+    let fd = check_os_result(unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM | libc::SOCK_NONBLOCK | libc::SOCK_CLOEXEC, 0) })?;
+    let stream = unsafe { net::UnixStream::from_raw_fd(fd) };
+
+    match check_os_result(unsafe { libc::connect(fd, &addr as *const _ as *const libc::sockaddr, len) }) {
+        Ok(_) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => {},
+        Err(e) => return Err(e)
+    }
+
+    UnixStream::from_stream(stream)
+}
+
+/// Abstract names follow a leading nul byte, and are not nul terminated.
+#[cfg(target_os = "linux")]
+fn abstract_sockaddr_un(name: &str) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let bytes = name.as_bytes();
+
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(invalid_input_io_error("name must be shorter than SUN_LEN"));
+    }
+
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut().skip(1).zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+
+    let len = mem::size_of::<libc::sa_family_t>() + 1 + bytes.len();
+
+    Ok((addr, len as libc::socklen_t))
+}
+
+#[cfg(not(target_os = "linux"))]
+fn connect_abstract(_: &str) -> io::Result<UnixStream> {
+    Err(invalid_input_io_error("abstract namespace is only available on linux"))
+}
+
+#[cfg(target_os = "linux")]
+fn bind_abstract(name: &str) -> io::Result<UnixListener> {
+    let addr = net::SocketAddr::from_abstract_name(name)?;
+    let listener = net::UnixListener::bind_addr(&addr)?;
+
+    UnixListener::from_listener(listener)
+}
+
+#[cfg(not(target_os = "linux"))]
+fn bind_abstract(_: &str) -> io::Result<UnixListener> {
+    Err(invalid_input_io_error("abstract namespace is only available on linux"))
+}
+
+fn bind_path(addr: &str, permissions: Option<u32>) -> io::Result<UnixListener> {
+    let filename = path::Path::new(addr);
+
+    match fs::metadata(filename).map(|meta| meta.file_type().is_socket()) {
+        Ok(true)  => fs::remove_file(filename)?,
+        _ => (),
+    }
+
+    match permissions {
+        Some(mode) => bind_restricted(filename, mode),
+        None => UnixListener::bind(filename)
+    }
+}
+
+/// Binds the socket, sets the permissions of its file and only then starts listening,
+/// so that no peer can connect while the default permissions are still in place.
+fn bind_restricted(filename: &path::Path, mode: u32) -> io::Result<UnixListener> {
+    let (addr, len) = sockaddr_un(filename)?;
+    let fd = check_os_result(unsafe { libc::socket(libc::AF_UNIX, libc::SOCK_STREAM, 0) })?;
+    let listener = unsafe { net::UnixListener::from_raw_fd(fd) };
+
+    check_os_result(unsafe { libc::fcntl(fd, libc::F_SETFD, libc::FD_CLOEXEC) })?;
+    check_os_result(unsafe { libc::bind(fd, &addr as *const _ as *const libc::sockaddr, len) })?;
+
+    if let Err(e) = restrict_and_listen(&listener, filename, mode) {
+        let _ = fs::remove_file(filename);
+        return Err(e);
+    }
+
+    UnixListener::from_listener(listener)
+}
+
+fn restrict_and_listen(listener: &net::UnixListener, filename: &path::Path, mode: u32) -> io::Result<()> {
+    fs::set_permissions(filename, fs::Permissions::from_mode(mode))?;
+    check_os_result(unsafe { libc::listen(listener.as_raw_fd(), 128) }).map(|_| ())
+}
+
+fn sockaddr_un(filename: &path::Path) -> io::Result<(libc::sockaddr_un, libc::socklen_t)> {
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    let bytes = filename.as_os_str().as_bytes();
+
+    // the path must leave room for the terminating nul byte
+    if bytes.len() >= addr.sun_path.len() {
+        return Err(invalid_input_io_error("path must be shorter than SUN_LEN"));
+    }
+
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut().zip(bytes) {
+        *dst = *src as libc::c_char;
+    }
+
+    Ok((addr, mem::size_of::<libc::sockaddr_un>() as libc::socklen_t))
+}
+
+fn check_os_result(res: libc::c_int) -> io::Result<libc::c_int> {
+    if res < 0 {
+        Err(io::Error::last_os_error())
+    } else {
+        Ok(res)
+    }
+}
+
 impl Transport for Ipc {
     fn connect(&self, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
-        let filename = path::Path::new(dest.addr);
-        let stream = UnixStream::connect(filename)?;
-        let stub = IpcPipeStub::new(stream, dest.recv_max_size);
-        let pipe = AsyncPipe::new(stub, dest.pids);
+        let stream = match abstract_name(dest.addr) {
+            Some(name) => connect_abstract(name)?,
+            None => UnixStream::connect(path::Path::new(dest.addr))?
+        };
 
-        Ok(Box::new(pipe))
+        Ok(create_pipe(stream, dest))
     }
 
     fn bind(&self, dest: &Destination) -> io::Result<Box<dyn Acceptor>> {
-        let filename = path::Path::new(dest.addr);
-
-        match fs::metadata(filename).map(|meta| meta.file_type().is_socket()) {
-            Ok(true)  => fs::remove_file(filename)?,
-            _ => (),
-        }
-
-        let listener = UnixListener::bind(filename)?;
-        let acceptor = IpcAcceptor::new(listener, dest.pids, dest.recv_max_size);
+        let listener = match abstract_name(dest.addr) {
+            Some(name) => bind_abstract(name)?,
+            None => bind_path(dest.addr, dest.ipc_permissions)?
+        };
+        let acceptor = IpcAcceptor::new(listener, dest.pids, dest.recv_max_size, dest.oversize_policy, dest.send_chunk_size, dest.versions.clone(), dest.recv_pool.cloned());
 
         Ok(Box::new(acceptor))
     }
+
+    fn info(&self) -> TransportInfo {
+        TransportInfo {
+            kind: TransportKind::Ipc,
+            vectored_io: false,
+            suggested_chunk_size: 64 * 1024
+        }
+    }
 }
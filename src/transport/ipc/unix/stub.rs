@@ -7,15 +7,32 @@
 use std::ops::Deref;
 use std::rc::Rc;
 use std::io;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::mem;
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use std::os::unix::io::AsRawFd;
 use std::net::Shutdown;
+use std::os::unix::net::SocketAddr;
+#[cfg(target_os = "linux")]
+use std::os::linux::net::SocketAddrExt;
+#[cfg(target_os = "linux")]
+use std::os::unix::ffi::OsStrExt;
+#[cfg(target_os = "linux")]
+use std::ffi::OsStr;
+#[cfg(target_os = "linux")]
+use std::path::PathBuf;
 
 use mio;
 
+#[cfg(any(target_os = "linux", target_os = "android"))]
+use libc;
 use mio_uds::UnixStream;
 
 use core::Message;
+use core::pool::RecvPool;
 use transport::ipc::send::SendOperation;
 use transport::ipc::recv::RecvOperation;
+use transport::{ProtocolVersions, Features, OversizePolicy, Address, Addresses, PeerCred};
 use transport::async::stub::*;
 use io_error::*;
 
@@ -28,8 +45,14 @@ use io_error::*;
 pub struct IpcPipeStub {
     stream: UnixStream,
     recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>,
     send_operation: Option<SendOperation>,
-    recv_operation: Option<RecvOperation>
+    recv_operation: Option<RecvOperation>,
+    dropped: usize,
+    handshake: PartialHandshake
 }
 
 impl Deref for IpcPipeStub {
@@ -40,12 +63,18 @@ impl Deref for IpcPipeStub {
 }
 
 impl IpcPipeStub {
-    pub fn new(stream: UnixStream, recv_max_size: u64) -> IpcPipeStub {
+    pub fn new(stream: UnixStream, recv_max_size: u64, policy: OversizePolicy, chunk_size: usize, protocol_versions: ProtocolVersions, pool: Option<RecvPool>) -> IpcPipeStub {
         IpcPipeStub {
             stream: stream,
             recv_max_size: recv_max_size,
+            oversize_policy: policy,
+            send_chunk_size: chunk_size,
+            versions: protocol_versions,
+            recv_pool: pool,
             send_operation: None,
-            recv_operation: None
+            recv_operation: None,
+            dropped: 0,
+            handshake: PartialHandshake::default()
         }
     }
 
@@ -59,7 +88,11 @@ impl IpcPipeStub {
     }
 
     fn run_recv_operation(&mut self, mut recv_operation: RecvOperation) -> io::Result<Option<Message>> {
-        match recv_operation.run(&mut self.stream)? {
+        let progress = recv_operation.run(&mut self.stream);
+
+        self.dropped += recv_operation.take_dropped();
+
+        match progress? {
             Some(msg) => Ok(Some(msg)),
             None => {
                 self.recv_operation = Some(recv_operation);
@@ -83,7 +116,7 @@ impl Drop for IpcPipeStub {
 
 impl Sender for IpcPipeStub {
     fn start_send(&mut self, msg: Rc<Message>) -> io::Result<bool> {
-        let send_operation = SendOperation::new(msg);
+        let send_operation = SendOperation::new(msg, self.send_chunk_size);
 
         self.run_send_operation(send_operation)
     }
@@ -109,7 +142,7 @@ impl Sender for IpcPipeStub {
 
 impl Receiver for IpcPipeStub {
     fn start_recv(&mut self) -> io::Result<Option<Message>> {
-        let recv_operation = RecvOperation::new(self.recv_max_size);
+        let recv_operation = RecvOperation::new(self.recv_max_size, self.oversize_policy, self.recv_pool.clone());
 
         self.run_recv_operation(recv_operation)
     }
@@ -125,6 +158,10 @@ impl Receiver for IpcPipeStub {
     fn has_pending_recv(&self) -> bool {
         self.recv_operation.is_some()
     }
+
+    fn take_dropped(&mut self) -> usize {
+        ::std::mem::replace(&mut self.dropped, 0)
+    }
 }
 
 /*****************************************************************************/
@@ -135,12 +172,76 @@ impl Receiver for IpcPipeStub {
 
 impl Handshake for IpcPipeStub {
     fn send_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
-        send_and_check_handshake(&mut self.stream, pids)
+        send_and_check_handshake(&mut self.stream, pids, &self.versions)
+    }
+    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<(u16, Features)> {
+        recv_and_check_handshake(&mut self.stream, pids, &self.versions, &mut self.handshake)
     }
-    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
-        recv_and_check_handshake(&mut self.stream, pids)
+    fn send_busy_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
+        send_busy_handshake(&mut self.stream, pids, &self.versions)
     }
 }
 
 impl AsyncPipeStub for IpcPipeStub {
+    fn addresses(&self) -> Addresses {
+        let local = self.stream.local_addr().ok().and_then(|addr| ipc_address(&addr));
+        let peer = self.stream.peer_addr().ok().and_then(|addr| ipc_address(&addr));
+
+        (local, peer)
+    }
+    fn peer_cred(&self) -> io::Result<PeerCred> {
+        peer_cred(&self.stream)
+    }
+}
+
+/// Reads the credentials the kernel recorded for the peer when the connection was established.
+#[cfg(any(target_os = "linux", target_os = "android"))]
+fn peer_cred(stream: &UnixStream) -> io::Result<PeerCred> {
+    let mut cred: libc::ucred = unsafe { mem::zeroed() };
+    let mut len = mem::size_of::<libc::ucred>() as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut cred as *mut libc::ucred as *mut libc::c_void,
+            &mut len)
+    };
+
+    if res < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    Ok(PeerCred {
+        uid: cred.uid,
+        gid: cred.gid,
+        pid: cred.pid
+    })
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android")))]
+fn peer_cred(_: &UnixStream) -> io::Result<PeerCred> {
+    Err(unsupported_io_error("peer credentials are only supported on linux"))
+}
+
+/// Unnamed sockets, like the connecting side of a pipe, have no address.
+#[cfg(not(target_os = "linux"))]
+pub fn ipc_address(addr: &SocketAddr) -> Option<Address> {
+    addr.as_pathname().map(|path| Address::Ipc(path.to_path_buf()))
+}
+
+/// Unnamed sockets, like the connecting side of a pipe, have no address.
+/// Abstract names are reported with the leading `@` they were given in the url.
+#[cfg(target_os = "linux")]
+pub fn ipc_address(addr: &SocketAddr) -> Option<Address> {
+    if let Some(path) = addr.as_pathname() {
+        return Some(Address::Ipc(path.to_path_buf()));
+    }
+
+    addr.as_abstract_name().map(|name| {
+        let mut path = PathBuf::from("@");
+
+        path.as_mut_os_string().push(OsStr::from_bytes(name));
+        Address::Ipc(path)
+    })
 }
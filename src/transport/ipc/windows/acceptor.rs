@@ -8,6 +8,7 @@ use mio;
 
 use mio_named_pipes::NamedPipe;
 
+use core::pool::RecvPool;
 use transport::*;
 use transport::acceptor::*;
 use transport::async::AsyncPipe;
@@ -16,16 +17,24 @@ use super::stub::IpcPipeStub;
 pub struct IpcAcceptor {
     addr: String,
     proto_ids: (u16, u16),
-    recv_max_size: u64
+    recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>
 }
 
 impl IpcAcceptor {
 
-    pub fn new(a: String, pids: (u16, u16), recv_max_size: u64) -> IpcAcceptor {
+    pub fn new(a: String, pids: (u16, u16), recv_max_size: u64, policy: OversizePolicy, chunk_size: usize, protocol_versions: ProtocolVersions, pool: Option<RecvPool>) -> IpcAcceptor {
         IpcAcceptor {
             addr: a,
             proto_ids: pids,
-            recv_max_size: recv_max_size
+            recv_max_size: recv_max_size,
+            oversize_policy: policy,
+            send_chunk_size: chunk_size,
+            versions: protocol_versions,
+            recv_pool: pool
         }
     }
 
@@ -49,7 +58,7 @@ impl IpcAcceptor {
     }
 
     fn create_pipe(&self, named_pipe: NamedPipe) -> Box<pipe::Pipe> {
-        let stub = IpcPipeStub::new_server(named_pipe, self.recv_max_size);
+        let stub = IpcPipeStub::new_server(named_pipe, self.recv_max_size, self.oversize_policy, self.send_chunk_size, self.versions.clone(), self.recv_pool.clone());
 
         Box::new(AsyncPipe::new(stub, self.proto_ids))
     }
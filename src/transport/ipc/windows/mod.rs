@@ -19,7 +19,7 @@ use winapi::um::winbase::FILE_FLAG_OVERLAPPED;
 use self::stub::IpcPipeStub;
 use self::acceptor::IpcAcceptor;
 
-use transport::{Transport, Destination};
+use transport::{Transport, TransportInfo, TransportKind, Destination};
 use transport::pipe::Pipe;
 use transport::acceptor::Acceptor;
 use transport::async::AsyncPipe;
@@ -33,7 +33,7 @@ impl Transport for Ipc {
         let name = format!(r"\\.\pipe\scaproust-pipe-{}", dest.addr);
         let file = try!(options.open(name));
         let named_pipe = unsafe { NamedPipe::from_raw_handle(file.into_raw_handle()) };
-        let stub = IpcPipeStub::new_client(named_pipe, dest.recv_max_size);
+        let stub = IpcPipeStub::new_client(named_pipe, dest.recv_max_size, dest.oversize_policy, dest.send_chunk_size, dest.versions.clone(), dest.recv_pool.cloned());
         let pipe = Box::new(AsyncPipe::new(stub, dest.pids));
 
         Ok(pipe)
@@ -41,8 +41,16 @@ impl Transport for Ipc {
 
     fn bind(&self, dest: &Destination) -> io::Result<Box<Acceptor>> {
         let addr = String::from(dest.addr);
-        let acceptor = Box::new(IpcAcceptor::new(addr, dest.pids, dest.recv_max_size));
+        let acceptor = Box::new(IpcAcceptor::new(addr, dest.pids, dest.recv_max_size, dest.oversize_policy, dest.send_chunk_size, dest.versions.clone(), dest.recv_pool.cloned()));
 
         Ok(acceptor)
     }
+
+    fn info(&self) -> TransportInfo {
+        TransportInfo {
+            kind: TransportKind::Ipc,
+            vectored_io: false,
+            suggested_chunk_size: 64 * 1024
+        }
+    }
 }
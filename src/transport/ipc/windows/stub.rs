@@ -14,8 +14,10 @@ use mio;
 use mio_named_pipes::NamedPipe;
 
 use core::Message;
+use core::pool::RecvPool;
 use transport::ipc::send::SendOperation;
 use transport::ipc::recv::RecvOperation;
+use transport::{ProtocolVersions, Features, OversizePolicy};
 use transport::async::stub::*;
 use io_error::*;
 
@@ -29,8 +31,14 @@ pub struct IpcPipeStub {
     server: bool,
     named_pipe: NamedPipe,
     recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>,
     send_operation: Option<SendOperation>,
-    recv_operation: Option<RecvOperation>
+    recv_operation: Option<RecvOperation>,
+    dropped: usize,
+    handshake: PartialHandshake
 }
 
 impl Deref for IpcPipeStub {
@@ -41,23 +49,35 @@ impl Deref for IpcPipeStub {
 }
 
 impl IpcPipeStub {
-    pub fn new_server(named_pipe: NamedPipe, recv_max_size: u64) -> IpcPipeStub {
+    pub fn new_server(named_pipe: NamedPipe, recv_max_size: u64, policy: OversizePolicy, chunk_size: usize, protocol_versions: ProtocolVersions, pool: Option<RecvPool>) -> IpcPipeStub {
         IpcPipeStub {
             server: true,
             named_pipe: named_pipe,
             recv_max_size: recv_max_size,
+            oversize_policy: policy,
+            send_chunk_size: chunk_size,
+            versions: protocol_versions,
+            recv_pool: pool,
             send_operation: None,
-            recv_operation: None
+            recv_operation: None,
+            dropped: 0,
+            handshake: PartialHandshake::default()
         }
     }
 
-    pub fn new_client(named_pipe: NamedPipe, recv_max_size: u64) -> IpcPipeStub {
+    pub fn new_client(named_pipe: NamedPipe, recv_max_size: u64, policy: OversizePolicy, chunk_size: usize, protocol_versions: ProtocolVersions, pool: Option<RecvPool>) -> IpcPipeStub {
         IpcPipeStub {
             server: false,
             named_pipe: named_pipe,
             recv_max_size: recv_max_size,
+            oversize_policy: policy,
+            send_chunk_size: chunk_size,
+            versions: protocol_versions,
+            recv_pool: pool,
             send_operation: None,
-            recv_operation: None
+            recv_operation: None,
+            dropped: 0,
+            handshake: PartialHandshake::default()
         }
     }
 
@@ -71,7 +91,11 @@ impl IpcPipeStub {
     }
 
     fn run_recv_operation(&mut self, mut recv_operation: RecvOperation) -> io::Result<Option<Message>> {
-        match recv_operation.run(&mut self.named_pipe)? {
+        let progress = recv_operation.run(&mut self.named_pipe);
+
+        self.dropped += recv_operation.take_dropped();
+
+        match progress? {
             Some(msg) => Ok(Some(msg)),
             None => {
                 self.recv_operation = Some(recv_operation);
@@ -95,7 +119,7 @@ impl Drop for IpcPipeStub {
 
 impl Sender for IpcPipeStub {
     fn start_send(&mut self, msg: Rc<Message>) -> io::Result<bool> {
-        let send_operation = SendOperation::new(msg);
+        let send_operation = SendOperation::new(msg, self.send_chunk_size);
 
         self.run_send_operation(send_operation)
     }
@@ -121,7 +145,7 @@ impl Sender for IpcPipeStub {
 
 impl Receiver for IpcPipeStub {
     fn start_recv(&mut self) -> io::Result<Option<Message>> {
-        let recv_operation = RecvOperation::new(self.recv_max_size);
+        let recv_operation = RecvOperation::new(self.recv_max_size, self.oversize_policy, self.recv_pool.clone());
 
         self.run_recv_operation(recv_operation)
     }
@@ -137,6 +161,10 @@ impl Receiver for IpcPipeStub {
     fn has_pending_recv(&self) -> bool {
         self.recv_operation.is_some()
     }
+
+    fn take_dropped(&mut self) -> usize {
+        ::std::mem::replace(&mut self.dropped, 0)
+    }
 }
 
 /*****************************************************************************/
@@ -147,10 +175,13 @@ impl Receiver for IpcPipeStub {
 
 impl Handshake for IpcPipeStub {
     fn send_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
-        send_and_check_handshake(&mut self.named_pipe, pids)
+        send_and_check_handshake(&mut self.named_pipe, pids, &self.versions)
+    }
+    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<(u16, Features)> {
+        recv_and_check_handshake(&mut self.named_pipe, pids, &self.versions, &mut self.handshake)
     }
-    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
-        recv_and_check_handshake(&mut self.named_pipe, pids)
+    fn send_busy_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
+        send_busy_handshake(&mut self.named_pipe, pids, &self.versions)
     }
 }
 
@@ -0,0 +1,546 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+//! Resolution of the host names found in the urls of the network transports.
+//! A name is resolved on a helper thread, so that a slow resolver never blocks the event loop,
+//! the endpoint waiting for the resolved addresses before connecting or binding.
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::io;
+use std::net::{self, ToSocketAddrs};
+use std::rc::Rc;
+use std::str::FromStr;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::Duration;
+
+use mio::{self, Evented, Ready, PollOpt};
+use mio_extras::channel;
+
+use core::Message;
+use transport::{Address, Addresses, PeerCred};
+use transport::endpoint::EndpointRegistrar;
+use transport::pipe::{self, Pipe};
+use transport::acceptor::{self, Acceptor};
+use io_error::*;
+
+/// Creates a pipe connected to one of the specified addresses.
+pub type Connector = Box<dyn FnMut(&net::SocketAddr) -> io::Result<Box<dyn Pipe>>>;
+
+/// Creates an acceptor bound to one of the specified addresses.
+pub type Binder = Box<dyn FnMut(&net::SocketAddr) -> io::Result<Box<dyn Acceptor>>>;
+
+/// Parses a `host:port` address, returning the socket address when the host is a literal ip address,
+/// or nothing when it is a name that needs to be resolved.
+/// Fails with `InvalidInput` when the address is malformed.
+pub fn parse(addr: &str) -> io::Result<Option<net::SocketAddr>> {
+    if let Ok(addr) = net::SocketAddr::from_str(addr) {
+        return Ok(Some(addr));
+    }
+
+    match addr.rfind(':') {
+        Some(index) if index > 0 && u16::from_str(&addr[index + 1..]).is_ok() => Ok(None),
+        _ => Err(invalid_input_io_error(format!("{} is not a valid address", addr)))
+    }
+}
+
+/// Resolves a host name into the addresses it maps to, in the resolver order.
+/// A failure has the `Other` kind, so that the endpoint is retried later.
+pub fn resolve(addr: &str) -> io::Result<Vec<net::SocketAddr>> {
+    match addr.to_socket_addrs() {
+        Ok(addrs) => {
+            let addrs: Vec<_> = addrs.collect();
+
+            if addrs.is_empty() {
+                Err(other_io_error(format!("{} did not resolve to any address", addr)))
+            } else {
+                Ok(addrs)
+            }
+        },
+        Err(e) => Err(other_io_error(format!("{}: {}", addr, e)))
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Lookup                                                                    */
+/*                                                                           */
+/*****************************************************************************/
+
+/// Resolution running on a helper thread, whose receiver becomes readable once the addresses are known.
+pub struct Lookup {
+    rx: channel::Receiver<io::Result<Vec<net::SocketAddr>>>
+}
+
+impl Lookup {
+    pub fn start(addr: &str) -> io::Result<Lookup> {
+        let (tx, receiver) = channel::channel();
+        let name = addr.to_owned();
+
+        thread::Builder::new().name(String::from("scaproust-lookup")).spawn(move || {
+            let _ = tx.send(resolve(&name));
+        })?;
+
+        Ok(Lookup { rx: receiver })
+    }
+
+    fn register<R : EndpointRegistrar + ?Sized>(&self, ctx: &mut R) {
+        ctx.register(&self.rx, Ready::readable(), PollOpt::edge());
+    }
+
+    fn deregister<R : EndpointRegistrar + ?Sized>(&self, ctx: &mut R) {
+        ctx.deregister(&self.rx);
+    }
+
+    /// Returns the outcome of the resolution once it is completed.
+    fn try_take(&self) -> Option<io::Result<Vec<net::SocketAddr>>> {
+        match self.rx.try_recv() {
+            Ok(res) => Some(res),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(other_io_error("host name lookup aborted")))
+        }
+    }
+
+    #[cfg(test)]
+    fn completed(res: io::Result<Vec<net::SocketAddr>>) -> Lookup {
+        let (tx, receiver) = channel::channel();
+
+        let _ = tx.send(res);
+
+        Lookup { rx: receiver }
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Connecting pipe                                                           */
+/*                                                                           */
+/*****************************************************************************/
+
+/// Pipe waiting for a host name to be resolved, then trying each of the addresses in turn,
+/// falling back on the next one when the connection fails before the handshake is completed.
+/// Once an address is connected, the pipe just forwards to the one created by the connector.
+pub struct ConnectingPipe {
+    lookup: Option<Lookup>,
+    addrs: VecDeque<net::SocketAddr>,
+    connector: Connector,
+    inner: Option<Box<dyn Pipe>>,
+    opened: bool
+}
+
+impl ConnectingPipe {
+    pub fn new(lookup: Lookup, connect: Connector) -> ConnectingPipe {
+        ConnectingPipe {
+            lookup: Some(lookup),
+            addrs: VecDeque::new(),
+            connector: connect,
+            inner: None,
+            opened: false
+        }
+    }
+
+    fn on_lookup_ready(&mut self, ctx: &mut dyn pipe::Context) {
+        let res = match self.lookup.as_ref().and_then(|lookup| lookup.try_take()) {
+            Some(res) => res,
+            None => return
+        };
+
+        if let Some(lookup) = self.lookup.take() {
+            lookup.deregister(ctx);
+        }
+
+        match res {
+            Ok(addrs) => {
+                self.addrs = addrs.into_iter().collect();
+                self.connect_next(ctx, None);
+            },
+            Err(e) => ctx.raise(pipe::Event::Error(e))
+        }
+    }
+
+    fn connect_next(&mut self, ctx: &mut dyn pipe::Context, failure: Option<io::Error>) {
+        let mut last_err = failure;
+
+        while let Some(addr) = self.addrs.pop_front() {
+            match (self.connector)(&addr) {
+                Ok(pipe) => {
+                    self.inner = Some(pipe);
+                    return self.watch(ctx, |pipe, ctx| pipe.open(ctx));
+                },
+                Err(e) => last_err = Some(e)
+            }
+        }
+
+        ctx.raise(pipe::Event::Error(last_err.unwrap_or_else(|| other_io_error("no address to connect to"))));
+    }
+
+    /// Runs the specified operation on the inner pipe, trying the next address if it fails before being opened.
+    fn watch<F>(&mut self, ctx: &mut dyn pipe::Context, f: F)
+    where F : FnOnce(&mut Box<dyn Pipe>, &mut dyn pipe::Context) {
+        let failure = match self.inner {
+            Some(ref mut pipe) if self.opened => return f(pipe, ctx),
+            Some(ref mut pipe) => {
+                let mut watcher = Watcher::new(ctx);
+
+                f(pipe, &mut watcher);
+                self.opened = watcher.opened;
+                watcher.failure
+            },
+            None => None
+        };
+
+        if let Some(err) = failure {
+            if self.addrs.is_empty() {
+                ctx.raise(pipe::Event::Error(err));
+            } else {
+                debug!("[{:?}] connection failed, trying the next address: {}", ctx, err);
+                if let Some(mut pipe) = self.inner.take() {
+                    pipe.close(&mut Watcher::muted(ctx));
+                }
+                self.connect_next(ctx, Some(err));
+            }
+        }
+    }
+}
+
+impl Pipe for ConnectingPipe {
+    fn ready(&mut self, ctx: &mut dyn pipe::Context, events: Ready) {
+        if self.lookup.is_some() {
+            self.on_lookup_ready(ctx);
+        } else {
+            self.watch(ctx, |pipe, ctx| pipe.ready(ctx, events));
+        }
+    }
+
+    fn open(&mut self, ctx: &mut dyn pipe::Context) {
+        if let Some(ref lookup) = self.lookup {
+            lookup.register(ctx);
+        }
+    }
+
+    fn close(&mut self, ctx: &mut dyn pipe::Context) {
+        if let Some(lookup) = self.lookup.take() {
+            lookup.deregister(ctx);
+        }
+
+        match self.inner {
+            Some(ref mut pipe) => pipe.close(ctx),
+            None => ctx.raise(pipe::Event::Closed)
+        }
+    }
+
+    fn send(&mut self, ctx: &mut dyn pipe::Context, msg: Rc<Message>) {
+        self.watch(ctx, |pipe, ctx| pipe.send(ctx, msg));
+    }
+
+    fn recv(&mut self, ctx: &mut dyn pipe::Context) {
+        self.watch(ctx, |pipe, ctx| pipe.recv(ctx));
+    }
+
+    fn flush(&mut self, ctx: &mut dyn pipe::Context) {
+        self.watch(ctx, |pipe, ctx| pipe.flush(ctx));
+    }
+
+    fn addresses(&self) -> Addresses {
+        self.inner.as_ref().map_or((None, None), |pipe| pipe.addresses())
+    }
+
+    fn handshake_duration(&self) -> Option<Duration> {
+        self.inner.as_ref().and_then(|pipe| pipe.handshake_duration())
+    }
+
+    fn peer_cred(&self) -> io::Result<PeerCred> {
+        match self.inner {
+            Some(ref pipe) => pipe.peer_cred(),
+            None => Err(not_connected_io_error("host name not resolved yet"))
+        }
+    }
+}
+
+/// Context given to the inner pipe until it is opened, holding back the error that makes the pipe try the next address.
+struct Watcher<'a> {
+    ctx: &'a mut dyn pipe::Context,
+    muted: bool,
+    opened: bool,
+    failure: Option<io::Error>
+}
+
+impl<'a> Watcher<'a> {
+    fn new(inner_ctx: &'a mut dyn pipe::Context) -> Watcher<'a> {
+        Watcher {
+            ctx: inner_ctx,
+            muted: false,
+            opened: false,
+            failure: None
+        }
+    }
+
+    fn muted(inner_ctx: &'a mut dyn pipe::Context) -> Watcher<'a> {
+        Watcher {
+            ctx: inner_ctx,
+            muted: true,
+            opened: false,
+            failure: None
+        }
+    }
+}
+
+impl<'a> EndpointRegistrar for Watcher<'a> {
+    fn register(&mut self, io: &dyn Evented, interest: Ready, opt: PollOpt) {
+        self.ctx.register(io, interest, opt)
+    }
+    fn reregister(&mut self, io: &dyn Evented, interest: Ready, opt: PollOpt) {
+        self.ctx.reregister(io, interest, opt)
+    }
+    fn deregister(&mut self, io: &dyn Evented) {
+        self.ctx.deregister(io)
+    }
+}
+
+impl<'a> fmt::Debug for Watcher<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(&*self.ctx, f)
+    }
+}
+
+impl<'a> pipe::Context for Watcher<'a> {
+    fn raise(&mut self, evt: pipe::Event) {
+        if self.muted {
+            return;
+        }
+
+        match evt {
+            pipe::Event::Error(e) => self.failure = Some(e),
+            pipe::Event::Opened(..) => {
+                self.opened = true;
+                self.ctx.raise(evt)
+            },
+            _ => self.ctx.raise(evt)
+        }
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Binding acceptor                                                          */
+/*                                                                           */
+/*****************************************************************************/
+
+/// Acceptor waiting for a host name to be resolved, then bound to the first address that can be,
+/// the failure to bind any of them being raised like any other acceptor error.
+/// Once bound, the acceptor just forwards to the one created by the binder.
+pub struct BindingAcceptor {
+    lookup: Option<Lookup>,
+    binder: Binder,
+    inner: Option<Box<dyn Acceptor>>
+}
+
+impl BindingAcceptor {
+    pub fn new(lookup: Lookup, bind: Binder) -> BindingAcceptor {
+        BindingAcceptor {
+            lookup: Some(lookup),
+            binder: bind,
+            inner: None
+        }
+    }
+
+    fn on_lookup_ready(&mut self, ctx: &mut dyn acceptor::Context) {
+        let res = match self.lookup.as_ref().and_then(|lookup| lookup.try_take()) {
+            Some(res) => res,
+            None => return
+        };
+
+        if let Some(lookup) = self.lookup.take() {
+            lookup.deregister(ctx);
+        }
+
+        match res.and_then(|addrs| self.bind_any(addrs)) {
+            Ok(mut acceptor) => {
+                acceptor.open(ctx);
+                self.inner = Some(acceptor);
+            },
+            Err(e) => ctx.raise(acceptor::Event::Error(e))
+        }
+    }
+
+    fn bind_any(&mut self, addrs: Vec<net::SocketAddr>) -> io::Result<Box<dyn Acceptor>> {
+        let mut last_err = None;
+
+        for addr in addrs {
+            match (self.binder)(&addr) {
+                Ok(acceptor) => return Ok(acceptor),
+                Err(e) => last_err = Some(e)
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| other_io_error("no address to bind to")))
+    }
+}
+
+impl Acceptor for BindingAcceptor {
+    fn ready(&mut self, ctx: &mut dyn acceptor::Context, events: mio::Ready) {
+        if self.lookup.is_some() {
+            self.on_lookup_ready(ctx);
+        } else if let Some(ref mut acceptor) = self.inner {
+            acceptor.ready(ctx, events);
+        }
+    }
+
+    fn open(&mut self, ctx: &mut dyn acceptor::Context) {
+        if let Some(ref lookup) = self.lookup {
+            lookup.register(ctx);
+        }
+    }
+
+    fn close(&mut self, ctx: &mut dyn acceptor::Context) {
+        if let Some(lookup) = self.lookup.take() {
+            lookup.deregister(ctx);
+        }
+
+        match self.inner {
+            Some(ref mut acceptor) => acceptor.close(ctx),
+            None => ctx.raise(acceptor::Event::Closed)
+        }
+    }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.inner.as_ref().and_then(|acceptor| acceptor.local_addr())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::net;
+    use std::rc::Rc;
+    use std::cell::RefCell;
+
+    use mio;
+
+    use core::Message;
+    use transport::Features;
+    use transport::tests::TestPipeContext;
+    use transport::pipe::{self, Pipe};
+    use io_error::*;
+
+    use super::*;
+
+    /// Pipe raising the specified outcome of the handshake once opened.
+    struct ScriptedPipe {
+        fails: bool,
+        closed: Rc<RefCell<usize>>
+    }
+
+    impl Pipe for ScriptedPipe {
+        fn ready(&mut self, _: &mut dyn pipe::Context, _: mio::Ready) {}
+        fn open(&mut self, ctx: &mut dyn pipe::Context) {
+            if self.fails {
+                ctx.raise(pipe::Event::Error(refused_io_error("connection refused")));
+            } else {
+                ctx.raise(pipe::Event::Opened(0, Features::default()));
+            }
+        }
+        fn close(&mut self, ctx: &mut dyn pipe::Context) {
+            *self.closed.borrow_mut() += 1;
+            ctx.raise(pipe::Event::Closed);
+        }
+        fn send(&mut self, _: &mut dyn pipe::Context, _: Rc<Message>) {}
+        fn recv(&mut self, _: &mut dyn pipe::Context) {}
+        fn flush(&mut self, _: &mut dyn pipe::Context) {}
+    }
+
+    fn addr(port: u16) -> net::SocketAddr {
+        net::SocketAddr::from(([127, 0, 0, 1], port))
+    }
+
+    fn connector(failing_ports: Vec<u16>, tried: Rc<RefCell<Vec<u16>>>, closed: Rc<RefCell<usize>>) -> Connector {
+        Box::new(move |addr: &net::SocketAddr| {
+            tried.borrow_mut().push(addr.port());
+
+            let pipe = ScriptedPipe {
+                fails: failing_ports.contains(&addr.port()),
+                closed: closed.clone()
+            };
+
+            Ok(Box::new(pipe) as Box<dyn Pipe>)
+        })
+    }
+
+    #[test]
+    fn parse_tells_literal_addresses_from_names() {
+        assert_eq!(Some(addr(5454)), parse("127.0.0.1:5454").unwrap());
+        assert_eq!(None, parse("localhost:5454").unwrap());
+        assert_eq!(io::ErrorKind::InvalidInput, parse("localhost").unwrap_err().kind());
+        assert_eq!(io::ErrorKind::InvalidInput, parse(":5454").unwrap_err().kind());
+    }
+
+    #[test]
+    fn resolve_failure_can_be_retried() {
+        let err = resolve("name.invalid:5454").unwrap_err();
+
+        assert_eq!(io::ErrorKind::Other, err.kind());
+    }
+
+    #[test]
+    fn connecting_pipe_falls_back_on_the_next_address_when_the_connection_fails() {
+        let tried = Rc::new(RefCell::new(Vec::new()));
+        let closed = Rc::new(RefCell::new(0));
+        let lookup = Lookup::completed(Ok(vec![addr(1), addr(2), addr(3)]));
+        let mut pipe = ConnectingPipe::new(lookup, connector(vec![1], tried.clone(), closed.clone()));
+        let mut ctx = TestPipeContext::new();
+
+        pipe.open(&mut ctx);
+        pipe.ready(&mut ctx, mio::Ready::readable());
+
+        assert_eq!(vec![1, 2], *tried.borrow());
+        assert_eq!(1, *closed.borrow());
+        assert_eq!(1, ctx.get_raised_events().len());
+        match ctx.get_raised_events()[0] {
+            pipe::Event::Opened(..) => {},
+            ref other => panic!("unexpected event {:?}", other)
+        }
+    }
+
+    #[test]
+    fn connecting_pipe_fails_once_every_address_failed() {
+        let tried = Rc::new(RefCell::new(Vec::new()));
+        let closed = Rc::new(RefCell::new(0));
+        let lookup = Lookup::completed(Ok(vec![addr(1), addr(2)]));
+        let mut pipe = ConnectingPipe::new(lookup, connector(vec![1, 2], tried.clone(), closed.clone()));
+        let mut ctx = TestPipeContext::new();
+
+        pipe.open(&mut ctx);
+        pipe.ready(&mut ctx, mio::Ready::readable());
+
+        assert_eq!(vec![1, 2], *tried.borrow());
+        assert_eq!(1, ctx.get_raised_events().len());
+        match ctx.get_raised_events()[0] {
+            pipe::Event::Error(ref e) => assert_eq!(io::ErrorKind::ConnectionRefused, e.kind()),
+            ref other => panic!("unexpected event {:?}", other)
+        }
+    }
+
+    #[test]
+    fn connecting_pipe_raises_the_lookup_failure() {
+        let tried = Rc::new(RefCell::new(Vec::new()));
+        let closed = Rc::new(RefCell::new(0));
+        let lookup = Lookup::completed(Err(other_io_error("lookup failed")));
+        let mut pipe = ConnectingPipe::new(lookup, connector(vec![], tried.clone(), closed));
+        let mut ctx = TestPipeContext::new();
+
+        pipe.open(&mut ctx);
+        pipe.ready(&mut ctx, mio::Ready::readable());
+
+        assert!(tried.borrow().is_empty());
+        assert_eq!(1, ctx.get_registrations().len());
+        assert_eq!(1, ctx.get_deregistrations());
+        match ctx.get_raised_events()[0] {
+            pipe::Event::Error(ref e) => assert_eq!(io::ErrorKind::Other, e.kind()),
+            ref other => panic!("unexpected event {:?}", other)
+        }
+    }
+}
@@ -7,22 +7,321 @@
 pub mod async;
 pub mod tcp;
 pub mod ipc;
+pub mod ws;
 pub mod endpoint;
 pub mod pipe;
 pub mod acceptor;
+pub mod lookup;
 
 use std::io::Result;
+use std::{error, io};
+use std::net;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::Duration;
+#[cfg(unix)]
+use std::os::unix::net::UnixStream;
+
+use core::pool::RecvPool;
 
 pub struct Destination<'a> {
     pub addr: &'a str,
     pub pids: (u16, u16),
     pub tcp_no_delay: bool,
-    pub recv_max_size: u64
+    pub tcp_keepalive: Option<Duration>,
+    pub reuse_addr: bool,
+    pub connect_source_addr: Option<net::SocketAddr>,
+    pub ipc_permissions: Option<u32>,
+    pub recv_max_size: u64,
+    pub send_chunk_size: usize,
+    pub oversize_policy: OversizePolicy,
+    pub versions: &'a ProtocolVersions,
+    pub recv_pool: Option<&'a RecvPool>
+}
+
+impl<'a> Destination<'a> {
+    /// Copies the destination, so that it can be used once the call to the transport returned.
+    pub fn to_buf(&self) -> DestinationBuf {
+        DestinationBuf {
+            addr: self.addr.to_owned(),
+            pids: self.pids,
+            tcp_no_delay: self.tcp_no_delay,
+            tcp_keepalive: self.tcp_keepalive,
+            reuse_addr: self.reuse_addr,
+            connect_source_addr: self.connect_source_addr,
+            ipc_permissions: self.ipc_permissions,
+            recv_max_size: self.recv_max_size,
+            send_chunk_size: self.send_chunk_size,
+            oversize_policy: self.oversize_policy,
+            versions: self.versions.clone(),
+            recv_pool: self.recv_pool.cloned()
+        }
+    }
+}
+
+/// Owned copy of a destination, kept by the endpoints that connect or bind later on,
+/// like those waiting for a host name to be resolved.
+pub struct DestinationBuf {
+    addr: String,
+    pids: (u16, u16),
+    tcp_no_delay: bool,
+    tcp_keepalive: Option<Duration>,
+    reuse_addr: bool,
+    connect_source_addr: Option<net::SocketAddr>,
+    ipc_permissions: Option<u32>,
+    recv_max_size: u64,
+    send_chunk_size: usize,
+    oversize_policy: OversizePolicy,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>
+}
+
+impl DestinationBuf {
+    pub fn as_dest(&self) -> Destination<'_> {
+        Destination {
+            addr: &self.addr,
+            pids: self.pids,
+            tcp_no_delay: self.tcp_no_delay,
+            tcp_keepalive: self.tcp_keepalive,
+            reuse_addr: self.reuse_addr,
+            connect_source_addr: self.connect_source_addr,
+            ipc_permissions: self.ipc_permissions,
+            recv_max_size: self.recv_max_size,
+            send_chunk_size: self.send_chunk_size,
+            oversize_policy: self.oversize_policy,
+            versions: &self.versions,
+            recv_pool: self.recv_pool.as_ref()
+        }
+    }
+}
+
+/// What a pipe does when it receives a message longer than the maximum size.
+/// See [ConfigOption::OversizePolicy](../enum.ConfigOption.html#variant.OversizePolicy).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OversizePolicy {
+    /// The pipe fails and is closed.
+    ClosePipe,
+    /// The message is read off the stream and discarded, keeping the pipe alive.
+    SkipMessage
+}
+
+/// Error carried by the `io::Error` raised by a pipe whose peer announces a message longer than
+/// [ConfigOption::RecvMaxSize](../enum.ConfigOption.html#variant.RecvMaxSize).
+/// The length is checked as soon as the size prefix is read, before the payload is allocated,
+/// and the error has the `InvalidData` kind. 
+/// It causes the pipe to be closed, unless the oversize policy is `SkipMessage`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MessageTooLong {
+    pub max_size: u64,
+    pub actual: u64
+}
+
+impl MessageTooLong {
+    /// Extracts the message length excess carried by the specified error, if any.
+    pub fn of(err: &io::Error) -> Option<MessageTooLong> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<MessageTooLong>()).cloned()
+    }
+}
+
+impl fmt::Display for MessageTooLong {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "message of {} bytes exceeds the maximum size of {} bytes", self.actual, self.max_size)
+    }
+}
+
+impl error::Error for MessageTooLong {}
+
+/// Error carried by the `io::Error` raised by a pipe whose peer answered the handshake with a busy signal,
+/// because it already had as many accepted pipes as allowed by
+/// [ConfigOption::MaxAcceptedPipes](../enum.ConfigOption.html#variant.MaxAcceptedPipes).
+/// The error has the `ConnectionRefused` kind, and the endpoint waits for the longest retry interval before reconnecting.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerBusy;
+
+impl PeerBusy {
+    /// Extracts the busy signal carried by the specified error, if any.
+    pub fn of(err: &io::Error) -> Option<PeerBusy> {
+        err.get_ref().and_then(|inner| inner.downcast_ref::<PeerBusy>()).cloned()
+    }
+}
+
+impl fmt::Display for PeerBusy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "peer is busy")
+    }
+}
+
+impl error::Error for PeerBusy {}
+
+/// Version of the SP protocol advertised in the handshake, 
+/// along with the peer versions accepted besides that one,
+/// and the feature bits advertised in the last reserved byte of the handshake.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ProtocolVersions {
+    pub advertised: u8,
+    pub compatible: Vec<u8>,
+    pub features: u8
+}
+
+impl ProtocolVersions {
+    /// Whether the handshake of a peer advertising the specified version can be accepted.
+    pub fn accepts(&self, version: u8) -> bool {
+        self.advertised == version || self.compatible.contains(&version)
+    }
+}
+
+/// Feature bit telling that the frames of the pipe start with an extension block,
+/// see [Message::get_deadline](../struct.Message.html#method.get_deadline).
+pub const FEATURE_EXTENSIONS: u8 = 0x01;
+
+/// Feature bit telling that the socket takes part in the credit based flow control between `Push` and `Pull`,
+/// see [ConfigOption::CreditFlowControl](../enum.ConfigOption.html#variant.CreditFlowControl).
+pub const FEATURE_CREDIT: u8 = 0x02;
+
+/// Feature bit telling that each message sent by a `Push` socket to a `Pull` socket starts with a sequence id
+/// to be acknowledged, see [ConfigOption::RequireAck](../enum.ConfigOption.html#variant.RequireAck).
+pub const FEATURE_SEQUENCED: u8 = 0x04;
+
+/// Feature bits of both sides of a pipe, as advertised in their handshakes.
+/// Each bit enables an optional feature that changes what is sent on the pipe,
+/// and is only advertised by a socket having enabled the matching option, 
+/// so that by default the handshake stays the one expected by the other implementations of the protocols.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features {
+    pub local: u8,
+    pub peer: u8
+}
+
+impl Features {
+    /// Whether the peer advertised the specified feature.
+    pub fn peer_has(&self, feature: u8) -> bool {
+        self.peer & feature == feature
+    }
+
+    /// Whether both sides advertised the specified feature.
+    pub fn both_have(&self, feature: u8) -> bool {
+        self.local & self.peer & feature == feature
+    }
+}
+
+/// Kind of transport an endpoint is using.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransportKind {
+    Tcp,
+    Ipc,
+    Ws,
+    /// Any transport registered by the application.
+    Custom
+}
+
+/// Static properties of a transport, that applications can use to tune their message sizes.
+/// See [Endpoint::transport_info](../struct.Endpoint.html#method.transport_info).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TransportInfo {
+    pub kind: TransportKind,
+    /// Whether the headers and the payload of a message are written in a single vectored write.
+    pub vectored_io: bool,
+    /// Number of payload bytes the transport can comfortably carry in one write,
+    /// zero value meaning that the transport has no preference.
+    pub suggested_chunk_size: usize
+}
+
+impl Default for TransportInfo {
+    fn default() -> TransportInfo {
+        TransportInfo {
+            kind: TransportKind::Custom,
+            vectored_io: false,
+            suggested_chunk_size: 0
+        }
+    }
+}
+
+/// Transport-neutral address of one side of an endpoint,
+/// see [Endpoint::local_addr](../struct.Endpoint.html#method.local_addr).
+/// It is displayed as `ip:port` for tcp and as the socket path for ipc.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    Tcp(net::SocketAddr),
+    Ipc(PathBuf),
+    /// Any address reported by a transport registered by the application.
+    Custom(String)
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Address::Tcp(ref addr)  => write!(f, "{}", addr),
+            Address::Ipc(ref path)  => write!(f, "{}", path.display()),
+            Address::Custom(ref s)  => write!(f, "{}", s)
+        }
+    }
+}
+
+/// Local and peer addresses of a pipe, each of them being `None` when it is unknown.
+pub type Addresses = (Option<Address>, Option<Address>);
+
+/// Credentials of the process on the other end of a local connection,
+/// see [Socket::peer_cred](../struct.Socket.html#method.peer_cred).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PeerCred {
+    pub uid: u32,
+    pub gid: u32,
+    pub pid: i32
+}
+
+/// An already connected stream, that a socket can use as a pipe,
+/// skipping the connection logic of the transports.
+/// See [Socket::connect_stream](../struct.Socket.html#method.connect_stream).
+pub enum Stream {
+    Tcp(net::TcpStream),
+    #[cfg(unix)]
+    Unix(UnixStream)
+}
+
+impl Stream {
+    /// Url describing the peer of the stream, used to identify the endpoint.
+    pub fn url(&self) -> String {
+        match *self {
+            Stream::Tcp(ref s) => match s.peer_addr() {
+                Ok(addr) => format!("tcp://{}", addr),
+                Err(_) => String::from("tcp://")
+            },
+            #[cfg(unix)]
+            Stream::Unix(ref s) => match s.peer_addr().ok().and_then(|addr| addr.as_pathname().map(|p| p.display().to_string())) {
+                Some(path) => format!("ipc://{}", path),
+                None => String::from("ipc://")
+            }
+        }
+    }
+
+    pub fn into_pipe(self, dest: &Destination) -> Result<Box<dyn pipe::Pipe>> {
+        match self {
+            Stream::Tcp(s) => tcp::connect_stream(s, dest),
+            #[cfg(unix)]
+            Stream::Unix(s) => ipc::connect_stream(s, dest)
+        }
+    }
+}
+
+impl From<net::TcpStream> for Stream {
+    fn from(stream: net::TcpStream) -> Stream {
+        Stream::Tcp(stream)
+    }
+}
+
+#[cfg(unix)]
+impl From<UnixStream> for Stream {
+    fn from(stream: UnixStream) -> Stream {
+        Stream::Unix(stream)
+    }
 }
 
 pub trait Transport {
     fn connect(&self, dest: &Destination) -> Result<Box<dyn pipe::Pipe>>;
     fn bind(&self, dest: &Destination) -> Result<Box<dyn acceptor::Acceptor>>;
+    fn info(&self) -> TransportInfo {
+        TransportInfo::default()
+    }
 }
 
 #[cfg(test)]
@@ -7,26 +7,35 @@
 use std::rc::Rc;
 use std::io;
 use std::fmt;
+use std::time::Duration;
 
 use mio::Ready;
 
 use core::Message;
 use transport::endpoint::*;
+use transport::{Addresses, PeerCred, Features};
+use io_error::*;
 
 pub enum Command {
     Open,
     Close,
     Send(Rc<Message>),
-    Recv
+    Recv,
+    Flush,
+    Reject
 }
 
 pub enum Event {
-    Opened,
+    /// The handshake is completed, holding the protocol id of the peer and the features of both sides.
+    Opened(u16, Features),
     Closed,
     CanSend(bool),
     CanRecv(bool),
     Sent,
     Received(Message),
+    /// Some received messages were discarded, see [OversizePolicy](../enum.OversizePolicy.html).
+    Dropped(usize),
+    Flushed,
     Error(io::Error)
 }
 
@@ -36,6 +45,22 @@ pub trait Pipe {
     fn close(&mut self, ctx: &mut dyn Context);
     fn send(&mut self, ctx: &mut dyn Context, msg: Rc<Message>);
     fn recv(&mut self, ctx: &mut dyn Context);
+    fn flush(&mut self, ctx: &mut dyn Context);
+    /// Sends the busy signal to the peer instead of the handshake, then closes the pipe.
+    fn reject(&mut self, ctx: &mut dyn Context) {
+        self.close(ctx)
+    }
+    fn addresses(&self) -> Addresses {
+        (None, None)
+    }
+    /// Time elapsed from the creation of the pipe to the completion of its handshake, once completed.
+    fn handshake_duration(&self) -> Option<Duration> {
+        None
+    }
+    /// Credentials of the peer process, for the transports whose peers are local processes.
+    fn peer_cred(&self) -> io::Result<PeerCred> {
+        Err(unsupported_io_error("peer credentials are not supported by this transport"))
+    }
 }
 
 pub trait Context : EndpointRegistrar + fmt::Debug {
@@ -54,7 +79,9 @@ impl Command {
             Command::Open    => "Open",
             Command::Close   => "Close",
             Command::Send(_) => "Send",
-            Command::Recv    => "Recv"
+            Command::Recv    => "Recv",
+            Command::Flush   => "Flush",
+            Command::Reject  => "Reject"
         }
     }
 }
@@ -68,12 +95,14 @@ impl fmt::Debug for Event {
 impl Event {
     pub fn name(&self) -> &'static str {
         match *self {
-            Event::Opened      => "Opened",
+            Event::Opened(..)  => "Opened",
             Event::Closed      => "Closed",
             Event::CanSend(_)  => "CanSend",
             Event::CanRecv(_)  => "CanRecv",
             Event::Sent        => "Sent",
             Event::Received(_) => "Received",
+            Event::Dropped(_)  => "Dropped",
+            Event::Flushed     => "Flushed",
             Event::Error(_)    => "Error",
         }
     }
@@ -5,20 +5,28 @@
 // This file may not be copied, modified, or distributed except according to those terms.
 
 use std::io;
+use std::time::Duration;
 
 use mio;
 use mio::tcp::{TcpListener, TcpStream};
 
+use core::pool::RecvPool;
 use transport::*;
 use transport::acceptor::*;
 use transport::async::AsyncPipe;
 use super::stub::TcpPipeStub;
+use super::set_stream_options;
 
 pub struct TcpAcceptor {
     listener: TcpListener,
     proto_ids: (u16, u16),
     no_delay: bool,
-    recv_max_size: u64
+    keepalive: Option<Duration>,
+    recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>
 }
 
 impl TcpAcceptor {
@@ -28,7 +36,12 @@ impl TcpAcceptor {
             listener: l,
             proto_ids: dest.pids,
             no_delay: dest.tcp_no_delay,
-            recv_max_size: dest.recv_max_size
+            keepalive: dest.tcp_keepalive,
+            recv_max_size: dest.recv_max_size,
+            oversize_policy: dest.oversize_policy,
+            send_chunk_size: dest.send_chunk_size,
+            versions: dest.versions.clone(),
+            recv_pool: dest.recv_pool.cloned()
         }
     }
 
@@ -38,7 +51,7 @@ impl TcpAcceptor {
         loop {
             match self.listener.accept() {
                 Ok((stream, _)) => {
-                    let _ = stream.set_nodelay(self.no_delay);
+                    let _ = set_stream_options(&stream, self.no_delay, self.keepalive);
                     let pipe = self.create_pipe(stream);
 
                     pipes.push(pipe);
@@ -59,7 +72,7 @@ impl TcpAcceptor {
     }
 
     fn create_pipe(&self, stream: TcpStream) -> Box<dyn pipe::Pipe> {
-        let stub = TcpPipeStub::new(stream, self.recv_max_size);
+        let stub = TcpPipeStub::new(stream, self.recv_max_size, self.oversize_policy, self.send_chunk_size, self.versions.clone(), self.recv_pool.clone());
 
         Box::new(AsyncPipe::new(stub, self.proto_ids))
     }
@@ -81,4 +94,8 @@ impl acceptor::Acceptor for TcpAcceptor {
         ctx.deregister(&self.listener);
         ctx.raise(Event::Closed);
     }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.listener.local_addr().ok().map(Address::Tcp)
+    }
 }
\ No newline at end of file
@@ -9,51 +9,169 @@ mod send;
 mod recv;
 mod acceptor;
 
-use std::str::FromStr;
 use std::io;
 use std::net;
+use std::time::Duration;
 
 use mio::tcp::{TcpListener, TcpStream};
+use socket2::{Socket, Domain, Type};
 
 use self::stub::TcpPipeStub;
 use self::acceptor::TcpAcceptor;
-use transport::{Transport, Destination};
+use transport::{Transport, TransportInfo, TransportKind, Destination};
 use transport::pipe::Pipe;
 use transport::acceptor::Acceptor;
 use transport::async::AsyncPipe;
-use io_error::*;
+use transport::lookup::{self, Lookup, Connector, Binder, ConnectingPipe, BindingAcceptor};
 
+/// TCP transport, accepting urls like `tcp://host:port`.
+/// A host name is resolved on a helper thread, then each of its addresses is tried in turn.
 pub struct Tcp;
 
 impl Tcp {
     fn connect(&self, addr: &net::SocketAddr, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
-        let stream = TcpStream::connect(addr)?;
-        stream.set_nodelay(dest.tcp_no_delay)?;
-        let stub = TcpPipeStub::new(stream, dest.recv_max_size);
-        let pipe = AsyncPipe::new(stub, dest.pids);
+        let stream = connect_from(addr, dest.connect_source_addr)?;
 
-        Ok(Box::new(pipe))
+        create_pipe(stream, dest)
     }
     fn bind(&self, addr: &net::SocketAddr, dest: &Destination) -> io::Result<Box<dyn Acceptor>> {
-        let listener = TcpListener::bind(addr)?;
+        let listener = bind_listener(addr, dest.reuse_addr)?;
         let acceptor = TcpAcceptor::new(listener, dest);
 
         Ok(Box::new(acceptor))
     }
 }
 
+/// Creates a pipe running the SP handshake over an already connected stream.
+pub fn connect_stream(stream: net::TcpStream, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
+    let stream = TcpStream::from_stream(stream)?;
+
+    create_pipe(stream, dest)
+}
+
+fn create_pipe(stream: TcpStream, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
+    set_stream_options(&stream, dest.tcp_no_delay, dest.tcp_keepalive)?;
+    let stub = TcpPipeStub::new(stream, dest.recv_max_size, dest.oversize_policy, dest.send_chunk_size, dest.versions.clone(), dest.recv_pool.cloned());
+    let pipe = AsyncPipe::new(stub, dest.pids);
+
+    Ok(Box::new(pipe))
+}
+
+/// Applies the socket options of a connected or accepted stream.
+fn set_stream_options(stream: &TcpStream, no_delay: bool, keepalive: Option<Duration>) -> io::Result<()> {
+    stream.set_nodelay(no_delay)?;
+    stream.set_keepalive(keepalive)
+}
+
+/// Creates a listener bound to the address, setting `SO_REUSEADDR` beforehand as requested,
+/// except on Windows where the platform default is kept, since the option allows port hijacking there.
+#[doc(hidden)]
+pub fn bind_listener(addr: &net::SocketAddr, reuse_addr: bool) -> io::Result<TcpListener> {
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+
+    if cfg!(not(windows)) {
+        socket.set_reuse_address(reuse_addr)?;
+    }
+
+    socket.bind(&(*addr).into())?;
+    socket.listen(1024)?;
+
+    TcpListener::from_std(socket.into())
+}
+
+/// Starts connecting a stream to the address, binding it first to the source address when one is given.
+#[doc(hidden)]
+pub fn connect_from(addr: &net::SocketAddr, source: Option<net::SocketAddr>) -> io::Result<TcpStream> {
+    let source = match source {
+        Some(x) => x,
+        None => return TcpStream::connect(addr)
+    };
+    let socket = Socket::new(Domain::for_address(*addr), Type::STREAM, None)?;
+
+    socket.bind(&source.into())?;
+
+    TcpStream::connect_stream(socket.into(), addr)
+}
+
 impl Transport for Tcp {
     fn connect(&self, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
-        match net::SocketAddr::from_str(dest.addr) {
-            Ok(addr) => self.connect(&addr, dest),
-            Err(_) => Err(invalid_input_io_error(dest.addr))
+        if let Some(addr) = lookup::parse(dest.addr)? {
+            return self.connect(&addr, dest);
         }
+
+        let buf = dest.to_buf();
+        let connector: Connector = Box::new(move |addr| Tcp.connect(addr, &buf.as_dest()));
+
+        Ok(Box::new(ConnectingPipe::new(Lookup::start(dest.addr)?, connector)))
     }
 
     fn bind(&self, dest: &Destination) -> io::Result<Box<dyn Acceptor>> {
-        match net::SocketAddr::from_str(dest.addr) {
-            Ok(addr) => self.bind(&addr, dest),
-            Err(_) => Err(invalid_input_io_error(dest.addr))
+        if let Some(addr) = lookup::parse(dest.addr)? {
+            return self.bind(&addr, dest);
         }
+
+        let buf = dest.to_buf();
+        let binder: Binder = Box::new(move |addr| Tcp.bind(addr, &buf.as_dest()));
+
+        Ok(Box::new(BindingAcceptor::new(Lookup::start(dest.addr)?, binder)))
+    }
+
+    fn info(&self) -> TransportInfo {
+        TransportInfo {
+            kind: TransportKind::Tcp,
+            vectored_io: true,
+            suggested_chunk_size: 64 * 1024
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net;
+    use std::time::Duration;
+
+    use mio::tcp::TcpStream;
+
+    use super::*;
+
+    #[cfg(unix)]
+    fn so_keepalive(stream: &TcpStream) -> bool {
+        use std::mem;
+        use std::os::unix::io::AsRawFd;
+        use libc;
+
+        let mut value: libc::c_int = 0;
+        let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+        let res = unsafe {
+            libc::getsockopt(
+                stream.as_raw_fd(),
+                libc::SOL_SOCKET,
+                libc::SO_KEEPALIVE,
+                &mut value as *mut libc::c_int as *mut libc::c_void,
+                &mut len)
+        };
+
+        assert_eq!(0, res, "getsockopt failed: {}", io::Error::last_os_error());
+        value != 0
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn keepalive_should_be_set_on_the_connected_and_accepted_streams() {
+        let listener = net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let connected = TcpStream::connect(&listener.local_addr().unwrap()).unwrap();
+        let accepted = TcpStream::from_stream(listener.accept().unwrap().0).unwrap();
+
+        assert!(!so_keepalive(&connected));
+        assert!(!so_keepalive(&accepted));
+
+        set_stream_options(&connected, true, Some(Duration::from_secs(30))).unwrap();
+        set_stream_options(&accepted, true, Some(Duration::from_secs(30))).unwrap();
+
+        assert!(so_keepalive(&connected));
+        assert!(so_keepalive(&accepted));
+
+        set_stream_options(&connected, true, None).unwrap();
+        assert!(!so_keepalive(&connected));
     }
 }
@@ -14,32 +14,60 @@ use mio::tcp::TcpStream;
 use iovec::IoVec;
 
 use core::Message;
+use transport::async::stub::{chunk_end, StallGuard};
 use io_error::*;
 
+/// A stream that can write several buffers at once.
+pub trait WriteBufs : Write {
+    fn write_bufs(&mut self, buffers: &[&IoVec]) -> io::Result<usize>;
+}
+
+impl WriteBufs for TcpStream {
+    fn write_bufs(&mut self, buffers: &[&IoVec]) -> io::Result<usize> {
+        TcpStream::write_bufs(self, buffers)
+    }
+}
+
+impl<'a, T:WriteBufs> WriteBufs for StallGuard<'a, T> {
+    fn write_bufs(&mut self, buffers: &[&IoVec]) -> io::Result<usize> {
+        let len = buffers.iter().map(|b| b.len()).sum();
+
+        self.guard(len, |stream| stream.write_bufs(buffers))
+    }
+}
+
 pub struct SendOperation {
-    step: Option<SendOperationStep>
+    step: Option<SendOperationStep>,
+    chunk_size: usize
 }
 
 impl SendOperation {
-    pub fn new(msg: Rc<Message>) -> SendOperation {
+    pub fn new(msg: Rc<Message>, max_chunk: usize) -> SendOperation {
+        let step = if msg.get_frame().is_some() {
+            SendOperationStep::Frame(msg, 0)
+        } else {
+            SendOperationStep::TransportHdr(msg, 0)
+        };
+
         SendOperation { 
-            step: Some(SendOperationStep::TransportHdr(msg, 0))
+            step: Some(step),
+            chunk_size: max_chunk
         }
     }
 
-    pub fn run(&mut self, stream: &mut TcpStream) -> io::Result<bool> {
+    pub fn run<T:WriteBufs>(&mut self, stream: &mut T) -> io::Result<bool> {
         if let Some(step) = self.step.take() {
-            self.resume_at(stream, step)
+            self.resume_at(&mut StallGuard::new(stream), step)
         } else {
             Err(other_io_error("Cannot resume already finished send operation"))
         }
     }
 
-    fn resume_at(&mut self, stream: &mut TcpStream, step: SendOperationStep) -> io::Result<bool> {
+    fn resume_at<T:WriteBufs>(&mut self, stream: &mut T, step: SendOperationStep) -> io::Result<bool> {
         let mut cur_step = step;
 
         loop {
-            let (passed, next_step) = cur_step.advance(stream)?;
+            let (passed, next_step) = cur_step.advance(stream, self.chunk_size)?;
 
             if next_step.is_terminal() {
                 return Ok(true);
@@ -58,17 +86,19 @@ enum SendOperationStep {
     TransportHdr(Rc<Message>, usize),
     ProtocolHdr(Rc<Message>, usize),
     UsrPayload(Rc<Message>, usize),
+    Frame(Rc<Message>, usize),
     Terminal
 }
 
 impl SendOperationStep {
     /// Writes the buffers composing the message to the specified stream.
     /// Returns whether the step has passed, and what is the next step.
-    fn advance(self, stream: &mut TcpStream) -> io::Result<(bool, SendOperationStep)> {
+    fn advance<T:WriteBufs>(self, stream: &mut T, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
         match self {
-            SendOperationStep::TransportHdr(msg, written) => write_transport_hdr(stream, msg, written),
-            SendOperationStep::ProtocolHdr(msg, written) => write_protocol_hdr(stream, msg, written),
-            SendOperationStep::UsrPayload(msg, written) => write_usr_payload(stream, msg, written),
+            SendOperationStep::TransportHdr(msg, written) => write_transport_hdr(stream, msg, written, chunk_size),
+            SendOperationStep::ProtocolHdr(msg, written) => write_protocol_hdr(stream, msg, written, chunk_size),
+            SendOperationStep::UsrPayload(msg, written) => write_usr_payload(stream, msg, written, chunk_size),
+            SendOperationStep::Frame(msg, written) => write_frame(stream, msg, written, chunk_size),
             SendOperationStep::Terminal => Err(other_io_error("Cannot advance terminal step of send operation"))
         }
     }
@@ -81,7 +111,7 @@ impl SendOperationStep {
     }
 }
 
-fn write_transport_hdr(stream: &mut TcpStream, msg: Rc<Message>, mut written: usize) -> io::Result<(bool, SendOperationStep)> {
+fn write_transport_hdr<T:WriteBufs>(stream: &mut T, msg: Rc<Message>, mut written: usize, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
     let mut buffer = [0u8; 8];
 
     BigEndian::write_u64(&mut buffer, msg.len() as u64);
@@ -94,6 +124,7 @@ fn write_transport_hdr(stream: &mut TcpStream, msg: Rc<Message>, mut written: us
 
     if msg.get_header().len() == 0 {
         let payload = msg.get_body();
+        let payload = &payload[..chunk_end(payload.len(), 0, chunk_size)];
 
         written += if payload.len() == 0 {
             write_buffer(stream, transport_hdr)?
@@ -105,6 +136,7 @@ fn write_transport_hdr(stream: &mut TcpStream, msg: Rc<Message>, mut written: us
     } else {
         let proto_hdr = msg.get_header();
         let payload = msg.get_body();
+        let payload = &payload[..chunk_end(payload.len(), 0, chunk_size)];
 
         written += if payload.len() == 0 {
             let buffers: &[&IoVec] = &[transport_hdr.into(), proto_hdr.into()];
@@ -130,10 +162,11 @@ fn write_transport_hdr(stream: &mut TcpStream, msg: Rc<Message>, mut written: us
     }
 }
 
-fn write_protocol_hdr(stream: &mut TcpStream, msg: Rc<Message>, mut written: usize) -> io::Result<(bool, SendOperationStep)> {
+fn write_protocol_hdr<T:WriteBufs>(stream: &mut T, msg: Rc<Message>, mut written: usize, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
     if written == 0 {
         let proto_hdr = msg.get_header();
         let payload = msg.get_body();
+        let payload = &payload[..chunk_end(payload.len(), 0, chunk_size)];
         let buffers: &[&IoVec] = &[proto_hdr.into(), payload.into()];
 
         written += write_buffers(stream, buffers)?;
@@ -141,6 +174,7 @@ fn write_protocol_hdr(stream: &mut TcpStream, msg: Rc<Message>, mut written: usi
         let proto_hdr = msg.get_header();
         let proto_hdr = &proto_hdr[written..];
         let payload = msg.get_body();
+        let payload = &payload[..chunk_end(payload.len(), 0, chunk_size)];
         let buffers: &[&IoVec] = &[proto_hdr.into(), payload.into()];
 
         written += write_buffers(stream, buffers)?;
@@ -158,14 +192,15 @@ fn write_protocol_hdr(stream: &mut TcpStream, msg: Rc<Message>, mut written: usi
     }
 }
 
-fn write_usr_payload(stream: &mut TcpStream, msg: Rc<Message>, mut written: usize) -> io::Result<(bool, SendOperationStep)> {
+fn write_usr_payload<T:WriteBufs>(stream: &mut T, msg: Rc<Message>, mut written: usize, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
     if written == 0 {
         let payload = msg.get_body();
+        let payload = &payload[..chunk_end(payload.len(), 0, chunk_size)];
 
         written += write_buffer(stream, payload)?;
     } else {
         let payload = msg.get_body();
-        let payload = &payload[written..];
+        let payload = &payload[written..chunk_end(payload.len(), written, chunk_size)];
 
         written += write_buffer(stream, payload)?;
     }
@@ -179,11 +214,26 @@ fn write_usr_payload(stream: &mut TcpStream, msg: Rc<Message>, mut written: usiz
     }
 }
 
-fn write_buffer(stream: &mut TcpStream, buffer: &[u8]) -> io::Result<usize> {
+fn write_frame<T:WriteBufs>(stream: &mut T, msg: Rc<Message>, mut written: usize, chunk_size: usize) -> io::Result<(bool, SendOperationStep)> {
+    let frame_len = {
+        let frame = msg.get_frame().unwrap_or_default();
+
+        written += write_buffer(stream, &frame[written..chunk_end(frame.len(), written, chunk_size)])?;
+        frame.len()
+    };
+
+    if written < frame_len {
+        Ok((false, SendOperationStep::Frame(msg, written)))
+    } else {
+        Ok((true, SendOperationStep::Terminal))
+    }
+}
+
+fn write_buffer<T:WriteBufs>(stream: &mut T, buffer: &[u8]) -> io::Result<usize> {
     flatten_would_block(stream.write(buffer))
 }
 
-fn write_buffers(stream: &mut TcpStream, buffers: &[&IoVec]) -> io::Result<usize> {
+fn write_buffers<T:WriteBufs>(stream: &mut T, buffers: &[&IoVec]) -> io::Result<usize> {
     flatten_would_block(stream.write_bufs(buffers))
 }
 
@@ -199,3 +249,80 @@ fn flatten_would_block(result: io::Result<usize>) -> io::Result<usize> {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use std::io::Write;
+    use std::rc::Rc;
+
+    use iovec::IoVec;
+
+    use core::Message;
+    use transport::async::stub::MAX_ZERO_WRITES;
+    use super::*;
+
+    /// Accepts nothing for the first `stalls` writes, then everything, and counts the writes.
+    struct StallingStream {
+        bytes: Vec<u8>,
+        writes: usize,
+        stalls: usize
+    }
+
+    impl StallingStream {
+        fn accept(&mut self, buffers: &[&[u8]]) -> usize {
+            self.writes += 1;
+            if self.stalls > 0 {
+                self.stalls -= 1;
+                return 0;
+            }
+
+            let before = self.bytes.len();
+
+            for buffer in buffers {
+                self.bytes.extend_from_slice(buffer);
+            }
+            self.bytes.len() - before
+        }
+    }
+
+    impl Write for StallingStream {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            Ok(self.accept(&[buf]))
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    impl WriteBufs for StallingStream {
+        fn write_bufs(&mut self, buffers: &[&IoVec]) -> io::Result<usize> {
+            let slices: Vec<&[u8]> = buffers.iter().map(|b| &b[..]).collect();
+
+            Ok(self.accept(&slices))
+        }
+    }
+
+    #[test]
+    fn send_retries_the_writes_that_accepted_nothing() {
+        let msg = Message::from_body(vec!(65, 66, 67, 69));
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
+        let mut stream = StallingStream { bytes: Vec::new(), writes: 0, stalls: MAX_ZERO_WRITES - 1 };
+
+        assert!(operation.run(&mut stream).expect("run should have succeeded"));
+        assert_eq!(MAX_ZERO_WRITES, stream.writes);
+        assert_eq!(&[0, 0, 0, 0, 0, 0, 0, 4, 65, 66, 67, 69], &stream.bytes[..]);
+    }
+
+    #[test]
+    fn send_fails_once_too_many_writes_in_a_row_accepted_nothing() {
+        let msg = Message::from_body(vec!(65, 66, 67, 69));
+        let mut operation = SendOperation::new(Rc::new(msg), 0);
+        let mut stream = StallingStream { bytes: Vec::new(), writes: 0, stalls: MAX_ZERO_WRITES };
+
+        let err = operation.run(&mut stream).expect_err("run should have failed");
+
+        assert_eq!(io::ErrorKind::WriteZero, err.kind());
+        assert_eq!(MAX_ZERO_WRITES, stream.writes);
+    }
+}
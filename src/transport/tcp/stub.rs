@@ -14,8 +14,10 @@ use mio;
 use mio::tcp::{TcpStream, Shutdown};
 
 use core::Message;
+use core::pool::RecvPool;
 use transport::tcp::send::SendOperation;
 use transport::tcp::recv::RecvOperation;
+use transport::{ProtocolVersions, Features, OversizePolicy, Address, Addresses};
 use transport::async::stub::*;
 use io_error::*;
 
@@ -28,8 +30,14 @@ use io_error::*;
 pub struct TcpPipeStub {
     stream: TcpStream,
     recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>,
     send_operation: Option<SendOperation>,
-    recv_operation: Option<RecvOperation>
+    recv_operation: Option<RecvOperation>,
+    dropped: usize,
+    handshake: PartialHandshake
 }
 
 impl Deref for TcpPipeStub {
@@ -40,12 +48,18 @@ impl Deref for TcpPipeStub {
 }
 
 impl TcpPipeStub {
-    pub fn new(stream: TcpStream, recv_max_size: u64) -> TcpPipeStub {
+    pub fn new(stream: TcpStream, recv_max_size: u64, policy: OversizePolicy, chunk_size: usize, protocol_versions: ProtocolVersions, pool: Option<RecvPool>) -> TcpPipeStub {
         TcpPipeStub {
             stream: stream,
             recv_max_size: recv_max_size,
+            oversize_policy: policy,
+            send_chunk_size: chunk_size,
+            versions: protocol_versions,
+            recv_pool: pool,
             send_operation: None,
-            recv_operation: None
+            recv_operation: None,
+            dropped: 0,
+            handshake: PartialHandshake::default()
         }
     }
 
@@ -59,7 +73,11 @@ impl TcpPipeStub {
     }
 
     fn run_recv_operation(&mut self, mut recv_operation: RecvOperation) -> io::Result<Option<Message>> {
-        match recv_operation.run(&mut self.stream)? {
+        let progress = recv_operation.run(&mut self.stream);
+
+        self.dropped += recv_operation.take_dropped();
+
+        match progress? {
             Some(msg) => Ok(Some(msg)),
             None => {
                 self.recv_operation = Some(recv_operation);
@@ -83,7 +101,7 @@ impl Drop for TcpPipeStub {
 
 impl Sender for TcpPipeStub {
     fn start_send(&mut self, msg: Rc<Message>) -> io::Result<bool> {
-        let send_operation = SendOperation::new(msg);
+        let send_operation = SendOperation::new(msg, self.send_chunk_size);
 
         self.run_send_operation(send_operation)
     }
@@ -109,7 +127,7 @@ impl Sender for TcpPipeStub {
 
 impl Receiver for TcpPipeStub {
     fn start_recv(&mut self) -> io::Result<Option<Message>> {
-        let recv_operation = RecvOperation::new(self.recv_max_size);
+        let recv_operation = RecvOperation::new(self.recv_max_size, self.oversize_policy, self.recv_pool.clone());
 
         self.run_recv_operation(recv_operation)
     }
@@ -125,6 +143,10 @@ impl Receiver for TcpPipeStub {
     fn has_pending_recv(&self) -> bool {
         self.recv_operation.is_some()
     }
+
+    fn take_dropped(&mut self) -> usize {
+        ::std::mem::replace(&mut self.dropped, 0)
+    }
 }
 
 /*****************************************************************************/
@@ -135,14 +157,23 @@ impl Receiver for TcpPipeStub {
 
 impl Handshake for TcpPipeStub {
     fn send_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
-        send_and_check_handshake(&mut self.stream, pids)
+        send_and_check_handshake(&mut self.stream, pids, &self.versions)
     }
-    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
-        recv_and_check_handshake(&mut self.stream, pids)
+    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<(u16, Features)> {
+        recv_and_check_handshake(&mut self.stream, pids, &self.versions, &mut self.handshake)
+    }
+    fn send_busy_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
+        send_busy_handshake(&mut self.stream, pids, &self.versions)
     }
 }
 
 impl AsyncPipeStub for TcpPipeStub {
+    fn addresses(&self) -> Addresses {
+        let local = self.stream.local_addr().ok().map(Address::Tcp);
+        let peer = self.stream.peer_addr().ok().map(Address::Tcp);
+
+        (local, peer)
+    }
     #[cfg(windows)]
     fn read_and_write_void(&mut self) {
         let mut buffer: [u8; 0] = [0; 0];
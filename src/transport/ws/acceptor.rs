@@ -0,0 +1,104 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::io;
+use std::time::Duration;
+
+use mio;
+use mio::tcp::{TcpListener, TcpStream};
+
+use core::pool::RecvPool;
+use transport::*;
+use transport::acceptor::*;
+use transport::async::AsyncPipe;
+use super::stub::{WsPipeStub, Role};
+
+pub struct WsAcceptor {
+    listener: TcpListener,
+    path: String,
+    proto_ids: (u16, u16),
+    no_delay: bool,
+    keepalive: Option<Duration>,
+    recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>
+}
+
+impl WsAcceptor {
+
+    pub fn new(l: TcpListener, bound_path: &str, dest: &Destination) -> WsAcceptor {
+        WsAcceptor {
+            listener: l,
+            path: String::from(bound_path),
+            proto_ids: dest.pids,
+            no_delay: dest.tcp_no_delay,
+            keepalive: dest.tcp_keepalive,
+            recv_max_size: dest.recv_max_size,
+            oversize_policy: dest.oversize_policy,
+            send_chunk_size: dest.send_chunk_size,
+            versions: dest.versions.clone(),
+            recv_pool: dest.recv_pool.cloned()
+        }
+    }
+
+    fn accept(&mut self, ctx: &mut dyn Context) {
+        let mut pipes = Vec::new();
+
+        loop {
+            match self.listener.accept() {
+                Ok((stream, _)) => {
+                    let _ = stream.set_nodelay(self.no_delay);
+                    let _ = stream.set_keepalive(self.keepalive);
+                    let pipe = self.create_pipe(stream);
+
+                    pipes.push(pipe);
+                },
+                Err(e) => {
+                    if e.kind() == io::ErrorKind::WouldBlock {
+                        break;
+                    } else {
+                        ctx.raise(Event::Error(e));
+                    }
+                }
+            }
+        }
+
+        if !pipes.is_empty() {
+            ctx.raise(Event::Accepted(pipes));
+        }
+    }
+
+    fn create_pipe(&self, stream: TcpStream) -> Box<dyn pipe::Pipe> {
+        let role = Role::Server { path: self.path.clone() };
+        let stub = WsPipeStub::new(stream, role, self.recv_max_size, self.oversize_policy, self.send_chunk_size, self.versions.clone(), self.recv_pool.clone());
+
+        Box::new(AsyncPipe::new(stub, self.proto_ids))
+    }
+}
+
+impl acceptor::Acceptor for WsAcceptor {
+    fn ready(&mut self, ctx: &mut dyn Context, events: mio::Ready) {
+        if events.is_readable() {
+            self.accept(ctx);
+        }
+    }
+
+    fn open(&mut self, ctx: &mut dyn Context) {
+        ctx.register(&self.listener, mio::Ready::readable(), mio::PollOpt::edge());
+        ctx.raise(Event::Opened);
+    }
+
+    fn close(&mut self, ctx: &mut dyn Context) {
+        ctx.deregister(&self.listener);
+        ctx.raise(Event::Closed);
+    }
+
+    fn local_addr(&self) -> Option<Address> {
+        self.listener.local_addr().ok().map(Address::Tcp)
+    }
+}
\ No newline at end of file
@@ -0,0 +1,554 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::io;
+
+use byteorder::{ BigEndian, ByteOrder };
+
+use core::pool::{RecvPool, take_buffer};
+use transport::MessageTooLong;
+use transport::async::stub::*;
+use io_error::*;
+
+// Base framing protocol of RFC 6455, section 5.
+// An SP message is carried as one websocket binary message,
+// possibly fragmented into a binary frame followed by continuation frames.
+
+pub const OPCODE_CONTINUATION: u8 = 0x0;
+pub const OPCODE_TEXT: u8 = 0x1;
+pub const OPCODE_BINARY: u8 = 0x2;
+pub const OPCODE_CLOSE: u8 = 0x8;
+pub const OPCODE_PING: u8 = 0x9;
+pub const OPCODE_PONG: u8 = 0xA;
+
+const MAX_CONTROL_PAYLOAD_LEN: u64 = 125;
+
+pub struct Frame {
+    pub fin: bool,
+    pub opcode: u8,
+    pub payload: Vec<u8>,
+    pub masked: bool,
+    /// The payload was too long and has been skipped.
+    pub oversized: bool
+}
+
+/// Appends a frame made of the concatenated parts to the output buffer.
+/// Clients must mask the frames they send, servers must not.
+pub fn encode_frame(output: &mut Vec<u8>, fin: bool, opcode: u8, parts: &[&[u8]], mask: Option<[u8; 4]>) {
+    let len: usize = parts.iter().map(|p| p.len()).sum();
+    let first = if fin { 0x80 | opcode } else { opcode };
+    let mask_bit = if mask.is_some() { 0x80 } else { 0 };
+
+    output.push(first);
+    if len < 126 {
+        output.push(mask_bit | len as u8);
+    } else if len <= 0xFFFF {
+        let mut ext = [0u8; 2];
+        BigEndian::write_u16(&mut ext, len as u16);
+        output.push(mask_bit | 126);
+        output.extend_from_slice(&ext);
+    } else {
+        let mut ext = [0u8; 8];
+        BigEndian::write_u64(&mut ext, len as u64);
+        output.push(mask_bit | 127);
+        output.extend_from_slice(&ext);
+    }
+
+    let payload_start = output.len() + mask.map_or(0, |_| 4);
+    if let Some(key) = mask {
+        output.extend_from_slice(&key);
+    }
+    for part in parts {
+        output.extend_from_slice(part);
+    }
+    if let Some(key) = mask {
+        apply_mask(&mut output[payload_start..], key);
+    }
+}
+
+fn apply_mask(payload: &mut [u8], key: [u8; 4]) {
+    for (i, b) in payload.iter_mut().enumerate() {
+        *b ^= key[i % 4];
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* FrameReader                                                               */
+/*                                                                           */
+/*****************************************************************************/
+
+/// Reads a single frame, possibly across several calls.
+/// Reads are bounded to the frame being received so that the next frames
+/// stay in the stream, where a level triggered readiness will report them.
+pub struct FrameReader {
+    head: [u8; 14],
+    head_len: usize,
+    head_read: usize,
+    payload: Option<(Vec<u8>, usize)>,
+    skip: Option<u64>
+}
+
+impl Default for FrameReader {
+    fn default() -> FrameReader {
+        FrameReader {
+            head: [0; 14],
+            head_len: 2,
+            head_read: 0,
+            payload: None,
+            skip: None
+        }
+    }
+}
+
+impl FrameReader {
+    /// Data frames whose payload is longer than `max_len`, when specified, are rejected,
+    /// unless `skip_oversize` is set, in which case their payload is read off the stream and discarded.
+    /// The payload of data frames is read into a buffer taken from the pool, if any.
+    pub fn read<T:io::Read>(&mut self, stream: &mut T, max_len: Option<u64>, skip_oversize: bool, pool: Option<&RecvPool>) -> io::Result<Option<Frame>> {
+        while self.payload.is_none() && self.skip.is_none() {
+            let read = stream.read_buffer(&mut self.head[self.head_read..self.head_len])?;
+
+            if read == 0 {
+                return Ok(None);
+            }
+
+            self.head_read += read;
+            if self.head_read == 2 {
+                self.head_len = 2 + extended_len_size(self.head[1]) + if self.is_masked() { 4 } else { 0 };
+            }
+            if self.head_read == self.head_len {
+                self.start_payload(max_len, skip_oversize, pool)?;
+            }
+        }
+
+        if let Some(remaining) = self.skip {
+            let remaining = skip_bytes(stream, remaining)?;
+
+            if remaining > 0 {
+                self.skip = Some(remaining);
+                return Ok(None);
+            }
+        }
+
+        if let Some((ref mut payload, ref mut read)) = self.payload {
+            if *read < payload.len() {
+                *read += stream.read_buffer(&mut payload[*read..])?;
+            }
+            if *read < payload.len() {
+                return Ok(None);
+            }
+        }
+
+        Ok(Some(self.take_frame()))
+    }
+
+    fn is_masked(&self) -> bool {
+        self.head[1] & 0x80 != 0
+    }
+
+    fn opcode(&self) -> u8 {
+        self.head[0] & 0x0F
+    }
+
+    fn start_payload(&mut self, max_len: Option<u64>, skip_oversize: bool, pool: Option<&RecvPool>) -> io::Result<()> {
+        let len = match self.head[1] & 0x7F {
+            126 => u64::from(BigEndian::read_u16(&self.head[2..4])),
+            127 => BigEndian::read_u64(&self.head[2..10]),
+            x => u64::from(x)
+        };
+
+        if self.head[0] & 0x70 != 0 {
+            return Err(invalid_data_io_error("frame uses reserved bits"));
+        }
+        if self.opcode() >= OPCODE_CLOSE {
+            if len > MAX_CONTROL_PAYLOAD_LEN {
+                return Err(invalid_data_io_error("control frame is too long"));
+            }
+
+            self.payload = Some((vec![0u8; len as usize], 0));
+            return Ok(());
+        } else if let Some(max) = max_len.filter(|&max| len > max) {
+            if skip_oversize {
+                self.skip = Some(len);
+                return Ok(());
+            }
+
+            return Err(invalid_data_io_error(MessageTooLong { max_size: max, actual: len }));
+        }
+
+        self.payload = Some((take_buffer(pool, len as usize), 0));
+        Ok(())
+    }
+
+    fn take_frame(&mut self) -> Frame {
+        let (mut data, _) = self.payload.take().unwrap_or_default();
+
+        if self.is_masked() {
+            let mask_start = self.head_len - 4;
+            let mut key = [0u8; 4];
+
+            key.copy_from_slice(&self.head[mask_start..self.head_len]);
+            apply_mask(&mut data, key);
+        }
+
+        let frame = Frame {
+            fin: self.head[0] & 0x80 != 0,
+            opcode: self.opcode(),
+            payload: data,
+            masked: self.is_masked(),
+            oversized: self.skip.is_some()
+        };
+
+        *self = FrameReader::default();
+        frame
+    }
+}
+
+/// Reads and discards up to `remaining` bytes, returning how many are still to be skipped.
+fn skip_bytes<T:io::Read>(stream: &mut T, mut remaining: u64) -> io::Result<u64> {
+    let mut scratch = [0u8; 4096];
+
+    while remaining > 0 {
+        let len = ::std::cmp::min(remaining, scratch.len() as u64) as usize;
+        let read = stream.read_buffer(&mut scratch[..len])?;
+
+        if read == 0 {
+            break;
+        }
+
+        remaining -= read as u64;
+    }
+
+    Ok(remaining)
+}
+
+fn extended_len_size(second_byte: u8) -> usize {
+    match second_byte & 0x7F {
+        126 => 2,
+        127 => 8,
+        _ => 0
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* MessageReader                                                             */
+/*                                                                           */
+/*****************************************************************************/
+
+pub enum Incoming {
+    Message(Vec<u8>),
+    Ping(Vec<u8>)
+}
+
+/// Reassembles the fragments of a binary message,
+/// reporting the pings interleaved with them, and skipping the pongs.
+/// When oversized messages are skipped, all their fragments are discarded
+/// and they are only counted.
+/// Frames must be masked when read by a server, and must not be when read by a client.
+#[derive(Default)]
+pub struct MessageReader {
+    frames: FrameReader,
+    masked: bool,
+    message: Option<Vec<u8>>,
+    discarding: bool,
+    dropped: usize
+}
+
+impl MessageReader {
+    pub fn new(masked_frames: bool) -> MessageReader {
+        MessageReader {
+            frames: FrameReader::default(),
+            masked: masked_frames,
+            message: None,
+            discarding: false,
+            dropped: 0
+        }
+    }
+
+    pub fn read<T:io::Read>(&mut self, stream: &mut T, size_limit: u64, skip_oversize: bool, pool: Option<&RecvPool>) -> io::Result<Option<Incoming>> {
+        loop {
+            let received = self.message.as_ref().map_or(0, |msg| msg.len() as u64);
+            let remaining = if self.discarding {
+                Some(0)
+            } else if size_limit > 0 {
+                Some(size_limit.saturating_sub(received))
+            } else {
+                None
+            };
+            let frame = match self.frames.read(stream, remaining, skip_oversize, pool) {
+                Ok(Some(frame)) => frame,
+                Ok(None) => return Ok(None),
+                // the frame reader only knows about the fragment it was reading
+                Err(e) => return Err(match MessageTooLong::of(&e) {
+                    Some(excess) => invalid_data_io_error(MessageTooLong { max_size: size_limit, actual: received + excess.actual }),
+                    None => e
+                })
+            };
+
+            if frame.masked != self.masked {
+                return Err(invalid_data_io_error(if self.masked { "unmasked frame from client" } else { "masked frame from server" }));
+            }
+
+            match frame.opcode {
+                OPCODE_PING => return Ok(Some(Incoming::Ping(frame.payload))),
+                OPCODE_PONG => continue,
+                OPCODE_CLOSE => return Err(eof_io_error("connection closed by peer")),
+                OPCODE_TEXT => return Err(invalid_data_io_error("text frames are not supported")),
+                OPCODE_BINARY if self.message.is_none() && !self.discarding => self.message = Some(frame.payload),
+                OPCODE_CONTINUATION if self.message.is_some() || self.discarding => {
+                    if let Some(ref mut msg) = self.message {
+                        msg.extend_from_slice(&frame.payload);
+                    }
+                    if let Some(pool) = pool {
+                        pool.give_back(frame.payload);
+                    }
+                },
+                _ => return Err(invalid_data_io_error("unexpected frame"))
+            }
+
+            if frame.oversized {
+                self.message = None;
+                self.discarding = true;
+            }
+
+            if frame.fin {
+                if self.discarding {
+                    self.discarding = false;
+                    self.dropped += 1;
+                } else {
+                    return Ok(self.message.take().map(Incoming::Message));
+                }
+            }
+        }
+    }
+
+    /// Returns the number of oversized messages skipped since the last call.
+    pub fn take_dropped(&mut self) -> usize {
+        ::std::mem::replace(&mut self.dropped, 0)
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* FrameWriter                                                               */
+/*                                                                           */
+/*****************************************************************************/
+
+/// Encoded frames waiting for the stream to accept them.
+#[derive(Default)]
+pub struct FrameWriter {
+    buffer: Vec<u8>,
+    written: usize
+}
+
+impl FrameWriter {
+    pub fn buffer(&mut self) -> &mut Vec<u8> {
+        &mut self.buffer
+    }
+
+    pub fn flush<T:io::Write>(&mut self, stream: &mut T) -> io::Result<bool> {
+        let mut stream = StallGuard::new(stream);
+
+        while self.written < self.buffer.len() {
+            let before = self.written;
+
+            if stream.write_buffer(&self.buffer, &mut self.written)? {
+                break;
+            }
+            if self.written == before {
+                return Ok(false);
+            }
+        }
+
+        self.buffer.clear();
+        self.written = 0;
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    fn read_all(bytes: Vec<u8>, max_size: u64) -> Vec<Incoming> {
+        let mut stream = Cursor::new(bytes);
+        let mut reader = MessageReader::default();
+        let mut incomings = Vec::new();
+
+        // the end of the cursor is reported as the peer closing the connection
+        while let Ok(Some(incoming)) = reader.read(&mut stream, max_size, false, None) {
+            incomings.push(incoming);
+        }
+
+        incomings
+    }
+
+    fn expect_message(incoming: &Incoming) -> &[u8] {
+        match *incoming {
+            Incoming::Message(ref msg) => msg,
+            Incoming::Ping(_) => panic!("unexpected ping")
+        }
+    }
+
+    #[test]
+    fn masked_frame_should_be_unmasked() {
+        let mut bytes = Vec::new();
+        encode_frame(&mut bytes, true, OPCODE_BINARY, &[b"ab", b"cd"], Some([1, 2, 3, 4]));
+
+        assert_eq!(vec![0x82, 0x84, 1, 2, 3, 4, b'a' ^ 1, b'b' ^ 2, b'c' ^ 3, b'd' ^ 4], bytes);
+
+        let mut stream = Cursor::new(bytes);
+        let frame = FrameReader::default().read(&mut stream, None, false, None).unwrap().unwrap();
+
+        assert!(frame.fin);
+        assert_eq!(OPCODE_BINARY, frame.opcode);
+        assert_eq!(b"abcd".to_vec(), frame.payload);
+    }
+
+    #[test]
+    fn long_payloads_should_use_extended_lengths() {
+        for &len in &[125usize, 126, 65535, 65536] {
+            let payload = vec![7u8; len];
+            let mut bytes = Vec::new();
+            encode_frame(&mut bytes, true, OPCODE_BINARY, &[&payload], None);
+
+            let incomings = read_all(bytes, 0);
+
+            assert_eq!(1, incomings.len());
+            assert_eq!(&payload[..], expect_message(&incomings[0]));
+        }
+    }
+
+    #[test]
+    fn fragments_should_be_reassembled_around_pings() {
+        let mut bytes = Vec::new();
+        encode_frame(&mut bytes, false, OPCODE_BINARY, &[b"ab"], None);
+        encode_frame(&mut bytes, true, OPCODE_PING, &[b"hi"], None);
+        encode_frame(&mut bytes, false, OPCODE_CONTINUATION, &[b"cd"], None);
+        encode_frame(&mut bytes, true, OPCODE_PONG, &[], None);
+        encode_frame(&mut bytes, true, OPCODE_CONTINUATION, &[b"ef"], None);
+
+        let incomings = read_all(bytes, 0);
+
+        assert_eq!(2, incomings.len());
+        match incomings[0] {
+            Incoming::Ping(ref payload) => assert_eq!(b"hi".to_vec(), *payload),
+            Incoming::Message(_) => panic!("expected a ping")
+        }
+        assert_eq!(b"abcdef", expect_message(&incomings[1]));
+    }
+
+    #[test]
+    fn server_should_reject_unmasked_frames() {
+        let mut bytes = Vec::new();
+        encode_frame(&mut bytes, true, OPCODE_BINARY, &[b"abc"], None);
+
+        let mut stream = Cursor::new(bytes);
+        let mut reader = MessageReader::new(true);
+        let err = reader.read(&mut stream, 0, false, None).err().expect("read should have failed");
+
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn client_should_reject_masked_frames() {
+        let mut bytes = Vec::new();
+        encode_frame(&mut bytes, true, OPCODE_BINARY, &[b"abc"], Some([1, 2, 3, 4]));
+
+        let mut stream = Cursor::new(bytes);
+        let mut reader = MessageReader::new(false);
+        let err = reader.read(&mut stream, 0, false, None).err().expect("read should have failed");
+
+        assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    }
+
+    #[test]
+    fn message_exceeding_max_size_should_fail() {
+        let mut bytes = Vec::new();
+        encode_frame(&mut bytes, false, OPCODE_BINARY, &[b"abc"], None);
+        encode_frame(&mut bytes, true, OPCODE_CONTINUATION, &[b"def"], None);
+
+        let mut stream = Cursor::new(bytes);
+        let mut reader = MessageReader::default();
+
+        let err = reader.read(&mut stream, 5, false, None).err().expect("read should have failed");
+
+        assert_eq!(Some(MessageTooLong { max_size: 5, actual: 6 }), MessageTooLong::of(&err));
+    }
+
+    #[test]
+    fn oversized_message_should_be_skipped_when_asked() {
+        let mut bytes = Vec::new();
+        encode_frame(&mut bytes, false, OPCODE_BINARY, &[b"abc"], None);
+        encode_frame(&mut bytes, false, OPCODE_CONTINUATION, &[b"def"], None);
+        encode_frame(&mut bytes, true, OPCODE_CONTINUATION, &[b"ghi"], None);
+        encode_frame(&mut bytes, true, OPCODE_BINARY, &[b"jk"], None);
+
+        let mut stream = Cursor::new(bytes);
+        let mut reader = MessageReader::default();
+
+        match reader.read(&mut stream, 5, true, None).unwrap() {
+            Some(Incoming::Message(msg)) => assert_eq!(b"jk".to_vec(), msg),
+            _ => panic!("expected the message following the skipped one")
+        }
+        assert_eq!(1, reader.take_dropped());
+        assert_eq!(0, reader.take_dropped());
+    }
+
+    #[test]
+    fn unexpected_continuation_should_fail() {
+        let mut bytes = Vec::new();
+        encode_frame(&mut bytes, true, OPCODE_CONTINUATION, &[b"abc"], None);
+
+        let mut stream = Cursor::new(bytes);
+        let mut reader = MessageReader::default();
+
+        assert!(reader.read(&mut stream, 0, false, None).is_err());
+    }
+
+    #[test]
+    fn writer_should_flush_everything() {
+        let mut writer = FrameWriter::default();
+        let mut stream = Cursor::new(Vec::new());
+
+        encode_frame(writer.buffer(), true, OPCODE_BINARY, &[b"abc"], None);
+
+        assert!(writer.flush(&mut stream).unwrap());
+        assert_eq!(vec![0x82, 3, b'a', b'b', b'c'], stream.into_inner());
+    }
+
+    /// Accepts nothing for ever, without reporting it would block.
+    struct StalledStream {
+        writes: usize
+    }
+
+    impl io::Write for StalledStream {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            self.writes += 1;
+            Ok(0)
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn writer_should_fail_on_a_stalled_stream() {
+        let mut writer = FrameWriter::default();
+        let mut stream = StalledStream { writes: 0 };
+
+        encode_frame(writer.buffer(), true, OPCODE_BINARY, &[b"abc"], None);
+
+        let err = writer.flush(&mut stream).expect_err("flush should have failed");
+
+        assert_eq!(io::ErrorKind::WriteZero, err.kind());
+        assert_eq!(MAX_ZERO_WRITES, stream.writes);
+    }
+}
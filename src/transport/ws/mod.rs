@@ -0,0 +1,114 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+mod stub;
+mod frame;
+mod upgrade;
+mod acceptor;
+
+use std::io;
+use std::net;
+
+use self::stub::{WsPipeStub, Role};
+use self::acceptor::WsAcceptor;
+use transport::{Transport, TransportInfo, TransportKind, Destination};
+use transport::tcp::{bind_listener, connect_from};
+use transport::lookup::{self, Lookup, Connector, Binder, ConnectingPipe, BindingAcceptor};
+use transport::pipe::Pipe;
+use transport::acceptor::Acceptor;
+use transport::async::AsyncPipe;
+
+/// WebSocket transport, accepting urls like `ws://host:port/path`.
+/// Each SP message travels as one binary websocket message, 
+/// and the SP handshake is exchanged in the first one, right after the HTTP Upgrade.
+/// Like with tcp, a host name is resolved on a helper thread, then each of its addresses is tried in turn.
+pub struct Ws;
+
+impl Ws {
+    fn connect(&self, addr: &net::SocketAddr, host: &str, path: &str, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
+        let stream = connect_from(addr, dest.connect_source_addr)?;
+        let role = Role::Client { host: String::from(host), path: String::from(path) };
+
+        stream.set_nodelay(dest.tcp_no_delay)?;
+        stream.set_keepalive(dest.tcp_keepalive)?;
+
+        let stub = WsPipeStub::new(stream, role, dest.recv_max_size, dest.oversize_policy, dest.send_chunk_size, dest.versions.clone(), dest.recv_pool.cloned());
+        let pipe = AsyncPipe::new(stub, dest.pids);
+
+        Ok(Box::new(pipe))
+    }
+    fn bind(&self, addr: &net::SocketAddr, path: &str, dest: &Destination) -> io::Result<Box<dyn Acceptor>> {
+        let listener = bind_listener(addr, dest.reuse_addr)?;
+        let acceptor = WsAcceptor::new(listener, path, dest);
+
+        Ok(Box::new(acceptor))
+    }
+}
+
+/// Splits the `host:port/path` part of an url, the path defaulting to `/`.
+fn split_path(addr: &str) -> (&str, &str) {
+    match addr.find('/') {
+        Some(index) => (&addr[..index], &addr[index..]),
+        None => (addr, "/")
+    }
+}
+
+impl Transport for Ws {
+    fn connect(&self, dest: &Destination) -> io::Result<Box<dyn Pipe>> {
+        let (host, path) = split_path(dest.addr);
+
+        if let Some(addr) = lookup::parse(host)? {
+            return self.connect(&addr, host, path, dest);
+        }
+
+        let buf = dest.to_buf();
+        let connector: Connector = Box::new(move |addr| {
+            let dest = buf.as_dest();
+            let (host, path) = split_path(dest.addr);
+
+            Ws.connect(addr, host, path, &dest)
+        });
+
+        Ok(Box::new(ConnectingPipe::new(Lookup::start(host)?, connector)))
+    }
+
+    fn bind(&self, dest: &Destination) -> io::Result<Box<dyn Acceptor>> {
+        let (host, path) = split_path(dest.addr);
+
+        if let Some(addr) = lookup::parse(host)? {
+            return self.bind(&addr, path, dest);
+        }
+
+        let buf = dest.to_buf();
+        let binder: Binder = Box::new(move |addr| {
+            let dest = buf.as_dest();
+            let (_, path) = split_path(dest.addr);
+
+            Ws.bind(addr, path, &dest)
+        });
+
+        Ok(Box::new(BindingAcceptor::new(Lookup::start(host)?, binder)))
+    }
+
+    fn info(&self) -> TransportInfo {
+        TransportInfo {
+            kind: TransportKind::Ws,
+            vectored_io: false,
+            suggested_chunk_size: 64 * 1024
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::split_path;
+
+    #[test]
+    fn path_should_default_to_root() {
+        assert_eq!(("127.0.0.1:5454", "/"), split_path("127.0.0.1:5454"));
+        assert_eq!(("localhost:5454", "/a/b"), split_path("localhost:5454/a/b"));
+    }
+}
@@ -0,0 +1,381 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::ops::Deref;
+use std::rc::Rc;
+use std::io;
+#[cfg(windows)]
+use std::io::Read;
+use std::io::Write;
+
+use byteorder::{ BigEndian, ByteOrder };
+
+use mio;
+use mio::tcp::{TcpStream, Shutdown};
+
+use core::Message;
+use core::pool::RecvPool;
+use transport::ws::frame::*;
+use transport::ws::upgrade::*;
+use transport::{ProtocolVersions, Features, OversizePolicy, Address, Addresses};
+use transport::async::stub::*;
+use io_error::*;
+
+/// Side of the HTTP Upgrade handshake played by the pipe.
+pub enum Role {
+    Client { host: String, path: String },
+    Server { path: String }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* WsPipeStub                                                                */
+/*                                                                           */
+/*****************************************************************************/
+
+pub struct WsPipeStub {
+    stream: TcpStream,
+    role: Role,
+    recv_max_size: u64,
+    oversize_policy: OversizePolicy,
+    send_chunk_size: usize,
+    versions: ProtocolVersions,
+    recv_pool: Option<RecvPool>,
+    seed: u64,
+    key: String,
+    head: HeadReader,
+    upgraded: bool,
+    handshake: Option<[u8; 8]>,
+    reader: MessageReader,
+    writer: FrameWriter,
+    sending: bool,
+    ponging: bool,
+    receiving: bool
+}
+
+impl Deref for WsPipeStub {
+    type Target = dyn mio::Evented;
+    fn deref(&self) -> &Self::Target {
+        &self.stream
+    }
+}
+
+impl WsPipeStub {
+    pub fn new(s: TcpStream, side: Role, max_size: u64, policy: OversizePolicy, chunk_size: usize, protocol_versions: ProtocolVersions, pool: Option<RecvPool>) -> WsPipeStub {
+        let mut random = create_seed();
+        let upgrade_key = create_key(&mut random);
+        let is_server = match side {
+            Role::Client { .. } => false,
+            Role::Server { .. } => true
+        };
+
+        WsPipeStub {
+            stream: s,
+            role: side,
+            recv_max_size: max_size,
+            oversize_policy: policy,
+            send_chunk_size: chunk_size,
+            versions: protocol_versions,
+            recv_pool: pool,
+            seed: random,
+            key: upgrade_key,
+            head: HeadReader::default(),
+            upgraded: false,
+            handshake: None,
+            reader: MessageReader::new(is_server),
+            writer: FrameWriter::default(),
+            sending: false,
+            ponging: false,
+            receiving: false
+        }
+    }
+
+    fn is_client(&self) -> bool {
+        match self.role {
+            Role::Client { .. } => true,
+            Role::Server { .. } => false
+        }
+    }
+
+    fn skip_oversize(&self) -> bool {
+        self.oversize_policy == OversizePolicy::SkipMessage
+    }
+
+    fn mask(&mut self) -> Option<[u8; 4]> {
+        if self.is_client() {
+            let mut key = [0u8; 4];
+
+            BigEndian::write_u32(&mut key, next_random(&mut self.seed) as u32);
+            Some(key)
+        } else {
+            None
+        }
+    }
+
+    fn encode(&mut self, output: &mut Vec<u8>, parts: &[&[u8]]) {
+        let len: usize = parts.iter().map(|p| p.len()).sum();
+
+        if self.send_chunk_size == 0 || len <= self.send_chunk_size {
+            let mask = self.mask();
+
+            return encode_frame(output, true, OPCODE_BINARY, parts, mask);
+        }
+
+        let payload = parts.concat();
+        let mut chunks = payload.chunks(self.send_chunk_size).peekable();
+        let mut opcode = OPCODE_BINARY;
+
+        while let Some(chunk) = chunks.next() {
+            let fin = chunks.peek().is_none();
+            let mask = self.mask();
+
+            encode_frame(output, fin, opcode, &[chunk], mask);
+            opcode = OPCODE_CONTINUATION;
+        }
+    }
+
+    fn run_send(&mut self) -> io::Result<bool> {
+        let sent = self.writer.flush(&mut self.stream)?;
+
+        if sent {
+            self.sending = false;
+            self.ponging = false;
+        }
+
+        Ok(sent)
+    }
+
+    fn run_recv(&mut self) -> io::Result<Option<Message>> {
+        let skip_oversize = self.skip_oversize();
+
+        loop {
+            match self.reader.read(&mut self.stream, self.recv_max_size, skip_oversize, self.recv_pool.as_ref())? {
+                Some(Incoming::Message(payload)) => {
+                    self.receiving = false;
+                    return Ok(Some(Message::from_body(payload)));
+                },
+                Some(Incoming::Ping(payload)) => self.pong(&payload)?,
+                None => {
+                    self.receiving = true;
+                    return Ok(None);
+                }
+            }
+        }
+    }
+
+    /// Answers a ping, the pong being written along with the pending frames if any.
+    /// When the stream does not accept it at once, the pong is reported as a pending send,
+    /// so that the pipe waits for the stream to be writable again to flush it.
+    fn pong(&mut self, payload: &[u8]) -> io::Result<()> {
+        let mask = self.mask();
+
+        encode_frame(self.writer.buffer(), true, OPCODE_PONG, &[payload], mask);
+
+        if self.sending {
+            return Ok(());
+        }
+
+        self.ponging = true;
+        self.run_send().map(|_| ())
+    }
+
+    fn upgrade(&mut self) -> io::Result<()> {
+        let head = match self.head.read(&mut self.stream)? {
+            Some(head) => head,
+            None => return Err(would_block_io_error("upgrade in progress"))
+        };
+
+        let response = match self.role {
+            Role::Client { .. } => {
+                check_response(&head, &self.key)?;
+                None
+            },
+            Role::Server { ref path } => {
+                let (requested, key) = parse_request(&head)?;
+
+                if &requested != path {
+                    let _ = self.stream.write(create_not_found_response().as_bytes());
+
+                    return Err(invalid_data_io_error(format!("no endpoint bound to {}", requested)));
+                }
+
+                Some(create_response(&key))
+            }
+        };
+
+        self.upgraded = true;
+
+        if let Some(response) = response {
+            let handshake = self.handshake.take().unwrap_or_default();
+
+            self.writer.buffer().extend_from_slice(response.as_bytes());
+            encode_frame(self.writer.buffer(), true, OPCODE_BINARY, &[&handshake], None);
+            self.sending = true;
+        }
+
+        self.flush_handshake()
+    }
+
+    /// Writes what is left of the upgrade and of the handshake, failing with `WouldBlock`
+    /// until the stream accepted all of it, so the handshake is resumed once it is writable again.
+    fn flush_handshake(&mut self) -> io::Result<()> {
+        if !self.sending || self.run_send()? {
+            Ok(())
+        } else {
+            Err(would_block_io_error("handshake in progress"))
+        }
+    }
+}
+
+impl Drop for WsPipeStub {
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Sender for WsPipeStub                                                     */
+/*                                                                           */
+/*****************************************************************************/
+
+impl Sender for WsPipeStub {
+    fn start_send(&mut self, msg: Rc<Message>) -> io::Result<bool> {
+        let mut output = Vec::new();
+
+        match msg.get_frame() {
+            Some(frame) => self.encode(&mut output, &[&frame[8..]]),
+            None => self.encode(&mut output, &[msg.get_header(), msg.get_body()])
+        }
+
+        self.writer.buffer().extend_from_slice(&output);
+        self.sending = true;
+        self.run_send()
+    }
+
+    fn resume_send(&mut self) -> io::Result<bool> {
+        if self.sending {
+            self.run_send()
+        } else if self.ponging {
+            // no message is being sent, so there is no progress to report
+            self.run_send().map(|_| false)
+        } else {
+            Err(other_io_error("Cannot resume send: no pending operation"))
+        }
+    }
+
+    fn has_pending_send(&self) -> bool {
+        self.sending || self.ponging
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Receiver for WsPipeStub                                                   */
+/*                                                                           */
+/*****************************************************************************/
+
+impl Receiver for WsPipeStub {
+    fn start_recv(&mut self) -> io::Result<Option<Message>> {
+        self.run_recv()
+    }
+
+    fn resume_recv(&mut self) -> io::Result<Option<Message>> {
+        if self.receiving {
+            self.run_recv()
+        } else {
+            Err(other_io_error("Cannot resume recv: no pending operation"))
+        }
+    }
+
+    fn has_pending_recv(&self) -> bool {
+        self.receiving
+    }
+
+    fn take_dropped(&mut self) -> usize {
+        self.reader.take_dropped()
+    }
+}
+
+/*****************************************************************************/
+/*                                                                           */
+/* Handshake for WsPipeStub                                                  */
+/*                                                                           */
+/*****************************************************************************/
+
+// The client sends its upgrade request immediately followed by the SP handshake in a binary frame.
+// The server can only answer once the request is read, so its own SP handshake
+// is kept aside until the upgrade response is written, right before it.
+
+impl Handshake for WsPipeStub {
+    fn send_handshake(&mut self, pids: (u16, u16)) -> io::Result<()> {
+        if self.sending {
+            return self.flush_handshake();
+        }
+
+        let (proto_id, _) = pids;
+        let handshake = create_local_handshake(proto_id, &self.versions);
+        let request = match self.role {
+            Role::Client { ref host, ref path } => Some(create_request(host, path, &self.key)),
+            Role::Server { .. } => None
+        };
+
+        match request {
+            Some(request) => {
+                let mask = self.mask();
+
+                self.writer.buffer().extend_from_slice(request.as_bytes());
+                encode_frame(self.writer.buffer(), true, OPCODE_BINARY, &[&handshake], mask);
+                self.sending = true;
+                self.flush_handshake()
+            },
+            None => {
+                self.handshake = Some(handshake);
+                Ok(())
+            }
+        }
+    }
+    fn recv_handshake(&mut self, pids: (u16, u16)) -> io::Result<(u16, Features)> {
+        if !self.upgraded {
+            self.upgrade()?;
+        } else {
+            self.flush_handshake()?;
+        }
+
+        loop {
+            match self.reader.read(&mut self.stream, 8, false, None)? {
+                Some(Incoming::Message(payload)) => {
+                    if payload.len() != 8 {
+                        return Err(invalid_data_io_error("received bad handshake"));
+                    }
+
+                    let mut handshake = [0u8; 8];
+                    handshake.copy_from_slice(&payload);
+
+                    return check_handshake(pids, &self.versions, &handshake);
+                },
+                Some(Incoming::Ping(payload)) => self.pong(&payload)?,
+                None => return Err(would_block_io_error("handshake in progress"))
+            }
+        }
+    }
+}
+
+impl AsyncPipeStub for WsPipeStub {
+    fn addresses(&self) -> Addresses {
+        let local = self.stream.local_addr().ok().map(Address::Tcp);
+        let peer = self.stream.peer_addr().ok().map(Address::Tcp);
+
+        (local, peer)
+    }
+    #[cfg(windows)]
+    fn read_and_write_void(&mut self) {
+        let mut buffer: [u8; 0] = [0; 0];
+
+        let _ = self.stream.read(&mut buffer);
+        let _ = self.stream.write(&buffer);
+    }
+}
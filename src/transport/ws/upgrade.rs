@@ -0,0 +1,290 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::io;
+
+use byteorder::{ BigEndian, ByteOrder };
+
+use transport::async::stub::*;
+use io_error::*;
+
+// HTTP Upgrade handshake of RFC 6455, section 4.
+// Only what is needed to open a binary connection is supported:
+// no extension, no sub-protocol negotiation, no redirection.
+
+const GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+const MAX_HEAD_LEN: usize = 8192;
+
+/// Bytes of an HTTP request or response head received so far.
+/// Reads are done one byte at a time, so that the frames sent right after the head
+/// stay in the stream, where a level triggered readiness will report them.
+#[derive(Default)]
+pub struct HeadReader {
+    buffer: Vec<u8>
+}
+
+impl HeadReader {
+    pub fn read<T:io::Read>(&mut self, stream: &mut T) -> io::Result<Option<String>> {
+        let mut byte = [0u8; 1];
+
+        while !self.buffer.ends_with(b"\r\n\r\n") {
+            if self.buffer.len() >= MAX_HEAD_LEN {
+                return Err(invalid_data_io_error("http head is too long"));
+            }
+
+            if stream.read_buffer(&mut byte)? == 0 {
+                return Ok(None);
+            }
+
+            self.buffer.push(byte[0]);
+        }
+
+        match String::from_utf8(::std::mem::take(&mut self.buffer)) {
+            Ok(head) => Ok(Some(head)),
+            Err(_) => Err(invalid_data_io_error("http head is not valid utf-8"))
+        }
+    }
+}
+
+pub fn create_request(host: &str, path: &str, key: &str) -> String {
+    format!(
+        "GET {} HTTP/1.1\r\n\
+         Host: {}\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Key: {}\r\n\
+         Sec-WebSocket-Version: 13\r\n\r\n",
+        path, host, key)
+}
+
+pub fn create_response(key: &str) -> String {
+    format!(
+        "HTTP/1.1 101 Switching Protocols\r\n\
+         Upgrade: websocket\r\n\
+         Connection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept_key(key))
+}
+
+pub fn create_not_found_response() -> String {
+    String::from("HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\n\r\n")
+}
+
+/// Checks an upgrade request, returning the requested path and the client key.
+pub fn parse_request(head: &str) -> io::Result<(String, String)> {
+    let mut lines = head.split("\r\n");
+    let request_line = lines.next().unwrap_or("");
+    let mut parts = request_line.split(' ');
+
+    if parts.next() != Some("GET") {
+        return Err(invalid_data_io_error("upgrade request must be a GET"));
+    }
+
+    let path = match parts.next() {
+        Some(p) if !p.is_empty() => String::from(p),
+        _ => return Err(invalid_data_io_error("upgrade request has no path"))
+    };
+
+    if !has_header_token(head, "upgrade", "websocket") {
+        return Err(invalid_data_io_error("request is not a websocket upgrade"));
+    }
+
+    match find_header(head, "sec-websocket-key") {
+        Some(key) => Ok((path, String::from(key))),
+        None => Err(invalid_data_io_error("upgrade request has no key"))
+    }
+}
+
+/// Checks the response of the server to an upgrade request sent with the specified key.
+pub fn check_response(head: &str, key: &str) -> io::Result<()> {
+    let status_line = head.split("\r\n").next().unwrap_or("");
+
+    if status_line.split(' ').nth(1) != Some("101") {
+        return Err(invalid_data_io_error(format!("upgrade refused: {}", status_line)));
+    }
+
+    match find_header(head, "sec-websocket-accept") {
+        Some(accept) if accept == accept_key(key) => Ok(()),
+        _ => Err(invalid_data_io_error("upgrade response has a bad accept key"))
+    }
+}
+
+fn find_header<'a>(head: &'a str, name: &str) -> Option<&'a str> {
+    head.split("\r\n").skip(1).filter_map(|line| {
+        let mut parts = line.splitn(2, ':');
+
+        match (parts.next(), parts.next()) {
+            (Some(n), Some(v)) if n.trim().eq_ignore_ascii_case(name) => Some(v.trim()),
+            _ => None
+        }
+    }).next()
+}
+
+fn has_header_token(head: &str, name: &str, token: &str) -> bool {
+    find_header(head, name).is_some_and(|v| v.split(',').any(|t| t.trim().eq_ignore_ascii_case(token)))
+}
+
+pub fn accept_key(key: &str) -> String {
+    let mut input = String::from(key);
+
+    input.push_str(GUID);
+
+    base64(&sha1(input.as_bytes()))
+}
+
+/// Builds a key from 16 bytes that the server will simply echo back hashed.
+pub fn create_key(seed: &mut u64) -> String {
+    let mut nonce = [0u8; 16];
+
+    BigEndian::write_u64(&mut nonce[..8], next_random(seed));
+    BigEndian::write_u64(&mut nonce[8..], next_random(seed));
+
+    base64(&nonce)
+}
+
+/// Xorshift generator, good enough for the keys and masks of the protocol
+/// that are meant to defeat caching proxies, not attackers.
+pub fn next_random(seed: &mut u64) -> u64 {
+    let mut x = *seed;
+
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *seed = x;
+    x
+}
+
+pub fn create_seed() -> u64 {
+    let ts = ::time::get_time();
+    let stack_addr = &ts as *const _ as u64;
+    let seed = (ts.sec as u64) ^ ((ts.nsec as u64) << 32) ^ stack_addr;
+
+    if seed == 0 { 0x9E37_79B9_7F4A_7C15 } else { seed }
+}
+
+fn base64(input: &[u8]) -> String {
+    const ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut output = String::with_capacity(input.chunks(3).len() * 4);
+
+    for chunk in input.chunks(3) {
+        let b0 = chunk[0] as usize;
+        let b1 = chunk.get(1).map_or(0, |&b| b as usize);
+        let b2 = chunk.get(2).map_or(0, |&b| b as usize);
+
+        output.push(ALPHABET[b0 >> 2] as char);
+        output.push(ALPHABET[((b0 & 0x03) << 4) | (b1 >> 4)] as char);
+        output.push(if chunk.len() > 1 { ALPHABET[((b1 & 0x0f) << 2) | (b2 >> 6)] as char } else { '=' });
+        output.push(if chunk.len() > 2 { ALPHABET[b2 & 0x3f] as char } else { '=' });
+    }
+
+    output
+}
+
+fn sha1(input: &[u8]) -> [u8; 20] {
+    let mut h: [u32; 5] = [0x6745_2301, 0xEFCD_AB89, 0x98BA_DCFE, 0x1032_5476, 0xC3D2_E1F0];
+    let mut padded = input.to_vec();
+    let bit_len = (input.len() as u64) * 8;
+
+    padded.push(0x80);
+    while padded.len() % 64 != 56 {
+        padded.push(0);
+    }
+    let mut len_bytes = [0u8; 8];
+    BigEndian::write_u64(&mut len_bytes, bit_len);
+    padded.extend_from_slice(&len_bytes);
+
+    for block in padded.chunks(64) {
+        let mut w = [0u32; 80];
+
+        for i in 0..16 {
+            w[i] = BigEndian::read_u32(&block[i * 4..i * 4 + 4]);
+        }
+        for i in 16..80 {
+            w[i] = (w[i - 3] ^ w[i - 8] ^ w[i - 14] ^ w[i - 16]).rotate_left(1);
+        }
+
+        let (mut a, mut b, mut c, mut d, mut e) = (h[0], h[1], h[2], h[3], h[4]);
+
+        for (i, wi) in w.iter().enumerate() {
+            let (f, k) = match i {
+                0..=19  => ((b & c) | (!b & d), 0x5A82_7999),
+                20..=39 => (b ^ c ^ d, 0x6ED9_EBA1),
+                40..=59 => ((b & c) | (b & d) | (c & d), 0x8F1B_BCDC),
+                _       => (b ^ c ^ d, 0xCA62_C1D6)
+            };
+            let temp = a.rotate_left(5).wrapping_add(f).wrapping_add(e).wrapping_add(k).wrapping_add(*wi);
+
+            e = d;
+            d = c;
+            c = b.rotate_left(30);
+            b = a;
+            a = temp;
+        }
+
+        h[0] = h[0].wrapping_add(a);
+        h[1] = h[1].wrapping_add(b);
+        h[2] = h[2].wrapping_add(c);
+        h[3] = h[3].wrapping_add(d);
+        h[4] = h[4].wrapping_add(e);
+    }
+
+    let mut digest = [0u8; 20];
+    for (i, x) in h.iter().enumerate() {
+        BigEndian::write_u32(&mut digest[i * 4..i * 4 + 4], *x);
+    }
+    digest
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn sha1_should_match_reference_digest() {
+        let digest = sha1(b"abc");
+        let hex: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+
+        assert_eq!("a9993e364706816aba3e25717850c26c9cd0d89d", hex);
+    }
+
+    #[test]
+    fn base64_should_pad_partial_chunks() {
+        assert_eq!("", base64(b""));
+        assert_eq!("Zg==", base64(b"f"));
+        assert_eq!("Zm8=", base64(b"fo"));
+        assert_eq!("Zm9v", base64(b"foo"));
+        assert_eq!("Zm9vYmFy", base64(b"foobar"));
+    }
+
+    #[test]
+    fn accept_key_should_match_rfc_sample() {
+        assert_eq!("s3pPLMBiTxaQ9kYGzzhZRbK+xOo=", accept_key("dGhlIHNhbXBsZSBub25jZQ=="));
+    }
+
+    #[test]
+    fn request_should_round_trip_through_parse() {
+        let request = create_request("localhost:5454", "/chat", "dGhlIHNhbXBsZSBub25jZQ==");
+        let (path, key) = parse_request(&request).unwrap();
+
+        assert_eq!("/chat", path);
+        assert_eq!("dGhlIHNhbXBsZSBub25jZQ==", key);
+        assert!(check_response(&create_response(&key), &key).is_ok());
+        assert!(check_response(&create_response("other"), &key).is_err());
+        assert!(check_response(&create_not_found_response(), &key).is_err());
+    }
+
+    #[test]
+    fn head_reader_should_stop_at_end_of_head() {
+        let mut stream = Cursor::new(b"GET / HTTP/1.1\r\n\r\nframe".to_vec());
+        let mut reader = HeadReader::default();
+        let head = reader.read(&mut stream).unwrap().unwrap();
+
+        assert_eq!("GET / HTTP/1.1\r\n\r\n", head);
+        assert_eq!(18, stream.position());
+    }
+}
@@ -106,3 +106,46 @@ fn avoid_to_broadcast_back_to_originator() {
     assert_eq!(vec![65, 66, 67], received2);
     drop(session);
 }
+
+#[test]
+fn application_header_resembling_an_endpoint_id_is_not_stripped() {
+    let (session, mut bus1, mut bus2, _) = before_each();
+    let url = urls::tcp::get();
+
+    bus1.bind(&url).unwrap();
+    bus2.connect(&url).unwrap();
+
+    sleep_some();
+
+    for first in &[0x00, 0x80, 0xC3, 0xFF] {
+        let sent = Message::from_header_and_body(vec![*first, 0, 0, 1], vec![65, 66, 67]);
+        bus1.send_msg(sent).unwrap();
+        let received = bus2.recv().unwrap();
+
+        assert_eq!(vec![*first, 0, 0, 1, 65, 66, 67], received);
+    }
+    drop(session);
+}
+
+#[test]
+fn application_header_is_kept_when_a_received_message_is_sent_back() {
+    let (session, mut bus1, mut bus2, mut bus3) = before_each();
+    let url = urls::tcp::get();
+
+    bus1.bind(&url).unwrap();
+    bus2.connect(&url).unwrap();
+    bus3.connect(&url).unwrap();
+
+    sleep_some();
+
+    bus2.send_msg(Message::from_header_and_body(vec![0x80, 0, 0, 1], vec![65])).unwrap();
+    let received = bus1.recv_msg().unwrap();
+
+    assert_eq!(&[0x80, 0, 0, 1, 65], received.get_body());
+
+    bus1.send_msg(received).unwrap();
+
+    assert_eq!(vec![0x80, 0, 0, 1, 65], bus3.recv().unwrap());
+    assert_eq!(io::ErrorKind::TimedOut, bus2.recv().unwrap_err().kind());
+    drop(session);
+}
@@ -148,4 +148,117 @@ fn forward_messages_back_and_forth() {
 
     drop(session);
     device_thread.join().unwrap().unwrap_err();
-}
\ No newline at end of file
+}
+
+#[test]
+fn drop_messages_looping_between_devices_after_max_hops() {
+    let (mut session, timeout) = before_each();
+    let d_rep_a = session.create_socket::<Rep>().expect("Failed to create socket !");
+    let d_req_a = session.create_socket::<Req>().expect("Failed to create socket !");
+    let d_rep_b = session.create_socket::<Rep>().expect("Failed to create socket !");
+    let d_req_b = session.create_socket::<Req>().expect("Failed to create socket !");
+    let mut req = session.create_socket::<Req>().expect("Failed to create socket !");
+    let mut front_a = d_rep_a.clone();
+    let mut front_b = d_rep_b.clone();
+    let mut probe_a = d_req_a.clone();
+    let mut probe_b = d_req_b.clone();
+
+    let url_a = urls::tcp::get();
+    let url_b = urls::tcp::get();
+
+    probe_a.set_option(ConfigOption::SizeHistogram(true)).unwrap();
+    probe_b.set_option(ConfigOption::SizeHistogram(true)).unwrap();
+    req.set_send_timeout(timeout).unwrap();
+    req.set_recv_timeout(timeout).unwrap();
+
+    let config = DeviceConfig { max_hops: 4 };
+    let device_a = session.create_bridge_device_with_config(d_rep_a, d_req_a, config).unwrap();
+    let device_b = session.create_bridge_device_with_config(d_rep_b, d_req_b, config).unwrap();
+    let thread_a = thread::spawn(move || device_a.run());
+    let thread_b = thread::spawn(move || device_b.run());
+
+    // the endpoints are added once the devices have enabled the frame extensions carrying the hop count
+    front_a.bind(&url_a).unwrap();
+    front_b.bind(&url_b).unwrap();
+    sleep_some();
+    probe_a.connect(&url_b).unwrap();
+    probe_b.connect(&url_a).unwrap();
+    req.connect(&url_a).unwrap();
+    sleep_some();
+
+    req.send(vec![65, 66, 67]).expect("Req should have sent a request");
+    let err = req.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    // device a forwards the request with 1 and 3 hops, device b with 2 and 4 hops, then device a drops it
+    assert_eq!(2, probe_a.size_histogram().unwrap().count());
+    assert_eq!(2, probe_b.size_histogram().unwrap().count());
+    sleep_some();
+    assert_eq!(2, probe_a.size_histogram().unwrap().count());
+    assert_eq!(2, probe_b.size_histogram().unwrap().count());
+
+    drop(front_a);
+    drop(front_b);
+    drop(probe_a);
+    drop(probe_b);
+    drop(session);
+    thread_a.join().unwrap().unwrap_err();
+    thread_b.join().unwrap().unwrap_err();
+}
+
+#[test]
+fn drop_bus_messages_looping_between_relay_devices_after_max_hops() {
+    let (mut session, timeout) = before_each();
+    let mut client = session.create_socket::<Bus>().expect("Failed to create socket !");
+    let d_a = session.create_socket::<Bus>().expect("Failed to create socket !");
+    let d_b = session.create_socket::<Bus>().expect("Failed to create socket !");
+    let d_c = session.create_socket::<Bus>().expect("Failed to create socket !");
+    let mut link_a = d_a.clone();
+    let mut link_b = d_b.clone();
+    let mut link_c = d_c.clone();
+
+    let url_a = urls::tcp::get();
+    let url_b = urls::tcp::get();
+    let url_c = urls::tcp::get();
+
+    client.set_send_timeout(timeout).unwrap();
+    client.set_recv_timeout(timeout).unwrap();
+
+    let config = DeviceConfig { max_hops: 4 };
+    let device_a = session.create_relay_device_with_config(d_a, config).unwrap();
+    let device_b = session.create_relay_device_with_config(d_b, config).unwrap();
+    let device_c = session.create_relay_device_with_config(d_c, config).unwrap();
+    let thread_a = thread::spawn(move || device_a.run());
+    let thread_b = thread::spawn(move || device_b.run());
+    let thread_c = thread::spawn(move || device_c.run());
+
+    link_a.bind(&url_a).unwrap();
+    link_b.bind(&url_b).unwrap();
+    link_c.bind(&url_c).unwrap();
+    link_a.connect(&url_b).unwrap();
+    link_b.connect(&url_c).unwrap();
+    link_c.connect(&url_a).unwrap();
+    client.connect(&url_a).unwrap();
+    sleep_some();
+
+    client.send(vec![65, 66, 67]).expect("Client should have sent a message");
+
+    // the message goes round the triangle both ways, and comes back to device a with 3 hops,
+    // which forwards it a last time, the client included, before the next device drops it
+    assert_eq!(vec![65, 66, 67], client.recv().unwrap());
+
+    // a copy can be dropped by a busy bus pipe, but the loop must not go on
+    let copies = 1 + (0..8).take_while(|_| client.recv().is_ok()).count();
+    assert!(copies <= 2, "the message looped {} times", copies);
+
+    let err = client.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    drop(link_a);
+    drop(link_b);
+    drop(link_c);
+    drop(session);
+    thread_a.join().unwrap().unwrap_err();
+    thread_b.join().unwrap().unwrap_err();
+    thread_c.join().unwrap().unwrap_err();
+}
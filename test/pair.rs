@@ -83,3 +83,163 @@ fn send_a_message_back_and_forth() {
     assert_eq!(vec![67, 66, 65], received_rtl);
     drop(session);
 }
+
+#[test]
+fn bursts_are_served_by_a_session_with_a_spin_budget() {
+    let _ = ::env_logger::init();
+    let mut session = SessionBuilder::new().
+        with("tcp", Tcp).
+        with_spin_budget(Duration::from_millis(2)).
+        build().
+        expect("Failed to create session !");
+    let mut left = session.create_socket::<Pair>().expect("Failed to create socket !");
+    let mut right = session.create_socket::<Pair>().expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    left.set_recv_timeout(make_timeout()).unwrap();
+    right.set_recv_timeout(make_timeout()).unwrap();
+    left.bind(&url).unwrap();
+    right.connect(&url).unwrap();
+    sleep_some();
+
+    for _ in 0..3 {
+        for i in 0..20 {
+            left.send(vec![i]).unwrap();
+            let msg = right.recv().unwrap();
+            right.send(msg).unwrap();
+            assert_eq!(vec![i], left.recv().unwrap());
+        }
+
+        // once idle, the I/O thread must go back to a blocking poll and still wake up for the next burst
+        sleep_some();
+    }
+    drop(session);
+}
+
+#[test]
+fn recv_timed_reports_the_time_spent_waiting() {
+    let (session, mut left, mut right, url) = before_each();
+    let delay = Duration::from_millis(100);
+
+    left.bind(&url).unwrap();
+    right.connect(&url).unwrap();
+    sleep_some();
+
+    let sender = thread::spawn(move || {
+        thread::sleep(delay);
+        left.send(vec![65, 66, 67]).unwrap();
+        left
+    });
+
+    let (msg, waited) = right.recv_timed().unwrap();
+    let _ = sender.join().unwrap();
+
+    assert_eq!(&[65, 66, 67], msg.get_body());
+    assert!(waited >= delay, "recv waited only {:?}", waited);
+    drop(session);
+}
+
+#[cfg(unix)]
+#[test]
+fn send_a_message_over_pre_established_streams() {
+    use std::os::unix::net::UnixStream;
+
+    let (session, mut left, mut right, _) = before_each();
+    let (left_stream, right_stream) = UnixStream::pair().unwrap();
+
+    left.connect_stream(left_stream).unwrap();
+    right.connect_stream(right_stream).unwrap();
+
+    left.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], right.recv().unwrap());
+
+    right.send(vec![67, 66, 65]).unwrap();
+    assert_eq!(vec![67, 66, 65], left.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn send_a_message_over_a_pre_established_tcp_connection() {
+    let (session, mut left, mut right, _) = before_each();
+    let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let right_stream = ::std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (left_stream, _) = listener.accept().unwrap();
+
+    left.connect_stream(left_stream).unwrap();
+    right.connect_stream(right_stream).unwrap();
+
+    left.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], right.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn recv_exact_checks_the_length_of_the_received_body() {
+    let (session, mut left, mut right, url) = before_each();
+
+    left.bind(&url).unwrap();
+    right.connect(&url).unwrap();
+    sleep_some();
+
+    left.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(&[65, 66, 67], right.recv_exact(3).unwrap().get_body());
+
+    left.send(vec![65, 66, 67, 68]).unwrap();
+    let err = right.recv_exact(3).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidData, err.kind());
+    assert_eq!(Some(LengthMismatch { expected: 3, actual: 4 }), LengthMismatch::of(&err));
+
+    left.send(vec![69]).unwrap();
+    assert_eq!(&[69], right.recv_exact(1).unwrap().get_body());
+    drop(session);
+}
+
+#[test]
+fn peers_advertising_a_non_zero_protocol_version_complete_the_handshake() {
+    let (session, mut left, mut right, url) = before_each();
+
+    left.set_option(ConfigOption::ProtocolVersion(3)).unwrap();
+    right.set_option(ConfigOption::ProtocolVersion(3)).unwrap();
+    left.bind(&url).unwrap();
+    right.connect_blocking(&url, make_timeout().unwrap()).unwrap();
+
+    left.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], right.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn peers_advertising_incompatible_protocol_versions_fail_the_handshake() {
+    let (session, mut left, mut right, url) = before_each();
+
+    left.set_option(ConfigOption::ProtocolVersion(1)).unwrap();
+    left.bind(&url).unwrap();
+
+    assert!(right.connect_blocking(&url, Duration::from_millis(200)).is_err());
+
+    right.set_option(ConfigOption::ProtocolVersion(2)).unwrap();
+    right.set_option(ConfigOption::CompatibleProtocolVersions(vec![1])).unwrap();
+    left.set_option(ConfigOption::CompatibleProtocolVersions(vec![2])).unwrap();
+    let right_url = urls::ipc::get();
+    right.bind(&right_url).unwrap();
+
+    assert!(left.connect_blocking(&right_url, make_timeout().unwrap()).is_ok());
+    drop(session);
+}
+
+#[test]
+fn echo_sends_back_the_messages_of_a_pair() {
+    let (mut session, mut left, _, url) = before_each();
+    let mut echo = session.create_socket::<Echo>().expect("Failed to create socket !");
+
+    echo.bind(&url).unwrap();
+    left.connect_blocking(&url, Duration::from_millis(300)).unwrap();
+
+    left.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], left.recv().unwrap());
+
+    left.send(vec![68, 69]).unwrap();
+    assert_eq!(vec![68, 69], left.recv().unwrap());
+    assert!(echo.recv().is_err());
+    drop(session);
+}
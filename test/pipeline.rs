@@ -12,6 +12,14 @@ pub use scaproust::*;
 
 pub use super::{urls, make_session, make_timeout, sleep_some};
 
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use scaproust::core::{EndpointId, Scheduled};
+use scaproust::core::socket::Protocol;
+use scaproust::core::endpoint::Pipe;
+use scaproust::core::context::{Context, Schedulable};
+
 fn before_each() -> (Session, Socket, Socket, String) {
     let _ = ::env_logger::init();
     let mut session = make_session();
@@ -55,3 +63,934 @@ fn send_a_message_through_remote_endpoint() {
     assert_eq!(vec![65, 66, 67], received);
     drop(session);
 }
+
+#[test]
+fn recv_reducer_sees_messages_the_app_never_receives() {
+    use std::sync::Arc;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    let (session, mut push, mut pull, url) = before_each();
+    let count = Arc::new(AtomicUsize::new(0));
+    let counter = count.clone();
+
+    pull.set_recv_reducer(Box::new(move |_| { counter.fetch_add(1, Ordering::SeqCst); }), false).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    push.send(vec![68, 69, 70]).unwrap();
+    push.send(vec![71, 72, 73]).unwrap();
+
+    sleep_some();
+
+    assert_eq!(3, count.load(Ordering::SeqCst));
+    drop(session);
+}
+
+#[test]
+fn recv_map_transforms_messages_before_delivery_and_drops_the_failed_ones() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.set_recv_map(Box::new(|msg: Message| {
+        if msg.get_body().is_empty() {
+            Err(io::Error::new(io::ErrorKind::InvalidData, "empty body"))
+        } else {
+            Ok(Message::from_body(msg.get_body().to_ascii_uppercase()))
+        }
+    })).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    push.send(b"abc".to_vec()).unwrap();
+    push.send(Vec::new()).unwrap();
+    push.send(b"def".to_vec()).unwrap();
+
+    assert_eq!(b"ABC".to_vec(), pull.recv().unwrap());
+    assert_eq!(b"DEF".to_vec(), pull.recv().unwrap());
+    assert_eq!(1, pull.dropped_count().unwrap());
+    drop(session);
+}
+
+#[test]
+fn set_option_error_names_the_rejected_option() {
+    let (session, mut push, _, _) = before_each();
+    let err = push.set_option(ConfigOption::Subscribe(String::from("A"))).unwrap_err();
+    let msg = err.to_string();
+    let rejected = OptionRejected::of(&err).expect("error should carry the rejected option");
+
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    assert_eq!("Subscribe", rejected.option);
+    assert_eq!(RejectReason::NotSupported, rejected.reason);
+    assert!(msg.contains("Subscribe"), "unexpected error: {}", msg);
+    assert!(msg.contains("not supported"), "unexpected error: {}", msg);
+    drop(session);
+}
+
+#[test]
+fn send_large_message_in_chunks_without_starving_other_endpoints() {
+    let (mut session, mut push, mut pull, url) = before_each();
+    let mut small_push = session.create_socket::<Push>().unwrap();
+    let mut small_pull = session.create_socket::<Pull>().unwrap();
+    let small_url = urls::tcp::get();
+    let large = vec![65u8; 4 * 1024 * 1024];
+
+    push.set_option(ConfigOption::SendChunkSize(4096)).unwrap();
+    pull.set_option(ConfigOption::RecvMaxSize(8 * 1024 * 1024)).unwrap();
+    small_pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    small_pull.bind(&small_url).unwrap();
+    small_push.connect(&small_url).unwrap();
+
+    sleep_some();
+
+    let large_sent = large.clone();
+    let push_thread = thread::spawn(move || push.send(large_sent));
+
+    small_push.send(vec![66, 67, 68]).unwrap();
+    assert_eq!(vec![66, 67, 68], small_pull.recv().unwrap());
+
+    pull.set_recv_timeout(Some(Duration::from_secs(5))).unwrap();
+    let received = pull.recv().unwrap();
+    assert!(received == large);
+
+    push_thread.join().unwrap().unwrap();
+    drop(session);
+}
+
+#[test]
+fn drop_messages_whose_deadline_has_expired() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::FrameExtensions(true)).unwrap();
+    pull.set_enforce_deadlines(true).unwrap();
+    push.bind(&url).unwrap();
+    pull.connect(&url).unwrap();
+
+    let now = ::std::time::Instant::now();
+    let past = now - Duration::from_millis(500);
+    let future = now + Duration::from_secs(60);
+
+    push.send_with_deadline(Message::from_body(vec![65, 66, 67]), past).unwrap();
+    push.send_with_deadline(Message::from_body(vec![67]), future).unwrap();
+    push.send(vec![68]).unwrap();
+
+    assert_eq!(vec![67], pull.recv().unwrap());
+    assert_eq!(vec![68], pull.recv().unwrap());
+    assert_eq!(1, pull.get_expired_count().unwrap());
+    drop(session);
+}
+
+#[test]
+fn deadline_is_not_transmitted_to_a_peer_without_frame_extensions() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::FrameExtensions(true)).unwrap();
+    push.bind(&url).unwrap();
+    pull.connect(&url).unwrap();
+
+    let past = ::std::time::Instant::now() - Duration::from_millis(500);
+
+    push.send_with_deadline(Message::from_body(vec![65]), past).unwrap();
+
+    let received = pull.recv_msg().unwrap();
+
+    assert_eq!(&[65], received.get_body());
+    assert_eq!(None, received.get_deadline());
+    drop(session);
+}
+
+#[test]
+fn sender_blocks_once_credit_granted_by_receiver_is_exhausted() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    pull.set_option(ConfigOption::CreditFlowControl(2)).unwrap();
+    push.set_send_timeout(Some(Duration::from_millis(200))).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65]).unwrap();
+    push.send(vec![66]).unwrap();
+
+    let err = push.send(vec![67]).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    assert_eq!(vec![65], pull.recv().unwrap());
+    sleep_some();
+    push.send(vec![67]).unwrap();
+
+    assert_eq!(vec![66], pull.recv().unwrap());
+    assert_eq!(vec![67], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn sender_does_not_wait_for_credit_from_a_receiver_without_flow_control() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    push.set_send_timeout(Some(Duration::from_millis(200))).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65]).unwrap();
+    push.send(vec![66]).unwrap();
+    push.send(vec![67]).unwrap();
+
+    assert_eq!(vec![65], pull.recv().unwrap());
+    assert_eq!(vec![66], pull.recv().unwrap());
+    assert_eq!(vec![67], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn credit_is_granted_to_existing_peers_when_enabled_on_the_receiver() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    push.set_send_timeout(Some(Duration::from_millis(200))).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    pull.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    sleep_some();
+
+    push.send(vec![65]).unwrap();
+
+    let err = push.send(vec![66]).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    assert_eq!(vec![65], pull.recv().unwrap());
+    sleep_some();
+    push.send(vec![66]).unwrap();
+    assert_eq!(vec![66], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn message_sent_right_after_connect_is_delivered_once_the_pipe_is_ready() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    push.send(vec![65, 66, 67]).unwrap();
+
+    let received = pull.recv().unwrap();
+    assert_eq!(vec![65, 66, 67], received);
+    drop(session);
+}
+
+#[test]
+fn send_async_returns_once_queued_until_the_queue_is_full() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    pull.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    push.set_send_timeout(Some(Duration::from_secs(2))).unwrap();
+    push.set_send_queue_size(2).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    // the first message uses the credit of the pull socket
+    push.send_async(vec![65]).unwrap();
+    sleep_some();
+
+    // none of these can be received yet: one is in flight and two are queued
+    for body in 66..69 {
+        push.send_async(vec![body]).unwrap();
+    }
+
+    // the queue is full, so this one is only queued once the receiver makes room
+    let sender = thread::spawn(move || push.send_async(vec![69]).map(|()| push));
+    let received = (0..5).map(|_| pull.recv().unwrap()).collect::<Vec<_>>();
+    let push = sender.join().unwrap().unwrap();
+
+    assert_eq!(vec![vec![65], vec![66], vec![67], vec![68], vec![69]], received);
+    drop(push);
+    drop(session);
+}
+
+#[test]
+fn send_survey_fails_on_a_push_without_sending_the_message() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    let err = push.send_survey(Message::from_body(vec![65, 66, 67])).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+
+    push.send(vec![68, 69]).unwrap();
+    assert_eq!(vec![68, 69], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn shutdown_refuses_new_connections_but_delivers_in_flight_messages() {
+    let (mut session, mut push, mut pull, url) = before_each();
+
+    push.bind(&url).unwrap();
+    pull.connect_blocking(&url, Duration::from_millis(300)).unwrap();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    push.shutdown(Duration::from_secs(1)).unwrap();
+
+    let mut late_pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let late_connect = late_pull.connect_blocking(&url, Duration::from_millis(300));
+    let received = pull.recv().unwrap();
+
+    assert!(late_connect.is_err());
+    assert_eq!(vec![65, 66, 67], received);
+    drop(session);
+}
+
+#[test]
+fn shutdown_delivers_the_queued_messages_before_closing() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    pull.set_option(ConfigOption::CreditFlowControl(1)).unwrap();
+    push.bind(&url).unwrap();
+    pull.connect_blocking(&url, Duration::from_millis(300)).unwrap();
+
+    // the first message uses the credit of the pull socket, the next one waits for more credit
+    // and the last one is queued behind it
+    for body in 65..68 {
+        push.send_async(vec![body]).unwrap();
+    }
+
+    let receiver = thread::spawn(move || (0..3).map(|_| pull.recv().unwrap()).collect::<Vec<_>>());
+
+    push.shutdown(Duration::from_secs(1)).unwrap();
+
+    assert_eq!(vec![vec![65], vec![66], vec![67]], receiver.join().unwrap());
+    drop(session);
+}
+
+#[test]
+fn cloned_push_sends_from_both_clones_and_survives_one_clone_drop() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.bind(&url).unwrap();
+    push.connect_blocking(&url, Duration::from_millis(300)).unwrap();
+
+    let mut clone = push.clone();
+    let sender = thread::spawn(move || {
+        clone.send(vec![66]).unwrap();
+        clone
+    });
+
+    push.send(vec![65]).unwrap();
+    let clone = sender.join().unwrap();
+
+    let mut received = vec![pull.recv().unwrap(), pull.recv().unwrap()];
+    received.sort();
+    assert_eq!(vec![vec![65], vec![66]], received);
+
+    drop(clone);
+    push.send(vec![67]).unwrap();
+    assert_eq!(vec![67], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn prewrapped_frame_is_relayed_unchanged_to_several_pulls() {
+    let (mut session, mut push, mut pull, url) = before_each();
+    let mut other_push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut other_pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let other_url = urls::tcp::get();
+    let frame: Arc<[u8]> = vec![0, 0, 0, 0, 0, 0, 0, 3, 65, 66, 67].into();
+
+    other_pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    other_pull.bind(&other_url).unwrap();
+    push.connect_blocking(&url, Duration::from_millis(300)).unwrap();
+    other_push.connect_blocking(&other_url, Duration::from_millis(300)).unwrap();
+
+    push.send_prewrapped(frame.clone()).unwrap();
+    other_push.send_prewrapped(frame.clone()).unwrap();
+
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+    assert_eq!(vec![65, 66, 67], other_pull.recv().unwrap());
+
+    let bad_frame: Arc<[u8]> = vec![0, 0, 0, 0, 0, 0, 0, 5, 65].into();
+    let err = push.send_prewrapped(bad_frame).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    drop(session);
+}
+
+#[test]
+fn recv_rate_limit_pauses_a_flooding_peer_without_delaying_the_others() {
+    let (mut session, mut fast_push, mut pull, url) = before_each();
+    let mut slow_push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    pull.set_option(ConfigOption::RecvRateLimit(5)).unwrap();
+    pull.bind(&url).unwrap();
+    fast_push.connect_blocking(&url, Duration::from_millis(300)).unwrap();
+    slow_push.connect_blocking(&url, Duration::from_millis(300)).unwrap();
+
+    for _ in 0..20 {
+        fast_push.send(vec![1]).unwrap();
+    }
+    sleep_some();
+    for _ in 0..5 {
+        assert_eq!(vec![1], pull.recv().unwrap());
+    }
+
+    slow_push.send(vec![2]).unwrap();
+    assert_eq!(vec![2], pull.recv().unwrap());
+
+    let err = pull.recv().expect_err("the fast peer should have been paused");
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    pull.set_recv_timeout(Some(Duration::from_secs(2))).unwrap();
+    assert_eq!(vec![1], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn decorated_pull_drops_every_other_message() {
+    let _ = ::env_logger::init();
+    let mut session = make_session();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket_with_decorator::<Pull, _>(DropEveryOther::decorate).expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    for i in 0..4 {
+        push.send(vec![i]).unwrap();
+    }
+
+    assert_eq!(vec![0], pull.recv().unwrap());
+    assert_eq!(vec![2], pull.recv().unwrap());
+
+    let not_received = pull.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, not_received.kind());
+    drop(session);
+}
+
+#[test]
+fn message_whose_ack_is_dropped_is_resent_and_deduplicated() {
+    let _ = ::env_logger::init();
+    let mut session = make_session();
+    let acks = Arc::new(AtomicUsize::new(0));
+    let acks_seen = acks.clone();
+    let mut push = session.create_socket_with_decorator::<Push, _>(move |proto| {
+        Box::new(DropFirstAck { inner: proto, acks: acks_seen.clone() }) as Box<dyn Protocol>
+    }).expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    push.set_option(ConfigOption::RequireAck(true)).unwrap();
+    push.set_option(ConfigOption::AckResendIvl(Duration::from_millis(100))).unwrap();
+    pull.set_option(ConfigOption::RequireAck(true)).unwrap();
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+
+    let duplicate = pull.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, duplicate.kind());
+    assert_eq!(2, acks.load(Ordering::SeqCst));
+
+    sleep_some();
+    assert_eq!(2, acks.load(Ordering::SeqCst));
+
+    push.send(vec![68]).unwrap();
+    assert_eq!(vec![68], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn recv_reports_eof_once_all_pushers_are_gone() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.set_option(ConfigOption::RecvEofOnNoPeers(true)).unwrap();
+    pull.bind(&url).unwrap();
+    let endpoint = push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+
+    endpoint.close().unwrap();
+    sleep_some();
+
+    let eof = pull.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::UnexpectedEof, eof.kind());
+    drop(session);
+}
+
+#[test]
+fn recv_waits_for_new_pushers_by_default() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.bind(&url).unwrap();
+    let endpoint = push.connect(&url).unwrap();
+    sleep_some();
+
+    endpoint.close().unwrap();
+    sleep_some();
+
+    let err = pull.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    drop(session);
+}
+
+#[test]
+fn messages_are_delivered_untouched_to_a_receiver_without_acknowledged_delivery() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::RequireAck(true)).unwrap();
+    push.set_option(ConfigOption::AckResendIvl(Duration::from_millis(100))).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![0x80, 0, 0, 1, 65]).unwrap();
+    assert_eq!(vec![0x80, 0, 0, 1, 65], pull.recv().unwrap());
+
+    let resent = pull.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, resent.kind());
+    drop(session);
+}
+
+#[test]
+fn unsequenced_message_is_not_altered_by_a_receiver_requiring_acks() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.set_option(ConfigOption::RequireAck(true)).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![0x80, 0, 0, 1, 65]).unwrap();
+    assert_eq!(vec![0x80, 0, 0, 1, 65], pull.recv().unwrap());
+    drop(session);
+}
+
+struct DropFirstAck {
+    inner: Box<dyn Protocol>,
+    acks: Arc<AtomicUsize>
+}
+
+impl Protocol for DropFirstAck {
+    fn id(&self) -> u16 { self.inner.id() }
+    fn peer_id(&self) -> u16 { self.inner.peer_id() }
+    fn add_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId, pipe: Pipe) { self.inner.add_pipe(ctx, eid, pipe) }
+    fn remove_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> Option<Pipe> { self.inner.remove_pipe(ctx, eid) }
+    fn send(&mut self, ctx: &mut dyn Context, msg: Message, timeout: Option<Scheduled>) { self.inner.send(ctx, msg, timeout) }
+    fn on_send_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_send_ack(ctx, eid) }
+    fn on_send_timeout(&mut self, ctx: &mut dyn Context) { self.inner.on_send_timeout(ctx) }
+    fn on_send_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_send_ready(ctx, eid) }
+    fn on_send_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_send_not_ready(ctx, eid) }
+    fn recv(&mut self, ctx: &mut dyn Context, timeout: Option<Scheduled>) { self.inner.recv(ctx, timeout) }
+    fn on_recv_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId, msg: Message) {
+        if self.acks.fetch_add(1, Ordering::SeqCst) > 0 {
+            self.inner.on_recv_ack(ctx, eid, msg)
+        }
+    }
+    fn on_recv_timeout(&mut self, ctx: &mut dyn Context) { self.inner.on_recv_timeout(ctx) }
+    fn on_recv_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_recv_ready(ctx, eid) }
+    fn on_recv_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_recv_not_ready(ctx, eid) }
+    fn is_send_ready(&self) -> bool { self.inner.is_send_ready() }
+    fn is_recv_ready(&self) -> bool { self.inner.is_recv_ready() }
+    fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> { self.inner.set_option(opt) }
+    fn on_timer_tick(&mut self, ctx: &mut dyn Context, task: Schedulable) { self.inner.on_timer_tick(ctx, task) }
+    fn on_device_plugged(&mut self, ctx: &mut dyn Context) { self.inner.on_device_plugged(ctx) }
+    fn features(&self) -> u8 { self.inner.features() }
+    fn on_option_set(&mut self, ctx: &mut dyn Context) { self.inner.on_option_set(ctx) }
+    fn close(&mut self, ctx: &mut dyn Context) { self.inner.close(ctx) }
+}
+
+struct DropEveryOther {
+    inner: Box<dyn Protocol>,
+    received: usize
+}
+
+impl DropEveryOther {
+    fn decorate(proto: Box<dyn Protocol>) -> Box<dyn Protocol> {
+        Box::new(DropEveryOther { inner: proto, received: 0 })
+    }
+}
+
+impl Protocol for DropEveryOther {
+    fn id(&self) -> u16 { self.inner.id() }
+    fn peer_id(&self) -> u16 { self.inner.peer_id() }
+    fn add_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId, pipe: Pipe) { self.inner.add_pipe(ctx, eid, pipe) }
+    fn remove_pipe(&mut self, ctx: &mut dyn Context, eid: EndpointId) -> Option<Pipe> { self.inner.remove_pipe(ctx, eid) }
+    fn send(&mut self, ctx: &mut dyn Context, msg: Message, timeout: Option<Scheduled>) { self.inner.send(ctx, msg, timeout) }
+    fn on_send_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_send_ack(ctx, eid) }
+    fn on_send_timeout(&mut self, ctx: &mut dyn Context) { self.inner.on_send_timeout(ctx) }
+    fn on_send_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_send_ready(ctx, eid) }
+    fn on_send_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_send_not_ready(ctx, eid) }
+    fn recv(&mut self, ctx: &mut dyn Context, timeout: Option<Scheduled>) { self.inner.recv(ctx, timeout) }
+    fn on_recv_ack(&mut self, ctx: &mut dyn Context, eid: EndpointId, msg: Message) {
+        self.received += 1;
+
+        if self.received % 2 == 0 {
+            // the protocol is still waiting for this pipe, read the next message instead
+            ctx.recv(eid);
+        } else {
+            self.inner.on_recv_ack(ctx, eid, msg)
+        }
+    }
+    fn on_recv_timeout(&mut self, ctx: &mut dyn Context) { self.inner.on_recv_timeout(ctx) }
+    fn on_recv_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_recv_ready(ctx, eid) }
+    fn on_recv_not_ready(&mut self, ctx: &mut dyn Context, eid: EndpointId) { self.inner.on_recv_not_ready(ctx, eid) }
+    fn is_send_ready(&self) -> bool { self.inner.is_send_ready() }
+    fn is_recv_ready(&self) -> bool { self.inner.is_recv_ready() }
+    fn set_option(&mut self, opt: ConfigOption) -> io::Result<()> { self.inner.set_option(opt) }
+    fn on_timer_tick(&mut self, ctx: &mut dyn Context, task: Schedulable) { self.inner.on_timer_tick(ctx, task) }
+    fn on_device_plugged(&mut self, ctx: &mut dyn Context) { self.inner.on_device_plugged(ctx) }
+    fn features(&self) -> u8 { self.inner.features() }
+    fn on_option_set(&mut self, ctx: &mut dyn Context) { self.inner.on_option_set(ctx) }
+    fn close(&mut self, ctx: &mut dyn Context) { self.inner.close(ctx) }
+}
+
+#[test]
+fn reply_to_should_route_an_answer_back_to_the_sender() {
+    let (mut session, mut push, mut pull, url) = before_each();
+    let reply_url = urls::tcp::get();
+    let mut reply_push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut reply_pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+
+    reply_push.set_send_timeout(make_timeout()).unwrap();
+    reply_pull.set_recv_timeout(make_timeout()).unwrap();
+    push.set_option(ConfigOption::FrameExtensions(true)).unwrap();
+    pull.set_option(ConfigOption::FrameExtensions(true)).unwrap();
+    pull.bind(&url).unwrap();
+    reply_pull.bind(&reply_url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    let mut request = Message::from_body(vec![65, 66, 67]);
+    request.set_reply_to(reply_url.as_bytes()).unwrap();
+    push.send_msg(request).unwrap();
+
+    let mut received = pull.recv_msg().unwrap();
+    let token = received.take_reply_to().expect("Missing reply-to token !");
+
+    assert_eq!(reply_url.as_bytes(), &token[..]);
+    assert_eq!(&[65, 66, 67], received.get_body());
+    assert!(received.reply_to().is_none());
+
+    reply_push.connect(&String::from_utf8(token).unwrap()).unwrap();
+    sleep_some();
+    reply_push.send(vec![67, 66, 65]).unwrap();
+
+    assert_eq!(vec![67, 66, 65], reply_pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn skip_oversized_message_and_keep_the_pipe() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.set_option(ConfigOption::RecvMaxSize(64)).unwrap();
+    pull.set_option(ConfigOption::OversizePolicy(OversizePolicy::SkipMessage)).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65u8; 1000]).unwrap();
+    push.send(vec![66, 67, 68]).unwrap();
+
+    assert_eq!(vec![66, 67, 68], pull.recv().unwrap());
+    assert_eq!(1, pull.dropped_count().unwrap());
+    drop(session);
+}
+
+#[test]
+fn close_pipe_on_oversized_message_by_default() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.set_option(ConfigOption::RecvMaxSize(64)).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65u8; 1000]).unwrap();
+    push.send(vec![66, 67, 68]).unwrap();
+
+    assert!(pull.recv().is_err());
+    assert_eq!(0, pull.dropped_count().unwrap());
+    drop(session);
+}
+
+#[test]
+fn connect_n_opens_parallel_connections_to_a_bound_pull() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.bind(&url).unwrap();
+    let endpoints = push.connect_n(&url, 3).unwrap();
+    sleep_some();
+
+    let mut local_addrs: Vec<String> = endpoints.iter().map(|ep| ep.local_addr().unwrap().to_string()).collect();
+    local_addrs.sort();
+    local_addrs.dedup();
+    assert_eq!(3, local_addrs.len());
+
+    for i in 0..6 {
+        push.send(vec![i]).unwrap();
+    }
+
+    let mut received: Vec<u8> = (0..6).map(|_| pull.recv().unwrap()[0]).collect();
+    received.sort();
+    assert_eq!(vec![0, 1, 2, 3, 4, 5], received);
+    assert_eq!(io::ErrorKind::InvalidInput, push.connect_n(&url, 0).err().unwrap().kind());
+    drop(session);
+}
+
+#[test]
+fn connect_n_spreads_sends_across_the_connections() {
+    use std::io::{Read, Write};
+
+    let (session, mut push, _, _) = before_each();
+    let listener = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("tcp://{}", listener.local_addr().unwrap());
+
+    push.connect_n(&url, 3).unwrap();
+
+    let mut streams: Vec<::std::net::TcpStream> = (0..3).map(|_| listener.accept().unwrap().0).collect();
+
+    for stream in &mut streams {
+        let mut handshake = [0u8; 8];
+
+        stream.set_read_timeout(make_timeout()).unwrap();
+        stream.read_exact(&mut handshake).unwrap();
+        stream.write_all(&[0, 83, 80, 0, 0, 81, 0, 0]).unwrap();
+    }
+    sleep_some();
+
+    for i in 0..3 {
+        push.send(vec![i]).unwrap();
+    }
+
+    let mut received = Vec::new();
+    for stream in &mut streams {
+        let mut msg = [0u8; 9];
+
+        stream.read_exact(&mut msg).unwrap();
+        assert_eq!([0, 0, 0, 0, 0, 0, 0, 1], msg[..8]);
+        received.push(msg[8]);
+    }
+
+    received.sort();
+    assert_eq!(vec![0, 1, 2], received);
+    drop(session);
+}
+
+#[test]
+fn recv_from_reports_the_endpoint_each_message_arrived_on() {
+    let (mut session, mut push, mut pull, url) = before_each();
+    let mut other_push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let other_url = urls::tcp::get();
+
+    push.bind(&url).unwrap();
+    other_push.bind(&other_url).unwrap();
+    let ep = pull.connect(&url).unwrap();
+    let other_ep = pull.connect(&other_url).unwrap();
+    sleep_some();
+
+    push.send(vec![65]).unwrap();
+    let (msg, eid) = pull.recv_from().unwrap();
+    assert_eq!(&[65], msg.get_body());
+    assert_eq!(ep.id(), Into::<usize>::into(eid));
+
+    other_push.send(vec![66]).unwrap();
+    let (msg, eid) = pull.recv_from().unwrap();
+    assert_eq!(&[66], msg.get_body());
+    assert_eq!(other_ep.id(), Into::<usize>::into(eid));
+    drop(session);
+}
+
+#[test]
+fn send_a_message_over_pipes_with_tcp_keepalive() {
+    let (session, mut push, mut pull, url) = before_each();
+    let keepalive = ConfigOption::TcpKeepalive(Some(Duration::from_secs(30)));
+
+    push.set_option(keepalive.clone()).unwrap();
+    pull.set_option(keepalive).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+
+    push.set_option(ConfigOption::TcpKeepalive(None)).unwrap();
+    drop(session);
+}
+
+#[test]
+fn recv_borrowed_lends_each_message_until_the_next_recv() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    push.send(vec![68, 69]).unwrap();
+
+    {
+        let first = pull.recv_borrowed().unwrap();
+
+        assert_eq!(&[65, 66, 67], &first[..]);
+        assert_eq!(3, first.get_body().len());
+    }
+
+    let second = pull.recv_borrowed().unwrap();
+
+    assert_eq!(&[68, 69], second.get_body());
+    drop(second);
+    drop(session);
+}
+
+#[test]
+fn recv_borrowed_reads_each_message_into_the_buffer_of_the_previous_one() {
+    let (session, mut push, mut pull, url) = before_each();
+    let pool = RecvPool::new(1, 64);
+
+    pull.set_recv_pool(pool.clone()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    let mut reused = None;
+
+    for i in 0..20u8 {
+        let sent = vec![i; 1 + i as usize];
+        push.send(sent.clone()).unwrap();
+
+        let received = pull.recv_borrowed().unwrap();
+        let buffer = received.as_ptr();
+
+        assert_eq!(&sent[..], &received[..]);
+        assert_eq!(buffer, *reused.get_or_insert(buffer));
+        assert_eq!(0, pool.available());
+    }
+
+    drop(session);
+}
+
+#[test]
+fn recv_pooled_reads_messages_into_the_buffers_of_the_pool() {
+    let (session, mut push, mut pull, url) = before_each();
+    let pool = RecvPool::new(1, 64);
+
+    pull.set_recv_pool(pool.clone()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    let mut reused = None;
+
+    for i in 0..20u8 {
+        let sent = vec![i; 1 + i as usize];
+        push.send(sent.clone()).unwrap();
+
+        let received = pull.recv_pooled().unwrap();
+        let buffer = received.as_ptr();
+
+        assert_eq!(&sent[..], &received[..]);
+        assert_eq!(buffer, *reused.get_or_insert(buffer));
+        assert_eq!(0, pool.available());
+        drop(received);
+        assert_eq!(1, pool.available());
+    }
+
+    drop(session);
+}
+
+#[test]
+fn send_without_peer_fails_immediately_when_a_peer_is_required() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_send_timeout(None).unwrap();
+    push.set_option(ConfigOption::SendRequiresPeer(true)).unwrap();
+
+    let err = push.send(vec![65, 66, 67]).unwrap_err();
+    assert_eq!(io::ErrorKind::NotConnected, err.kind());
+
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn duplicate_dedup_id_is_dropped_while_in_the_window() {
+    let (session, mut push, mut pull, url) = before_each();
+
+    push.set_option(ConfigOption::FrameExtensions(true)).unwrap();
+    pull.set_option(ConfigOption::DedupWindow(1)).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    push.send_with_dedup_id(Message::from_body(vec![65]), 1).unwrap();
+    push.send_with_dedup_id(Message::from_body(vec![66]), 1).unwrap();
+    push.send_with_dedup_id(Message::from_body(vec![67]), 2).unwrap();
+    push.send_with_dedup_id(Message::from_body(vec![68]), 1).unwrap();
+
+    assert_eq!(vec![65], pull.recv().unwrap());
+    assert_eq!(vec![67], pull.recv().unwrap());
+    assert_eq!(vec![68], pull.recv().unwrap());
+    assert_eq!(1, pull.duplicate_count().unwrap());
+    drop(session);
+}
+
+#[test]
+fn drop_lingers_until_the_queued_messages_are_sent() {
+    let (session, mut push, mut pull, url) = before_each();
+    let pull_url = url.clone();
+
+    push.set_option(ConfigOption::Linger(Some(Duration::from_secs(5)))).unwrap();
+    push.set_send_timeout(Some(Duration::from_secs(5))).unwrap();
+    push.connect(&url).unwrap();
+    push.send_async(vec![65]).unwrap();
+    push.send_async(vec![66]).unwrap();
+    push.send_async(vec![67]).unwrap();
+
+    let receiver = thread::spawn(move || {
+        sleep_some();
+        pull.bind(&pull_url).unwrap();
+        (0..3).map(|_| pull.recv().unwrap()).collect::<Vec<_>>()
+    });
+
+    drop(push);
+
+    let received = receiver.join().unwrap();
+    assert_eq!(vec![vec![65], vec![66], vec![67]], received);
+    drop(session);
+}
+
+#[test]
+fn drop_discards_the_queued_messages_once_the_linger_expires() {
+    let (session, mut push, _, url) = before_each();
+
+    push.set_option(ConfigOption::Linger(Some(Duration::from_millis(200)))).unwrap();
+    push.connect(&url).unwrap();
+    push.send_async(vec![65]).unwrap();
+
+    let dropped_at = ::std::time::Instant::now();
+    drop(push);
+    let elapsed = dropped_at.elapsed();
+
+    assert!(elapsed >= Duration::from_millis(150));
+    assert!(elapsed < Duration::from_secs(2));
+    drop(session);
+}
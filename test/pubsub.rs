@@ -115,3 +115,224 @@ fn ignores_messages_based_on_subscriptions() {
     assert_eq!(vec![65, 66, 67], received3);
     drop(session);
 }
+
+#[test]
+fn expired_messages_are_dropped_without_altering_the_topic() {
+    let (session, mut publ, mut sub1, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    publ.set_option(ConfigOption::FrameExtensions(true)).unwrap();
+    sub1.set_enforce_deadlines(true).unwrap();
+    sub1.set_option(ConfigOption::Subscribe(String::from("A"))).unwrap();
+    publ.bind(&url).unwrap();
+    sub1.connect(&url).unwrap();
+
+    sleep_some();
+
+    let now = ::std::time::Instant::now();
+    let past = now - Duration::from_millis(500);
+    let future = now + Duration::from_secs(60);
+
+    publ.send_with_deadline(Message::from_body(vec![65, 66]), past).unwrap();
+    sleep_some();
+    publ.send_with_deadline(Message::from_body(vec![66]), future).unwrap();
+    sleep_some();
+    publ.send_with_deadline(Message::from_body(vec![65]), future).unwrap();
+
+    assert_eq!(vec![65], sub1.recv().unwrap());
+    assert_eq!(1, sub1.get_expired_count().unwrap());
+    drop(session);
+}
+
+#[test]
+fn set_subscriptions_should_replace_previous_topics() {
+    let (session, mut publ, mut sub1, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    publ.bind(&url).unwrap();
+    sub1.connect(&url).unwrap();
+    sub1.set_option(ConfigOption::Subscribe(String::from("A"))).unwrap();
+    sleep_some();
+
+    publ.send(vec![65, 49]).unwrap();
+    assert_eq!(vec![65, 49], sub1.recv().unwrap());
+
+    sub1.set_subscriptions(vec![vec![66], vec![67]]).unwrap();
+
+    publ.send(vec![65, 50]).unwrap();
+    sleep_some();
+    publ.send(vec![66, 49]).unwrap();
+    sleep_some();
+    publ.send(vec![67, 49]).unwrap();
+
+    assert_eq!(vec![66, 49], sub1.recv().unwrap());
+    assert_eq!(vec![67, 49], sub1.recv().unwrap());
+
+    let not_received = sub1.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, not_received.kind());
+    drop(session);
+}
+
+#[test]
+fn deliver_retained_message_to_late_subscriber() {
+    let (session, mut publ, mut sub1, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    publ.set_option(ConfigOption::PubRetainLast(true)).unwrap();
+    publ.bind(&url).unwrap();
+    publ.send(vec![65, 66, 67]).unwrap();
+
+    sub1.set_option(ConfigOption::Subscribe(String::from("A"))).unwrap();
+    sub1.connect(&url).unwrap();
+
+    let received = sub1.recv().unwrap();
+
+    assert_eq!(vec![65, 66, 67], received);
+    drop(session);
+}
+
+#[test]
+fn deliver_retained_message_of_each_topic_to_late_subscriber() {
+    let (session, mut publ, mut sub1, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    publ.set_option(ConfigOption::PubRetainLast(true)).unwrap();
+    publ.set_option(ConfigOption::PubRetainTopicLen(1)).unwrap();
+    publ.bind(&url).unwrap();
+    publ.send(vec![65, 49]).unwrap();
+    publ.send(vec![66, 49]).unwrap();
+
+    sub1.set_option(ConfigOption::Subscribe(String::from("A"))).unwrap();
+    sub1.connect(&url).unwrap();
+
+    assert_eq!(vec![65, 49], sub1.recv().unwrap());
+
+    let not_received = sub1.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, not_received.kind());
+    drop(session);
+}
+
+#[test]
+fn replay_history_to_late_subscriber_in_order() {
+    let (session, mut publ, mut sub1, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    publ.set_option(ConfigOption::PubHistory(3)).unwrap();
+    publ.bind(&url).unwrap();
+    for i in 0..5 {
+        publ.send(vec![65 + i]).unwrap();
+    }
+
+    sub1.set_option(ConfigOption::Subscribe(String::from(""))).unwrap();
+    sub1.connect(&url).unwrap();
+
+    assert_eq!(vec![67], sub1.recv().unwrap());
+    assert_eq!(vec![68], sub1.recv().unwrap());
+    assert_eq!(vec![69], sub1.recv().unwrap());
+
+    let not_received = sub1.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, not_received.kind());
+    drop(session);
+}
+
+#[cfg(unix)]
+fn wait_fd_readable(fd: ::std::os::unix::io::RawFd) -> bool {
+    use mio::{Poll, Events, Ready, PollOpt, Token};
+    use mio::unix::EventedFd;
+
+    let poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(1);
+
+    poll.register(&EventedFd(&fd), Token(0), Ready::readable(), PollOpt::level()).unwrap();
+    poll.poll(&mut events, Some(super::make_hard_timeout())).unwrap();
+
+    events.iter().any(|e| e.readiness().is_readable())
+}
+
+#[cfg(unix)]
+#[test]
+fn pending_pipes_should_count_pipes_blocked_on_a_message() {
+    let (session, mut publ, mut sub, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    sub.set_option(ConfigOption::Subscribe(String::from(""))).unwrap();
+    sub.set_option(ConfigOption::RecvMaxSize(64 * 1024 * 1024)).unwrap();
+    sub.bind(&url).unwrap();
+    publ.connect_blocking(&url, super::make_hard_timeout()).unwrap();
+
+    assert_eq!((0, 0), publ.pending_pipes().unwrap());
+
+    // The message is much larger than the socket buffers, and the subscriber does not read it,
+    // so the publisher pipe is still writing it once the subscriber pipe can receive.
+    let recv_fd = sub.recv_fd().unwrap();
+    publ.send(vec![65; 32 * 1024 * 1024]).unwrap();
+    assert!(wait_fd_readable(recv_fd));
+
+    assert_eq!((1, 0), publ.pending_pipes().unwrap());
+    assert_eq!((0, 1), sub.pending_pipes().unwrap());
+    drop(session);
+}
+
+#[test]
+fn capabilities_should_be_derived_from_the_protocol() {
+    let (_session, mut publ, mut sub, _, _) = before_each();
+    let pub_caps = publ.capabilities().unwrap();
+    let sub_caps = sub.capabilities().unwrap();
+
+    assert!(pub_caps.can_send);
+    assert!(!pub_caps.can_recv);
+    assert!(!pub_caps.has_subscriptions);
+    assert!(pub_caps.is_broadcast);
+
+    assert!(!sub_caps.can_send);
+    assert!(sub_caps.can_recv);
+    assert!(sub_caps.has_subscriptions);
+    assert!(!sub_caps.is_broadcast);
+}
+
+#[test]
+fn subscription_validator_should_reject_invalid_topics() {
+    let (session, mut publ, mut sub1, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    sub1.set_subscription_validator(Box::new(|topic| topic.starts_with(b"app/"))).unwrap();
+    publ.set_subscription_validator(Box::new(|_| true)).unwrap_err();
+
+    let rejected = sub1.set_option(ConfigOption::Subscribe(String::from("sys/"))).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, rejected.kind());
+    let rejected = sub1.set_subscriptions(vec![b"app/a".to_vec(), b"sys/".to_vec()]).unwrap_err();
+    assert_eq!(io::ErrorKind::InvalidInput, rejected.kind());
+
+    sub1.set_option(ConfigOption::Subscribe(String::from("app/"))).unwrap();
+    publ.bind(&url).unwrap();
+    sub1.connect(&url).unwrap();
+    sleep_some();
+
+    publ.send(b"sys/1".to_vec()).unwrap();
+    sleep_some();
+    publ.send(b"app/1".to_vec()).unwrap();
+
+    assert_eq!(b"app/1".to_vec(), sub1.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn new_sibling_should_keep_the_subscriptions() {
+    let (session, mut publ, mut sub1, _, _) = before_each();
+    let url = urls::tcp::get();
+
+    sub1.set_option(ConfigOption::Subscribe(String::from("A"))).unwrap();
+
+    let mut sibling = sub1.new_sibling().unwrap();
+
+    publ.bind(&url).unwrap();
+    sibling.connect(&url).unwrap();
+    sleep_some();
+
+    publ.send(vec![66, 66]).unwrap();
+    sleep_some();
+    publ.send(vec![65, 65]).unwrap();
+
+    assert_eq!(vec![65, 65], sibling.recv().unwrap());
+    drop(session);
+}
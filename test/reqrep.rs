@@ -68,6 +68,7 @@ fn refuse_to_send_a_reply_before_receiving_a_request() {
 
     let not_sent = rep.send(vec![66, 65, 67]).unwrap_err();
     assert_eq!(io::ErrorKind::InvalidData, not_sent.kind());
+    assert_eq!(Some(RepError::SendWithoutRecv), RepError::of(&not_sent));
     drop(session);
 }
 
@@ -99,3 +100,255 @@ fn resend_the_request_silently_when_the_timeout_is_reached() {
     assert_eq!(vec![66, 65, 67], received_reply);
     drop(session);
 }
+
+#[test]
+fn serve_concurrent_requests_with_a_worker_pool() {
+    let (mut session, mut req1, mut rep, url) = before_each();
+    let mut req2 = session.create_socket::<Req>().unwrap();
+    let mut req3 = session.create_socket::<Req>().unwrap();
+    let timeout = make_timeout();
+
+    req2.set_recv_timeout(timeout).unwrap();
+    req3.set_recv_timeout(timeout).unwrap();
+
+    rep.bind(&url).unwrap();
+    req1.connect(&url).unwrap();
+    req2.connect(&url).unwrap();
+    req3.connect(&url).unwrap();
+
+    let server_thread = thread::spawn(move || RepServer::serve(rep, 2, |msg| {
+        let mut body: Vec<u8> = msg.into();
+        body.reverse();
+        Message::from_body(body)
+    }));
+
+    sleep_some();
+
+    req1.send(vec![65, 66, 67]).unwrap();
+    req2.send(vec![68, 69, 70]).unwrap();
+    req3.send(vec![71, 72, 73]).unwrap();
+
+    assert_eq!(vec![67, 66, 65], req1.recv().unwrap());
+    assert_eq!(vec![70, 69, 68], req2.recv().unwrap());
+    assert_eq!(vec![73, 72, 71], req3.recv().unwrap());
+
+    drop(session);
+    server_thread.join().unwrap().unwrap_err();
+}
+
+#[test]
+fn worker_pool_keeps_serving_after_a_handler_panic() {
+    let (session, mut req, mut rep, url) = before_each();
+
+    rep.bind(&url).unwrap();
+    req.connect(&url).unwrap();
+    req.set_recv_timeout(Some(Duration::from_millis(200))).unwrap();
+
+    let server_thread = thread::spawn(move || RepServer::serve(rep, 1, |msg| {
+        let body: Vec<u8> = msg.into();
+        if body.is_empty() {
+            panic!("handler failure");
+        }
+        Message::from_body(body)
+    }));
+
+    sleep_some();
+
+    req.send(vec![]).unwrap();
+    let err = req.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+
+    req.set_recv_timeout(make_timeout()).unwrap();
+    req.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], req.recv().unwrap());
+
+    drop(session);
+    server_thread.join().unwrap().unwrap_err();
+}
+
+#[test]
+fn connected_endpoint_should_report_the_peer_protocol_id() {
+    let (session, mut req, mut rep, url) = before_each();
+
+    let rep_ep = rep.bind(&url).unwrap();
+    let req_ep = req.connect(&url).unwrap();
+    sleep_some();
+
+    assert_eq!(Some(proto::REP), req_ep.peer_protocol_id());
+    assert_eq!(None, rep_ep.peer_protocol_id());
+    drop(session);
+}
+
+#[test]
+fn tcp_endpoints_should_report_their_addresses() {
+    let (session, mut req, mut rep, url) = before_each();
+    let bound_addr = url.trim_start_matches("tcp://").to_owned();
+
+    let rep_ep = rep.bind(&url).unwrap();
+    let req_ep = req.connect(&url).unwrap();
+    sleep_some();
+
+    let local = req_ep.local_addr().expect("connected endpoint should have a local address");
+    let peer = req_ep.peer_addr().expect("connected endpoint should have a peer address");
+
+    match local {
+        Address::Tcp(addr) => assert!(addr.ip().is_loopback()),
+        _ => panic!("local address should be a tcp one")
+    }
+    assert_eq!(bound_addr, peer.to_string());
+    assert_eq!(Some(bound_addr), rep_ep.local_addr().map(|addr| addr.to_string()));
+    assert_eq!(None, rep_ep.peer_addr());
+    drop(session);
+}
+
+#[test]
+fn set_option_error_tells_an_invalid_value_apart() {
+    let (session, _, mut rep, _) = before_each();
+    let err = rep.set_option(ConfigOption::MaxTtl(0)).unwrap_err();
+    let msg = err.to_string();
+    let rejected = OptionRejected::of(&err).expect("error should carry the rejected option");
+    let source = ::std::error::Error::source(rejected).expect("rejection should keep its source");
+
+    assert_eq!("MaxTtl", rejected.option);
+    assert_eq!(RejectReason::InvalidValue, rejected.reason);
+    assert!(source.to_string().contains("max ttl"), "unexpected source: {}", source);
+    assert!(msg.contains("MaxTtl"), "unexpected error: {}", msg);
+    assert!(msg.contains("invalid value"), "unexpected error: {}", msg);
+    drop(session);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn ipc_pipe_should_report_the_credentials_of_its_peer() {
+    let (session, mut req, mut rep, _) = before_each();
+    let url = urls::ipc::get();
+
+    rep.bind(&url).unwrap();
+    let req_ep = req.connect(&url).unwrap();
+
+    req.send(vec![65, 66, 67]).unwrap();
+    let (_, eid) = rep.recv_from().unwrap();
+    let cred = rep.peer_cred(eid).unwrap();
+
+    assert_eq!(::std::process::id() as i32, cred.pid);
+    assert_eq!(cred, req.peer_cred(EndpointId::from(req_ep.id())).unwrap());
+    drop(session);
+}
+
+#[test]
+fn tcp_pipe_should_not_report_peer_credentials() {
+    let (session, mut req, mut rep, url) = before_each();
+
+    rep.bind(&url).unwrap();
+    req.connect(&url).unwrap();
+
+    req.send(vec![65, 66, 67]).unwrap();
+    let (_, eid) = rep.recv_from().unwrap();
+    let err = rep.peer_cred(eid).unwrap_err();
+
+    assert_eq!(io::ErrorKind::Unsupported, err.kind());
+    drop(session);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn ipc_endpoints_should_report_their_addresses() {
+    let (session, mut req, mut rep, _) = before_each();
+    let url = urls::ipc::get();
+    let path = url.trim_start_matches("ipc://").to_owned();
+
+    let rep_ep = rep.bind(&url).unwrap();
+    let req_ep = req.connect(&url).unwrap();
+    sleep_some();
+
+    assert_eq!(None, req_ep.local_addr());
+    assert_eq!(Some(path.clone()), req_ep.peer_addr().map(|addr| addr.to_string()));
+    assert_eq!(Some(Address::Ipc(::std::path::PathBuf::from(&path))), rep_ep.local_addr());
+    assert_eq!(None, rep_ep.peer_addr());
+    drop(session);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn ipc_socket_file_should_get_the_requested_permissions() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let (session, mut req, mut rep, _) = before_each();
+    let url = urls::ipc::get();
+    let path = url.trim_start_matches("ipc://").to_owned();
+
+    rep.set_option(ConfigOption::IpcSocketPermissions(Some(0o600))).unwrap();
+    rep.bind(&url).unwrap();
+    req.connect(&url).unwrap();
+
+    let mode = ::std::fs::metadata(&path).unwrap().permissions().mode();
+    assert_eq!(0o600, mode & 0o777);
+
+    req.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], rep.recv().unwrap());
+    drop(session);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn ipc_can_use_the_abstract_namespace() {
+    let (session, mut req, mut rep, _) = before_each();
+    let url = urls::ipc::get().replace("ipc:///tmp/", "ipc://@");
+    let name = url.trim_start_matches("ipc://").to_owned();
+
+    let rep_ep = rep.bind(&url).unwrap();
+    req.connect(&url).unwrap();
+
+    req.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], rep.recv().unwrap());
+    rep.send(vec![67, 66, 65]).unwrap();
+    assert_eq!(vec![67, 66, 65], req.recv().unwrap());
+
+    assert_eq!(Some(Address::Ipc(::std::path::PathBuf::from(&name))), rep_ep.local_addr());
+    // the kernel lists the sockets of the abstract namespace with a leading @
+    let sockets = ::std::fs::read_to_string("/proc/net/unix").unwrap();
+    assert!(sockets.lines().any(|line| line.ends_with(&format!(" {}", name))));
+    drop(session);
+}
+
+#[test]
+fn connected_endpoint_should_report_its_handshake_duration_once_active() {
+    let (session, mut req, mut rep, url) = before_each();
+
+    let rep_ep = rep.bind(&url).unwrap();
+    let req_ep = req.connect(&url).unwrap();
+    sleep_some();
+
+    let info = req_ep.info().expect("connected endpoint should report its info");
+    let handshake_duration = info.handshake_duration.expect("connected endpoint should report its handshake duration");
+
+    assert!(handshake_duration < Duration::from_secs(5));
+    assert_eq!(info.peer_addr, req_ep.peer_addr());
+    assert_eq!(None, rep_ep.info().and_then(|info| info.handshake_duration));
+    drop(session);
+}
+
+#[test]
+fn a_cancelled_request_no_longer_waits_for_its_reply() {
+    let (session, mut req, mut rep, url) = before_each();
+
+    rep.bind(&url).unwrap();
+    req.connect(&url).unwrap();
+
+    req.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], rep.recv().unwrap());
+
+    req.cancel_request().unwrap();
+    rep.send(vec![67, 66, 65]).unwrap();
+
+    let not_received = req.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::Other, not_received.kind());
+
+    req.send(vec![68, 69]).unwrap();
+    assert_eq!(vec![68, 69], rep.recv().unwrap());
+    rep.send(vec![69, 68]).unwrap();
+    assert_eq!(vec![69, 68], req.recv().unwrap());
+
+    rep.cancel_request().unwrap();
+    drop(session);
+}
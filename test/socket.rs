@@ -176,3 +176,833 @@ fn set_option_should_support_max_recv_size() {
     assert!(req.set_option(ConfigOption::RecvMaxSize(64)).is_ok());
     assert!(rep.set_option(ConfigOption::RecvMaxSize(64)).is_ok());
 }
+
+#[test]
+fn close_graceful_lets_queued_message_be_delivered_before_removal() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let timeout = Some(Duration::from_millis(50));
+
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    let endpoint = push.connect(&url).unwrap();
+    push.set_send_timeout(timeout).unwrap();
+
+    sleep_some();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    endpoint.close_graceful(Duration::from_millis(500)).unwrap();
+
+    let received = pull.recv().unwrap();
+    assert_eq!(vec![65, 66, 67], received);
+
+    sleep_some();
+
+    let err = push.send(vec![68, 69, 70]).unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    drop(session);
+}
+
+#[test]
+fn bind_in_range_should_skip_ports_already_in_use() {
+    let (mut session, _) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let ports = urls::tcp::get_port_range(4);
+    let _listener = ::std::net::TcpListener::bind(("127.0.0.1", ports.start)).unwrap();
+
+    let (_, port) = pull.bind_in_range("tcp://127.0.0.1", ports.clone()).unwrap();
+
+    assert!(port > ports.start && port < ports.end);
+    drop(session);
+}
+
+#[test]
+fn is_connected_should_reflect_established_pipes() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+
+    push.bind(&url).unwrap();
+    assert!(!push.is_connected());
+    assert!(!pull.is_connected());
+
+    let ep = pull.connect(&url).unwrap();
+    sleep_some();
+    assert!(push.is_connected());
+    assert!(pull.is_connected());
+
+    ep.close().unwrap();
+    sleep_some();
+    assert!(!pull.is_connected());
+    drop(session);
+}
+
+#[test]
+fn operations_should_fail_with_closed_error_once_the_session_is_dropped() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    push.set_send_timeout(None).unwrap();
+    drop(session);
+    sleep_some();
+
+    let err = push.send(vec![65, 66, 67]).unwrap_err();
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+
+    let err = push.bind(&url).err().expect("Bind should have failed !");
+    assert_eq!(io::ErrorKind::BrokenPipe, err.kind());
+}
+
+#[test]
+fn rebind_should_move_acceptor_while_keeping_accepted_pipes() {
+    let (mut session, url_a) = before_each();
+    let url_b = urls::tcp::get();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push_a = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut push_b = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    push_a.set_send_timeout(make_timeout()).unwrap();
+    push_b.set_send_timeout(make_timeout()).unwrap();
+
+    let ep = pull.bind(&url_a).unwrap();
+    push_a.connect(&url_a).unwrap();
+    sleep_some();
+
+    ep.rebind(&url_b).unwrap();
+    sleep_some();
+    push_b.connect(&url_b).unwrap();
+    sleep_some();
+
+    push_b.send(vec![66]).unwrap();
+    assert_eq!(vec![66], pull.recv().unwrap());
+
+    push_a.send(vec![65]).unwrap();
+    assert_eq!(vec![65], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn paused_endpoint_should_not_reconnect_until_resumed() {
+    let (mut session, url) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    push.set_option(ConfigOption::RetryIvl(Duration::from_millis(20))).unwrap();
+
+    let ep = push.connect(&url).unwrap();
+    ep.pause_reconnect().unwrap();
+    sleep_some();
+
+    assert_eq!(0, push.pending_retries().unwrap());
+
+    pull.bind(&url).unwrap();
+    sleep_some();
+
+    assert!(!push.is_connected());
+    assert_eq!(0, push.pending_retries().unwrap());
+
+    ep.resume_reconnect().unwrap();
+    sleep_some();
+
+    assert!(push.is_connected());
+    drop(session);
+}
+
+#[test]
+fn closed_endpoint_should_stop_reconnecting_while_the_socket_stays_alive() {
+    let (mut session, url) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    push.set_option(ConfigOption::RetryIvl(Duration::from_secs(1))).unwrap();
+
+    let ep = push.connect(&url).unwrap();
+    sleep_some();
+    assert_eq!(1, push.pending_retries().unwrap());
+
+    ep.close().unwrap();
+    sleep_some();
+    assert_eq!(0, push.pending_retries().unwrap());
+
+    pull.bind(&url).unwrap();
+    sleep_some();
+    assert!(!push.is_connected());
+
+    push.connect(&url).unwrap();
+    sleep_some();
+    assert!(push.is_connected());
+    drop(session);
+}
+
+#[test]
+fn closed_socket_should_release_the_address_it_was_bound_to() {
+    let (mut session, url) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+
+    pull.bind(&url).unwrap();
+    drop(pull);
+    sleep_some();
+
+    let mut restarted = session.create_socket::<Pull>().expect("Failed to create socket !");
+    restarted.bind(&url).unwrap();
+    drop(session);
+}
+
+#[cfg(not(windows))]
+#[test]
+fn reuse_addr_should_decide_whether_an_address_still_in_time_wait_can_be_bound() {
+    use std::io::Read;
+    use std::net;
+
+    let (mut session, url) = before_each();
+    let addr = &url["tcp://".len()..];
+
+    // the accepting side closes first, so its end of the connection lingers in TIME_WAIT
+    {
+        let listener = net::TcpListener::bind(addr).unwrap();
+        let mut client = net::TcpStream::connect(addr).unwrap();
+        let (server, _) = listener.accept().unwrap();
+
+        drop(server);
+        assert_eq!(0, client.read(&mut [0u8; 1]).unwrap());
+    }
+
+    let mut strict = session.create_socket::<Pull>().expect("Failed to create socket !");
+    strict.set_option(ConfigOption::ReuseAddr(false)).unwrap();
+    let err = strict.bind(&url).err().expect("Bind should have failed !");
+    assert_eq!(io::ErrorKind::AddrInUse, err.kind());
+
+    let mut restarted = session.create_socket::<Pull>().expect("Failed to create socket !");
+    restarted.bind(&url).unwrap();
+    drop(session);
+}
+
+#[cfg(target_os = "linux")]
+#[test]
+fn connected_endpoint_should_keep_its_source_address_when_reconnecting() {
+    let (mut session, url) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let source = "127.0.0.2:0".parse().unwrap();
+
+    push.set_option(ConfigOption::RetryIvl(Duration::from_millis(20))).unwrap();
+    push.set_option(ConfigOption::ConnectSourceAddr(Some(source))).unwrap();
+    pull.set_recv_timeout(make_timeout()).unwrap();
+
+    let ep = push.connect(&url).unwrap();
+    sleep_some();
+    assert_eq!(1, push.pending_retries().unwrap());
+
+    pull.bind(&url).unwrap();
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+
+    match ep.local_addr() {
+        Some(Address::Tcp(addr)) => assert_eq!(source.ip(), addr.ip()),
+        other => panic!("unexpected local address {:?}", other)
+    }
+    drop(session);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn recv_timeout_should_be_due_once_the_test_clock_is_advanced() {
+    let clock = TestClock::new();
+    let mut session = SessionBuilder::new().with_clock(clock.clone()).build().expect("Failed to create session !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let timeout = Duration::from_secs(3600);
+
+    pull.set_recv_timeout(Some(timeout)).unwrap();
+
+    let recv_thread = thread::spawn(move || pull.recv());
+
+    clock.wait_scheduled(1);
+    clock.advance(timeout);
+
+    let err = recv_thread.join().unwrap().unwrap_err();
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    drop(session);
+}
+
+#[cfg(feature = "testing")]
+#[test]
+fn recv_rate_window_should_follow_the_test_clock() {
+    let clock = TestClock::new();
+    let mut session = SessionBuilder::new().with("tcp", Tcp).with_clock(clock.clone()).build().expect("Failed to create session !");
+    let mut other_session = make_session();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = other_session.create_socket::<Push>().expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    pull.set_option(ConfigOption::RecvRateLimit(3)).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    push.send(vec![1]).unwrap();
+    push.send(vec![2]).unwrap();
+    assert_eq!(vec![1], pull.recv().unwrap());
+    assert_eq!(vec![2], pull.recv().unwrap());
+
+    // the window is still open as long as the test clock is not advanced
+    thread::sleep(Duration::from_millis(1100));
+    push.send(vec![3]).unwrap();
+    push.send(vec![4]).unwrap();
+    assert_eq!(vec![3], pull.recv().unwrap());
+    sleep_some();
+    let err = pull.try_recv().expect_err("the peer should have been paused");
+    assert_eq!(io::ErrorKind::WouldBlock, err.kind());
+
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(vec![4], pull.recv().unwrap());
+    drop(session);
+    drop(other_session);
+}
+
+#[test]
+fn connect_blocking_should_return_once_connected() {
+    let (mut session, url) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    pull.bind(&url).unwrap();
+    push.connect_blocking(&url, Duration::from_secs(1)).unwrap();
+
+    assert!(push.is_connected());
+    drop(session);
+}
+
+#[test]
+fn connect_blocking_should_time_out_when_no_peer_is_listening() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    let err = push.connect_blocking(&url, Duration::from_millis(200)).err().expect("Connect should have failed !");
+
+    assert_eq!(io::ErrorKind::TimedOut, err.kind());
+    assert!(!push.is_connected());
+    drop(session);
+}
+
+#[test]
+fn transport_info_should_describe_the_endpoint_transport() {
+    let (mut session, tcp_url) = before_each();
+    let ipc_url = urls::ipc::get();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+
+    let tcp_ep = pull.bind(&tcp_url).unwrap();
+    let ipc_ep = pull.bind(&ipc_url).unwrap();
+    let tcp_info = push.connect(&tcp_url).unwrap().transport_info();
+    let ipc_info = push.connect(&ipc_url).unwrap().transport_info();
+
+    assert_eq!(tcp_info, tcp_ep.transport_info());
+    assert_eq!(ipc_info, ipc_ep.transport_info());
+    assert_eq!(TransportKind::Tcp, tcp_info.kind);
+    assert_eq!(TransportKind::Ipc, ipc_info.kind);
+    assert!(tcp_info.vectored_io);
+    assert!(!ipc_info.vectored_io);
+    assert!(tcp_info.suggested_chunk_size > 0);
+    assert!(ipc_info.suggested_chunk_size > 0);
+}
+
+#[test]
+fn endpoint_config_should_round_trip_through_dump() {
+    let (mut session, bind_url) = before_each();
+    let connect_url = urls::tcp::get();
+    let mut peer = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let endpoint_options = |priority: u8, no_delay: bool| vec![
+        ConfigOption::SendPriority(priority),
+        ConfigOption::RecvPriority(priority),
+        ConfigOption::RecvWeight(3),
+        ConfigOption::TcpNoDelay(no_delay),
+        ConfigOption::TcpKeepalive(Some(Duration::from_secs(30))),
+        ConfigOption::ReuseAddr(no_delay),
+        ConfigOption::ConnectSourceAddr(None),
+        ConfigOption::IpcSocketPermissions(Some(0o600)),
+        ConfigOption::RecvMaxSize(64 * 1024),
+        ConfigOption::SendChunkSize(4096),
+        ConfigOption::OversizePolicy(OversizePolicy::SkipMessage),
+        ConfigOption::ProtocolVersion(0),
+        ConfigOption::CompatibleProtocolVersions(vec![1])
+    ];
+    let configs = vec![
+        EndpointConfig { url: bind_url, role: EndpointRole::Bind, options: endpoint_options(2, false) },
+        EndpointConfig { url: connect_url.clone(), role: EndpointRole::Connect, options: endpoint_options(4, true) }
+    ];
+
+    peer.bind(&connect_url).unwrap();
+
+    let mut endpoints = push.apply_endpoint_config(&configs).unwrap();
+    assert_eq!(2, endpoints.len());
+    assert_eq!(configs, push.dump_endpoint_config().unwrap());
+
+    endpoints.pop().unwrap().close().unwrap();
+    assert_eq!(&configs[..1], &push.dump_endpoint_config().unwrap()[..]);
+}
+
+#[test]
+fn session_should_report_whether_its_reactor_is_alive() {
+    let _ = ::env_logger::init();
+    let session = make_session();
+
+    assert!(session.is_healthy());
+
+    let mut session = SessionBuilder::new().
+        with("crash", Crash).
+        build().
+        expect("Failed to create session !");
+    let mut pair = session.create_socket::<Pair>().expect("Failed to create socket !");
+
+    assert!(session.is_healthy());
+    assert!(pair.connect("crash://reactor").is_err());
+    assert!(!session.is_healthy_within(Duration::from_millis(100)));
+}
+
+#[test]
+fn session_should_refuse_to_create_sockets_beyond_its_cap() {
+    let _ = ::env_logger::init();
+    let mut session = SessionBuilder::new().
+        with("tcp", Tcp).
+        with_max_sockets(2).
+        build().
+        expect("Failed to create session !");
+    let first = session.create_socket::<Pair>().expect("Failed to create socket !");
+    let _second = session.create_socket::<Pair>().expect("Failed to create socket !");
+
+    assert!(session.create_socket::<Pair>().is_err());
+
+    drop(first);
+
+    assert!(session.create_socket::<Pair>().is_ok());
+}
+
+struct TaggedIds;
+
+const ID_TAG: usize = 0x4000_0000;
+
+impl EndpointIdAllocator for TaggedIds {
+    fn allocate(&mut self, _: usize, seq: usize) -> usize {
+        ID_TAG | seq
+    }
+}
+
+#[test]
+fn session_should_use_its_endpoint_id_allocator() {
+    let _ = ::env_logger::init();
+    let mut session = SessionBuilder::new().
+        with("tcp", Tcp).
+        with_endpoint_id_allocator(TaggedIds).
+        build().
+        expect("Failed to create session !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    let bound = pull.bind(&url).unwrap();
+    let connected = push.connect(&url).unwrap();
+
+    assert_eq!(ID_TAG, bound.id() & ID_TAG);
+    assert_eq!(ID_TAG, connected.id() & ID_TAG);
+    assert!(bound.id() != connected.id());
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+}
+
+fn position_of(events: &[ReactorEvent], kind: ReactorEventKind, endpoint_id: usize) -> usize {
+    events.iter().position(|e| e.kind == kind && e.endpoint_id == endpoint_id).expect("event not recorded")
+}
+
+#[test]
+fn session_should_record_endpoint_events_in_its_ring() {
+    let _ = ::env_logger::init();
+    let mut session = SessionBuilder::new().
+        with("tcp", Tcp).
+        with_event_ring(64).
+        build().
+        expect("Failed to create session !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    let bound = pull.bind(&url).unwrap();
+    let connected = push.connect(&url).unwrap();
+    let connected_id = connected.id();
+    sleep_some();
+    connected.close().unwrap();
+    sleep_some();
+
+    let events = session.recent_events();
+    let accepted_id = events.iter().find(|e| e.kind == ReactorEventKind::PipeAccepted).expect("no pipe accepted").endpoint_id;
+    let bound_opened = position_of(&events, ReactorEventKind::AcceptorOpened, bound.id());
+    let accepted = position_of(&events, ReactorEventKind::PipeAccepted, accepted_id);
+    let accepted_opened = position_of(&events, ReactorEventKind::PipeOpened, accepted_id);
+    let connected_opened = position_of(&events, ReactorEventKind::PipeOpened, connected_id);
+    let connected_closed = position_of(&events, ReactorEventKind::PipeClosed, connected_id);
+
+    assert!(bound_opened < accepted);
+    assert!(accepted < accepted_opened);
+    assert!(connected_opened < connected_closed);
+    assert!(events.windows(2).all(|w| w[0].seq + 1 == w[1].seq));
+    assert!(make_session().recent_events().is_empty());
+}
+
+#[test]
+fn rebind_to_a_busy_address_should_give_up_after_max_attempts() {
+    let _ = ::env_logger::init();
+    let mut session = SessionBuilder::new().
+        with("tcp", Tcp).
+        with_event_ring(64).
+        build().
+        expect("Failed to create session !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let busy = ::std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let busy_url = format!("tcp://{}", busy.local_addr().unwrap());
+
+    pull.set_option(ConfigOption::RetryIvl(Duration::from_millis(10))).unwrap();
+    pull.set_option(ConfigOption::RetryIvlMax(Some(Duration::from_millis(40)))).unwrap();
+    pull.set_option(ConfigOption::MaxRebindAttempts(Some(3))).unwrap();
+
+    let bound = pull.bind(&urls::tcp::get()).unwrap();
+    bound.rebind(&busy_url).unwrap();
+
+    let mut closed = None;
+    for _ in 0..50 {
+        sleep_some();
+        closed = session.recent_events().into_iter().find(|e| e.kind == ReactorEventKind::EndpointClosed);
+        if closed.is_some() {
+            break;
+        }
+    }
+
+    let closed = closed.expect("endpoint was not given up");
+    let os_error = closed.os_error.expect("no error reported");
+
+    assert_eq!(bound.id(), closed.endpoint_id);
+    assert_eq!(io::ErrorKind::AddrInUse, io::Error::from_raw_os_error(os_error).kind());
+    assert_eq!(Some(ErrorCategory::AddrInUse), closed.error_category);
+    assert!(pull.dump_endpoint_config().unwrap().is_empty());
+}
+
+/// Picks the next counter value for the first endpoint, then keeps returning it.
+#[derive(Default)]
+struct ClaimingIds {
+    claimed: Option<usize>
+}
+
+impl EndpointIdAllocator for ClaimingIds {
+    fn allocate(&mut self, _: usize, seq: usize) -> usize {
+        *self.claimed.get_or_insert(seq + 1)
+    }
+}
+
+#[test]
+fn session_should_not_fall_back_on_an_endpoint_id_picked_by_its_allocator() {
+    let _ = ::env_logger::init();
+    let mut session = SessionBuilder::new().
+        with("tcp", Tcp).
+        with_endpoint_id_allocator(ClaimingIds::default()).
+        build().
+        expect("Failed to create session !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    let bound = pull.bind(&url).unwrap();
+    let connected = push.connect(&url).unwrap();
+
+    assert!(bound.id() != connected.id());
+
+    push.set_send_timeout(Some(Duration::from_millis(500))).unwrap();
+    pull.set_recv_timeout(Some(Duration::from_millis(500))).unwrap();
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+}
+
+#[test]
+fn size_histogram_should_bucket_the_sizes_of_the_messages() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let sizes = [0, 1, 3, 100, 1000, 1023];
+
+    push.set_option(ConfigOption::SizeHistogram(true)).unwrap();
+    pull.set_option(ConfigOption::SizeHistogram(true)).unwrap();
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+
+    for size in sizes.iter() {
+        push.send(vec![65; *size]).unwrap();
+        assert_eq!(*size, pull.recv().unwrap().len());
+    }
+
+    let sent = push.size_histogram().unwrap();
+    let received = pull.size_histogram().unwrap();
+
+    assert_eq!(sent, received);
+    assert_eq!(&[1, 1, 1, 0, 0, 0, 0, 1, 0, 0, 2], sent.buckets());
+    assert_eq!(2, sent.count_of_size(512));
+    assert_eq!(6, sent.count());
+    assert_eq!(2127, sent.total_size());
+    assert_eq!(354.5, sent.average_size());
+}
+
+#[test]
+fn size_histogram_should_stay_empty_unless_enabled() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    push.send(vec![65, 66, 67]).unwrap();
+    pull.recv().unwrap();
+
+    assert_eq!(0, push.size_histogram().unwrap().count());
+    assert_eq!(0, pull.size_histogram().unwrap().count());
+}
+
+#[test]
+fn stats_should_count_messages_bytes_and_connections() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    let ep = push.connect(&url).unwrap();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    push.send(vec![68, 69]).unwrap();
+    pull.recv().unwrap();
+    pull.recv().unwrap();
+
+    let sent = push.stats().unwrap();
+    let received = pull.stats().unwrap();
+
+    assert_eq!(2, sent.messages_sent);
+    assert_eq!(5, sent.bytes_sent);
+    assert_eq!(0, sent.messages_recv);
+    assert_eq!(1, sent.connect_count);
+    assert_eq!(0, sent.accept_count);
+    assert_eq!(1, sent.current_connections);
+    assert_eq!(2, received.messages_recv);
+    assert_eq!(5, received.bytes_recv);
+    assert_eq!(0, received.connect_count);
+    assert_eq!(1, received.accept_count);
+    assert_eq!(1, received.current_connections);
+
+    ep.close().unwrap();
+    sleep_some();
+
+    let closed = push.stats().unwrap();
+
+    assert_eq!(2, closed.messages_sent);
+    assert_eq!(1, closed.connect_count);
+    assert_eq!(0, closed.current_connections);
+}
+
+#[test]
+fn new_sibling_should_copy_the_options_but_not_the_endpoints() {
+    let (mut session, url) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let sibling_url = urls::tcp::get();
+
+    pull.set_recv_timeout(Some(Duration::from_millis(100))).unwrap();
+    pull.set_option(ConfigOption::RecvMaxSize(4)).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+    assert!(pull.is_connected());
+
+    let mut sibling = pull.new_sibling().unwrap();
+
+    assert!(sibling.id() != pull.id());
+    assert!(!sibling.is_connected());
+    assert_eq!(0, sibling.stats().unwrap().current_connections);
+    assert_eq!(io::ErrorKind::TimedOut, sibling.recv().unwrap_err().kind());
+
+    let mut sibling_push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    sibling.bind(&sibling_url).unwrap();
+    sibling_push.connect(&sibling_url).unwrap();
+    sibling_push.send(vec![65; 10]).unwrap();
+    assert_eq!(io::ErrorKind::TimedOut, sibling.recv().unwrap_err().kind());
+
+    sibling_push.connect(&sibling_url).unwrap();
+    sibling_push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], sibling.recv().unwrap());
+    assert_eq!(1, pull.stats().unwrap().current_connections);
+}
+
+#[test]
+fn tcp_endpoints_should_resolve_host_names() {
+    let (mut session, _) = before_each();
+    let url = urls::tcp::get().replace("127.0.0.1", "localhost");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    push.set_send_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+    drop(session);
+}
+
+#[test]
+fn connect_to_a_malformed_url_should_fail_immediately() {
+    let (mut session, _) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    for url in &["tcp//127.0.0.1:5454", "tcp://not-an-address", "unknown://127.0.0.1:5454"] {
+        let err = push.connect(url).err().expect("Connect should have failed !");
+
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
+
+#[test]
+fn connect_to_a_dead_address_should_return_an_endpoint_that_reconnects() {
+    let (mut session, _) = before_each();
+    let url = urls::ipc::get();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+
+    push.set_send_timeout(make_timeout()).unwrap();
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    push.connect(&url).expect("Connect should have returned an endpoint !");
+    pull.bind(&url).unwrap();
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+}
+
+struct Crash;
+
+impl transport::Transport for Crash {
+    fn connect(&self, _: &transport::Destination) -> io::Result<Box<dyn transport::pipe::Pipe>> {
+        panic!("Crash transport kills the reactor thread")
+    }
+    fn bind(&self, _: &transport::Destination) -> io::Result<Box<dyn transport::acceptor::Acceptor>> {
+        panic!("Crash transport kills the reactor thread")
+    }
+}
+
+#[cfg(unix)]
+fn is_fd_readable(fd: ::std::os::unix::io::RawFd) -> bool {
+    use mio::{Poll, Events, Ready, PollOpt, Token};
+    use mio::unix::EventedFd;
+
+    let poll = Poll::new().unwrap();
+    let mut events = Events::with_capacity(1);
+
+    poll.register(&EventedFd(&fd), Token(0), Ready::readable(), PollOpt::level()).unwrap();
+    poll.poll(&mut events, Some(Duration::from_millis(0))).unwrap();
+
+    events.iter().any(|e| e.readiness().is_readable())
+}
+
+#[cfg(unix)]
+#[test]
+fn readiness_fds_should_follow_the_socket_readiness() {
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let send_fd = push.send_fd().unwrap();
+    let recv_fd = pull.recv_fd().unwrap();
+
+    assert_eq!(send_fd, push.send_fd().unwrap());
+    assert!(!is_fd_readable(send_fd));
+    assert!(!is_fd_readable(recv_fd));
+
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+    assert!(is_fd_readable(send_fd));
+    assert!(!is_fd_readable(recv_fd));
+
+    push.send(vec![65, 66, 67]).unwrap();
+    sleep_some();
+    assert!(is_fd_readable(recv_fd));
+
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+    sleep_some();
+    assert!(!is_fd_readable(recv_fd));
+    drop(session);
+}
+
+#[cfg(unix)]
+#[test]
+fn wakeup_fd_should_become_readable_when_a_request_is_pending() {
+    use std::sync::mpsc;
+
+    let (mut session, url) = before_each();
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut other = session.create_socket::<Push>().expect("Failed to create socket !");
+    let wakeup_fd = session.wakeup_fd();
+    let (release_tx, release_rx) = mpsc::channel::<()>();
+
+    assert!(wakeup_fd >= 0);
+    sleep_some();
+    assert!(!is_fd_readable(wakeup_fd));
+
+    // Stall the I/O thread in the reducer, so the next request stays pending.
+    pull.set_recv_reducer(Box::new(move |_| { let _ = release_rx.recv(); }), false).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    push.send(vec![65, 66, 67]).unwrap();
+    sleep_some();
+
+    let request_thread = thread::spawn(move || other.set_send_timeout(make_timeout()));
+
+    sleep_some();
+    assert!(is_fd_readable(wakeup_fd));
+
+    release_tx.send(()).unwrap();
+    request_thread.join().unwrap().unwrap();
+    sleep_some();
+    assert!(!is_fd_readable(wakeup_fd));
+    drop(session);
+}
+
+#[test]
+fn accepts_over_the_limit_get_the_busy_signal_and_the_peer_backs_off() {
+    let (mut session, url) = before_each();
+    let mut pull = session.create_socket::<Pull>().expect("Failed to create socket !");
+    let mut push = session.create_socket::<Push>().expect("Failed to create socket !");
+    let mut busy = session.create_socket::<Push>().expect("Failed to create socket !");
+
+    pull.set_option(ConfigOption::MaxAcceptedPipes(Some(1))).unwrap();
+    pull.set_recv_timeout(make_timeout()).unwrap();
+    pull.bind(&url).unwrap();
+    push.connect(&url).unwrap();
+    sleep_some();
+
+    busy.set_option(ConfigOption::RetryIvl(Duration::from_millis(20))).unwrap();
+    busy.set_option(ConfigOption::RetryIvlMax(Some(Duration::from_secs(10)))).unwrap();
+    busy.connect(&url).unwrap();
+    sleep_some();
+
+    assert_eq!(1, pull.stats().unwrap().busy_rejected);
+    assert_eq!(1, pull.stats().unwrap().current_connections);
+    assert_eq!(1, busy.stats().unwrap().busy_received);
+    assert_eq!(0, busy.stats().unwrap().connect_count);
+
+    thread::sleep(Duration::from_millis(300));
+
+    assert_eq!(1, busy.stats().unwrap().busy_received);
+
+    push.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], pull.recv().unwrap());
+}
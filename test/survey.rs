@@ -81,3 +81,47 @@ fn refuse_to_send_a_response_before_receiving_a_survey() {
     assert_eq!(io::ErrorKind::InvalidData, not_sent.kind());
     drop(session);
 }
+
+#[test]
+fn session_default_survey_deadline_is_applied_to_new_surveyors() {
+    let _ = ::env_logger::init();
+    let mut session = SessionBuilder::new().
+        with("tcp", Tcp).
+        with_default(proto::SURVEYOR, ConfigOption::SurveyDeadline(Duration::from_millis(50))).
+        build().
+        expect("Failed to create session !");
+    let mut surv = session.create_socket::<Surveyor>().expect("Failed to create socket !");
+    let mut resp = session.create_socket::<Respondent>().expect("Failed to create socket !");
+    let url = urls::tcp::get();
+
+    surv.set_recv_timeout(make_timeout()).unwrap();
+    resp.set_recv_timeout(make_timeout()).unwrap();
+    surv.bind(&url).unwrap();
+    resp.connect(&url).unwrap();
+    sleep_some();
+
+    surv.send(vec![65, 66, 67]).expect("Surveyor should have sent a survey");
+    resp.recv().expect("Respondent should have received a survey");
+    thread::sleep(Duration::from_millis(200));
+
+    let err = surv.recv().unwrap_err();
+    assert_eq!(io::ErrorKind::Other, err.kind());
+    drop(session);
+}
+
+#[test]
+fn send_survey_should_report_the_number_of_reached_respondents() {
+    let (session, mut surv, mut resp1, mut resp2, url) = before_each();
+    surv.bind(&url).unwrap();
+    resp1.connect(&url).unwrap();
+    resp2.connect(&url).unwrap();
+
+    sleep_some();
+
+    let reached = surv.send_survey(Message::from_body(vec![65, 66, 67])).unwrap();
+    assert_eq!(2, reached);
+
+    resp1.recv().expect("Respondent 1 should have received a survey");
+    resp2.recv().expect("Respondent 2 should have received a survey");
+    drop(session);
+}
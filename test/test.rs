@@ -8,6 +8,8 @@ extern crate log;
 extern crate env_logger;
 extern crate scaproust;
 extern crate rand;
+#[cfg(unix)]
+extern crate mio;
 
 mod socket;
 mod fair_queue;
@@ -20,6 +22,7 @@ mod bus;
 mod device;
 mod reqrep_device;
 mod probe;
+mod ws;
 
 pub use std::time::Duration;
 pub use std::thread;
@@ -52,6 +55,7 @@ pub fn make_session() -> Session {
     SessionBuilder::new().
         with("tcp", Tcp).
         with("ipc", Ipc).
+        with("ws", Ws).
         build().
         expect("Failed to create session !")
 }
@@ -67,19 +71,38 @@ pub mod urls {
     const FIRST_PORT: usize = 18080;
 
     fn next_port() -> usize {
+        next_ports(1)
+    }
+
+    // Reserves `count` consecutive ports, returning the first one
+    fn next_ports(count: usize) -> usize {
         unsafe {
             // If the atomic was never used, set it to the initial port
             NEXT_PORT.compare_and_swap(0, FIRST_PORT, SeqCst);
 
             // Get and increment the port list
-            NEXT_PORT.fetch_add(1, SeqCst)
+            NEXT_PORT.fetch_add(count, SeqCst)
         }
     }
 
     pub mod tcp {
+        use std::ops::Range;
+
         pub fn get() -> String {
             format!("tcp://127.0.0.1:{}", super::next_port())
         }
+
+        pub fn get_port_range(count: usize) -> Range<u16> {
+            let first = super::next_ports(count);
+
+            (first as u16)..((first + count) as u16)
+        }
+    }
+
+    pub mod ws {
+        pub fn get() -> String {
+            format!("ws://127.0.0.1:{}/scaproust", super::next_port())
+        }
     }
 
     pub mod ipc {
@@ -0,0 +1,142 @@
+// Copyright (c) 2015-2017 Contributors as noted in the AUTHORS file.
+//
+// Licensed under the Apache License, Version 2.0 <LICENSE-APACHE or http://www.apache.org/licenses/LICENSE-2.0>
+// or the MIT license <LICENSE-MIT or http://opensource.org/licenses/MIT>, at your option.
+// This file may not be copied, modified, or distributed except according to those terms.
+
+use std::io::{Read, Write};
+use std::net::TcpStream;
+
+pub use scaproust::*;
+
+pub use super::{urls, make_session, make_timeout, make_hard_timeout, sleep_some};
+
+fn before_each() -> (Session, Socket, Socket, String) {
+    let _ = ::env_logger::init();
+    let mut session = make_session();
+    let mut left = session.create_socket::<Pair>().expect("Failed to create socket !");
+    let mut right = session.create_socket::<Pair>().expect("Failed to create socket !");
+    let url = urls::ws::get();
+    let timeout = make_timeout();
+
+    left.set_send_timeout(timeout).expect("Failed to set send timeout !");
+    left.set_recv_timeout(timeout).expect("Failed to set recv timeout !");
+
+    right.set_send_timeout(timeout).expect("Failed to set send timeout !");
+    right.set_recv_timeout(timeout).expect("Failed to set recv timeout !");
+
+    (session, left, right, url)
+}
+
+#[test]
+fn send_messages_both_ways_over_websocket() {
+    let (session, mut left, mut right, url) = before_each();
+
+    let bound = left.bind(&url).unwrap();
+    sleep_some();
+    let connected = right.connect(&url).unwrap();
+    sleep_some();
+
+    left.send(vec![65, 66, 67]).unwrap();
+    assert_eq!(vec![65, 66, 67], right.recv().unwrap());
+
+    right.send(vec![67, 66, 65]).unwrap();
+    assert_eq!(vec![67, 66, 65], left.recv().unwrap());
+
+    assert_eq!(TransportKind::Ws, bound.transport_info().kind);
+    assert_eq!(TransportKind::Ws, connected.transport_info().kind);
+    drop(session);
+}
+
+#[test]
+fn large_message_split_across_frames_should_be_reassembled() {
+    let (session, mut left, mut right, url) = before_each();
+
+    right.set_option(ConfigOption::SendChunkSize(1000)).unwrap();
+    left.bind(&url).unwrap();
+    sleep_some();
+    right.connect(&url).unwrap();
+    sleep_some();
+
+    let sent: Vec<u8> = (0..100_000u32).map(|x| x as u8).collect();
+    right.send(sent.clone()).unwrap();
+    let received = left.recv().unwrap();
+
+    assert_eq!(sent, received);
+    drop(session);
+}
+
+#[test]
+fn connecting_to_another_path_should_not_open_a_pipe() {
+    let (session, mut left, mut right, url) = before_each();
+
+    left.bind(&url).unwrap();
+    sleep_some();
+    right.connect(&format!("{}/other", url)).unwrap();
+    sleep_some();
+
+    assert!(right.send(vec![65, 66, 67]).is_err());
+    drop(session);
+}
+
+fn read_head(stream: &mut TcpStream) -> String {
+    let mut head = Vec::new();
+    let mut byte = [0u8; 1];
+
+    while !head.ends_with(b"\r\n\r\n") {
+        stream.read_exact(&mut byte).unwrap();
+        head.push(byte[0]);
+    }
+
+    String::from_utf8(head).unwrap()
+}
+
+fn masked_frame(first: u8, payload: &[u8]) -> Vec<u8> {
+    let mask = [1u8, 2, 3, 4];
+    let mut frame = vec![first, 0x80 | payload.len() as u8];
+
+    frame.extend_from_slice(&mask);
+    frame.extend(payload.iter().enumerate().map(|(i, b)| b ^ mask[i % 4]));
+    frame
+}
+
+#[test]
+fn raw_websocket_client_should_get_pongs_and_reassembled_messages() {
+    let (session, mut left, _, url) = before_each();
+    let addr = url.trim_start_matches("ws://").trim_end_matches("/scaproust").to_owned();
+
+    left.bind(&url).unwrap();
+    sleep_some();
+
+    let mut client = TcpStream::connect(&addr[..]).unwrap();
+    client.set_read_timeout(Some(make_hard_timeout())).unwrap();
+    client.write_all(format!(
+        "GET /scaproust HTTP/1.1\r\nHost: {}\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Key: dGhlIHNhbXBsZSBub25jZQ==\r\nSec-WebSocket-Version: 13\r\n\r\n", addr).as_bytes()).unwrap();
+
+    let head = read_head(&mut client);
+    assert!(head.starts_with("HTTP/1.1 101"));
+    assert!(head.contains("Sec-WebSocket-Accept: s3pPLMBiTxaQ9kYGzzhZRbK+xOo=\r\n"));
+
+    let mut handshake = [0u8; 10];
+    client.read_exact(&mut handshake).unwrap();
+    assert_eq!([0x82, 8, 0, 83, 80], handshake[..5]);
+
+    client.write_all(&masked_frame(0x82, &handshake[2..])).unwrap();
+    client.write_all(&masked_frame(0x02, b"AB")).unwrap();
+    client.write_all(&masked_frame(0x89, b"hi")).unwrap();
+    client.write_all(&masked_frame(0x80, b"C")).unwrap();
+
+    assert_eq!(b"ABC".to_vec(), left.recv().unwrap());
+
+    let mut pong = [0u8; 4];
+    client.read_exact(&mut pong).unwrap();
+    assert_eq!([0x8A, 2, b'h', b'i'], pong);
+
+    left.send(b"xyz".to_vec()).unwrap();
+    let mut frame = [0u8; 5];
+    client.read_exact(&mut frame).unwrap();
+    assert_eq!([0x82, 3, b'x', b'y', b'z'], frame);
+
+    drop(session);
+}